@@ -22,13 +22,18 @@ mod utils;
 
 use self::blocks_api::BlockHandler;
 use self::blocks_api::HeaderHandler;
+use self::chain_api::ChainBackgroundValidationHandler;
 use self::chain_api::ChainCompactHandler;
 use self::chain_api::ChainHandler;
+use self::chain_api::ChainSupplyHandler;
 use self::chain_api::ChainValidationHandler;
 use self::chain_api::OutputHandler;
+use self::chain_api::OutputHistoryHandler;
+use self::chain_api::PaymentProofHandler;
 use self::peers_api::PeerHandler;
 use self::peers_api::PeersAllHandler;
 use self::peers_api::PeersConnectedHandler;
+use self::pool_api::PoolFeeEstimateHandler;
 use self::pool_api::PoolInfoHandler;
 use self::pool_api::PoolPushHandler;
 use self::server_api::IndexHandler;
@@ -94,8 +99,12 @@ pub fn build_router(
 		"get chain".to_string(),
 		"post chain/compact".to_string(),
 		"get chain/validate".to_string(),
+		"post chain/validate/start".to_string(),
+		"post chain/validate/cancel".to_string(),
+		"get chain/supply?height=1000".to_string(),
 		"get chain/outputs/byids?id=xxx,yyy,zzz".to_string(),
 		"get chain/outputs/byheight?start_height=101&end_height=200".to_string(),
+		"get chain/outputs/xxx/history".to_string(),
 		"get status".to_string(),
 		"get txhashset/roots".to_string(),
 		"get txhashset/lastoutputs?n=10".to_string(),
@@ -117,6 +126,10 @@ pub fn build_router(
 		chain: Arc::downgrade(&chain),
 	};
 
+	let output_history_handler = OutputHistoryHandler {
+		chain: Arc::downgrade(&chain),
+	};
+
 	let block_handler = BlockHandler {
 		chain: Arc::downgrade(&chain),
 	};
@@ -129,9 +142,18 @@ pub fn build_router(
 	let chain_compact_handler = ChainCompactHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let chain_supply_handler = ChainSupplyHandler {
+		chain: Arc::downgrade(&chain),
+	};
+	let payment_proof_handler = PaymentProofHandler {
+		chain: Arc::downgrade(&chain),
+	};
 	let chain_validation_handler = ChainValidationHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let chain_background_validation_handler = ChainBackgroundValidationHandler {
+		chain: Arc::downgrade(&chain),
+	};
 	let status_handler = StatusHandler {
 		chain: Arc::downgrade(&chain),
 		peers: Arc::downgrade(&peers),
@@ -148,6 +170,9 @@ pub fn build_router(
 	let pool_push_handler = PoolPushHandler {
 		tx_pool: Arc::downgrade(&tx_pool),
 	};
+	let pool_fee_estimate_handler = PoolFeeEstimateHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
 	let peers_all_handler = PeersAllHandler {
 		peers: Arc::downgrade(&peers),
 	};
@@ -165,13 +190,27 @@ pub fn build_router(
 	router.add_route("/v1/headers/*", Arc::new(header_handler))?;
 	router.add_route("/v1/chain", Arc::new(chain_tip_handler))?;
 	router.add_route("/v1/chain/outputs/*", Arc::new(output_handler))?;
+	router.add_route(
+		"/v1/chain/outputs/*/history",
+		Arc::new(output_history_handler),
+	)?;
 	router.add_route("/v1/chain/compact", Arc::new(chain_compact_handler))?;
 	router.add_route("/v1/chain/validate", Arc::new(chain_validation_handler))?;
+	router.add_route(
+		"/v1/chain/validate/*",
+		Arc::new(chain_background_validation_handler),
+	)?;
+	router.add_route("/v1/chain/supply", Arc::new(chain_supply_handler))?;
+	router.add_route("/v1/chain/payment_proof", Arc::new(payment_proof_handler))?;
 	router.add_route("/v1/txhashset/*", Arc::new(txhashset_handler))?;
 	router.add_route("/v1/status", Arc::new(status_handler))?;
 	router.add_route("/v1/kerneldownload", Arc::new(kernel_download_handler))?;
 	router.add_route("/v1/pool", Arc::new(pool_info_handler))?;
 	router.add_route("/v1/pool/push", Arc::new(pool_push_handler))?;
+	router.add_route(
+		"/v1/pool/fee_estimate",
+		Arc::new(pool_fee_estimate_handler),
+	)?;
 	router.add_route("/v1/peers/all", Arc::new(peers_all_handler))?;
 	router.add_route("/v1/peers/connected", Arc::new(peers_connected_handler))?;
 	router.add_route("/v1/peers/**", Arc::new(peer_handler))?;
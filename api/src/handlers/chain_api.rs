@@ -15,6 +15,7 @@
 use super::utils::{get_output, w};
 use crate::chain;
 use crate::core::core::hash::Hashed;
+use crate::core::libtx::payment_proof::PaymentProof;
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
@@ -22,6 +23,7 @@ use crate::util;
 use crate::util::secp::pedersen::Commitment;
 use crate::web::*;
 use failure::ResultExt;
+use futures::Future;
 use hyper::{Body, Request, StatusCode};
 use std::sync::Weak;
 
@@ -64,6 +66,34 @@ impl Handler for ChainValidationHandler {
 	}
 }
 
+/// Background chain validation handler. Starts or cancels a full validation
+/// of the chain state, run by the node a small chunk at a time. Its progress
+/// is reported in the status.
+/// POST /v1/chain/validate/start
+/// POST /v1/chain/validate/cancel
+pub struct ChainBackgroundValidationHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl Handler for ChainBackgroundValidationHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let chain = w_fut!(&self.chain);
+		let (res, not_done) = match right_path_element!(req) {
+			"start" => (chain.start_validation(), "validation already running"),
+			"cancel" => (chain.cancel_validation(), "no validation running"),
+			_ => return response(StatusCode::BAD_REQUEST, "invalid url"),
+		};
+		match res {
+			Ok(true) => response(StatusCode::OK, "{}"),
+			Ok(false) => response(StatusCode::CONFLICT, not_done),
+			Err(e) => response(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				format!("background validation failed: {}", e),
+			),
+		}
+	}
+}
+
 /// Chain compaction handler. Trigger a compaction of the chain state to regain
 /// storage space.
 /// POST /v1/chain/compact
@@ -83,6 +113,111 @@ impl Handler for ChainCompactHandler {
 	}
 }
 
+/// Chain supply handler. Audit the coin supply against the emission schedule
+/// at the chain head, or at the provided height.
+/// GET /v1/chain/supply
+/// GET /v1/chain/supply?height=1000
+pub struct ChainSupplyHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl ChainSupplyHandler {
+	fn get_supply(&self, req: &Request<Body>) -> Result<Supply, Error> {
+		let params = QueryParams::from(req.uri().query().unwrap_or(""));
+		let height = match params.get("height") {
+			Some(height) => Some(
+				height
+					.parse::<u64>()
+					.map_err(|_| ErrorKind::RequestError(format!("invalid height: {}", height)))?,
+			),
+			None => None,
+		};
+		let audit = w(&self.chain)?
+			.supply_audit(height)
+			.map_err(|e| ErrorKind::Internal(format!("can't audit supply: {}", e)))?;
+		Ok(Supply::from_audit(audit))
+	}
+}
+
+impl Handler for ChainSupplyHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_supply(&req))
+	}
+}
+
+/// Output history handler. When an output was created and, if it was, spent.
+/// Only available on archive nodes.
+/// GET /v1/chain/outputs/<commit>/history
+pub struct OutputHistoryHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl OutputHistoryHandler {
+	fn get_history(&self, id: &str) -> Result<OutputHistory, Error> {
+		let c = util::from_hex(String::from(id)).context(ErrorKind::Argument(format!(
+			"Not a valid commitment: {}",
+			id
+		)))?;
+		let commit = Commitment::from_vec(c);
+		match w(&self.chain)?.get_output_history(&commit) {
+			Ok(history) => Ok(OutputHistory::from_history(&commit, history)),
+			Err(e) => match e.kind() {
+				chain::ErrorKind::StoreErr(_, _) => Err(ErrorKind::NotFound)?,
+				_ => Err(ErrorKind::Internal(format!(
+					"can't get output history: {}",
+					e
+				)))?,
+			},
+		}
+	}
+}
+
+impl Handler for OutputHistoryHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let mut path_elems = req.uri().path().trim_end_matches('/').rsplit('/');
+		match path_elems.nth(1) {
+			Some(id) => result_to_response(self.get_history(id)),
+			None => response(StatusCode::BAD_REQUEST, "invalid url"),
+		}
+	}
+}
+
+/// Payment proof handler. Verifies a payment proof is signed by its recipient
+/// and its kernel is on chain, telling which block the kernel is in.
+/// POST /v1/chain/payment_proof
+pub struct PaymentProofHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl PaymentProofHandler {
+	fn verify_proof(&self, proof: PaymentProof) -> Result<PaymentProofStatus, Error> {
+		match w(&self.chain)?.verify_payment_proof(&proof) {
+			Ok(header) => Ok(PaymentProofStatus::from_proof(&proof, &header)),
+			Err(e) => match e.kind() {
+				chain::ErrorKind::TxKernelNotFound => Err(ErrorKind::NotFound)?,
+				chain::ErrorKind::InvalidPaymentProof(msg) => Err(ErrorKind::RequestError(msg))?,
+				_ => Err(ErrorKind::Internal(format!(
+					"can't verify payment proof: {}",
+					e
+				)))?,
+			},
+		}
+	}
+}
+
+impl Handler for PaymentProofHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let handler = PaymentProofHandler {
+			chain: self.chain.clone(),
+		};
+		Box::new(
+			parse_body(req)
+				.and_then(move |proof: PaymentProof| handler.verify_proof(proof))
+				.then(result_to_response),
+		)
+	}
+}
+
 // Supports retrieval of multiple outputs in a single request -
 // GET /v1/chain/outputs/byids?id=xxx,yyy,zzz
 // GET /v1/chain/outputs/byids?id=xxx&id=yyy&id=zzz
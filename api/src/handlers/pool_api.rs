@@ -18,7 +18,7 @@ use crate::core::core::Transaction;
 use crate::core::ser;
 use crate::pool;
 use crate::rest::*;
-use crate::router::{Handler, ResponseFuture};
+use crate::router::{Handler, RemoteAddr, ResponseFuture};
 use crate::types::*;
 use crate::util;
 use crate::util::RwLock;
@@ -46,6 +46,35 @@ impl Handler for PoolInfoHandler {
 	}
 }
 
+/// Fee for a transaction to get mined within a number of blocks, one by
+/// default, estimated from the transactions recently mined and evicted.
+/// GET /v1/pool/fee_estimate
+/// GET /v1/pool/fee_estimate?target_blocks=5
+pub struct PoolFeeEstimateHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl PoolFeeEstimateHandler {
+	fn estimate_fee(&self, req: &Request<Body>) -> Result<pool::FeeEstimate, Error> {
+		let params = QueryParams::from(req.uri().query().unwrap_or(""));
+		let target_blocks = match params.get("target_blocks") {
+			Some(target) => target.parse::<u64>().map_err(|_| {
+				ErrorKind::RequestError(format!("invalid target_blocks: {}", target))
+			})?,
+			None => 1,
+		};
+		let pool = w(&self.tx_pool)?;
+		let estimate = pool.read().estimate_fee(target_blocks);
+		Ok(estimate)
+	}
+}
+
+impl Handler for PoolFeeEstimateHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.estimate_fee(&req))
+	}
+}
+
 /// Dummy wrapper for the hex-encoded serialized transaction.
 #[derive(Serialize, Deserialize)]
 struct TxWrapper {
@@ -63,6 +92,11 @@ impl PoolPushHandler {
 		let params = QueryParams::from(req.uri().query());
 
 		let fluff = params.get("fluff").is_some();
+		// Clients are told apart by IP, all the unknown ones sharing a quota.
+		let client = match req.extensions().get::<RemoteAddr>() {
+			Some(RemoteAddr(addr)) => addr.ip().to_string(),
+			None => "?.?.?.?".to_string(),
+		};
 		let pool_arc = match w(&self.tx_pool) {
 			//w(&self.tx_pool).clone();
 			Ok(p) => p,
@@ -80,10 +114,7 @@ impl PoolPushHandler {
 						.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)).into())
 				})
 				.and_then(move |tx: Transaction| {
-					let source = pool::TxSource {
-						debug_name: "push-api".to_string(),
-						identifier: "?.?.?.?".to_string(),
-					};
+					let source = pool::TxSource::api_client(client);
 					info!(
 						"Pushing transaction {} to pool (inputs: {}, outputs: {}, kernels: {})",
 						tx.hash(),
@@ -68,12 +68,33 @@ pub struct StatusHandler {
 
 impl StatusHandler {
 	fn get_status(&self) -> Result<Status, Error> {
-		let head = w(&self.chain)?
+		let chain = w(&self.chain)?;
+		let head = chain
 			.head()
 			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let validation = chain
+			.validation_progress()
+			.map_err(|e| ErrorKind::Internal(format!("can't get validation: {}", e)))?;
+		let peers = w(&self.peers)?;
+		let database = match (chain.db_stats(), peers.db_stats()) {
+			(Ok(chain_stats), Ok(peer_stats)) => Some(DatabaseStats {
+				chain: StoreStats::from_stats(chain_stats),
+				peers: StoreStats::from_stats(peer_stats),
+			}),
+			(Err(e), _) => {
+				error!("status: can't get the chain database stats: {}", e);
+				None
+			}
+			(_, Err(e)) => {
+				error!("status: can't get the peer database stats: {:?}", e);
+				None
+			}
+		};
 		Ok(Status::from_tip_and_peers(
 			head,
-			w(&self.peers)?.peer_count(),
+			peers.peer_count(),
+			validation,
+			database,
 		))
 	}
 }
@@ -16,7 +16,7 @@ use epic_chain as chain;
 use epic_core as core;
 use epic_p2p as p2p;
 use epic_pool as pool;
-
+use epic_store as store;
 use epic_util as util;
 
 use failure;
@@ -22,8 +22,10 @@ use crate::router::{Handler, HandlerObj, ResponseFuture, Router};
 use crate::web::response;
 use failure::{Backtrace, Context, Fail, ResultExt};
 use futures::sync::oneshot;
-use futures::Stream;
+use futures::{future, Stream};
 use hyper::rt::Future;
+use hyper::server::conn::AddrStream;
+use hyper::service::make_service_fn;
 use hyper::{rt, Body, Request, Server, StatusCode};
 use rustls;
 use rustls::internal::pemfile;
@@ -191,7 +193,9 @@ impl ApiServer {
 			.name("apis".to_string())
 			.spawn(move || {
 				let server = Server::bind(&addr)
-					.serve(router)
+					.serve(make_service_fn(move |conn: &AddrStream| {
+						future::ok::<_, hyper::Error>(router.with_remote_addr(conn.remote_addr()))
+					}))
 					// TODO graceful shutdown is unstable, investigate
 					//.with_graceful_shutdown(rx)
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
@@ -233,7 +237,14 @@ impl ApiServer {
 					})
 					.filter_map(|x| x);
 				let server = Server::builder(tls)
-					.serve(router)
+					.serve(make_service_fn(
+						move |conn: &tokio_rustls::TlsStream<tokio_tcp::TcpStream, _>| {
+							future::ok::<_, hyper::Error>(match conn.get_ref().0.peer_addr() {
+								Ok(addr) => router.with_remote_addr(addr),
+								Err(_) => router.clone(),
+							})
+						},
+					))
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
 
 				rt::run(server);
@@ -19,6 +19,7 @@ use hyper::service::{NewService, Service};
 use hyper::{Body, Method, Request, Response, StatusCode};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 lazy_static! {
@@ -98,8 +99,14 @@ pub enum RouterError {
 #[derive(Clone)]
 pub struct Router {
 	nodes: Vec<Node>,
+	remote_addr: Option<SocketAddr>,
 }
 
+/// Address of the client a request comes from, added to the request
+/// extensions when the server knows it.
+#[derive(Clone, Copy, Debug)]
+pub struct RemoteAddr(pub SocketAddr);
+
 #[derive(Debug, Clone, Copy)]
 struct NodeId(usize);
 
@@ -121,7 +128,19 @@ impl Router {
 		let root = Node::new(calculate_hash(&""), None);
 		let mut nodes = vec![];
 		nodes.push(root);
-		Router { nodes }
+		Router {
+			nodes,
+			remote_addr: None,
+		}
+	}
+
+	/// Router for the requests of a single connection, from the provided
+	/// client address.
+	pub fn with_remote_addr(&self, addr: SocketAddr) -> Router {
+		Router {
+			nodes: self.nodes.clone(),
+			remote_addr: Some(addr),
+		}
 	}
 
 	pub fn add_middleware(&mut self, mw: HandlerObj) {
@@ -209,7 +228,10 @@ impl Service for Router {
 	type Error = hyper::Error;
 	type Future = ResponseFuture;
 
-	fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+	fn call(&mut self, mut req: Request<Self::ReqBody>) -> Self::Future {
+		if let Some(addr) = self.remote_addr {
+			req.extensions_mut().insert(RemoteAddr(addr));
+		}
 		match self.get(req.uri().path()) {
 			Err(_) => not_found(),
 			Ok(mut handlers) => match handlers.next() {
@@ -17,9 +17,12 @@ use std::sync::Arc;
 use crate::chain;
 use crate::core::core::hash::Hashed;
 use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::global::{self, VersionCheckStatus};
+use crate::core::libtx::payment_proof::PaymentProof;
 use crate::core::pow::PoWType;
 use crate::core::{core, ser};
 use crate::p2p;
+use crate::store;
 use crate::util;
 use crate::util::secp::pedersen;
 use bigint::uint::U256;
@@ -27,7 +30,7 @@ use epic_core::pow::Proof;
 use serde;
 use serde::de::MapAccess;
 use serde::ser::SerializeStruct;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Display};
 
 macro_rules! no_dup {
@@ -62,6 +65,78 @@ impl Tip {
 	}
 }
 
+/// Supply audit of the chain at a given block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Supply {
+	/// Height of the audited block
+	pub height: u64,
+	// Hash of the audited block
+	pub hash: String,
+	// Expected mining rewards emitted so far
+	pub mining_reward: u64,
+	// Foundation levy paid out so far
+	pub foundation_reward: u64,
+	// Foundation levy accrued since the last payout
+	pub foundation_pending: u64,
+	// Expected total supply
+	pub total_supply: u64,
+	// Total overage expected by the consensus rules
+	pub total_overage: i64,
+	// Whether the expected supply matches the total overage
+	pub supply_matches_overage: bool,
+	// Whether the kernel sums match the expected supply
+	pub kernel_sums_valid: bool,
+	// Why the kernel sums did not validate
+	pub kernel_sums_error: Option<String>,
+	// Number of unspent outputs
+	pub utxo_count: u64,
+	// Number of output leaves pruned
+	pub pruned_output_count: u64,
+	// Number of pruned output leaves compacted away
+	pub compacted_output_count: u64,
+	// Number of rangeproof leaves pruned
+	pub pruned_rproof_count: u64,
+	// Number of pruned rangeproof leaves compacted away
+	pub compacted_rproof_count: u64,
+	// Number of kernels
+	pub kernel_count: u64,
+	// Size of the header MMR
+	pub header_mmr_size: u64,
+	// Size of the output MMR
+	pub output_mmr_size: u64,
+	// Size of the rangeproof MMR
+	pub rproof_mmr_size: u64,
+	// Size of the kernel MMR
+	pub kernel_mmr_size: u64,
+}
+
+impl Supply {
+	pub fn from_audit(audit: chain::SupplyAudit) -> Supply {
+		Supply {
+			height: audit.height,
+			hash: util::to_hex(audit.hash.to_vec()),
+			mining_reward: audit.mining_reward,
+			foundation_reward: audit.foundation_reward,
+			foundation_pending: audit.foundation_pending,
+			total_supply: audit.total_supply,
+			total_overage: audit.total_overage,
+			supply_matches_overage: audit.supply_matches_overage,
+			kernel_sums_valid: audit.kernel_sums_valid,
+			kernel_sums_error: audit.kernel_sums_error,
+			utxo_count: audit.utxo_count,
+			pruned_output_count: audit.pruned_output_count,
+			compacted_output_count: audit.compacted_output_count,
+			pruned_rproof_count: audit.pruned_rproof_count,
+			compacted_rproof_count: audit.compacted_rproof_count,
+			kernel_count: audit.kernel_count,
+			header_mmr_size: audit.header_mmr_size,
+			output_mmr_size: audit.output_mmr_size,
+			rproof_mmr_size: audit.rproof_mmr_size,
+			kernel_mmr_size: audit.kernel_mmr_size,
+		}
+	}
+}
+
 /// Status page containing different server information
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Status {
@@ -73,15 +148,147 @@ pub struct Status {
 	pub connections: u32,
 	// The state of the current fork Tip
 	pub tip: Tip,
+	// The outcome of the last node version check
+	#[serde(default)]
+	pub version_check: Option<VersionCheck>,
+	// The last background chain validation, if any
+	#[serde(default)]
+	pub validation: Option<ChainValidation>,
+	// Usage of the chain and peer databases
+	#[serde(default)]
+	pub database: Option<DatabaseStats>,
 }
 
 impl Status {
-	pub fn from_tip_and_peers(current_tip: chain::Tip, connections: u32) -> Status {
+	pub fn from_tip_and_peers(
+		current_tip: chain::Tip,
+		connections: u32,
+		validation: Option<chain::ValidationProgress>,
+		database: Option<DatabaseStats>,
+	) -> Status {
 		Status {
 			protocol_version: p2p::msg::ProtocolVersion::default().into(),
-			user_agent: p2p::msg::USER_AGENT.to_string(),
+			user_agent: p2p::msg::user_agent(),
 			connections: connections,
 			tip: Tip::from_tip(current_tip),
+			version_check: Some(VersionCheck::from_check(global::get_version_check())),
+			validation: validation.map(ChainValidation::from_progress),
+			database,
+		}
+	}
+}
+
+/// Usage of the chain and peer databases
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseStats {
+	pub chain: StoreStats,
+	pub peers: StoreStats,
+}
+
+/// Usage of a database
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoreStats {
+	// Size of the database file, in bytes
+	pub file_size: u64,
+	// How large the database can get before it gets resized, in bytes
+	pub map_size: u64,
+	// Size of a page, in bytes
+	pub page_size: u64,
+	// Number of pages holding entries
+	pub used_pages: u64,
+	// Number of pages left free by deletions, until compacted
+	pub free_pages: u64,
+	// Number of entries
+	pub entries: u64,
+	// Number of entries by kind
+	pub entries_by_kind: BTreeMap<String, u64>,
+}
+
+impl StoreStats {
+	pub fn from_stats(stats: store::StoreStats) -> StoreStats {
+		StoreStats {
+			file_size: stats.file_size(),
+			map_size: stats.map_size,
+			page_size: stats.page_size,
+			used_pages: stats.used_pages,
+			free_pages: stats.free_pages,
+			entries: stats.entries,
+			entries_by_kind: stats.entries_by_prefix,
+		}
+	}
+}
+
+/// Outcome of the last check of the node version against the allowed one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VersionCheck {
+	// Where the allowed version comes from
+	pub source: String,
+	// One of "not_checked", "up_to_date", "outdated" or "unavailable"
+	pub status: String,
+	// The allowed version, as major.minor
+	pub allowed_version: Option<String>,
+	// Timestamp of the last check
+	pub last_checked: Option<i64>,
+	// Why the version source could not be used
+	pub error: Option<String>,
+}
+
+impl VersionCheck {
+	pub fn from_check(check: global::VersionCheck) -> VersionCheck {
+		let status = match check.status {
+			VersionCheckStatus::NotChecked => "not_checked",
+			VersionCheckStatus::UpToDate => "up_to_date",
+			VersionCheckStatus::Outdated => "outdated",
+			VersionCheckStatus::Unavailable => "unavailable",
+		};
+		VersionCheck {
+			source: check.source,
+			status: status.to_string(),
+			allowed_version: check
+				.allowed_version
+				.map(|v| format!("{}.{}", v.version_major, v.version_minor)),
+			last_checked: check.last_checked,
+			error: check.error,
+		}
+	}
+}
+
+/// Progress of a background validation of the full chain state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainValidation {
+	// One of "mmrs", "rangeproofs", "kernel_signatures" or "done"
+	pub stage: String,
+	// Overall progress, in percent
+	pub percent: f64,
+	// Estimated time left, in seconds, while running
+	pub eta_secs: Option<u64>,
+	// Timestamp of the start of the validation
+	pub started_at: i64,
+	// Time spent validating so far, in seconds
+	pub elapsed_secs: u64,
+	// Whether the validation was cancelled
+	pub cancelled: bool,
+	// What the validation found wrong, stopping it
+	pub error: Option<String>,
+}
+
+impl ChainValidation {
+	pub fn from_progress(progress: chain::ValidationProgress) -> ChainValidation {
+		let cp = progress.checkpoint;
+		let stage = match cp.stage {
+			chain::ValidationStage::Mmrs => "mmrs",
+			chain::ValidationStage::RangeProofs => "rangeproofs",
+			chain::ValidationStage::KernelSignatures => "kernel_signatures",
+			chain::ValidationStage::Done => "done",
+		};
+		ChainValidation {
+			stage: stage.to_string(),
+			percent: progress.percent,
+			eta_secs: progress.eta_secs,
+			started_at: cp.started_at,
+			elapsed_secs: cp.elapsed_ms / 1000,
+			cancelled: cp.cancelled,
+			error: cp.error,
 		}
 	}
 }
@@ -233,6 +440,61 @@ impl<'de> serde::de::Visitor<'de> for PrintableCommitmentVisitor {
 	}
 }
 
+/// When an output was created and spent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutputHistory {
+	/// The output commitment
+	pub commit: PrintableCommitment,
+	/// Height of the block the output was created in
+	pub created_height: u64,
+	/// Height of the block the output was spent in, if spent
+	pub spent_height: Option<u64>,
+	/// Hash of the block the output was spent in, if spent
+	pub spending_block: Option<String>,
+}
+
+impl OutputHistory {
+	pub fn from_history(
+		commit: &pedersen::Commitment,
+		history: chain::OutputHistory,
+	) -> OutputHistory {
+		OutputHistory {
+			commit: PrintableCommitment {
+				commit: commit.clone(),
+			},
+			created_height: history.created_height,
+			spent_height: history.spent_height,
+			spending_block: history.spending_block.map(|h| h.to_hex()),
+		}
+	}
+}
+
+/// A payment proof found valid, with the block its kernel is in
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaymentProofStatus {
+	/// Amount paid
+	pub amount: u64,
+	/// Excess of the payment kernel
+	pub excess: PrintableCommitment,
+	/// Height of the block the kernel is in
+	pub height: u64,
+	/// Hash of the block the kernel is in
+	pub block_hash: String,
+}
+
+impl PaymentProofStatus {
+	pub fn from_proof(proof: &PaymentProof, header: &core::BlockHeader) -> PaymentProofStatus {
+		PaymentProofStatus {
+			amount: proof.amount,
+			excess: PrintableCommitment {
+				commit: proof.excess,
+			},
+			height: header.height,
+			block_hash: header.hash().to_hex(),
+		}
+	}
+}
+
 // As above, except formatted a bit better for human viewing
 #[derive(Debug, Clone)]
 pub struct OutputPrintable {
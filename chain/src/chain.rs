@@ -15,13 +15,17 @@
 //! Facade and handler for the rest of the blockchain implementation
 //! and mostly the chain pipeline.
 
+use crate::core::consensus;
 use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
 use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::core::pmmr;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::{
-	Block, BlockHeader, BlockSums, Committed, Output, OutputIdentifier, Transaction, TxKernelEntry,
+	Block, BlockHeader, BlockSums, Committed, Output, OutputIdentifier, Transaction, TxKernel,
+	TxKernelEntry,
 };
 use crate::core::global;
+use crate::core::libtx::payment_proof::PaymentProof;
 use crate::core::pow;
 use crate::core::ser::{Readable, StreamingReader};
 use crate::error::{Error, ErrorKind};
@@ -30,11 +34,15 @@ use crate::store;
 use crate::txhashset;
 use crate::txhashset::TxHashSet;
 use crate::types::{
-	BlockStatus, ChainAdapter, NoStatus, Options, Tip, TxHashSetRoots, TxHashsetWriteStatus,
+	BlockStatus, ChainAdapter, NoStatus, Options, OutputHistory, SupplyAudit, Tip, TxHashSetRoots,
+	TxHashsetWriteStatus, ValidationCheckpoint, ValidationProgress, ValidationStage,
 };
 use crate::util::secp::pedersen::{Commitment, RangeProof};
-use crate::util::RwLock;
+use crate::util::{static_secp_instance, RwLock};
+use chrono::prelude::Utc;
 use epic_store::Error::NotFoundErr;
+use epic_store::StoreStats;
+use std::cmp;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::Read;
@@ -49,6 +57,19 @@ pub const MAX_ORPHAN_SIZE: usize = 200;
 /// When evicting, very old orphans are evicted first
 const MAX_ORPHAN_AGE_SECS: u64 = 300;
 
+/// MMR positions hashed per background validation chunk
+const VALIDATION_MMR_CHUNK: u64 = 50_000;
+
+/// Rangeproofs verified per background validation chunk
+const VALIDATION_RANGEPROOF_CHUNK: u64 = 500;
+
+/// Kernel signatures verified per background validation chunk
+const VALIDATION_KERNEL_CHUNK: u64 = 1_000;
+
+/// Database statistics walk the whole database, they're only refreshed
+/// after that long
+const DB_STATS_MAX_AGE_SECS: u64 = 600;
+
 #[derive(Debug, Clone)]
 struct Orphan {
 	block: Block,
@@ -152,6 +173,10 @@ pub struct Chain {
 	// POW verification function
 	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
 	archive_mode: bool,
+	// blocks below the head to keep when not in archive mode, at least the horizon
+	block_retention: u64,
+	// last database statistics and when we got them
+	db_stats: RwLock<Option<(Instant, StoreStats)>>,
 	genesis: BlockHeader,
 }
 
@@ -171,9 +196,21 @@ impl Chain {
 
 		// open the txhashset, creating a new one if necessary
 		let mut txhashset = txhashset::TxHashSet::open(db_root.clone(), store.clone(), None)?;
+		if archive_mode {
+			txhashset.enable_output_history();
+		}
 
 		setup_head(&genesis, &store, &mut txhashset)?;
 
+		// Nodes predating the kernel_pos index build it once.
+		if !store.kernel_pos_indexed()? {
+			let mut batch = store.batch()?;
+			txhashset::extending(&mut txhashset, &mut batch, |extension| {
+				extension.rebuild_kernel_index()
+			})?;
+			batch.commit()?;
+		}
+
 		let chain = Chain {
 			db_root,
 			store,
@@ -183,6 +220,8 @@ impl Chain {
 			pow_verifier,
 			verifier_cache,
 			archive_mode,
+			block_retention: 0,
+			db_stats: RwLock::new(None),
 			genesis: genesis.header.clone(),
 		};
 
@@ -201,6 +240,18 @@ impl Chain {
 		self.store.clone()
 	}
 
+	/// Keep full blocks for the provided number of blocks below the head when
+	/// not in archive mode. Older blocks get removed on compaction.
+	pub fn set_block_retention(&mut self, blocks: u64) {
+		self.block_retention = blocks;
+	}
+
+	/// Number of blocks below the head we keep full blocks for, never less
+	/// than the cut-through horizon.
+	pub fn block_retention(&self) -> u64 {
+		cmp::max(self.block_retention, global::cut_through_horizon() as u64)
+	}
+
 	fn log_heads(&self) -> Result<(), Error> {
 		let head = self.store.head()?;
 		debug!(
@@ -464,13 +515,21 @@ impl Chain {
 		}
 	}
 
-	/// Validate the tx against the current UTXO set.
+	/// Validate the tx against the current UTXO set, and its NoRecentDuplicate
+	/// kernels against the kernels of the recent blocks.
 	pub fn validate_tx(&self, tx: &Transaction) -> Result<(), Error> {
+		let height = self.next_block_height()?;
+		if tx.kernels().iter().any(|k| k.is_no_recent_duplicate())
+			&& !consensus::nrd_enabled(height)
+		{
+			return Err(ErrorKind::NRDKernelNotEnabled.into());
+		}
 		let txhashset = self.txhashset.read();
 		txhashset::utxo_view(&txhashset, |utxo| {
 			utxo.validate_tx(tx)?;
 			Ok(())
-		})
+		})?;
+		txhashset.verify_nrd_kernels(tx.kernels(), height)
 	}
 
 	fn next_block_height(&self) -> Result<u64, Error> {
@@ -500,6 +559,19 @@ impl Chain {
 		}
 	}
 
+	/// Verify the relative height locked kernels of the tx reference kernels
+	/// at least their relative height below the next block.
+	pub fn verify_tx_relative_lock_height(&self, tx: &Transaction) -> Result<(), Error> {
+		let height = self.next_block_height()?;
+		if tx.kernels().iter().any(|k| k.is_relative_height_locked())
+			&& !consensus::relative_lock_enabled(height)
+		{
+			return Err(ErrorKind::RelativeLockNotEnabled.into());
+		}
+		let txhashset = self.txhashset.read();
+		txhashset.verify_relative_lock_heights(tx.kernels(), height)
+	}
+
 	/// Validate the current chain state.
 	pub fn validate(&self, fast_validation: bool) -> Result<(), Error> {
 		let header = self.store.head_header()?;
@@ -521,6 +593,247 @@ impl Chain {
 		})
 	}
 
+	/// Audit the coin supply at the provided height (the chain head if none).
+	/// Sums the emission schedule up to that block, checks it against the
+	/// kernel sums of the txhashset rewound there and reports the state of
+	/// the MMRs. Rewinding below the horizon of a pruned node will fail.
+	pub fn supply_audit(&self, height: Option<u64>) -> Result<SupplyAudit, Error> {
+		let head = self.head()?;
+		let height = height.unwrap_or(head.height);
+		if height > head.height {
+			return Err(ErrorKind::InvalidBlockHeight.into());
+		}
+		let header = self.get_header_by_height(height)?;
+		let genesis_had_reward = self.get_header_by_height(0)?.kernel_mmr_size > 0;
+
+		let mut mining_reward = if genesis_had_reward {
+			consensus::reward_at_height(0)
+		} else {
+			0
+		};
+		let mut foundation_reward = 0;
+		let mut foundation_pending = 0;
+		for h in 1..=height {
+			mining_reward += consensus::reward_at_height(h);
+			if consensus::is_foundation_height(h) {
+				foundation_reward += consensus::cumulative_reward_foundation(h);
+				foundation_pending = 0;
+			} else {
+				foundation_pending += consensus::reward_foundation_at_height(h);
+			}
+		}
+
+		let total_supply = mining_reward + foundation_reward;
+		let total_overage = consensus::total_overage_at_height(height, genesis_had_reward);
+		let supply_matches_overage = total_overage >= 0 && total_supply == total_overage as u64;
+		if !supply_matches_overage {
+			warn!(
+				"supply_audit: expected supply {} at {} doesn't match total overage {}",
+				total_supply, height, total_overage
+			);
+		}
+
+		let mut txhashset = self.txhashset.write();
+		txhashset::extending_readonly(&mut txhashset, |extension| {
+			extension.rewind(&header)?;
+
+			let kernel_sums = extension.validate_kernel_sums();
+			let (header_mmr_size, output_mmr_size, rproof_mmr_size, kernel_mmr_size) =
+				extension.sizes();
+			let utxo_count = extension.utxo_count();
+			let ((pruned_output_count, compacted_output_count), pruned_rproofs) =
+				extension.pruned_leaf_counts();
+
+			Ok(SupplyAudit {
+				height: header.height,
+				hash: header.hash(),
+				mining_reward,
+				foundation_reward,
+				foundation_pending,
+				total_supply,
+				total_overage,
+				supply_matches_overage,
+				kernel_sums_valid: kernel_sums.is_ok(),
+				kernel_sums_error: kernel_sums.err().map(|e| e.to_string()),
+				utxo_count,
+				pruned_output_count,
+				compacted_output_count,
+				pruned_rproof_count: pruned_rproofs.0,
+				compacted_rproof_count: pruned_rproofs.1,
+				kernel_count: pmmr::n_leaves(kernel_mmr_size),
+				header_mmr_size,
+				output_mmr_size,
+				rproof_mmr_size,
+				kernel_mmr_size,
+			})
+		})
+	}
+
+	/// Rebuild the index of output MMR positions from the current chain state,
+	/// dropping any stale entry. Returns the number of outputs indexed.
+	pub fn rebuild_output_index(&self) -> Result<u64, Error> {
+		let mut txhashset = self.txhashset.write();
+		let mut batch = self.store.batch()?;
+		let count = txhashset::extending(&mut txhashset, &mut batch, |extension| {
+			extension.rebuild_index()
+		})?;
+		batch.commit()?;
+		Ok(count)
+	}
+
+	/// Start a background validation of the chain state, unless one is
+	/// already running. The validation itself is carried out a chunk at a
+	/// time through validation_step. Returns whether a new one was started.
+	pub fn start_validation(&self) -> Result<bool, Error> {
+		if let Some(cp) = self.validation_checkpoint()? {
+			if cp.is_running() {
+				return Ok(false);
+			}
+		}
+		let batch = self.store.batch()?;
+		batch.save_validation_checkpoint(&ValidationCheckpoint::new(Utc::now().timestamp()))?;
+		batch.commit()?;
+		Ok(true)
+	}
+
+	/// Cancel the running background validation, if any. Returns whether one
+	/// was running.
+	pub fn cancel_validation(&self) -> Result<bool, Error> {
+		let batch = self.store.batch()?;
+		match batch.get_validation_checkpoint() {
+			Ok(mut cp) if cp.is_running() => {
+				cp.cancelled = true;
+				batch.save_validation_checkpoint(&cp)?;
+				batch.commit()?;
+				Ok(true)
+			}
+			Ok(_) | Err(NotFoundErr(_)) => Ok(false),
+			Err(e) => Err(ErrorKind::StoreErr(e, "chain cancel validation".to_owned()).into()),
+		}
+	}
+
+	/// Checkpoint of the last background validation, if one ever ran.
+	pub fn validation_checkpoint(&self) -> Result<Option<ValidationCheckpoint>, Error> {
+		match self.store.get_validation_checkpoint() {
+			Ok(cp) => Ok(Some(cp)),
+			Err(NotFoundErr(_)) => Ok(None),
+			Err(e) => Err(ErrorKind::StoreErr(e, "chain validation checkpoint".to_owned()).into()),
+		}
+	}
+
+	/// Run the next chunk of the running background validation, if any, and
+	/// save where we got to. Elapsed is the wall-clock time since the previous
+	/// chunk, for the ETA. Only holds the txhashset read lock for the chunk,
+	/// so block processing is never held up for long. Returns whether there's
+	/// work left.
+	pub fn validation_step(&self, elapsed: Duration) -> Result<bool, Error> {
+		let cp = match self.validation_checkpoint()? {
+			Some(cp) => cp,
+			None => return Ok(false),
+		};
+		if !cp.is_running() {
+			return Ok(false);
+		}
+
+		let mut next = cp.clone();
+		let res = {
+			let txhashset = self.txhashset.read();
+			let (header_size, output_size, _, kernel_size) = txhashset.sizes();
+			match cp.stage {
+				ValidationStage::Mmrs => {
+					let to = cp.pos + VALIDATION_MMR_CHUNK - 1;
+					txhashset.validate_mmrs_range(cp.pos, to).map(|_| {
+						if to >= header_size.max(output_size).max(kernel_size) {
+							next.stage = ValidationStage::RangeProofs;
+							next.pos = 1;
+						} else {
+							next.pos = to + 1;
+						}
+					})
+				}
+				ValidationStage::RangeProofs => txhashset
+					.verify_rangeproofs_from(cp.pos, VALIDATION_RANGEPROOF_CHUNK)
+					.map(|(pos, count)| {
+						if count == 0 {
+							next.stage = ValidationStage::KernelSignatures;
+							next.pos = 1;
+						} else {
+							next.pos = pos;
+						}
+					}),
+				ValidationStage::KernelSignatures => txhashset
+					.verify_kernel_signatures_from(cp.pos, VALIDATION_KERNEL_CHUNK)
+					.map(|(pos, count)| {
+						if count == 0 {
+							next.stage = ValidationStage::Done;
+						} else {
+							next.pos = pos;
+						}
+					}),
+				ValidationStage::Done => Ok(()),
+			}
+		};
+		match res {
+			Ok(_) => {}
+			Err(ref e) if e.is_bad_data() => {
+				error!("background validation failed: {}", e);
+				next.error = Some(e.to_string());
+			}
+			Err(e) => return Err(e),
+		}
+		next.elapsed_ms += elapsed.as_millis() as u64;
+
+		// Don't overwrite a cancellation (or a restart) that came in while
+		// we were busy with the chunk.
+		let batch = self.store.batch()?;
+		if batch.get_validation_checkpoint()? != cp {
+			return Ok(false);
+		}
+		batch.save_validation_checkpoint(&next)?;
+		batch.commit()?;
+
+		if next.stage == ValidationStage::Done {
+			info!(
+				"background validation: chain state valid, took {}s",
+				next.elapsed_ms / 1000
+			);
+		}
+		Ok(next.is_running())
+	}
+
+	/// Progress of the last background validation, if one ever ran. The
+	/// percentage is weighted by the rough share of the work each stage
+	/// takes, rangeproofs being by far the most expensive.
+	pub fn validation_progress(&self) -> Result<Option<ValidationProgress>, Error> {
+		let cp = match self.validation_checkpoint()? {
+			Some(cp) => cp,
+			None => return Ok(None),
+		};
+		let (header_size, output_size, _, kernel_size) = self.txhashset.read().sizes();
+		let (done, share, size) = match cp.stage {
+			ValidationStage::Mmrs => (0.0, 5.0, header_size.max(output_size).max(kernel_size)),
+			ValidationStage::RangeProofs => (5.0, 75.0, output_size),
+			ValidationStage::KernelSignatures => (80.0, 20.0, kernel_size),
+			ValidationStage::Done => (100.0, 0.0, 0),
+		};
+		let fraction = if size > 0 {
+			(cp.pos.saturating_sub(1) as f64 / size as f64).min(1.0)
+		} else {
+			0.0
+		};
+		let percent = done + share * fraction;
+		let eta_secs = if cp.is_running() && percent > 0.0 {
+			Some((cp.elapsed_ms as f64 / 1000.0 * (100.0 - percent) / percent) as u64)
+		} else {
+			None
+		};
+		Ok(Some(ValidationProgress {
+			checkpoint: cp,
+			percent,
+			eta_secs,
+		}))
+	}
+
 	/// *** Only used in tests. ***
 	/// Convenience for setting roots on a block header when
 	/// creating a chain fork during tests.
@@ -886,7 +1199,39 @@ impl Chain {
 		}
 
 		let header = self.get_block_header(&h)?;
+		self.write_txhashset(header, txhashset_data, status)
+	}
+
+	/// Restores a txhashset from a local snapshot, for a header already in
+	/// our header chain. Goes through the same validation as a txhashset
+	/// received from a peer, only skipping the check that state sync is needed,
+	/// so snapshots within the horizon can be restored too.
+	pub fn txhashset_restore(
+		&self,
+		h: Hash,
+		txhashset_data: File,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<(), Error> {
+		status.on_setup();
+
+		let header = self.get_block_header(&h)?;
+		let body_head = self.head()?;
+		if body_head.height >= header.height {
+			return Err(ErrorKind::InvalidTxHashSet(format!(
+				"snapshot at {} not ahead of our head at {}",
+				header.height, body_head.height
+			))
+			.into());
+		}
+		self.write_txhashset(header, txhashset_data, status)
+	}
 
+	fn write_txhashset(
+		&self,
+		header: BlockHeader,
+		txhashset_data: File,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<(), Error> {
 		// Write txhashset to sandbox (in the Epic specific tmp dir)
 		let sandbox_dir = self.get_tmp_dir();
 		txhashset::clean_txhashset_folder(&sandbox_dir);
@@ -931,6 +1276,7 @@ impl Chain {
 			)?;
 
 			extension.rebuild_index()?;
+			extension.rebuild_kernel_index()?;
 			Ok(())
 		})?;
 
@@ -969,6 +1315,9 @@ impl Chain {
 				self.store.clone(),
 				Some(&header),
 			)?;
+			if self.archive_mode {
+				txhashset.enable_output_history();
+			}
 
 			self.rebuild_header_mmr(&Tip::from_header(&header), &mut txhashset)?;
 			txhashset::clean_header_folder(&sandbox_dir);
@@ -987,7 +1336,7 @@ impl Chain {
 	}
 
 	/// Cleanup old blocks from the db.
-	/// Determine the cutoff height from the horizon and the current block height.
+	/// Determine the cutoff height from the block retention and the current block height.
 	/// *Only* runs if we are not in archive mode.
 	fn remove_historical_blocks(
 		&self,
@@ -998,7 +1347,7 @@ impl Chain {
 			return Ok(());
 		}
 
-		let horizon = global::cut_through_horizon() as u64;
+		let retention = self.block_retention();
 		let head = batch.head()?;
 
 		let tail = match batch.tail() {
@@ -1006,19 +1355,19 @@ impl Chain {
 			Err(_) => Tip::from_header(&self.genesis),
 		};
 
-		let cutoff = head.height.saturating_sub(horizon);
+		let cutoff = head.height.saturating_sub(retention);
 
 		debug!(
-			"remove_historical_blocks: head height: {}, tail height: {}, horizon: {}, cutoff: {}",
-			head.height, tail.height, horizon, cutoff,
+			"remove_historical_blocks: head height: {}, tail height: {}, retention: {}, cutoff: {}",
+			head.height, tail.height, retention, cutoff,
 		);
 
-		if cutoff == 0 {
+		if cutoff <= tail.height {
 			return Ok(());
 		}
 
 		let mut count = 0;
-		let tail_hash = txhashset.get_header_hash_by_height(head.height - horizon)?;
+		let tail_hash = txhashset.get_header_hash_by_height(cutoff)?;
 		let tail = batch.get_block_header(&tail_hash)?;
 
 		// Remove old blocks (including short lived fork blocks) which height < tail.height
@@ -1250,6 +1599,70 @@ impl Chain {
 		}
 	}
 
+	/// Gets the header of the block a kernel with the provided excess is in,
+	/// along with the kernel.
+	pub fn get_header_for_kernel(
+		&self,
+		excess: &Commitment,
+	) -> Result<(TxKernel, BlockHeader), Error> {
+		let txhashset = self.txhashset.read();
+		let (kernel, pos) = txhashset
+			.find_kernel(excess)?
+			.ok_or_else(|| Error::from(ErrorKind::TxKernelNotFound))?;
+
+		// first block with the kernel in its kernel MMR
+		let mut min = 0;
+		let mut max = self.head()?.height;
+		while min < max {
+			let height = min + (max - min) / 2;
+			if txhashset.get_header_by_height(height)?.kernel_mmr_size < pos {
+				min = height + 1;
+			} else {
+				max = height;
+			}
+		}
+		Ok((kernel, txhashset.get_header_by_height(min)?))
+	}
+
+	/// Verifies a payment proof is signed by its recipient and its kernel is
+	/// on chain. Returns the header of the block the kernel is in.
+	pub fn verify_payment_proof(&self, proof: &PaymentProof) -> Result<BlockHeader, Error> {
+		{
+			let secp = static_secp_instance();
+			let secp = secp.lock();
+			proof
+				.verify(&secp)
+				.map_err(|e| ErrorKind::InvalidPaymentProof(e.to_string()))?;
+		}
+		let (_, header) = self.get_header_for_kernel(&proof.excess)?;
+		Ok(header)
+	}
+
+	/// Usage statistics of the chain database. Getting them walks the whole
+	/// database so they are cached, for DB_STATS_MAX_AGE_SECS.
+	pub fn db_stats(&self) -> Result<StoreStats, Error> {
+		if let Some((at, ref stats)) = *self.db_stats.read() {
+			if at.elapsed() < Duration::from_secs(DB_STATS_MAX_AGE_SECS) {
+				return Ok(stats.clone());
+			}
+		}
+		let stats = self.store.stats()?;
+		*self.db_stats.write() = Some((Instant::now(), stats.clone()));
+		Ok(stats)
+	}
+
+	/// When the output with the provided commitment was created and spent.
+	/// Only tracked in archive mode, and only for outputs created since the
+	/// node started maintaining the index.
+	pub fn get_output_history(&self, commit: &Commitment) -> Result<OutputHistory, Error> {
+		if !self.archive_mode {
+			return Err(ErrorKind::Other("output history requires archive mode".to_owned()).into());
+		}
+		self.store
+			.get_output_history(commit)
+			.map_err(|e| ErrorKind::StoreErr(e, "chain get output history".to_owned()).into())
+	}
+
 	/// Verifies the given block header is actually on the current chain.
 	/// Checks the header_by_height index to verify the header is where we say
 	/// it is
@@ -87,6 +87,21 @@ pub enum ErrorKind {
 	/// An output with that commitment already exists (should be unique)
 	#[fail(display = "Duplicate Commitment: {:?}", _0)]
 	DuplicateCommitment(Commitment),
+	/// A NoRecentDuplicate kernel with that excess is within the relative
+	/// height of another one
+	#[fail(display = "Recent Duplicate Kernel: {:?}", _0)]
+	RecentDuplicateKernel(Commitment),
+	/// NoRecentDuplicate kernels are not valid yet at the next block height
+	#[fail(display = "NoRecentDuplicate kernels not enabled")]
+	NRDKernelNotEnabled,
+	/// The kernel referenced by a relative height locked kernel is not
+	/// confirmed, or not long enough
+	#[fail(display = "Relative Lock Height not reached for kernel: {:?}", _0)]
+	RelativeLockHeight(Commitment),
+	/// Relative height locked kernels are not valid yet at the next block
+	/// height
+	#[fail(display = "Relative height locked kernels not enabled")]
+	RelativeLockNotEnabled,
 	/// Attempt to spend a coinbase output before it sufficiently matures.
 	#[fail(display = "Attempt to spend immature coinbase")]
 	ImmatureCoinbase,
@@ -102,6 +117,9 @@ pub enum ErrorKind {
 	/// Tx kernel not found
 	#[fail(display = "Tx kernel not found")]
 	TxKernelNotFound,
+	/// Payment proof not signed by its recipient
+	#[fail(display = "Invalid Payment Proof: {}", _0)]
+	InvalidPaymentProof(String),
 	/// output spent
 	#[fail(display = "Output is spent")]
 	OutputSpent,
@@ -46,4 +46,7 @@ pub mod types;
 pub use crate::chain::{Chain, MAX_ORPHAN_SIZE};
 pub use crate::error::{Error, ErrorKind};
 pub use crate::store::ChainStore;
-pub use crate::types::{BlockStatus, ChainAdapter, Options, Tip, TxHashsetWriteStatus};
+pub use crate::types::{
+	BlockStatus, ChainAdapter, Options, OutputHistory, SupplyAudit, Tip, TxHashsetWriteStatus,
+	ValidationCheckpoint, ValidationProgress, ValidationStage,
+};
@@ -147,6 +147,14 @@ pub fn process_block(b: &Block, ctx: &mut BlockContext<'_>) -> Result<Option<Tip
 		// Validate the block against the UTXO set.
 		validate_utxo(b, &mut extension)?;
 
+		// Check the NoRecentDuplicate kernels of the block against the kernels
+		// of the previous blocks within their relative height.
+		verify_nrd_kernels(b, &mut extension)?;
+
+		// Check the relative height locked kernels of the block reference
+		// kernels old enough.
+		verify_relative_lock_heights(b, &mut extension)?;
+
 		// Using block_sums (utxo_sum, kernel_sum) for the previous block from the db
 		// we can verify_kernel_sums across the full UTXO sum and full kernel sum
 		// accounting for inputs/outputs/kernels in this new block.
@@ -784,6 +792,17 @@ fn validate_utxo(block: &Block, ext: &txhashset::Extension<'_>) -> Result<(), Er
 	ext.utxo_view().validate_block(block)
 }
 
+fn verify_nrd_kernels(block: &Block, ext: &txhashset::Extension<'_>) -> Result<(), Error> {
+	ext.verify_nrd_kernels(block)
+}
+
+fn verify_relative_lock_heights(
+	block: &Block,
+	ext: &txhashset::Extension<'_>,
+) -> Result<(), Error> {
+	ext.verify_relative_lock_heights(block)
+}
+
 fn check_bad_header(header: &BlockHeader) -> Result<(), Error> {
 	let bad_hashes =
 		[
@@ -19,11 +19,11 @@ use crate::core::core::feijoada::Policy;
 use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::{Block, BlockHeader, BlockSums};
 use crate::core::pow::{Difficulty, PoWType};
-use crate::types::Tip;
+use crate::types::{OutputHistory, Tip, ValidationCheckpoint};
 use crate::util::secp::pedersen::Commitment;
 use croaring::Bitmap;
 use epic_store as store;
-use epic_store::{option_to_not_found, to_key, Error, SerIterator};
+use epic_store::{option_to_not_found, to_key, Error, KVStore, SerIterator, StoreStats};
 use std::sync::Arc;
 
 const STORE_SUBPATH: &'static str = "chain";
@@ -37,10 +37,16 @@ const SYNC_HEAD_PREFIX: u8 = 's' as u8;
 const COMMIT_POS_PREFIX: u8 = 'c' as u8;
 const BLOCK_INPUT_BITMAP_PREFIX: u8 = 'B' as u8;
 const BLOCK_SUMS_PREFIX: u8 = 'M' as u8;
-
-/// All chain-related database operations
-pub struct ChainStore {
-	db: store::Store,
+const OUTPUT_HISTORY_PREFIX: u8 = 'O' as u8;
+const VALIDATION_CHECKPOINT_PREFIX: u8 = 'V' as u8;
+const PMMR_JOURNAL_PREFIX: u8 = 'J' as u8;
+const KERNEL_POS_PREFIX: u8 = 'k' as u8;
+const KERNEL_POS_INDEXED_PREFIX: u8 = 'K' as u8;
+
+/// All chain-related database operations, on top of a key-value store (LMDB
+/// by default).
+pub struct ChainStore<S = store::Store> {
+	db: S,
 }
 
 impl ChainStore {
@@ -49,9 +55,20 @@ impl ChainStore {
 		let db = store::Store::new(db_root, None, Some(STORE_SUBPATH.clone()), None)?;
 		Ok(ChainStore { db })
 	}
+
+	/// Compact the chain database under the provided root, which must not be
+	/// open meanwhile. Returns the size of the database file before and after.
+	pub fn compact(db_root: &str) -> Result<(u64, u64), Error> {
+		store::compact(db_root, None)
+	}
 }
 
-impl ChainStore {
+impl<S: KVStore> ChainStore<S> {
+	/// Create a chain store on top of the provided key-value store.
+	pub fn with_store(db: S) -> ChainStore<S> {
+		ChainStore { db }
+	}
+
 	/// The current chain head.
 	pub fn head(&self) -> Result<Tip, Error> {
 		option_to_not_found(self.db.get_ser(&vec![HEAD_PREFIX]), "HEAD")
@@ -121,6 +138,65 @@ impl ChainStore {
 		)
 	}
 
+	/// Get the kernel MMR positions of the kernels with the given excess, in
+	/// the order they were applied. Empty if there are none.
+	pub fn get_kernel_pos(&self, excess: &Commitment) -> Result<Vec<u64>, Error> {
+		let key = to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec());
+		Ok(self.db.get_ser(&key)?.unwrap_or_default())
+	}
+
+	/// Whether the kernel_pos index was built for the whole kernel MMR, it's
+	/// maintained as blocks get applied from there on.
+	pub fn kernel_pos_indexed(&self) -> Result<bool, Error> {
+		self.db.exists(&vec![KERNEL_POS_INDEXED_PREFIX])
+	}
+
+	/// Get the history of the given output commitment (archive mode only).
+	pub fn get_output_history(&self, commit: &Commitment) -> Result<OutputHistory, Error> {
+		option_to_not_found(
+			self.db.get_ser(&to_key(
+				OUTPUT_HISTORY_PREFIX,
+				&mut commit.as_ref().to_vec(),
+			)),
+			&format!("Output history for: {:?}", commit),
+		)
+	}
+
+	/// Checkpoint of the background chain validation, if one ever ran.
+	pub fn get_validation_checkpoint(&self) -> Result<ValidationCheckpoint, Error> {
+		option_to_not_found(
+			self.db.get_ser(&vec![VALIDATION_CHECKPOINT_PREFIX]),
+			"VALIDATION_CHECKPOINT",
+		)
+	}
+
+	/// Id of the last committed journaled sync of the named MMR backend.
+	pub fn get_pmmr_journal_id(&self, name: &str) -> Result<u64, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(PMMR_JOURNAL_PREFIX, &mut name.as_bytes().to_vec())),
+			&format!("PMMR journal id: {}", name),
+		)
+	}
+
+	/// Page usage of the chain database.
+	pub fn usage(&self) -> Result<StoreStats, Error> {
+		self.db.usage()
+	}
+
+	/// Usage statistics of the chain database, walking all of it.
+	pub fn stats(&self) -> Result<StoreStats, Error> {
+		self.db.stats(&[
+			(BLOCK_PREFIX, "blocks"),
+			(BLOCK_HEADER_PREFIX, "headers"),
+			(BLOCK_SUMS_PREFIX, "block_sums"),
+			(BLOCK_INPUT_BITMAP_PREFIX, "block_input_bitmaps"),
+			(COMMIT_POS_PREFIX, "output_pos"),
+			(KERNEL_POS_PREFIX, "kernel_pos"),
+			(OUTPUT_HISTORY_PREFIX, "output_history"),
+		])
+	}
+
 	/// Builds a new batch to be used with this store.
 	pub fn batch(&self) -> Result<Batch<'_>, Error> {
 		Ok(Batch {
@@ -278,6 +354,123 @@ impl<'a> Batch<'a> {
 		Ok(())
 	}
 
+	/// Add the kernel MMR position of a kernel with the given excess to the
+	/// kernel_pos index. Kernels may share their excess, so we keep all
+	/// their positions.
+	pub fn save_kernel_pos(&self, excess: &Commitment, pos: u64) -> Result<(), Error> {
+		let mut list = self.get_kernel_pos(excess)?;
+		list.push(pos);
+		self.db.put_ser(
+			&to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec())[..],
+			&list,
+		)
+	}
+
+	/// Get the kernel MMR positions of the kernels with the given excess.
+	pub fn get_kernel_pos(&self, excess: &Commitment) -> Result<Vec<u64>, Error> {
+		let key = to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec());
+		Ok(self.db.get_ser(&key)?.unwrap_or_default())
+	}
+
+	/// Drop the positions of the kernels with the given excess beyond the
+	/// provided kernel MMR size, as we rewind the kernel MMR.
+	pub fn rewind_kernel_pos(
+		&self,
+		excess: &Commitment,
+		kernel_mmr_size: u64,
+	) -> Result<(), Error> {
+		let key = to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec());
+		let mut list = self.get_kernel_pos(excess)?;
+		list.retain(|pos| *pos <= kernel_mmr_size);
+		if !list.is_empty() {
+			self.db.put_ser(&key, &list)
+		} else if self.db.exists(&key)? {
+			self.db.delete(&key)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Clear all entries from the kernel_pos index (must be rebuilt after).
+	pub fn clear_kernel_pos(&self) -> Result<(), Error> {
+		let key = to_key(KERNEL_POS_PREFIX, &mut "".to_string().into_bytes());
+		for (k, _) in self.db.iter::<Vec<u64>>(&key)? {
+			self.db.delete(&k)?;
+		}
+		if self.db.exists(&vec![KERNEL_POS_INDEXED_PREFIX])? {
+			self.db.delete(&vec![KERNEL_POS_INDEXED_PREFIX])?;
+		}
+		Ok(())
+	}
+
+	/// Mark the kernel_pos index as built for the whole kernel MMR.
+	pub fn save_kernel_pos_indexed(&self) -> Result<(), Error> {
+		self.db.put(&vec![KERNEL_POS_INDEXED_PREFIX], &[])
+	}
+
+	/// Save the history of an output commitment.
+	pub fn save_output_history(
+		&self,
+		commit: &Commitment,
+		history: &OutputHistory,
+	) -> Result<(), Error> {
+		self.db.put_ser(
+			&to_key(OUTPUT_HISTORY_PREFIX, &mut commit.as_ref().to_vec())[..],
+			history,
+		)
+	}
+
+	/// Get the history of an output commitment.
+	pub fn get_output_history(&self, commit: &Commitment) -> Result<OutputHistory, Error> {
+		option_to_not_found(
+			self.db.get_ser(&to_key(
+				OUTPUT_HISTORY_PREFIX,
+				&mut commit.as_ref().to_vec(),
+			)),
+			&format!("Output history for commit: {:?}", commit),
+		)
+	}
+
+	/// Delete the history of an output commitment, if we have any.
+	pub fn delete_output_history(&self, commit: &Commitment) -> Result<(), Error> {
+		let key = to_key(OUTPUT_HISTORY_PREFIX, &mut commit.as_ref().to_vec());
+		if self.db.exists(&key)? {
+			self.db.delete(&key)?;
+		}
+		Ok(())
+	}
+
+	/// Save the id of the journaled sync of the named MMR backend, committed
+	/// along with this batch.
+	pub fn save_pmmr_journal_id(&self, name: &str, id: u64) -> Result<(), Error> {
+		self.db.put_ser(
+			&to_key(PMMR_JOURNAL_PREFIX, &mut name.as_bytes().to_vec())[..],
+			&id,
+		)
+	}
+
+	/// Id of the last journaled sync of the named MMR backend.
+	pub fn get_pmmr_journal_id(&self, name: &str) -> Result<u64, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(PMMR_JOURNAL_PREFIX, &mut name.as_bytes().to_vec())),
+			&format!("PMMR journal id: {}", name),
+		)
+	}
+
+	/// Save the checkpoint of the background chain validation.
+	pub fn save_validation_checkpoint(&self, cp: &ValidationCheckpoint) -> Result<(), Error> {
+		self.db.put_ser(&vec![VALIDATION_CHECKPOINT_PREFIX], cp)
+	}
+
+	/// Get the checkpoint of the background chain validation.
+	pub fn get_validation_checkpoint(&self) -> Result<ValidationCheckpoint, Error> {
+		option_to_not_found(
+			self.db.get_ser(&vec![VALIDATION_CHECKPOINT_PREFIX]),
+			"VALIDATION_CHECKPOINT",
+		)
+	}
+
 	/// Get the previous header.
 	pub fn get_previous_header(&self, header: &BlockHeader) -> Result<BlockHeader, Error> {
 		self.get_block_header(&header.prev_hash)
@@ -27,12 +27,14 @@ use crate::core::ser::{PMMRIndexHashable, PMMRable};
 use crate::error::{Error, ErrorKind};
 use crate::store::{Batch, ChainStore};
 use crate::txhashset::{RewindableKernelView, UTXOView};
-use crate::types::{Tip, TxHashSetRoots, TxHashsetWriteStatus};
+use crate::types::{OutputHistory, Tip, TxHashSetRoots, TxHashsetWriteStatus};
 use crate::util::secp::pedersen::{Commitment, RangeProof};
 use crate::util::{file, secp_static, zip};
 use croaring::Bitmap;
 use epic_store;
-use epic_store::pmmr::{clean_files_by_prefix, PMMRBackend, PMMR_FILES};
+use epic_store::pmmr::{
+	clean_files_by_prefix, recover_journal, PMMRBackend, PMMRCheck, PMMR_FILES,
+};
 use std::collections::HashSet;
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
@@ -52,6 +54,7 @@ const KERNEL_SUBDIR: &'static str = "kernel";
 const TXHASHSET_ZIP: &'static str = "txhashset_snapshot";
 
 struct PMMRHandle<T: PMMRable> {
+	name: String,
 	backend: PMMRBackend<T>,
 	last_pos: u64,
 }
@@ -72,7 +75,23 @@ impl<T: PMMRable> PMMRHandle<T> {
 		)))?;
 		let backend = PMMRBackend::new(path_str.to_string(), prunable, fixed_size, header)?;
 		let last_pos = backend.unpruned_size();
-		Ok(PMMRHandle { backend, last_pos })
+		Ok(PMMRHandle {
+			name: file_name.to_owned(),
+			backend,
+			last_pos,
+		})
+	}
+
+	// Journal the pending changes of the backend ahead of a sync, saving the
+	// id of the journal in the batch so it gets committed along with them.
+	// Journals of earlier syncs the store hasn't committed yet are kept, the
+	// batch may still be a child of one that ends up never committed.
+	fn write_journal(&self, batch: &Batch<'_>, store: &ChainStore) -> Result<(), Error> {
+		let id = batch.get_pmmr_journal_id(&self.name).unwrap_or(0) + 1;
+		let committed_id = store.get_pmmr_journal_id(&self.name).ok();
+		self.backend.write_journal(id, committed_id)?;
+		batch.save_pmmr_journal_id(&self.name, id)?;
+		Ok(())
 	}
 }
 
@@ -107,6 +126,9 @@ pub struct TxHashSet {
 
 	// chain store used as index of commitments to MMR positions
 	commit_index: Arc<ChainStore>,
+
+	// whether we maintain the output history index (archive mode only)
+	output_history: bool,
 }
 
 impl TxHashSet {
@@ -116,6 +138,8 @@ impl TxHashSet {
 		commit_index: Arc<ChainStore>,
 		header: Option<&BlockHeader>,
 	) -> Result<TxHashSet, Error> {
+		recover_journals(&root_dir, &commit_index)?;
+
 		Ok(TxHashSet {
 			header_pmmr_h: PMMRHandle::new(
 				&root_dir,
@@ -158,9 +182,16 @@ impl TxHashSet {
 				None,
 			)?,
 			commit_index,
+			output_history: false,
 		})
 	}
 
+	/// Maintain the index of when each output was created and spent as blocks
+	/// are applied and rewound. Requires all blocks to be kept (archive mode).
+	pub fn enable_output_history(&mut self) {
+		self.output_history = true;
+	}
+
 	/// Close all backend file handles
 	pub fn release_backend_files(&mut self) {
 		self.header_pmmr_h.backend.release_files();
@@ -235,6 +266,54 @@ impl TxHashSet {
 		Ok(header)
 	}
 
+	/// Checks the NoRecentDuplicate kernels provided, for a block at the given
+	/// height on top of the current state, are no recent duplicates.
+	pub fn verify_nrd_kernels(&self, kernels: &[TxKernel], height: u64) -> Result<(), Error> {
+		let kernel_pmmr =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		verify_nrd_kernels(
+			kernels,
+			height,
+			|h| self.get_header_by_height(h),
+			|excess| Ok(self.commit_index.get_kernel_pos(excess)?),
+			|pos| kernel_pmmr.get_data(pos).map(|x| x.kernel),
+		)
+	}
+
+	/// Checks the relative height locked kernels provided, for a block at the
+	/// given height on top of the current state, reference kernels from
+	/// blocks at least their relative height earlier.
+	pub fn verify_relative_lock_heights(
+		&self,
+		kernels: &[TxKernel],
+		height: u64,
+	) -> Result<(), Error> {
+		let kernel_pmmr =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		verify_relative_lock_heights(
+			kernels,
+			height,
+			|h| self.get_header_by_height(h),
+			|excess| Ok(self.commit_index.get_kernel_pos(excess)?),
+			|pos| kernel_pmmr.get_data(pos).map(|x| x.kernel),
+		)
+	}
+
+	/// Finds a kernel with the provided excess in the kernel MMR, through the
+	/// kernel_pos index, along with its position.
+	pub fn find_kernel(&self, excess: &Commitment) -> Result<Option<(TxKernel, u64)>, Error> {
+		let kernel_pmmr =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		for pos in self.commit_index.get_kernel_pos(excess)? {
+			if let Some(entry) = kernel_pmmr.get_data(pos) {
+				if entry.kernel.excess == *excess {
+					return Ok(Some((entry.kernel, pos)));
+				}
+			}
+		}
+		Ok(None)
+	}
+
 	/// returns outputs from the given insertion (leaf) index up to the
 	/// specified limit. Also returns the last index actually populated
 	pub fn outputs_by_insertion_index(
@@ -280,6 +359,88 @@ impl TxHashSet {
 		}
 	}
 
+	/// Sizes of the header, output, rangeproof and kernel MMRs.
+	pub fn sizes(&self) -> (u64, u64, u64, u64) {
+		(
+			self.header_pmmr_h.last_pos,
+			self.output_pmmr_h.last_pos,
+			self.rproof_pmmr_h.last_pos,
+			self.kernel_pmmr_h.last_pos,
+		)
+	}
+
+	/// Validate the parent hashes of all our MMRs between positions from and
+	/// to (inclusive). Positions beyond the size of an MMR are skipped.
+	pub fn validate_mmrs_range(&self, from: u64, to: u64) -> Result<(), Error> {
+		let header_pmmr: ReadonlyPMMR<'_, BlockHeader, _> =
+			ReadonlyPMMR::at(&self.header_pmmr_h.backend, self.header_pmmr_h.last_pos);
+		let output_pmmr: ReadonlyPMMR<'_, Output, _> =
+			ReadonlyPMMR::at(&self.output_pmmr_h.backend, self.output_pmmr_h.last_pos);
+		let rproof_pmmr: ReadonlyPMMR<'_, RangeProof, _> =
+			ReadonlyPMMR::at(&self.rproof_pmmr_h.backend, self.rproof_pmmr_h.last_pos);
+		let kernel_pmmr: ReadonlyPMMR<'_, TxKernel, _> =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+
+		header_pmmr
+			.validate_range(from, to)
+			.and_then(|_| output_pmmr.validate_range(from, to))
+			.and_then(|_| rproof_pmmr.validate_range(from, to))
+			.and_then(|_| kernel_pmmr.validate_range(from, to))
+			.map_err(|e| ErrorKind::InvalidTxHashSet(e).into())
+	}
+
+	/// Verify the rangeproofs of up to max unspent outputs, starting at the
+	/// provided output MMR position. Returns the position to carry on from
+	/// and the number of rangeproofs verified, 0 once we're past the last one.
+	pub fn verify_rangeproofs_from(&self, from: u64, max: u64) -> Result<(u64, u64), Error> {
+		let output_pmmr: ReadonlyPMMR<'_, Output, _> =
+			ReadonlyPMMR::at(&self.output_pmmr_h.backend, self.output_pmmr_h.last_pos);
+		let rproof_pmmr: ReadonlyPMMR<'_, RangeProof, _> =
+			ReadonlyPMMR::at(&self.rproof_pmmr_h.backend, self.rproof_pmmr_h.last_pos);
+
+		let mut commits: Vec<Commitment> = vec![];
+		let mut proofs: Vec<RangeProof> = vec![];
+		let mut next = from;
+		for pos in output_pmmr.leaf_pos_iter_from(from).take(max as usize) {
+			// Output and corresponding rangeproof *must* exist.
+			match (output_pmmr.get_data(pos), rproof_pmmr.get_data(pos)) {
+				(None, _) => return Err(ErrorKind::OutputNotFound.into()),
+				(_, None) => return Err(ErrorKind::RangeproofNotFound.into()),
+				(Some(output), Some(proof)) => {
+					commits.push(output.commit);
+					proofs.push(proof);
+				}
+			}
+			next = pos + 1;
+		}
+		if proofs.len() > 0 {
+			Output::batch_verify_proofs(&commits, &proofs)?;
+		}
+		Ok((next, proofs.len() as u64))
+	}
+
+	/// Verify the signatures of up to max kernels, starting at the provided
+	/// kernel MMR position. Returns the position to carry on from and the
+	/// number of signatures verified, 0 once we're past the last one.
+	pub fn verify_kernel_signatures_from(&self, from: u64, max: u64) -> Result<(u64, u64), Error> {
+		let kernel_pmmr: ReadonlyPMMR<'_, TxKernel, _> =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+
+		let mut count = 0;
+		let mut pos = from.max(1);
+		while count < max && pos <= kernel_pmmr.unpruned_size() {
+			if pmmr::is_leaf(pos) {
+				let kernel = kernel_pmmr
+					.get_data(pos)
+					.ok_or::<Error>(ErrorKind::TxKernelNotFound.into())?;
+				kernel.verify()?;
+				count += 1;
+			}
+			pos += 1;
+		}
+		Ok((pos, count))
+	}
+
 	/// Return Commit's MMR position
 	pub fn get_output_pos(&self, commit: &Commitment) -> Result<u64, Error> {
 		Ok(self.commit_index.get_output_pos(&commit)?)
@@ -466,6 +627,11 @@ where
 				trees.kernel_pmmr_h.backend.discard();
 			} else {
 				trace!("Committing txhashset extension. sizes {:?}", sizes);
+				let store = trees.commit_index.clone();
+				trees.header_pmmr_h.write_journal(&child_batch, &store)?;
+				trees.output_pmmr_h.write_journal(&child_batch, &store)?;
+				trees.rproof_pmmr_h.write_journal(&child_batch, &store)?;
+				trees.kernel_pmmr_h.write_journal(&child_batch, &store)?;
 				child_batch.commit()?;
 				trees.header_pmmr_h.backend.sync()?;
 				trees.output_pmmr_h.backend.sync()?;
@@ -533,6 +699,8 @@ where
 				trees.sync_pmmr_h.backend.discard();
 			} else {
 				trace!("Committing txhashset sync_head extension. size {:?}", size);
+				let store = trees.commit_index.clone();
+				trees.sync_pmmr_h.write_journal(&child_batch, &store)?;
 				child_batch.commit()?;
 				trees.sync_pmmr_h.backend.sync()?;
 				trees.sync_pmmr_h.last_pos = size;
@@ -594,6 +762,8 @@ where
 				trees.header_pmmr_h.backend.discard();
 			} else {
 				trace!("Committing txhashset header extension. size {:?}", size);
+				let store = trees.commit_index.clone();
+				trees.header_pmmr_h.write_journal(&child_batch, &store)?;
 				child_batch.commit()?;
 				trees.header_pmmr_h.backend.sync()?;
 				trees.header_pmmr_h.last_pos = size;
@@ -787,6 +957,9 @@ pub struct Extension<'a> {
 	/// Rollback flag.
 	rollback: bool,
 
+	/// Whether to maintain the output history index.
+	output_history: bool,
+
 	/// Batch in which the extension occurs, public so it can be used within
 	/// an `extending` closure. Just be careful using it that way as it will
 	/// get rolled back with the extension (i.e on a losing fork).
@@ -823,6 +996,7 @@ impl<'a> Committed for Extension<'a> {
 
 impl<'a> Extension<'a> {
 	fn new(trees: &'a mut TxHashSet, batch: &'a Batch<'_>, header: BlockHeader) -> Extension<'a> {
+		let output_history = trees.output_history;
 		Extension {
 			header,
 			header_pmmr: PMMR::at(
@@ -842,6 +1016,7 @@ impl<'a> Extension<'a> {
 				trees.kernel_pmmr_h.last_pos,
 			),
 			rollback: false,
+			output_history,
 			batch,
 		}
 	}
@@ -867,17 +1042,19 @@ impl<'a> Extension<'a> {
 		self.apply_header(&b.header)?;
 
 		for out in b.outputs() {
-			let pos = self.apply_output(out)?;
+			let pos = self.apply_output(out, &b.header)?;
 			// Update the output_pos index for the new output.
 			self.batch.save_output_pos(&out.commitment(), pos)?;
 		}
 
 		for input in b.inputs() {
-			self.apply_input(input)?;
+			self.apply_input(input, &b.header)?;
 		}
 
 		for kernel in b.kernels() {
-			self.apply_kernel(kernel)?;
+			let pos = self.apply_kernel(kernel)?;
+			// Update the kernel_pos index for the new kernel.
+			self.batch.save_kernel_pos(&kernel.excess, pos)?;
 		}
 
 		// Update the header on the extension to reflect the block we just applied.
@@ -886,7 +1063,7 @@ impl<'a> Extension<'a> {
 		Ok(())
 	}
 
-	fn apply_input(&mut self, input: &Input) -> Result<(), Error> {
+	fn apply_input(&mut self, input: &Input, header: &BlockHeader) -> Result<(), Error> {
 		let commit = input.commitment();
 		let pos_res = self.batch.get_output_pos(&commit);
 		if let Ok(pos) = pos_res {
@@ -914,10 +1091,19 @@ impl<'a> Extension<'a> {
 		} else {
 			return Err(ErrorKind::AlreadySpent(commit).into());
 		}
+
+		if self.output_history {
+			// The output may predate the index (fast sync), nothing to record then.
+			if let Ok(mut history) = self.batch.get_output_history(&commit) {
+				history.spent_height = Some(header.height);
+				history.spending_block = Some(header.hash());
+				self.batch.save_output_history(&commit, &history)?;
+			}
+		}
 		Ok(())
 	}
 
-	fn apply_output(&mut self, out: &Output) -> Result<(u64), Error> {
+	fn apply_output(&mut self, out: &Output, header: &BlockHeader) -> Result<(u64), Error> {
 		let commit = out.commitment();
 
 		if let Ok(pos) = self.batch.get_output_pos(&commit) {
@@ -955,15 +1141,21 @@ impl<'a> Extension<'a> {
 			}
 		}
 
+		if self.output_history {
+			self.batch
+				.save_output_history(&commit, &OutputHistory::created(header.height))?;
+		}
+
 		Ok(output_pos)
 	}
 
-	/// Push kernel onto MMR (hash and data files).
-	fn apply_kernel(&mut self, kernel: &TxKernel) -> Result<(), Error> {
-		self.kernel_pmmr
+	/// Push kernel onto MMR (hash and data files), returning its position.
+	fn apply_kernel(&mut self, kernel: &TxKernel) -> Result<u64, Error> {
+		let pos = self
+			.kernel_pmmr
 			.push(kernel)
 			.map_err(&ErrorKind::TxHashSetErr)?;
-		Ok(())
+		Ok(pos)
 	}
 
 	fn apply_header(&mut self, header: &BlockHeader) -> Result<(), Error> {
@@ -991,6 +1183,31 @@ impl<'a> Extension<'a> {
 		}
 	}
 
+	/// Checks the NoRecentDuplicate kernels of the block, about to be applied,
+	/// are no recent duplicates of the kernels of the blocks before it.
+	pub fn verify_nrd_kernels(&self, b: &Block) -> Result<(), Error> {
+		verify_nrd_kernels(
+			b.kernels(),
+			b.header.height,
+			|h| self.get_header_by_height(h),
+			|excess| Ok(self.batch.get_kernel_pos(excess)?),
+			|pos| self.kernel_pmmr.get_data(pos).map(|x| x.kernel),
+		)
+	}
+
+	/// Checks the relative height locked kernels of the block, about to be
+	/// applied, reference kernels from blocks at least their relative height
+	/// before it.
+	pub fn verify_relative_lock_heights(&self, b: &Block) -> Result<(), Error> {
+		verify_relative_lock_heights(
+			b.kernels(),
+			b.header.height,
+			|h| self.get_header_by_height(h),
+			|excess| Ok(self.batch.get_kernel_pos(excess)?),
+			|pos| self.kernel_pmmr.get_data(pos).map(|x| x.kernel),
+		)
+	}
+
 	/// Compares the provided header to the header in the header MMR at that height.
 	/// If these match we know the header is on the current chain.
 	pub fn is_on_current_chain(&mut self, header: &BlockHeader) -> Result<(), Error> {
@@ -1047,6 +1264,12 @@ impl<'a> Extension<'a> {
 		// Rewound input (spent) pos will be added back to the MMR.
 		let rewind_rm_pos = input_pos_to_rewind(header, &self.header, &self.batch)?;
 
+		if self.output_history {
+			self.rewind_output_history(header)?;
+		}
+
+		self.rewind_kernel_pos(header.kernel_mmr_size)?;
+
 		let header_pos = pmmr::insertion_to_pmmr_index(header.height + 1);
 
 		self.rewind_to_pos(
@@ -1062,6 +1285,41 @@ impl<'a> Extension<'a> {
 		Ok(())
 	}
 
+	/// Undo the output history of every block above the provided one, going
+	/// back from our current header. Spends are undone before the outputs
+	/// they spent, as we walk the blocks backwards.
+	fn rewind_output_history(&self, header: &BlockHeader) -> Result<(), Error> {
+		let mut current = self.header.clone();
+		while current.height > header.height {
+			let block = self.batch.get_block(&current.hash())?;
+			for input in block.inputs() {
+				let commit = input.commitment();
+				if let Ok(mut history) = self.batch.get_output_history(&commit) {
+					history.spent_height = None;
+					history.spending_block = None;
+					self.batch.save_output_history(&commit, &history)?;
+				}
+			}
+			for out in block.outputs() {
+				self.batch.delete_output_history(&out.commitment())?;
+			}
+			current = self.batch.get_previous_header(&current)?;
+		}
+		Ok(())
+	}
+
+	/// Drop the kernel_pos index entries of the kernels beyond the provided
+	/// kernel MMR size, before these kernels get rewound.
+	fn rewind_kernel_pos(&self, kernel_mmr_size: u64) -> Result<(), Error> {
+		for pos in (kernel_mmr_size + 1)..=self.kernel_pmmr.last_pos {
+			if let Some(kernel) = self.kernel_pmmr.get_data(pos) {
+				self.batch
+					.rewind_kernel_pos(&kernel.excess(), kernel_mmr_size)?;
+			}
+		}
+		Ok(())
+	}
+
 	/// Rewinds the MMRs to the provided positions, given the output and
 	/// kernel we want to rewind to.
 	fn rewind_to_pos(
@@ -1257,9 +1515,10 @@ impl<'a> Extension<'a> {
 		Ok((output_sum, kernel_sum))
 	}
 
-	/// Rebuild the index of MMR positions to the corresponding UTXOs.
+	/// Rebuild the index of MMR positions to the corresponding UTXOs, returning
+	/// the number of UTXOs indexed.
 	/// This is a costly operation performed only when we receive a full new chain state.
-	pub fn rebuild_index(&self) -> Result<(), Error> {
+	pub fn rebuild_index(&self) -> Result<u64, Error> {
 		let now = Instant::now();
 
 		self.batch.clear_output_pos()?;
@@ -1278,7 +1537,34 @@ impl<'a> Extension<'a> {
 			now.elapsed().as_secs(),
 		);
 
-		Ok(())
+		Ok(count)
+	}
+
+	/// Rebuild the index of kernel MMR positions by kernel excess, returning
+	/// the number of kernels indexed. Goes through the whole kernel MMR, only
+	/// needed when we receive a full new chain state or upgrade a node that
+	/// didn't maintain it.
+	pub fn rebuild_kernel_index(&self) -> Result<u64, Error> {
+		let now = Instant::now();
+
+		self.batch.clear_kernel_pos()?;
+
+		let mut count = 0;
+		for pos in 1..=self.kernel_pmmr.last_pos {
+			if let Some(kernel) = self.kernel_pmmr.get_data(pos) {
+				self.batch.save_kernel_pos(&kernel.excess(), pos)?;
+				count += 1;
+			}
+		}
+		self.batch.save_kernel_pos_indexed()?;
+
+		debug!(
+			"txhashset: rebuild_kernel_index: {} kernels, took {}s",
+			count,
+			now.elapsed().as_secs(),
+		);
+
+		Ok(count)
 	}
 
 	/// Force the rollback of this extension, no matter the result
@@ -1309,6 +1595,22 @@ impl<'a> Extension<'a> {
 		}
 	}
 
+	/// Number of unspent outputs, the leaves left in the output MMR
+	pub fn utxo_count(&self) -> u64 {
+		self.output_pmmr.leaf_pos_iter().count() as u64
+	}
+
+	/// Leaves pruned from the output and rangeproof MMRs, as per their leaf
+	/// sets, along with those of them compacted away, as per their prune
+	/// lists: ((output pruned, output compacted), (rproof pruned, rproof
+	/// compacted)).
+	pub fn pruned_leaf_counts(&self) -> ((u64, u64), (u64, u64)) {
+		(
+			pruned_leaf_count(&self.output_pmmr),
+			pruned_leaf_count(&self.rproof_pmmr),
+		)
+	}
+
 	/// Sizes of each of the sum trees
 	pub fn sizes(&self) -> (u64, u64, u64, u64) {
 		(
@@ -1527,6 +1829,18 @@ pub fn clean_txhashset_folder(root_dir: &PathBuf) {
 	}
 }
 
+// Leaves of the MMR no longer in its leaf set, and how many of them were
+// compacted away.
+fn pruned_leaf_count<T, B>(pmmr: &PMMR<'_, T, B>) -> (u64, u64)
+where
+	T: PMMRable,
+	B: Backend<T>,
+{
+	let leaves = pmmr::n_leaves(pmmr.unpruned_size());
+	let unpruned = pmmr.leaf_pos_iter().count() as u64;
+	(leaves - unpruned, pmmr.n_compacted_leaves())
+}
+
 /// Clean the header folder
 pub fn clean_header_folder(root_dir: &PathBuf) {
 	let header_path = root_dir.clone().join(HEADERHASHSET_SUBDIR);
@@ -1537,6 +1851,155 @@ pub fn clean_header_folder(root_dir: &PathBuf) {
 	}
 }
 
+// Checks no NoRecentDuplicate kernel, for a block at the given height, shares
+// its excess with another one from a block less than its relative height
+// earlier. Kernels with that excess are found through the kernel_pos index,
+// those past the kernel MMR as of the block its relative height earlier are
+// recent.
+fn verify_nrd_kernels<H, P, K>(
+	kernels: &[TxKernel],
+	height: u64,
+	header_by_height: H,
+	kernel_pos: P,
+	kernel_at: K,
+) -> Result<(), Error>
+where
+	H: Fn(u64) -> Result<BlockHeader, Error>,
+	P: Fn(&Commitment) -> Result<Vec<u64>, Error>,
+	K: Fn(u64) -> Option<TxKernel>,
+{
+	for kernel in kernels.iter().filter(|k| k.is_no_recent_duplicate()) {
+		let positions = kernel_pos(&kernel.excess)?;
+		if positions.is_empty() {
+			continue;
+		}
+		let cutoff_pos = if kernel.lock_height <= height {
+			header_by_height(height - kernel.lock_height)?.kernel_mmr_size
+		} else {
+			0
+		};
+		let recent = positions
+			.into_iter()
+			.filter(|pos| *pos > cutoff_pos)
+			.filter_map(|pos| kernel_at(pos))
+			.any(|k| k.excess == kernel.excess && k.is_no_recent_duplicate());
+		if recent {
+			return Err(ErrorKind::RecentDuplicateKernel(kernel.excess).into());
+		}
+	}
+	Ok(())
+}
+
+// Checks every relative height locked kernel, for a block at the given
+// height, references a kernel already in the kernel MMR as of the block its
+// relative height earlier. Kernels with that excess are found through the
+// kernel_pos index, and checked against the kernel MMR.
+fn verify_relative_lock_heights<H, P, K>(
+	kernels: &[TxKernel],
+	height: u64,
+	header_by_height: H,
+	kernel_pos: P,
+	kernel_at: K,
+) -> Result<(), Error>
+where
+	H: Fn(u64) -> Result<BlockHeader, Error>,
+	P: Fn(&Commitment) -> Result<Vec<u64>, Error>,
+	K: Fn(u64) -> Option<TxKernel>,
+{
+	// kernels missing their reference don't verify in the first place
+	let rel_locks = kernels
+		.iter()
+		.filter(|k| k.is_relative_height_locked())
+		.filter_map(|k| k.rel_kernel.map(|rel_kernel| (k.lock_height, rel_kernel)));
+	for (relative_height, rel_kernel) in rel_locks {
+		let confirmed = relative_height <= height && {
+			let header = header_by_height(height - relative_height)?;
+			kernel_pos(&rel_kernel)?
+				.into_iter()
+				.filter(|pos| *pos <= header.kernel_mmr_size)
+				.any(|pos| kernel_at(pos).map_or(false, |k| k.excess == rel_kernel))
+		};
+		if !confirmed {
+			return Err(ErrorKind::RelativeLockHeight(rel_kernel).into());
+		}
+	}
+	Ok(())
+}
+
+/// Undo the syncs of the MMR backends that the chain store never
+/// committed, most likely because we stopped in between. Each MMR sync is
+/// journaled ahead and the id of the journal committed along with the
+/// chain store batch. The txhashset must not be open meanwhile.
+fn recover_journals(root_dir: &str, store: &ChainStore) -> Result<(), Error> {
+	let header_dir = Path::new(root_dir).join(HEADERHASHSET_SUBDIR);
+	let txhashset_dir = Path::new(root_dir).join(TXHASHSET_SUBDIR);
+	let dirs = vec![
+		(header_dir.join(HEADER_HEAD_SUBDIR), HEADER_HEAD_SUBDIR),
+		(header_dir.join(SYNC_HEAD_SUBDIR), SYNC_HEAD_SUBDIR),
+		(txhashset_dir.join(OUTPUT_SUBDIR), OUTPUT_SUBDIR),
+		(txhashset_dir.join(RANGE_PROOF_SUBDIR), RANGE_PROOF_SUBDIR),
+		(txhashset_dir.join(KERNEL_SUBDIR), KERNEL_SUBDIR),
+	];
+	for (dir, name) in dirs {
+		if recover_journal(dir, store.get_pmmr_journal_id(name).ok())? {
+			warn!("txhashset: undid the uncommitted sync of the {} MMR", name);
+		}
+	}
+	Ok(())
+}
+
+/// Check the files of all the MMR backends against the sizes the chain store
+/// expects them to have, repairing what an interrupted write left behind.
+/// The header MMRs follow the header and sync heads, the other MMRs the chain
+/// head. The txhashset must not be open meanwhile.
+pub fn check_backend_files(root_dir: &str, store: &ChainStore) -> Result<PMMRCheck, Error> {
+	recover_journals(root_dir, store)?;
+
+	let header = store.head_header()?;
+	let header_mmr_size = |tip: Tip| pmmr::insertion_to_pmmr_index(tip.height + 2) - 1;
+	let header_dir = Path::new(root_dir).join(HEADERHASHSET_SUBDIR);
+	let txhashset_dir = Path::new(root_dir).join(TXHASHSET_SUBDIR);
+
+	let checks = vec![
+		PMMRBackend::<BlockHeader>::check_files(
+			header_dir.join(HEADER_HEAD_SUBDIR),
+			false,
+			true,
+			header_mmr_size(store.header_head()?),
+		)?,
+		PMMRBackend::<BlockHeader>::check_files(
+			header_dir.join(SYNC_HEAD_SUBDIR),
+			false,
+			true,
+			header_mmr_size(store.get_sync_head()?),
+		)?,
+		PMMRBackend::<Output>::check_files(
+			txhashset_dir.join(OUTPUT_SUBDIR),
+			true,
+			true,
+			header.output_mmr_size,
+		)?,
+		PMMRBackend::<RangeProof>::check_files(
+			txhashset_dir.join(RANGE_PROOF_SUBDIR),
+			true,
+			true,
+			header.output_mmr_size,
+		)?,
+		PMMRBackend::<TxKernel>::check_files(
+			txhashset_dir.join(KERNEL_SUBDIR),
+			false,
+			false,
+			header.kernel_mmr_size,
+		)?,
+	];
+	let mut res = PMMRCheck::default();
+	for check in checks {
+		res.repaired.extend(check.repaired);
+		res.errors.extend(check.errors);
+	}
+	Ok(res)
+}
+
 fn expected_file(path: &Path) -> bool {
 	use lazy_static::lazy_static;
 	use regex::Regex;
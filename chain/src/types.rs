@@ -118,6 +118,172 @@ impl ser::Readable for Tip {
 	}
 }
 
+/// When an output was created and, if it was, spent. Only maintained in
+/// archive mode, where we keep every block around to rewind it on reorgs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputHistory {
+	/// Height of the block the output was created in
+	pub created_height: u64,
+	/// Height of the block the output was spent in
+	pub spent_height: Option<u64>,
+	/// Hash of the block the output was spent in
+	pub spending_block: Option<Hash>,
+}
+
+impl OutputHistory {
+	/// History of an output created at the provided height, still unspent.
+	pub fn created(height: u64) -> OutputHistory {
+		OutputHistory {
+			created_height: height,
+			spent_height: None,
+			spending_block: None,
+		}
+	}
+}
+
+impl ser::Writeable for OutputHistory {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.created_height)?;
+		match (self.spent_height, self.spending_block) {
+			(Some(height), Some(block)) => {
+				writer.write_u8(1)?;
+				writer.write_u64(height)?;
+				writer.write_fixed_bytes(&block)
+			}
+			_ => writer.write_u8(0),
+		}
+	}
+}
+
+impl ser::Readable for OutputHistory {
+	fn read(reader: &mut dyn ser::Reader) -> Result<OutputHistory, ser::Error> {
+		let mut history = OutputHistory::created(reader.read_u64()?);
+		match reader.read_u8()? {
+			0 => {}
+			1 => {
+				history.spent_height = Some(reader.read_u64()?);
+				history.spending_block = Some(Hash::read(reader)?);
+			}
+			_ => return Err(ser::Error::CorruptedData),
+		}
+		Ok(history)
+	}
+}
+
+/// Stages of the background chain validation, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationStage {
+	/// Checking the parent hashes of the header, output, rangeproof and
+	/// kernel MMRs
+	Mmrs,
+	/// Verifying the rangeproofs of the unspent outputs
+	RangeProofs,
+	/// Verifying the kernel signatures
+	KernelSignatures,
+	/// Everything checked out
+	Done,
+}
+
+/// Where the background chain validation is at, saved after each chunk so
+/// it can resume after a restart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationCheckpoint {
+	/// When the validation was started
+	pub started_at: i64,
+	/// Wall-clock time spent on the validation so far, in milliseconds, not
+	/// counting the time the node was down
+	pub elapsed_ms: u64,
+	/// Current stage
+	pub stage: ValidationStage,
+	/// MMR position to resume the current stage from
+	pub pos: u64,
+	/// Whether the validation was cancelled before the end
+	pub cancelled: bool,
+	/// What the validation found wrong, stopping it
+	pub error: Option<String>,
+}
+
+impl ValidationCheckpoint {
+	/// A brand new validation, starting now.
+	pub fn new(started_at: i64) -> ValidationCheckpoint {
+		ValidationCheckpoint {
+			started_at,
+			elapsed_ms: 0,
+			stage: ValidationStage::Mmrs,
+			pos: 1,
+			cancelled: false,
+			error: None,
+		}
+	}
+
+	/// Whether there's still work left for this validation.
+	pub fn is_running(&self) -> bool {
+		self.stage != ValidationStage::Done && !self.cancelled && self.error.is_none()
+	}
+}
+
+impl ser::Writeable for ValidationCheckpoint {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_i64(self.started_at)?;
+		writer.write_u64(self.elapsed_ms)?;
+		writer.write_u8(match self.stage {
+			ValidationStage::Mmrs => 0,
+			ValidationStage::RangeProofs => 1,
+			ValidationStage::KernelSignatures => 2,
+			ValidationStage::Done => 3,
+		})?;
+		writer.write_u64(self.pos)?;
+		writer.write_u8(self.cancelled as u8)?;
+		match self.error {
+			Some(ref e) => {
+				writer.write_u8(1)?;
+				writer.write_bytes(e)
+			}
+			None => writer.write_u8(0),
+		}
+	}
+}
+
+impl ser::Readable for ValidationCheckpoint {
+	fn read(reader: &mut dyn ser::Reader) -> Result<ValidationCheckpoint, ser::Error> {
+		let started_at = reader.read_i64()?;
+		let elapsed_ms = reader.read_u64()?;
+		let stage = match reader.read_u8()? {
+			0 => ValidationStage::Mmrs,
+			1 => ValidationStage::RangeProofs,
+			2 => ValidationStage::KernelSignatures,
+			3 => ValidationStage::Done,
+			_ => return Err(ser::Error::CorruptedData),
+		};
+		let pos = reader.read_u64()?;
+		let cancelled = reader.read_u8()? == 1;
+		let error = match reader.read_u8()? {
+			0 => None,
+			_ => Some(String::from_utf8_lossy(&reader.read_bytes_len_prefix()?).into_owned()),
+		};
+		Ok(ValidationCheckpoint {
+			started_at,
+			elapsed_ms,
+			stage,
+			pos,
+			cancelled,
+			error,
+		})
+	}
+}
+
+/// Progress of the background chain validation, as reported by the status
+/// API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationProgress {
+	/// The underlying checkpoint
+	pub checkpoint: ValidationCheckpoint,
+	/// Overall progress, in percent
+	pub percent: f64,
+	/// Estimated time left, in seconds, while running
+	pub eta_secs: Option<u64>,
+}
+
 /// Bridge between the chain pipeline and the rest of the system. Handles
 /// downstream processing of valid blocks by the rest of the system, most
 /// importantly the broadcasting of blocks to our peers.
@@ -171,3 +337,51 @@ pub enum BlockStatus {
 	/// Previous block was not our previous chain head.
 	Reorg,
 }
+
+/// Audit of the coin supply and the txhashset at a given block, comparing
+/// what the emission schedule says should exist with what the UTXO set and
+/// kernels actually commit to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupplyAudit {
+	/// Height of the audited block
+	pub height: u64,
+	/// Hash of the audited block
+	pub hash: Hash,
+	/// Expected mining rewards emitted up to this block
+	pub mining_reward: u64,
+	/// Foundation levy actually paid out up to this block
+	pub foundation_reward: u64,
+	/// Foundation levy accrued since the last payout, not yet emitted
+	pub foundation_pending: u64,
+	/// Expected total supply, mining rewards plus foundation payouts
+	pub total_supply: u64,
+	/// Total overage the consensus rules expect at this block, which the
+	/// kernel sums get checked against
+	pub total_overage: i64,
+	/// Whether the expected total supply matches the total overage
+	pub supply_matches_overage: bool,
+	/// Whether the UTXO sum minus the expected supply matches the kernel sum
+	pub kernel_sums_valid: bool,
+	/// Why the kernel sums did not validate, if they didn't
+	pub kernel_sums_error: Option<String>,
+	/// Number of unspent outputs
+	pub utxo_count: u64,
+	/// Number of output leaves pruned from the leaf set
+	pub pruned_output_count: u64,
+	/// Number of the pruned output leaves compacted away
+	pub compacted_output_count: u64,
+	/// Number of rangeproof leaves pruned from the leaf set
+	pub pruned_rproof_count: u64,
+	/// Number of the pruned rangeproof leaves compacted away
+	pub compacted_rproof_count: u64,
+	/// Number of kernels
+	pub kernel_count: u64,
+	/// Size of the header MMR
+	pub header_mmr_size: u64,
+	/// Size of the output MMR
+	pub output_mmr_size: u64,
+	/// Size of the rangeproof MMR
+	pub rproof_mmr_size: u64,
+	/// Size of the kernel MMR
+	pub kernel_mmr_size: u64,
+}
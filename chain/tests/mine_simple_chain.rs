@@ -13,16 +13,19 @@
 // limitations under the License.
 
 use self::chain::types::NoopAdapter;
-use self::chain::Chain;
+use self::chain::{Chain, ErrorKind};
 use self::core::core::hash::Hashed;
 use self::core::core::verifier_cache::LruVerifierCache;
-use self::core::core::{Block, BlockHeader, OutputIdentifier, Transaction};
+use self::core::core::{
+	Block, BlockHeader, HeaderVersion, OutputIdentifier, Transaction, TxKernel,
+};
 use self::core::genesis;
 use self::core::global::ChainTypes;
-use self::core::libtx::{self, build, reward};
+use self::core::libtx::payment_proof::{self, PaymentProof};
+use self::core::libtx::{self, aggsig, build, reward};
 use self::core::pow::{Difficulty, PoWType};
 use self::core::{consensus, global, pow};
-use self::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use self::keychain::{BlindSum, ExtKeychain, ExtKeychainPath, Identifier, Keychain};
 use self::util::{Mutex, RwLock, StopState};
 use chrono::Duration;
 use epic_chain as chain;
@@ -526,6 +529,422 @@ fn output_header_mappings() {
 	clean_output_dir(".epic_header_for_output");
 }
 
+/// Test the supply audit against the emission schedule, at the head and at a
+/// previous height
+#[test]
+fn supply_audit() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	{
+		let chain = setup(".epic_supply", pow::mine_genesis_block().unwrap());
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+
+		// stay below the first foundation payout
+		for n in 1..consensus::foundation_height() {
+			let prev = chain.head_header().unwrap();
+			let b = prepare_block(&kc, &prev, &chain, n + 1);
+			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		}
+		let height = consensus::foundation_height() - 1;
+
+		let audit = chain.supply_audit(None).unwrap();
+		assert_eq!(audit.height, height);
+		assert_eq!(audit.hash, chain.head().unwrap().last_block_h);
+		assert!(audit.kernel_sums_valid, "{:?}", audit.kernel_sums_error);
+		assert_eq!(
+			audit.total_overage,
+			consensus::total_overage_at_height(height, false)
+		);
+		assert!(audit.supply_matches_overage);
+		assert_eq!(audit.foundation_reward, 0);
+		assert_eq!(
+			audit.foundation_pending,
+			(1..=height)
+				.map(consensus::reward_foundation_at_height)
+				.sum::<u64>()
+		);
+		assert_eq!(audit.pruned_output_count, 0);
+		assert_eq!(audit.compacted_output_count, 0);
+		assert_eq!(audit.pruned_rproof_count, 0);
+		assert_eq!(audit.compacted_rproof_count, 0);
+
+		// one coinbase output and kernel per block since
+		let previous = chain.supply_audit(Some(2)).unwrap();
+		assert_eq!(previous.height, 2);
+		assert!(previous.kernel_sums_valid);
+		assert!(previous.supply_matches_overage);
+		assert_eq!(
+			previous.total_supply as i64,
+			consensus::total_overage_at_height(2, false)
+		);
+		assert_eq!(audit.utxo_count - previous.utxo_count, height - 2);
+		assert_eq!(audit.kernel_count - previous.kernel_count, height - 2);
+
+		// auditing didn't move the chain state
+		assert_eq!(chain.supply_audit(None).unwrap(), audit);
+		assert!(chain.supply_audit(Some(height + 1)).is_err());
+	}
+	// Cleanup chain directory
+	clean_output_dir(".epic_supply");
+}
+
+/// Test a background validation runs through all its stages a chunk at a
+/// time, and can be cancelled and restarted
+#[test]
+fn background_validation() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	{
+		let chain = setup(".epic_bg_validation", pow::mine_genesis_block().unwrap());
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+		for n in 1..4 {
+			let prev = chain.head_header().unwrap();
+			let b = prepare_block(&kc, &prev, &chain, n + 1);
+			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		}
+
+		assert!(chain.validation_progress().unwrap().is_none());
+		assert!(!chain.cancel_validation().unwrap());
+
+		// cancel halfway and start over
+		assert!(chain.start_validation().unwrap());
+		assert!(!chain.start_validation().unwrap());
+		let step = std::time::Duration::from_millis(10);
+		assert!(chain.validation_step(step).unwrap());
+		assert!(chain.cancel_validation().unwrap());
+		assert!(!chain.validation_step(step).unwrap());
+		let cp = chain.validation_checkpoint().unwrap().unwrap();
+		assert!(cp.cancelled);
+		assert!(!cp.is_running());
+		assert!(chain.start_validation().unwrap());
+
+		let mut steps = 0;
+		while chain.validation_step(step).unwrap() {
+			steps += 1;
+			assert!(steps < 100);
+		}
+		let progress = chain.validation_progress().unwrap().unwrap();
+		assert_eq!(progress.checkpoint.stage, chain::ValidationStage::Done);
+		assert_eq!(progress.checkpoint.error, None);
+		assert!(!progress.checkpoint.cancelled);
+		assert_eq!(progress.percent, 100.0);
+		assert_eq!(progress.eta_secs, None);
+	}
+	// Cleanup chain directory
+	clean_output_dir(".epic_bg_validation");
+}
+
+/// Test the output history index of an archive node is maintained as outputs
+/// get created and spent, and rewound on a reorg
+#[test]
+fn output_history() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+	let chain_dir = ".epic_output_history";
+	clean_output_dir(chain_dir);
+	{
+		let chain = chain::Chain::init(
+			chain_dir.to_string(),
+			Arc::new(NoopAdapter {}),
+			pow::mine_genesis_block().unwrap(),
+			pow::verify_size,
+			Arc::new(RwLock::new(LruVerifierCache::new())),
+			true,
+		)
+		.unwrap();
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+
+		let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 2);
+		let coinbase = b.outputs()[0].commitment();
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		for n in 3..5 {
+			let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, n);
+			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		}
+		let fork_point = chain.head_header().unwrap();
+
+		let history = chain.get_output_history(&coinbase).unwrap();
+		assert_eq!(history.created_height, 1);
+		assert_eq!(history.spent_height, None);
+
+		// spend the (now mature) coinbase of the first block
+		let key_id2 = ExtKeychainPath::new(1, 2, 0, 0, 0).to_identifier();
+		let key_id30 = ExtKeychainPath::new(1, 30, 0, 0, 0).to_identifier();
+		let tx = build::transaction(
+			vec![
+				build::coinbase_input(consensus::reward_at_height(1), key_id2),
+				build::output(consensus::reward_at_height(1) - 20000, key_id30),
+				build::with_fee(20000),
+			],
+			&kc,
+		)
+		.unwrap();
+		let change = tx.outputs()[0].commitment();
+		let b = prepare_block_tx(&kc, &fork_point, &chain, 5, vec![&tx]);
+		let spending_block = b.hash();
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+
+		let history = chain.get_output_history(&coinbase).unwrap();
+		assert_eq!(history.spent_height, Some(4));
+		assert_eq!(history.spending_block, Some(spending_block));
+		assert_eq!(chain.get_output_history(&change).unwrap().created_height, 4);
+
+		// a fork with more work, not including the spend, takes over
+		let b = prepare_fork_block(&kc, &fork_point, &chain, 6);
+		let fork_coinbase = b.outputs()[0].commitment();
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		assert_ne!(chain.head().unwrap().last_block_h, spending_block);
+
+		let history = chain.get_output_history(&coinbase).unwrap();
+		assert_eq!(history.created_height, 1);
+		assert_eq!(history.spent_height, None);
+		assert_eq!(history.spending_block, None);
+		assert!(chain.get_output_history(&change).is_err());
+		assert_eq!(
+			chain
+				.get_output_history(&fork_coinbase)
+				.unwrap()
+				.created_height,
+			4
+		);
+	}
+	// Cleanup chain directory
+	clean_output_dir(chain_dir);
+}
+
+/// Test a txhashset extension synced to disk but not committed to the db, as
+/// left by a crash in between, gets undone when the chain is opened again
+#[test]
+fn uncommitted_extension() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let chain_dir = ".epic_uncommitted";
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let (head, coinbase, b) = {
+		let chain = setup(chain_dir, pow::mine_genesis_block().unwrap());
+		let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 2);
+		let coinbase = OutputIdentifier::from_output(&b.outputs()[0]);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		for n in 3..5 {
+			let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, n);
+			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		}
+
+		// spend the coinbase of the first block
+		let key_id2 = ExtKeychainPath::new(1, 2, 0, 0, 0).to_identifier();
+		let key_id30 = ExtKeychainPath::new(1, 30, 0, 0, 0).to_identifier();
+		let tx = build::transaction(
+			vec![
+				build::coinbase_input(consensus::reward_at_height(1), key_id2),
+				build::output(consensus::reward_at_height(1) - 20000, key_id30),
+				build::with_fee(20000),
+			],
+			&kc,
+		)
+		.unwrap();
+		let b = prepare_block_tx(&kc, &chain.head_header().unwrap(), &chain, 5, vec![&tx]);
+
+		// the MMR files get synced, but the batch is dropped without a commit
+		let txhashset = chain.txhashset();
+		let mut txhashset = txhashset.write();
+		let store = chain.store();
+		let mut batch = store.batch().unwrap();
+		chain::txhashset::extending(&mut txhashset, &mut batch, |ext| ext.apply_block(&b)).unwrap();
+		(chain.head().unwrap(), coinbase, b)
+	};
+	{
+		let chain = chain::Chain::init(
+			chain_dir.to_string(),
+			Arc::new(NoopAdapter {}),
+			pow::mine_genesis_block().unwrap(),
+			pow::verify_size,
+			Arc::new(RwLock::new(LruVerifierCache::new())),
+			false,
+		)
+		.unwrap();
+		assert_eq!(chain.head().unwrap(), head);
+		assert!(chain.is_unspent(&coinbase).is_ok());
+		chain.validate(false).unwrap();
+
+		// and the block can be processed for good
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		assert!(chain.is_unspent(&coinbase).is_err());
+		chain.validate(false).unwrap();
+	}
+	// Cleanup chain directory
+	clean_output_dir(chain_dir);
+}
+
+/// Test a NoRecentDuplicate kernel can't be replayed less than its relative
+/// height after it was included, but can be from there on
+#[test]
+fn nrd_kernel_relative_height() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let chain_dir = ".epic_nrd_kernel";
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	{
+		let chain = setup(chain_dir, pow::mine_genesis_block().unwrap());
+		let key_id = ExtKeychainPath::new(1, 40, 0, 0, 0).to_identifier();
+		let kernel = TxKernel::empty().with_relative_height(2);
+		let tx = kernel_only_tx(&kc, &key_id, kernel);
+
+		let b = prepare_block_tx(&kc, &chain.head_header().unwrap(), &chain, 2, vec![&tx]);
+		assert_eq!(
+			b.header.version,
+			HeaderVersion::new(consensus::NRD_HEADER_VERSION)
+		);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+
+		// the same kernel in the next block is a recent duplicate
+		match chain.validate_tx(&tx) {
+			Err(e) => match e.kind() {
+				ErrorKind::RecentDuplicateKernel(_) => {}
+				_ => panic!("Expected recent duplicate kernel error, got {:?}", e),
+			},
+			Ok(_) => panic!("Expected recent duplicate kernel error"),
+		}
+		let b = prepare_block_tx(&kc, &chain.head_header().unwrap(), &chain, 3, vec![&tx]);
+		assert!(chain.process_block(b, chain::Options::SKIP_POW).is_err());
+
+		// two blocks later it's not anymore
+		let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 3);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		chain.validate_tx(&tx).unwrap();
+		let b = prepare_block_tx(&kc, &chain.head_header().unwrap(), &chain, 4, vec![&tx]);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		assert_eq!(chain.head_header().unwrap().height, 3);
+		chain.validate(false).unwrap();
+	}
+	// Cleanup chain directory
+	clean_output_dir(chain_dir);
+}
+
+/// Test a relative height locked kernel can't be included less than its
+/// relative height after the kernel it references, but can be from there on
+#[test]
+fn relative_lock_height() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let chain_dir = ".epic_relative_lock";
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	{
+		let chain = setup(chain_dir, pow::mine_genesis_block().unwrap());
+		let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 2);
+		let rel_kernel = b.kernels()[0].excess;
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+
+		let key_id = ExtKeychainPath::new(1, 41, 0, 0, 0).to_identifier();
+		let kernel = TxKernel::empty().with_relative_lock(2, rel_kernel);
+		let tx = kernel_only_tx(&kc, &key_id, kernel);
+
+		// the referenced kernel is only one block below the next one
+		match chain.verify_tx_relative_lock_height(&tx) {
+			Err(e) => match e.kind() {
+				ErrorKind::RelativeLockHeight(_) => {}
+				_ => panic!("Expected relative lock height error, got {:?}", e),
+			},
+			Ok(_) => panic!("Expected relative lock height error"),
+		}
+		let b = prepare_block_tx(&kc, &chain.head_header().unwrap(), &chain, 3, vec![&tx]);
+		assert_eq!(
+			b.header.version,
+			HeaderVersion::new(consensus::RELATIVE_LOCK_HEADER_VERSION)
+		);
+		assert!(chain.process_block(b, chain::Options::SKIP_POW).is_err());
+
+		// a block later it's far enough
+		let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 3);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		chain.verify_tx_relative_lock_height(&tx).unwrap();
+		let b = prepare_block_tx(&kc, &chain.head_header().unwrap(), &chain, 4, vec![&tx]);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		assert_eq!(chain.head_header().unwrap().height, 3);
+		chain.validate(false).unwrap();
+
+		// a kernel nobody knows about is never confirmed
+		let unknown_id = ExtKeychainPath::new(1, 42, 0, 0, 0).to_identifier();
+		let unknown = kc.commit(0, &unknown_id).unwrap();
+		let kernel = TxKernel::empty().with_relative_lock(1, unknown);
+		let tx = kernel_only_tx(&kc, &key_id, kernel);
+		assert!(chain.verify_tx_relative_lock_height(&tx).is_err());
+	}
+	// Cleanup chain directory
+	clean_output_dir(chain_dir);
+}
+
+/// Test a payment proof verifies against the chain once its kernel is in a
+/// block, and tells which block
+#[test]
+fn payment_proof() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let chain_dir = ".epic_payment_proof";
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let recipient = ExtKeychain::from_random_seed(false).unwrap();
+	{
+		let chain = setup(chain_dir, pow::mine_genesis_block().unwrap());
+		let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 2);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+
+		let key_id = ExtKeychainPath::new(1, 43, 0, 0, 0).to_identifier();
+		let tx = kernel_only_tx(&kc, &key_id, TxKernel::empty());
+		let excess = tx.kernels()[0].excess;
+		let sender_address = payment_proof::address(&kc, &key_id).unwrap();
+		let proof =
+			PaymentProof::create(&recipient, &key_id, 1_000, excess, sender_address).unwrap();
+
+		// not on chain yet
+		match chain.verify_payment_proof(&proof) {
+			Err(e) => match e.kind() {
+				ErrorKind::TxKernelNotFound => {}
+				_ => panic!("Expected kernel not found error, got {:?}", e),
+			},
+			Ok(_) => panic!("Expected kernel not found error"),
+		}
+
+		let b = prepare_block_tx(&kc, &chain.head_header().unwrap(), &chain, 3, vec![&tx]);
+		let hash = b.hash();
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		let b = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 4);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+
+		let header = chain.verify_payment_proof(&proof).unwrap();
+		assert_eq!(header.height, 2);
+		assert_eq!(header.hash(), hash);
+
+		// the recipient didn't sign for another amount
+		let mut bad_proof = proof.clone();
+		bad_proof.amount = 10_000;
+		match chain.verify_payment_proof(&bad_proof) {
+			Err(e) => match e.kind() {
+				ErrorKind::InvalidPaymentProof(_) => {}
+				_ => panic!("Expected invalid payment proof error, got {:?}", e),
+			},
+			Ok(_) => panic!("Expected invalid payment proof error"),
+		}
+	}
+	// Cleanup chain directory
+	clean_output_dir(chain_dir);
+}
+
+// A transaction made of the single provided kernel, its excess balanced by
+// the offset, so it can be included again and again.
+fn kernel_only_tx<K>(kc: &K, key_id: &Identifier, mut kernel: TxKernel) -> Transaction
+where
+	K: Keychain,
+{
+	let excess = kc
+		.blind_sum(&BlindSum::new().add_key_id(key_id.to_value_path(0)))
+		.unwrap();
+	let offset = kc
+		.blind_sum(&BlindSum::new().sub_key_id(key_id.to_value_path(0)))
+		.unwrap();
+
+	let msg = kernel.msg_to_sign().unwrap();
+	let skey = excess.secret_key(kc.secp()).unwrap();
+	kernel.excess = kc.secp().commit(0, skey).unwrap();
+	let pubkey = kernel.excess.to_pubkey(kc.secp()).unwrap();
+	kernel.excess_sig =
+		aggsig::sign_with_blinding(kc.secp(), &msg, &excess, Some(&pubkey)).unwrap();
+
+	Transaction::empty().with_kernel(kernel).with_offset(offset)
+}
+
 fn prepare_block<K>(kc: &K, prev: &BlockHeader, chain: &Chain, diff: u64) -> Block
 where
 	K: Keychain,
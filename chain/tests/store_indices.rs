@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use self::chain::store::ChainStore;
 use self::chain::{Error, Tip};
 use self::core::core::hash::Hashed;
 use self::core::core::Block;
@@ -23,14 +24,10 @@ use env_logger;
 use epic_chain as chain;
 use epic_core as core;
 use epic_keychain as keychain;
-use std::fs;
+use epic_store::MemStore;
 use std::sync::Arc;
 
-fn clean_output_dir(dir_name: &str) {
-	let _ = fs::remove_dir_all(dir_name);
-}
-
-fn setup_chain(genesis: &Block, chain_store: Arc<chain::store::ChainStore>) -> Result<(), Error> {
+fn setup_chain(genesis: &Block, chain_store: Arc<ChainStore<MemStore>>) -> Result<(), Error> {
 	let batch = chain_store.batch()?;
 	batch.save_block_header(&genesis.header)?;
 	batch.save_block(&genesis)?;
@@ -47,13 +44,11 @@ fn test_various_store_indices() {
 		Ok(_) => println!("Initializing env logger"),
 		Err(e) => println!("env logger already initialized: {:?}", e),
 	};
-	let chain_dir = ".epic_idx_1";
-	clean_output_dir(chain_dir);
 
 	let keychain = ExtKeychain::from_random_seed(false).unwrap();
 	let key_id = ExtKeychainPath::new(1, 1, 0, 0, 0).to_identifier();
 
-	let chain_store = Arc::new(chain::store::ChainStore::new(chain_dir).unwrap());
+	let chain_store = Arc::new(ChainStore::with_store(MemStore::new()));
 
 	global::set_mining_mode(ChainTypes::AutomatedTesting);
 	let genesis = pow::mine_genesis_block().unwrap();
@@ -95,6 +90,4 @@ fn test_various_store_indices() {
 		// Check the batch did not commit any changes to the store .
 		assert!(chain_store.get_block(&block_hash).is_ok());
 	}
-	// Cleanup chain directory
-	clean_output_dir(chain_dir);
 }
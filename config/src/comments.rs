@@ -111,6 +111,24 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"block_retention_days".to_string(),
+		"
+#number of days of full blocks to keep when not in archive mode, older blocks
+#are pruned. The cut-through horizon (about a week) is always kept.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"db_compact_free_percent".to_string(),
+		"
+#compact the chain and peer databases on startup when at least this percentage
+#of their file is left free by deleted blocks and peers, 0 to never do it
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"skip_sync_wait".to_string(),
 		"
@@ -259,6 +277,19 @@ fn comments() -> HashMap<String, String> {
 # A preferred dandelion_peer, mainly used for testing dandelion
 # dandelion_peer = \"10.0.0.1:13144\"
 
+#bandwidth limits, in bytes per second, unlimited when not set
+#max upload and download rates over all peers
+#upload_rate = 1000000
+#download_rate = 1000000
+
+#max upload and download rates for each peer
+#peer_upload_rate = 250000
+#peer_download_rate = 250000
+
+#max rate for txhashset archive and kernel data transfers, which have their own
+#budget and don't count against the limits above
+#attachment_rate = 500000
+
 "
 		.to_string(),
 	);
@@ -305,6 +336,48 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"min_relay_fee_base".to_string(),
+		"
+#minimum base fee for a transaction to get selected to build a block
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"replace_by_fee".to_string(),
+		"
+#whether a transaction paying a higher fee can replace the pool transactions
+#it double spends
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_txs_per_peer".to_string(),
+		"
+#maximum number of pool transactions received from a single peer
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_txs_per_api_client".to_string(),
+		"
+#maximum number of pool transactions pushed by a single API client
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_admitted_weight_per_min".to_string(),
+		"
+#maximum total weight of transactions from a single peer or API client
+#admitted to the pool over any rolling minute
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.stratum_mining_config]".to_string(),
 		"
@@ -364,6 +437,41 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"[server.version_check_config]".to_string(),
+		"
+################################################
+### VERSION CHECK CONFIGURATION              ###
+################################################
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"source".to_string(),
+		"
+#where to get the minimum allowed node version from, checked periodically,
+#the node stops when outdated unless the version comes from its peers
+#DNS - TXT record of dns_domain, queried through dns_server if set
+#HTTP - plain text body served at http_url
+#File - first line of the file at file_path
+#Peers - version advertised by at least two thirds of our peers, only warned about
+#None - no version check
+#dns_server = \"127.0.0.1:53\"
+#http_url = \"https://epic.tech/version\"
+#file_path = \"/etc/epic/version\"
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"interval_mins".to_string(),
+		"
+#minutes between two version checks
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[logging]".to_string(),
 		"
@@ -309,13 +309,44 @@ pub const MAX_BLOCK_WEIGHT: usize = 40_000;
 /// Fork every 6 months.
 pub const HARD_FORK_INTERVAL: u64 = YEAR_HEIGHT / 2;
 
+/// Header version of the blocks that may hold NoRecentDuplicate kernels.
+pub const NRD_HEADER_VERSION: u16 = 7;
+
+/// Largest relative height of a NoRecentDuplicate kernel, a week of blocks.
+pub const NRD_MAX_RELATIVE_HEIGHT: u64 = WEEK_HEIGHT;
+
+/// Header version of the blocks that may hold relative height locked
+/// kernels, as well as NoRecentDuplicate ones.
+pub const RELATIVE_LOCK_HEADER_VERSION: u16 = 8;
+
 /// Check whether the block version is valid at a given height, implements
 /// 6 months interval scheduled hard forks for the first 2 years.
 pub fn valid_header_version(height: u64, version: HeaderVersion) -> bool {
+	// NoRecentDuplicate and relative height locked kernels are not scheduled
+	// yet, but can be exercised on test chains
+	if global::is_automated_testing_mode()
+		&& (version == HeaderVersion(NRD_HEADER_VERSION)
+			|| version == HeaderVersion(RELATIVE_LOCK_HEADER_VERSION))
+	{
+		return true;
+	}
 	// uncomment below as we go from hard fork to hard fork
 	version == HeaderVersion::default()
 }
 
+/// Whether blocks at the given height may hold NoRecentDuplicate kernels,
+/// that is whether the header version introducing them is valid there.
+pub fn nrd_enabled(height: u64) -> bool {
+	valid_header_version(height, HeaderVersion(NRD_HEADER_VERSION))
+}
+
+/// Whether blocks at the given height may hold relative height locked
+/// kernels, that is whether the header version introducing them is valid
+/// there.
+pub fn relative_lock_enabled(height: u64) -> bool {
+	valid_header_version(height, HeaderVersion(RELATIVE_LOCK_HEADER_VERSION))
+}
+
 /// Number of blocks used to calculate difficulty adjustments
 pub const DIFFICULTY_ADJUST_WINDOW: u64 = HOUR_HEIGHT;
 
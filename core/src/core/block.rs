@@ -63,6 +63,12 @@ pub enum Error {
 	WeightExceeded,
 	/// Kernel not valid due to lock_height exceeding block header height
 	KernelLockHeight(u64),
+	/// NoRecentDuplicate kernel in a block with a header version predating
+	/// them
+	NRDKernelPreHeaderVersion,
+	/// Relative height locked kernel in a block with a header version
+	/// predating them
+	RelativeLockPreHeaderVersion,
 	/// Underlying tx related error
 	Transaction(transaction::Error),
 	/// Underlying Secp256k1 error (signature validation or invalid public key
@@ -652,6 +658,7 @@ impl Block {
 		// Caller must validate the block as necessary.
 		Block {
 			header: BlockHeader {
+				version: new_header_version(prev.height + 1, agg_tx.kernels()),
 				height: prev.height + 1,
 				timestamp,
 				prev_hash: prev.hash(),
@@ -697,6 +704,7 @@ impl Block {
 		// Caller must validate the block as necessary.
 		Block {
 			header: BlockHeader {
+				version: new_header_version(prev.height + 1, agg_tx.kernels()),
 				height: prev.height + 1,
 				timestamp,
 				prev_hash: prev.hash(),
@@ -830,6 +838,8 @@ impl Block {
 		self.body.validate(Weighting::AsBlock, verifier)?;
 
 		self.verify_kernel_lock_heights()?;
+		self.verify_nrd_kernels_for_header_version()?;
+		self.verify_relative_locks_for_header_version()?;
 		self.verify_coinbase()?;
 
 		// take the kernel offset for this block (block offset minus previous) and
@@ -896,10 +906,49 @@ impl Block {
 		for k in &self.body.kernels {
 			// check we have no kernels with lock_heights greater than current height
 			// no tx can be included in a block earlier than its lock_height
-			if k.lock_height > self.header.height {
+			if k.is_height_locked() && k.lock_height > self.header.height {
 				return Err(Error::KernelLockHeight(k.lock_height));
 			}
 		}
 		Ok(())
 	}
+
+	// NoRecentDuplicate kernels are only valid from the header version
+	// introducing them. Whether they're recent duplicates of kernels from
+	// previous blocks is checked against the chain.
+	fn verify_nrd_kernels_for_header_version(&self) -> Result<(), Error> {
+		if self.header.version.0 < consensus::NRD_HEADER_VERSION
+			&& self.body.kernels.iter().any(|k| k.is_no_recent_duplicate())
+		{
+			return Err(Error::NRDKernelPreHeaderVersion);
+		}
+		Ok(())
+	}
+
+	// Relative height locked kernels are only valid from the header version
+	// introducing them. Whether the kernels they reference are old enough is
+	// checked against the chain.
+	fn verify_relative_locks_for_header_version(&self) -> Result<(), Error> {
+		if self.header.version.0 < consensus::RELATIVE_LOCK_HEADER_VERSION
+			&& self.body.kernels.iter().any(|k| k.is_relative_height_locked())
+		{
+			return Err(Error::RelativeLockPreHeaderVersion);
+		}
+		Ok(())
+	}
+}
+
+// Header version of a new block at the given height, the one introducing
+// the kernels the block holds when they need one and it's valid there.
+fn new_header_version(height: u64, kernels: &[TxKernel]) -> HeaderVersion {
+	if kernels.iter().any(|k| k.is_relative_height_locked())
+		&& consensus::relative_lock_enabled(height)
+	{
+		HeaderVersion(consensus::RELATIVE_LOCK_HEADER_VERSION)
+	} else if kernels.iter().any(|k| k.is_no_recent_duplicate()) && consensus::nrd_enabled(height)
+	{
+		HeaderVersion(consensus::NRD_HEADER_VERSION)
+	} else {
+		HeaderVersion::default()
+	}
 }
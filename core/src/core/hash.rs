@@ -243,8 +243,13 @@ impl<D: DefaultHashable> Hashed for D {
 impl<D: DefaultHashable> DefaultHashable for &D {}
 impl<D: DefaultHashable, E: DefaultHashable> DefaultHashable for (D, E) {}
 impl<D: DefaultHashable, E: DefaultHashable, F: DefaultHashable> DefaultHashable for (D, E, F) {}
+impl<D: DefaultHashable, E: DefaultHashable, F: DefaultHashable, G: DefaultHashable> DefaultHashable
+	for (D, E, F, G)
+{
+}
 
 /// Implement Hashed trait for external types here
+impl DefaultHashable for crate::util::secp::pedersen::Commitment {}
 impl DefaultHashable for crate::util::secp::pedersen::RangeProof {}
 impl DefaultHashable for Vec<u8> {}
 impl DefaultHashable for u64 {}
@@ -55,6 +55,18 @@ pub trait Backend<T: PMMRable> {
 	/// Iterator over current (unpruned, unremoved) leaf positions.
 	fn leaf_pos_iter(&self) -> Box<Iterator<Item = u64> + '_>;
 
+	/// Iterator over current leaf positions from the provided one on. Backends
+	/// should avoid walking the positions before it.
+	fn leaf_pos_iter_from(&self, from: u64) -> Box<Iterator<Item = u64> + '_> {
+		Box::new(self.leaf_pos_iter().skip_while(move |&pos| pos < from))
+	}
+
+	/// Number of leaves compacted away, their hashes and data gone from the
+	/// backend storage. Backends that never compact have none.
+	fn n_compacted_leaves(&self) -> u64 {
+		0
+	}
+
 	/// Remove Hash by insertion position. An index is also provided so the
 	/// underlying backend can implement some rollback of positions up to a
 	/// given index (practically the index is the height of a block that
@@ -79,6 +79,11 @@ where
 		self.backend.leaf_pos_iter()
 	}
 
+	/// Number of leaves compacted away by the backend.
+	pub fn n_compacted_leaves(&self) -> u64 {
+		self.backend.n_compacted_leaves()
+	}
+
 	/// Returns a vec of the peaks of this MMR.
 	pub fn peaks(&self) -> Vec<Hash> {
 		let peaks_pos = peaks(self.last_pos);
@@ -297,30 +302,7 @@ where
 
 	/// Walks all unpruned nodes in the MMR and revalidate all parent hashes
 	pub fn validate(&self) -> Result<(), String> {
-		// iterate on all parent nodes
-		for n in 1..(self.last_pos + 1) {
-			let height = bintree_postorder_height(n);
-			if height > 0 {
-				if let Some(hash) = self.get_hash(n) {
-					let left_pos = n - (1 << height);
-					let right_pos = n - 1;
-					// using get_from_file here for the children (they may have been "removed")
-					if let Some(left_child_hs) = self.get_from_file(left_pos) {
-						if let Some(right_child_hs) = self.get_from_file(right_pos) {
-							// hash the two child nodes together with parent_pos and compare
-							if (left_child_hs, right_child_hs).hash_with_index(n - 1) != hash {
-								return Err(format!(
-									"Invalid MMR, hash of parent at {} does \
-									 not match children.",
-									n
-								));
-							}
-						}
-					}
-				}
-			}
-		}
-		Ok(())
+		self.readonly_pmmr().validate_range(1, self.last_pos)
 	}
 
 	/// Is the MMR empty?
@@ -17,7 +17,9 @@
 use std::marker;
 
 use crate::core::hash::{Hash, ZERO_HASH};
-use crate::core::pmmr::pmmr::{bintree_rightmost, insertion_to_pmmr_index, peaks};
+use crate::core::pmmr::pmmr::{
+	bintree_postorder_height, bintree_rightmost, insertion_to_pmmr_index, peaks,
+};
 use crate::core::pmmr::{is_leaf, Backend};
 use crate::ser::{PMMRIndexHashable, PMMRable};
 
@@ -86,6 +88,52 @@ where
 		}
 	}
 
+	/// Iterator over current (unpruned, unremoved) leaf positions.
+	pub fn leaf_pos_iter(&self) -> impl Iterator<Item = u64> + '_ {
+		let last_pos = self.last_pos;
+		self.backend
+			.leaf_pos_iter()
+			.take_while(move |&pos| pos <= last_pos)
+	}
+
+	/// Iterator over current (unpruned, unremoved) leaf positions from the
+	/// provided one on.
+	pub fn leaf_pos_iter_from(&self, from: u64) -> impl Iterator<Item = u64> + '_ {
+		let last_pos = self.last_pos;
+		self.backend
+			.leaf_pos_iter_from(from)
+			.take_while(move |&pos| pos <= last_pos)
+	}
+
+	/// Revalidate the hashes of the unpruned parent nodes between positions
+	/// from and to (inclusive) against their children, so a large MMR can be
+	/// validated a range at a time.
+	pub fn validate_range(&self, from: u64, to: u64) -> Result<(), String> {
+		for n in from.max(1)..(to.min(self.last_pos) + 1) {
+			let height = bintree_postorder_height(n);
+			if height > 0 {
+				if let Some(hash) = self.get_hash(n) {
+					let left_pos = n - (1 << height);
+					let right_pos = n - 1;
+					// using get_from_file here for the children (they may have been "removed")
+					if let Some(left_child_hs) = self.backend.get_from_file(left_pos) {
+						if let Some(right_child_hs) = self.backend.get_from_file(right_pos) {
+							// hash the two child nodes together with parent_pos and compare
+							if (left_child_hs, right_child_hs).hash_with_index(n - 1) != hash {
+								return Err(format!(
+									"Invalid MMR, hash of parent at {} does \
+									 not match children.",
+									n
+								));
+							}
+						}
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
 	/// Is the MMR empty?
 	pub fn is_empty(&self) -> bool {
 		self.last_pos == 0
@@ -32,6 +32,7 @@ use crate::{consensus, global};
 use enum_primitive::FromPrimitive;
 use std::cmp::Ordering;
 use std::cmp::{max, min};
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::{error, fmt};
 
@@ -47,6 +48,14 @@ enum_from_primitive! {
 		Coinbase = 1,
 		/// A kernel with an expicit lock height.
 		HeightLocked = 2,
+		/// A kernel that can't share its excess with another such kernel
+		/// less than its relative height (held in place of the lock height)
+		/// blocks earlier, protecting against the replay of old transactions.
+		NoRecentDuplicate = 3,
+		/// A kernel that can't be included in a block less than its relative
+		/// height (held in place of the lock height) blocks after the block
+		/// holding the earlier kernel it references.
+		RelativeHeightLocked = 4,
 	}
 }
 
@@ -103,6 +112,8 @@ pub enum Error {
 	/// Validation error relating to kernel features.
 	/// It is invalid for a transaction to contain a coinbase kernel, for example.
 	InvalidKernelFeatures,
+	/// Two NoRecentDuplicate kernels share the same excess.
+	RecentDuplicateKernel,
 	/// Signature verification error.
 	IncorrectSignature,
 	/// Underlying serialization error.
@@ -165,6 +176,10 @@ pub struct TxKernel {
 	/// The max lock_height of all *inputs* to this transaction
 	#[serde(with = "secp_ser::string_or_u64")]
 	pub lock_height: u64,
+	/// Excess of the earlier kernel a relative height locked kernel is
+	/// relative to, None for any other kernel.
+	#[serde(default, with = "secp_ser::option_commitment_serde")]
+	pub rel_kernel: Option<Commitment>,
 	/// Remainder of the sum of all transaction commitments. If the transaction
 	/// is well formed, amounts components should sum to zero and the excess
 	/// is hence a valid public key.
@@ -194,6 +209,11 @@ impl Writeable for TxKernel {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
 		self.features.write(writer)?;
 		ser_multiwrite!(writer, [write_u64, self.fee], [write_u64, self.lock_height]);
+		if self.is_relative_height_locked() {
+			self.rel_kernel
+				.ok_or(ser::Error::CorruptedData)?
+				.write(writer)?;
+		}
 		self.excess.write(writer)?;
 		self.excess_sig.write(writer)?;
 		Ok(())
@@ -202,10 +222,19 @@ impl Writeable for TxKernel {
 
 impl Readable for TxKernel {
 	fn read(reader: &mut dyn Reader) -> Result<TxKernel, ser::Error> {
+		let features = KernelFeatures::read(reader)?;
+		let fee = reader.read_u64()?;
+		let lock_height = reader.read_u64()?;
+		let rel_kernel = if features.is_relative_height_locked() {
+			Some(Commitment::read(reader)?)
+		} else {
+			None
+		};
 		Ok(TxKernel {
-			features: KernelFeatures::read(reader)?,
-			fee: reader.read_u64()?,
-			lock_height: reader.read_u64()?,
+			features,
+			fee,
+			lock_height,
+			rel_kernel,
 			excess: Commitment::read(reader)?,
 			excess_sig: secp::Signature::read(reader)?,
 		})
@@ -236,6 +265,16 @@ impl KernelFeatures {
 	pub fn is_height_locked(&self) -> bool {
 		*self == KernelFeatures::HeightLocked
 	}
+
+	/// Is this a no recent duplicate kernel?
+	pub fn is_no_recent_duplicate(&self) -> bool {
+		*self == KernelFeatures::NoRecentDuplicate
+	}
+
+	/// Is this a relative height locked kernel?
+	pub fn is_relative_height_locked(&self) -> bool {
+		*self == KernelFeatures::RelativeHeightLocked
+	}
 }
 
 impl TxKernel {
@@ -254,15 +293,32 @@ impl TxKernel {
 		self.features.is_height_locked()
 	}
 
+	/// Is this a no recent duplicate kernel?
+	pub fn is_no_recent_duplicate(&self) -> bool {
+		self.features.is_no_recent_duplicate()
+	}
+
+	/// Is this a relative height locked kernel?
+	pub fn is_relative_height_locked(&self) -> bool {
+		self.features.is_relative_height_locked()
+	}
+
 	/// Return the excess commitment for this tx_kernel.
 	pub fn excess(&self) -> Commitment {
 		self.excess
 	}
 
 	/// The msg signed as part of the tx kernel.
-	/// Consists of the fee and the lock_height.
+	/// Consists of the fee and the lock_height (the relative height for no
+	/// recent duplicate and relative height locked kernels), as well as the
+	/// referenced kernel excess for the latter.
 	pub fn msg_to_sign(&self) -> Result<secp::Message, Error> {
-		let msg = kernel_sig_msg(self.fee, self.lock_height, self.features)?;
+		let msg = match self.rel_kernel {
+			Some(ref rel_kernel) if self.is_relative_height_locked() => {
+				relative_kernel_sig_msg(self.fee, self.lock_height, rel_kernel)?
+			}
+			_ => kernel_sig_msg(self.fee, self.lock_height, self.features)?,
+		};
 		Ok(msg)
 	}
 
@@ -270,7 +326,9 @@ impl TxKernel {
 	/// as a public key and checking the signature verifies with the fee as
 	/// message.
 	pub fn verify(&self) -> Result<(), Error> {
-		if self.is_coinbase() && self.fee != 0 || !self.is_height_locked() && self.lock_height != 0
+		if self.is_coinbase() && self.fee != 0
+			|| (self.is_plain() || self.is_coinbase()) && self.lock_height != 0
+			|| self.is_relative_height_locked() != self.rel_kernel.is_some()
 		{
 			return Err(Error::InvalidKernelFeatures);
 		}
@@ -300,6 +358,7 @@ impl TxKernel {
 			features: KernelFeatures::Plain,
 			fee: 0,
 			lock_height: 0,
+			rel_kernel: None,
 			excess: Commitment::from_vec(vec![0; 33]),
 			excess_sig: secp::Signature::from_raw_data(&[0; 64]).unwrap(),
 		}
@@ -315,15 +374,38 @@ impl TxKernel {
 		TxKernel {
 			features: kernel_features(lock_height),
 			lock_height,
+			rel_kernel: None,
+			..self
+		}
+	}
+
+	/// Builds a new no recent duplicate tx kernel with the provided relative
+	/// height.
+	pub fn with_relative_height(self, relative_height: u64) -> TxKernel {
+		TxKernel {
+			features: KernelFeatures::NoRecentDuplicate,
+			lock_height: relative_height,
+			rel_kernel: None,
+			..self
+		}
+	}
+
+	/// Builds a new relative height locked tx kernel, not valid less than
+	/// the provided relative height after the kernel with the provided
+	/// excess.
+	pub fn with_relative_lock(self, relative_height: u64, rel_kernel: Commitment) -> TxKernel {
+		TxKernel {
+			features: KernelFeatures::RelativeHeightLocked,
+			lock_height: relative_height,
+			rel_kernel: Some(rel_kernel),
 			..self
 		}
 	}
 }
 
 /// Wrapper around a tx kernel used when maintaining them in the MMR.
-/// These will be useful once we implement relative lockheights via relative kernels
-/// as a kernel may have an optional rel_kernel but we will not want to store these
-/// directly in the kernel MMR.
+/// The rel_kernel of relative height locked kernels is kept along, as their
+/// signature can't be verified without it.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TxKernelEntry {
 	/// The underlying tx kernel.
@@ -599,10 +681,12 @@ impl TransactionBody {
 			.saturating_add(kernel_len.saturating_mul(consensus::BLOCK_KERNEL_WEIGHT))
 	}
 
-	/// Lock height of a body is the max lock height of the kernels.
+	/// Lock height of a body is the max lock height of the height locked
+	/// kernels.
 	pub fn lock_height(&self) -> u64 {
 		self.kernels
 			.iter()
+			.filter(|x| x.is_height_locked())
 			.map(|x| x.lock_height)
 			.max()
 			.unwrap_or(0)
@@ -696,6 +780,18 @@ impl TransactionBody {
 		Ok(())
 	}
 
+	// Verify no two NoRecentDuplicate kernels share their excess, whatever
+	// their relative height they would be duplicates within the same block.
+	fn verify_no_recent_duplicates(&self) -> Result<(), Error> {
+		let mut excesses = HashSet::new();
+		for kernel in self.kernels.iter().filter(|x| x.is_no_recent_duplicate()) {
+			if !excesses.insert(kernel.excess) {
+				return Err(Error::RecentDuplicateKernel);
+			}
+		}
+		Ok(())
+	}
+
 	/// "Lightweight" validation that we can perform quickly during read/deserialization.
 	/// Subset of full validation that skips expensive verification steps, specifically -
 	/// * rangeproof verification
@@ -704,6 +800,7 @@ impl TransactionBody {
 		self.verify_weight(weighting)?;
 		self.verify_sorted()?;
 		self.verify_cut_through()?;
+		self.verify_no_recent_duplicates()?;
 		Ok(())
 	}
 
@@ -1439,9 +1536,13 @@ impl From<Output> for OutputIdentifier {
 
 /// Construct msg from tx fee, lock_height and kernel features.
 ///
-/// msg = hash(features)                       for coinbase kernels
-///       hash(features || fee)                for plain kernels
-///       hash(features || fee || lock_height) for height locked kernels
+/// msg = hash(features)                           for coinbase kernels
+///       hash(features || fee)                    for plain kernels
+///       hash(features || fee || lock_height)     for height locked kernels
+///       hash(features || fee || relative_height) for no recent duplicate kernels
+///
+/// The relative height of no recent duplicate kernels is passed as their
+/// lock_height and must be between 1 and `consensus::NRD_MAX_RELATIVE_HEIGHT`.
 ///
 pub fn kernel_sig_msg(
 	fee: u64,
@@ -1452,6 +1553,11 @@ pub fn kernel_sig_msg(
 		KernelFeatures::Coinbase => fee == 0 && lock_height == 0,
 		KernelFeatures::Plain => lock_height == 0,
 		KernelFeatures::HeightLocked => true,
+		KernelFeatures::NoRecentDuplicate => {
+			lock_height > 0 && lock_height <= consensus::NRD_MAX_RELATIVE_HEIGHT
+		}
+		// signed along with the kernel they reference
+		KernelFeatures::RelativeHeightLocked => false,
 	};
 	if !valid_features {
 		return Err(Error::InvalidKernelFeatures);
@@ -1459,11 +1565,34 @@ pub fn kernel_sig_msg(
 	let hash = match features {
 		KernelFeatures::Coinbase => (features).hash(),
 		KernelFeatures::Plain => (features, fee).hash(),
-		KernelFeatures::HeightLocked => (features, fee, lock_height).hash(),
+		KernelFeatures::HeightLocked | KernelFeatures::NoRecentDuplicate => {
+			(features, fee, lock_height).hash()
+		}
+		KernelFeatures::RelativeHeightLocked => unreachable!(),
 	};
 	Ok(secp::Message::from_slice(&hash.as_bytes())?)
 }
 
+/// Construct msg from tx fee, relative height and the excess of the kernel
+/// a relative height locked kernel references.
+///
+/// msg = hash(features || fee || relative_height || rel_kernel)
+///
+/// The relative height must be at least 1.
+///
+pub fn relative_kernel_sig_msg(
+	fee: u64,
+	relative_height: u64,
+	rel_kernel: &Commitment,
+) -> Result<secp::Message, Error> {
+	if relative_height == 0 {
+		return Err(Error::InvalidKernelFeatures);
+	}
+	let features = KernelFeatures::RelativeHeightLocked;
+	let hash = (features, fee, relative_height, rel_kernel).hash();
+	Ok(secp::Message::from_slice(&hash.as_bytes())?)
+}
+
 /// kernel features as determined by lock height
 pub fn kernel_features(lock_height: u64) -> KernelFeatures {
 	if lock_height > 0 {
@@ -1493,6 +1622,7 @@ mod test {
 		let kernel = TxKernel {
 			features: KernelFeatures::Plain,
 			lock_height: 0,
+			rel_kernel: None,
 			excess: commit,
 			excess_sig: sig.clone(),
 			fee: 10,
@@ -1511,6 +1641,7 @@ mod test {
 		let kernel = TxKernel {
 			features: KernelFeatures::HeightLocked,
 			lock_height: 100,
+			rel_kernel: None,
 			excess: commit,
 			excess_sig: sig.clone(),
 			fee: 10,
@@ -1524,6 +1655,74 @@ mod test {
 		assert_eq!(kernel2.excess, commit);
 		assert_eq!(kernel2.excess_sig, sig.clone());
 		assert_eq!(kernel2.fee, 10);
+
+		// and a no recent duplicate kernel with its relative height
+		let kernel = TxKernel::empty().with_fee(10).with_relative_height(1440);
+
+		let mut vec = vec![];
+		ser::serialize(&mut vec, &kernel).expect("serialized failed");
+		let kernel2: TxKernel = ser::deserialize(&mut &vec[..]).unwrap();
+		assert_eq!(kernel2.features, KernelFeatures::NoRecentDuplicate);
+		assert_eq!(kernel2.lock_height, 1440);
+		assert_eq!(kernel2.fee, 10);
+		assert_eq!(kernel2.rel_kernel, None);
+
+		// and a relative height locked kernel with the excess it references
+		let kernel = TxKernel::empty().with_fee(10);
+		let kernel = kernel.with_relative_lock(60, commit);
+
+		let mut vec = vec![];
+		ser::serialize(&mut vec, &kernel).expect("serialized failed");
+		let kernel2: TxKernel = ser::deserialize(&mut &vec[..]).unwrap();
+		assert_eq!(kernel2.features, KernelFeatures::RelativeHeightLocked);
+		assert_eq!(kernel2.lock_height, 60);
+		assert_eq!(kernel2.rel_kernel, Some(commit));
+		assert_eq!(kernel2.fee, 10);
+
+		// which can't be written without it
+		let kernel = TxKernel {
+			rel_kernel: None,
+			..kernel
+		};
+		assert!(ser::serialize(&mut vec![], &kernel).is_err());
+	}
+
+	#[test]
+	fn no_recent_duplicate_sig_msg() {
+		let features = KernelFeatures::NoRecentDuplicate;
+		assert!(kernel_sig_msg(10, 0, features).is_err());
+		assert!(kernel_sig_msg(10, consensus::NRD_MAX_RELATIVE_HEIGHT + 1, features).is_err());
+
+		// signs the relative height, unlike a plain kernel
+		let msg = kernel_sig_msg(10, 1440, features).unwrap();
+		assert_ne!(msg, kernel_sig_msg(10, 0, KernelFeatures::Plain).unwrap());
+		let height_locked = KernelFeatures::HeightLocked;
+		assert_ne!(msg, kernel_sig_msg(10, 1440, height_locked).unwrap());
+		assert_ne!(msg, kernel_sig_msg(10, 1441, features).unwrap());
+	}
+
+	#[test]
+	fn relative_height_locked_sig_msg() {
+		let keychain = ExtKeychain::from_random_seed(false).unwrap();
+		let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+		let commit = keychain.commit(0, &key_id).unwrap();
+		let key_id = ExtKeychain::derive_key_id(1, 2, 0, 0, 0);
+		let other_commit = keychain.commit(0, &key_id).unwrap();
+
+		let features = KernelFeatures::RelativeHeightLocked;
+		assert!(kernel_sig_msg(10, 60, features).is_err());
+		assert!(relative_kernel_sig_msg(10, 0, &commit).is_err());
+
+		// signs the referenced kernel along with the relative height
+		let msg = relative_kernel_sig_msg(10, 60, &commit).unwrap();
+		let height_locked = KernelFeatures::HeightLocked;
+		assert_ne!(msg, kernel_sig_msg(10, 60, height_locked).unwrap());
+		assert_ne!(msg, relative_kernel_sig_msg(10, 61, &commit).unwrap());
+		assert_ne!(msg, relative_kernel_sig_msg(10, 60, &other_commit).unwrap());
+
+		let kernel = TxKernel::empty().with_fee(10);
+		let kernel = kernel.with_relative_lock(60, commit);
+		assert_eq!(kernel.msg_to_sign().unwrap(), msg);
 	}
 
 	#[test]
@@ -1581,8 +1780,14 @@ mod test {
 		let features = KernelFeatures::from_u8(2).unwrap();
 		assert_eq!(features, KernelFeatures::HeightLocked);
 
+		let features = KernelFeatures::from_u8(3).unwrap();
+		assert_eq!(features, KernelFeatures::NoRecentDuplicate);
+
+		let features = KernelFeatures::from_u8(4).unwrap();
+		assert_eq!(features, KernelFeatures::RelativeHeightLocked);
+
 		// Verify we cannot deserialize an unexpected kernel feature
-		let features = KernelFeatures::from_u8(3);
+		let features = KernelFeatures::from_u8(5);
 		assert_eq!(features, None);
 	}
 }
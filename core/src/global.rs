@@ -165,6 +165,10 @@ lazy_static! {
 	pub static ref EPIC_VERSION : RwLock<Option<Version>> =
 			RwLock::new(None);
 
+	/// Store the outcome of the last check of our version against the allowed one
+	pub static ref VERSION_CHECK : RwLock<VersionCheck> =
+			RwLock::new(VersionCheck::default());
+
 	/// Store the timeout for the header sync
 	pub static ref HEADER_SYNC_TIMEOUT : RwLock<i64> =
 			RwLock::new(10);
@@ -217,6 +221,18 @@ pub fn get_epic_version() -> Option<Version> {
 	epic_version.clone()
 }
 
+/// Set the outcome of the last version check
+pub fn set_version_check(check: VersionCheck) {
+	let mut version_check = VERSION_CHECK.write();
+	*version_check = check;
+}
+
+/// Get the outcome of the last version check
+pub fn get_version_check() -> VersionCheck {
+	let version_check = VERSION_CHECK.read();
+	version_check.clone()
+}
+
 /// Set the path to the foundation.json file (file with the foundation wallet outputs/kernels)
 pub fn set_foundation_path(path: String) {
 	let mut foundation_path = FOUNDATION_FILE.write();
@@ -580,22 +596,62 @@ impl Version {
 	}
 }
 
+/// Outcome of checking our version against the allowed one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VersionCheckStatus {
+	/// No check completed yet (or checks disabled)
+	NotChecked,
+	/// Our version is allowed
+	UpToDate,
+	/// Our version is older than the allowed one
+	Outdated,
+	/// The version source could not be reached or gave an invalid answer
+	Unavailable,
+}
+
+/// Result of the last version check
+#[derive(Debug, Clone)]
+pub struct VersionCheck {
+	/// Description of the source the allowed version was taken from
+	pub source: String,
+	/// Outcome of the check
+	pub status: VersionCheckStatus,
+	/// Allowed version, if the source answered
+	pub allowed_version: Option<Version>,
+	/// Timestamp of the last check
+	pub last_checked: Option<i64>,
+	/// Why the source could not be used, if it couldn't
+	pub error: Option<String>,
+}
+
+impl Default for VersionCheck {
+	fn default() -> VersionCheck {
+		VersionCheck {
+			source: "none".to_string(),
+			status: VersionCheckStatus::NotChecked,
+			allowed_version: None,
+			last_checked: None,
+			error: None,
+		}
+	}
+}
+
 pub fn get_file_sha256(path: &str) -> String {
-	let mut file = File::open(path).expect(
+	try_get_file_sha256(path).expect(
 		format!(
 			"Error trying to read the foundation.json. Couldn't find/open the file {}!",
 			path
 		)
 		.as_str(),
-	);
+	)
+}
+
+/// Sha256 of the file at the provided path, as a hex string. Fails if the
+/// file can't be read.
+pub fn try_get_file_sha256(path: &str) -> std::io::Result<String> {
+	let mut file = File::open(path)?;
 	let mut sha256 = Sha256::new();
-	std::io::copy(&mut file, &mut sha256).expect(
-		format!(
-			"Error trying to read the foundation.json. Couldn't find/open the file {}!",
-			path
-		)
-		.as_str(),
-	);
+	std::io::copy(&mut file, &mut sha256)?;
 	let hash = sha256.result();
-	format!("{:x}", hash)
+	Ok(format!("{:x}", hash))
 }
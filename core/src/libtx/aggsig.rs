@@ -461,3 +461,132 @@ pub fn sign_with_blinding(
 	let sig = aggsig::sign_single(secp, &msg, skey, None, None, None, pubkey_sum, None)?;
 	Ok(sig)
 }
+
+/// Calculates an adaptor partial signature, which only adds up to a valid
+/// signature once the secret of the adaptor point is added to the sum of the
+/// partial signatures. All the signers must sign with the adaptor point
+/// added to their nonce sum, it's done here so that the nonce sum is the one
+/// of [`calculate_partial_sig`](fn.calculate_partial_sig.html).
+///
+/// # Arguments
+///
+/// * `secp` - A Secp256k1 Context initialized for Signing
+/// * `sec_key` - The signer's secret key
+/// * `sec_nonce` - The signer's secret nonce
+/// * `nonce_sum` - The sum of the public nonces of all signers, without the
+/// adaptor point.
+/// * `adaptor_point` - The public key of the adaptor secret.
+/// * `pubkey_sum` - (Optional) The sum of the public keys of all signers.
+/// * `msg` - The message to sign.
+pub fn calculate_adaptor_partial_sig(
+	secp: &Secp256k1,
+	sec_key: &SecretKey,
+	sec_nonce: &SecretKey,
+	nonce_sum: &PublicKey,
+	adaptor_point: &PublicKey,
+	pubkey_sum: Option<&PublicKey>,
+	msg: &secp::Message,
+) -> Result<Signature, Error> {
+	let nonce_sum = PublicKey::from_combination(secp, vec![nonce_sum, adaptor_point])?;
+	calculate_partial_sig(secp, sec_key, sec_nonce, &nonce_sum, pubkey_sum, msg)
+}
+
+/// Verifies an adaptor partial signature from a public key, with the same
+/// nonce sum, adaptor point and public key sum it was created with.
+pub fn verify_adaptor_partial_sig(
+	secp: &Secp256k1,
+	sig: &Signature,
+	nonce_sum: &PublicKey,
+	adaptor_point: &PublicKey,
+	pubkey: &PublicKey,
+	pubkey_sum: Option<&PublicKey>,
+	msg: &secp::Message,
+) -> Result<(), Error> {
+	let nonce_sum = PublicKey::from_combination(secp, vec![nonce_sum, adaptor_point])?;
+	verify_partial_sig(secp, sig, &nonce_sum, pubkey, pubkey_sum, msg)
+}
+
+/// Completes the adaptor partial signatures of all the signers into the
+/// final signature, with the adaptor secret. The signature is verified
+/// against the public key sum, so it can go in a kernel as is.
+///
+/// # Arguments
+///
+/// * `secp` - A Secp256k1 Context initialized for Signing and Verification
+/// * `part_sigs` - The adaptor partial signatures of all the signers
+/// * `nonce_sum` - The sum of the public nonces of all signers, without the
+/// adaptor point.
+/// * `adaptor_secret` - The secret of the adaptor point.
+/// * `pubkey_sum` - The sum of the public keys of all signers.
+/// * `msg` - The signed message.
+pub fn complete_adaptor_sig(
+	secp: &Secp256k1,
+	part_sigs: Vec<&Signature>,
+	nonce_sum: &PublicKey,
+	adaptor_secret: &SecretKey,
+	pubkey_sum: &PublicKey,
+	msg: &secp::Message,
+) -> Result<Signature, Error> {
+	let adaptor_point = PublicKey::from_secret_key(secp, adaptor_secret)?;
+	let nonce_sum = PublicKey::from_combination(secp, vec![nonce_sum, &adaptor_point])?;
+	let sig = add_signatures(secp, part_sigs, &nonce_sum)?;
+	let s = sig_scalar(secp, &sig)?;
+
+	// Signers negate their nonce when the nonce sum has no quadratic residue
+	// y, the secret then has to be negated too.
+	let candidates = vec![
+		secp.blind_sum(vec![s.clone(), adaptor_secret.clone()], vec![])?,
+		secp.blind_sum(vec![s], vec![adaptor_secret.clone()])?,
+	];
+	for s in candidates {
+		let sig = with_sig_scalar(&sig, &s)?;
+		if verify_single(secp, &sig, msg, None, pubkey_sum, Some(pubkey_sum), false) {
+			return Ok(sig);
+		}
+	}
+	Err(ErrorKind::Signature("Adaptor signature completion error".to_string()).into())
+}
+
+/// Extracts the adaptor secret from a final signature, provided the adaptor
+/// partial signatures it was completed from.
+///
+/// # Arguments
+///
+/// * `secp` - A Secp256k1 Context initialized for Signing
+/// * `sig` - The final signature, as found in a kernel
+/// * `part_sigs` - The adaptor partial signatures of all the signers
+/// * `nonce_sum` - The sum of the public nonces of all signers, without the
+/// adaptor point.
+/// * `adaptor_point` - The public key of the adaptor secret.
+pub fn extract_adaptor_secret(
+	secp: &Secp256k1,
+	sig: &Signature,
+	part_sigs: Vec<&Signature>,
+	nonce_sum: &PublicKey,
+	adaptor_point: &PublicKey,
+) -> Result<SecretKey, Error> {
+	let nonce_sum = PublicKey::from_combination(secp, vec![nonce_sum, adaptor_point])?;
+	let adaptor_sig = add_signatures(secp, part_sigs, &nonce_sum)?;
+	let diff = secp.blind_sum(
+		vec![sig_scalar(secp, sig)?],
+		vec![sig_scalar(secp, &adaptor_sig)?],
+	)?;
+	let candidates = vec![diff.clone(), secp.blind_sum(vec![], vec![diff])?];
+	for secret in candidates {
+		if PublicKey::from_secret_key(secp, &secret)? == *adaptor_point {
+			return Ok(secret);
+		}
+	}
+	Err(ErrorKind::Signature("Adaptor secret extraction error".to_string()).into())
+}
+
+// The s part of a signature, the first 32 bytes being the nonce x coordinate.
+fn sig_scalar(secp: &Secp256k1, sig: &Signature) -> Result<SecretKey, Error> {
+	Ok(SecretKey::from_slice(secp, &sig.to_raw_data()[32..])?)
+}
+
+fn with_sig_scalar(sig: &Signature, s: &SecretKey) -> Result<Signature, Error> {
+	let mut raw = sig.to_raw_data();
+	raw[32..].copy_from_slice(&s.0);
+	Ok(Signature::from_raw_data(&raw)?)
+}
@@ -28,6 +28,7 @@
 use crate::core::{Input, Output, OutputFeatures, Transaction, TxKernel};
 use crate::keychain::{BlindSum, BlindingFactor, Identifier, Keychain};
 use crate::libtx::{aggsig, proof, Error};
+use crate::util::secp::pedersen::Commitment;
 
 /// Context information available to transaction combinators.
 pub struct Context<'a, K>
@@ -137,6 +138,40 @@ where
 	)
 }
 
+/// Makes the kernel of the transaction being built a no recent duplicate
+/// one, with the provided relative height. The transaction then can't be
+/// included in a block less than relative_height blocks after another one
+/// with the same kernel excess.
+pub fn with_relative_height<K>(relative_height: u64) -> Box<Append<K>>
+where
+	K: Keychain,
+{
+	Box::new(
+		move |_build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
+			(tx, kern.with_relative_height(relative_height), sum)
+		},
+	)
+}
+
+/// Makes the kernel of the transaction being built a relative height locked
+/// one. The transaction then can't be included in a block less than
+/// relative_height blocks after the one holding the kernel with the provided
+/// excess.
+pub fn with_relative_lock<K>(relative_height: u64, rel_kernel: Commitment) -> Box<Append<K>>
+where
+	K: Keychain,
+{
+	Box::new(
+		move |_build, (tx, kern, sum)| -> (Transaction, TxKernel, BlindSum) {
+			(
+				tx,
+				kern.with_relative_lock(relative_height, rel_kernel),
+				sum,
+			)
+		},
+	)
+}
+
 /// Adds a known excess value on the transaction being built. Usually used in
 /// combination with the initial_tx function when a new transaction is built
 /// by adding to a pre-existing one.
@@ -327,4 +362,54 @@ mod test {
 
 		tx.validate(Weighting::AsTransaction, vc.clone()).unwrap();
 	}
+	#[test]
+	fn blind_no_recent_duplicate_tx() {
+		let keychain = ExtKeychain::from_random_seed(false).unwrap();
+		let key_id1 = ExtKeychainPath::new(1, 1, 0, 0, 0).to_identifier();
+		let key_id2 = ExtKeychainPath::new(1, 2, 0, 0, 0).to_identifier();
+
+		let vc = verifier_cache();
+
+		let tx = transaction(
+			vec![
+				input(6, key_id1),
+				output(2, key_id2),
+				with_fee(4),
+				with_relative_height(1440),
+			],
+			&keychain,
+		)
+		.unwrap();
+
+		tx.validate(Weighting::AsTransaction, vc.clone()).unwrap();
+		assert!(tx.kernels()[0].is_no_recent_duplicate());
+		// the relative height is no lock height
+		assert_eq!(tx.lock_height(), 0);
+	}
+
+	#[test]
+	fn blind_relative_height_locked_tx() {
+		let keychain = ExtKeychain::from_random_seed(false).unwrap();
+		let key_id1 = ExtKeychainPath::new(1, 1, 0, 0, 0).to_identifier();
+		let key_id2 = ExtKeychainPath::new(1, 2, 0, 0, 0).to_identifier();
+		let rel_kernel = keychain.commit(0, &key_id1).unwrap();
+
+		let vc = verifier_cache();
+
+		let tx = transaction(
+			vec![
+				input(6, key_id1),
+				output(2, key_id2),
+				with_fee(4),
+				with_relative_lock(60, rel_kernel),
+			],
+			&keychain,
+		)
+		.unwrap();
+
+		tx.validate(Weighting::AsTransaction, vc.clone()).unwrap();
+		assert_eq!(tx.kernels()[0].rel_kernel, Some(rel_kernel));
+		// the relative height is no lock height
+		assert_eq!(tx.lock_height(), 0);
+	}
 }
@@ -44,6 +44,9 @@ pub enum ErrorKind {
 	/// Rangeproof error
 	#[fail(display = "Rangeproof Error")]
 	RangeProof(String),
+	/// Multi-party session error
+	#[fail(display = "Session Error: {}", _0)]
+	Session(String),
 }
 
 impl Fail for Error {
@@ -24,6 +24,8 @@
 pub mod aggsig;
 pub mod build;
 mod error;
+pub mod multisig;
+pub mod payment_proof;
 pub mod proof;
 pub mod reward;
 pub mod secp_ser;
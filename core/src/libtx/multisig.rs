@@ -0,0 +1,493 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Multi-party (n-of-n) signing of transaction kernels and building of
+//! outputs owned jointly by all parties. Every participant runs its own
+//! session, numbered from 0, and sends the messages of each round to all the
+//! others. Messages serialize to JSON so parties running other software can
+//! take part.
+//!
+//! Signing a kernel takes two rounds, after which any participant can
+//! assemble the signed kernel:
+//!   1. public nonce and public excess of each participant
+//!   2. partial signature of each participant, verified on receipt
+//!
+//! Building a shared output takes three rounds, after which any participant
+//! can assemble the output and its bulletproof:
+//!   1. commitment to the blinding share and proof nonce share of each
+//!      participant
+//!   2. bulletproof t_one and t_two keys of each participant
+//!   3. bulletproof tau_x share of each participant
+//!
+//! Nonce shares and tau_x shares must only go to the other participants, the
+//! rest of the messages can be public.
+
+use std::collections::BTreeMap;
+
+use rand::thread_rng;
+
+use crate::core::{Output, OutputFeatures, TxKernel};
+use crate::keychain::BlindingFactor;
+use crate::libtx::error::{Error, ErrorKind};
+use crate::libtx::{aggsig, proof, secp_ser};
+use crate::util::secp::key::{PublicKey, SecretKey};
+use crate::util::secp::pedersen::Commitment;
+use crate::util::secp::{Secp256k1, Signature};
+
+/// First round of kernel signing, the public nonce and public excess of a
+/// participant.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignRound1 {
+	/// Participant sending it
+	pub id: usize,
+	/// Public version of the secret nonce of the participant
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub public_nonce: PublicKey,
+	/// Commitment to zero with the excess share of the participant
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::commitment_from_hex"
+	)]
+	pub public_excess: Commitment,
+}
+
+/// Second round of kernel signing, the partial signature of a participant.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignRound2 {
+	/// Participant sending it
+	pub id: usize,
+	/// Partial signature of the kernel message
+	#[serde(with = "secp_ser::sig_serde")]
+	pub part_sig: Signature,
+}
+
+/// Session of a participant signing a kernel along with all the others.
+pub struct SignSession {
+	kernel: TxKernel,
+	participants: usize,
+	id: usize,
+	sec_key: SecretKey,
+	sec_nonce: SecretKey,
+	round1: BTreeMap<usize, SignRound1>,
+	round2: BTreeMap<usize, SignRound2>,
+}
+
+impl SignSession {
+	/// Start signing the provided kernel, its features, fee and lock height
+	/// already set, as participant id of the given number of participants.
+	/// The secret key is the share of the kernel excess of the participant.
+	pub fn new(
+		secp: &Secp256k1,
+		kernel: TxKernel,
+		participants: usize,
+		id: usize,
+		sec_key: SecretKey,
+	) -> Result<SignSession, Error> {
+		check_participant(participants, id)?;
+		let mut session = SignSession {
+			kernel,
+			participants,
+			id,
+			sec_key,
+			sec_nonce: aggsig::create_secnonce(secp)?,
+			round1: BTreeMap::new(),
+			round2: BTreeMap::new(),
+		};
+		let round1 = session.round1(secp)?;
+		session.add_round1(round1)?;
+		Ok(session)
+	}
+
+	/// Our first round message.
+	pub fn round1(&self, secp: &Secp256k1) -> Result<SignRound1, Error> {
+		Ok(SignRound1 {
+			id: self.id,
+			public_nonce: PublicKey::from_secret_key(secp, &self.sec_nonce)?,
+			public_excess: secp.commit(0, self.sec_key.clone())?,
+		})
+	}
+
+	/// Record the first round message of a participant.
+	pub fn add_round1(&mut self, msg: SignRound1) -> Result<(), Error> {
+		let id = msg.id;
+		collect(&mut self.round1, self.participants, id, msg)
+	}
+
+	/// Our second round message, our partial signature, once we have the
+	/// first round messages of all the participants.
+	pub fn round2(&mut self, secp: &Secp256k1) -> Result<SignRound2, Error> {
+		let (nonce_sum, excess_sum) = self.sums(secp)?;
+		let part_sig = aggsig::calculate_partial_sig(
+			secp,
+			&self.sec_key,
+			&self.sec_nonce,
+			&nonce_sum,
+			Some(&excess_sum),
+			&self.kernel.msg_to_sign()?,
+		)?;
+		let msg = SignRound2 {
+			id: self.id,
+			part_sig,
+		};
+		collect(&mut self.round2, self.participants, self.id, msg.clone())?;
+		Ok(msg)
+	}
+
+	/// Verify and record the second round message of a participant.
+	pub fn add_round2(&mut self, secp: &Secp256k1, msg: SignRound2) -> Result<(), Error> {
+		check_participant(self.participants, msg.id)?;
+		let (nonce_sum, excess_sum) = self.sums(secp)?;
+		let pubkey = self.round1[&msg.id].public_excess.to_pubkey(secp)?;
+		aggsig::verify_partial_sig(
+			secp,
+			&msg.part_sig,
+			&nonce_sum,
+			&pubkey,
+			Some(&excess_sum),
+			&self.kernel.msg_to_sign()?,
+		)?;
+		collect(&mut self.round2, self.participants, msg.id, msg)
+	}
+
+	/// The kernel with its excess and signature, once we have the second
+	/// round messages of all the participants.
+	pub fn finalize(&self, secp: &Secp256k1) -> Result<TxKernel, Error> {
+		let (nonce_sum, excess_sum) = self.sums(secp)?;
+		let part_sigs = complete(&self.round2, self.participants, 2)?
+			.into_iter()
+			.map(|msg| &msg.part_sig)
+			.collect();
+		let sig = aggsig::add_signatures(secp, part_sigs, &nonce_sum)?;
+		aggsig::verify_completed_sig(
+			secp,
+			&sig,
+			&excess_sum,
+			Some(&excess_sum),
+			&self.kernel.msg_to_sign()?,
+		)?;
+
+		let mut kernel = self.kernel.clone();
+		kernel.excess = self.excess(secp)?;
+		kernel.excess_sig = sig;
+		Ok(kernel)
+	}
+
+	// Sum of the public excesses of all the participants, the kernel excess.
+	fn excess(&self, secp: &Secp256k1) -> Result<Commitment, Error> {
+		let excesses = complete(&self.round1, self.participants, 1)?
+			.into_iter()
+			.map(|msg| msg.public_excess)
+			.collect();
+		Ok(secp.commit_sum(excesses, vec![])?)
+	}
+
+	// Sums of the public nonces and of the public excesses, as a public key.
+	fn sums(&self, secp: &Secp256k1) -> Result<(PublicKey, PublicKey), Error> {
+		let nonces = complete(&self.round1, self.participants, 1)?
+			.into_iter()
+			.map(|msg| &msg.public_nonce)
+			.collect();
+		let nonce_sum = PublicKey::from_combination(secp, nonces)?;
+		let excess_sum = self.excess(secp)?.to_pubkey(secp)?;
+		Ok((nonce_sum, excess_sum))
+	}
+}
+
+/// First round of shared output building, the commitment to the blinding
+/// share of a participant and its share of the bulletproof nonce.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutputRound1 {
+	/// Participant sending it
+	pub id: usize,
+	/// Commitment to zero with the blinding share of the participant
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::commitment_from_hex"
+	)]
+	pub commit: Commitment,
+	/// Share of the nonce the bulletproof is built with
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::blind_from_hex"
+	)]
+	pub nonce_share: BlindingFactor,
+}
+
+/// Second round of shared output building, the bulletproof t_one and t_two
+/// keys of a participant.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutputRound2 {
+	/// Participant sending it
+	pub id: usize,
+	/// Bulletproof t_one key of the participant
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub t_one: PublicKey,
+	/// Bulletproof t_two key of the participant
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub t_two: PublicKey,
+}
+
+/// Third round of shared output building, the bulletproof tau_x share of a
+/// participant.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutputRound3 {
+	/// Participant sending it
+	pub id: usize,
+	/// Bulletproof tau_x share of the participant
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::blind_from_hex"
+	)]
+	pub tau_x: BlindingFactor,
+}
+
+/// Session of a participant building an output of known value, blinded by
+/// the sum of the blinding shares of all the participants, along with its
+/// bulletproof.
+pub struct OutputSession {
+	value: u64,
+	participants: usize,
+	id: usize,
+	blind: SecretKey,
+	nonce_share: SecretKey,
+	private_nonce: SecretKey,
+	round1: BTreeMap<usize, OutputRound1>,
+	round2: BTreeMap<usize, OutputRound2>,
+	round3: BTreeMap<usize, OutputRound3>,
+}
+
+impl OutputSession {
+	/// Start building an output of the provided value, as participant id of
+	/// the given number of participants, with our blinding share.
+	pub fn new(
+		secp: &Secp256k1,
+		value: u64,
+		participants: usize,
+		id: usize,
+		blind: SecretKey,
+	) -> Result<OutputSession, Error> {
+		check_participant(participants, id)?;
+		let mut session = OutputSession {
+			value,
+			participants,
+			id,
+			blind,
+			nonce_share: SecretKey::new(secp, &mut thread_rng()),
+			private_nonce: SecretKey::new(secp, &mut thread_rng()),
+			round1: BTreeMap::new(),
+			round2: BTreeMap::new(),
+			round3: BTreeMap::new(),
+		};
+		let round1 = session.round1(secp)?;
+		session.add_round1(round1)?;
+		Ok(session)
+	}
+
+	/// Our first round message.
+	pub fn round1(&self, secp: &Secp256k1) -> Result<OutputRound1, Error> {
+		Ok(OutputRound1 {
+			id: self.id,
+			commit: secp.commit(0, self.blind.clone())?,
+			nonce_share: BlindingFactor::from_secret_key(self.nonce_share.clone()),
+		})
+	}
+
+	/// Record the first round message of a participant.
+	pub fn add_round1(&mut self, msg: OutputRound1) -> Result<(), Error> {
+		let id = msg.id;
+		collect(&mut self.round1, self.participants, id, msg)
+	}
+
+	/// Our second round message, once we have the first round messages of
+	/// all the participants.
+	pub fn round2(&mut self, secp: &Secp256k1) -> Result<OutputRound2, Error> {
+		let commit = self.commit(secp)?;
+		let mut t_one = PublicKey::new();
+		let mut t_two = PublicKey::new();
+		secp.bullet_proof_multisig(
+			self.value,
+			self.blind.clone(),
+			self.common_nonce(secp)?,
+			None,
+			None,
+			None,
+			Some(&mut t_one),
+			Some(&mut t_two),
+			vec![commit],
+			Some(&self.private_nonce),
+			1,
+		);
+		let msg = OutputRound2 {
+			id: self.id,
+			t_one,
+			t_two,
+		};
+		collect(&mut self.round2, self.participants, self.id, msg.clone())?;
+		Ok(msg)
+	}
+
+	/// Record the second round message of a participant.
+	pub fn add_round2(&mut self, msg: OutputRound2) -> Result<(), Error> {
+		let id = msg.id;
+		collect(&mut self.round2, self.participants, id, msg)
+	}
+
+	/// Our third round message, once we have the second round messages of
+	/// all the participants.
+	pub fn round3(&mut self, secp: &Secp256k1) -> Result<OutputRound3, Error> {
+		let commit = self.commit(secp)?;
+		let (mut t_one, mut t_two) = self.t_sums(secp)?;
+		let mut tau_x = SecretKey::new(secp, &mut thread_rng());
+		secp.bullet_proof_multisig(
+			self.value,
+			self.blind.clone(),
+			self.common_nonce(secp)?,
+			None,
+			None,
+			Some(&mut tau_x),
+			Some(&mut t_one),
+			Some(&mut t_two),
+			vec![commit],
+			Some(&self.private_nonce),
+			2,
+		);
+		let msg = OutputRound3 {
+			id: self.id,
+			tau_x: BlindingFactor::from_secret_key(tau_x),
+		};
+		collect(&mut self.round3, self.participants, self.id, msg.clone())?;
+		Ok(msg)
+	}
+
+	/// Record the third round message of a participant.
+	pub fn add_round3(&mut self, msg: OutputRound3) -> Result<(), Error> {
+		let id = msg.id;
+		collect(&mut self.round3, self.participants, id, msg)
+	}
+
+	/// The shared output with its bulletproof, once we have the third round
+	/// messages of all the participants.
+	pub fn finalize(&self, secp: &Secp256k1) -> Result<Output, Error> {
+		let commit = self.commit(secp)?;
+		let (mut t_one, mut t_two) = self.t_sums(secp)?;
+		let tau_xs = complete(&self.round3, self.participants, 3)?
+			.into_iter()
+			.map(|msg| msg.tau_x.secret_key(secp))
+			.collect::<Result<Vec<_>, _>>()?;
+		let mut tau_x = secp.blind_sum(tau_xs, vec![])?;
+		let proof = secp
+			.bullet_proof_multisig(
+				self.value,
+				self.blind.clone(),
+				self.common_nonce(secp)?,
+				None,
+				None,
+				Some(&mut tau_x),
+				Some(&mut t_one),
+				Some(&mut t_two),
+				vec![commit],
+				Some(&self.private_nonce),
+				0,
+			)
+			.ok_or_else(|| ErrorKind::RangeProof("multisig bulletproof failed".to_string()))?;
+		proof::verify(secp, commit, proof, None)
+			.map_err(|e| ErrorKind::RangeProof(e.to_string()))?;
+
+		Ok(Output {
+			features: OutputFeatures::Plain,
+			commit,
+			proof,
+		})
+	}
+
+	/// Commitment of the shared output, once we have the first round
+	/// messages of all the participants.
+	pub fn commit(&self, secp: &Secp256k1) -> Result<Commitment, Error> {
+		let mut commits = complete(&self.round1, self.participants, 1)?
+			.into_iter()
+			.map(|msg| msg.commit)
+			.collect::<Vec<_>>();
+		commits.push(secp.commit_value(self.value)?);
+		Ok(secp.commit_sum(commits, vec![])?)
+	}
+
+	// Nonce the bulletproof is built with, the sum of the nonce shares.
+	fn common_nonce(&self, secp: &Secp256k1) -> Result<SecretKey, Error> {
+		let shares = complete(&self.round1, self.participants, 1)?
+			.into_iter()
+			.map(|msg| msg.nonce_share.secret_key(secp))
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(secp.blind_sum(shares, vec![])?)
+	}
+
+	// Sums of the t_one and t_two keys of all the participants.
+	fn t_sums(&self, secp: &Secp256k1) -> Result<(PublicKey, PublicKey), Error> {
+		let round2 = complete(&self.round2, self.participants, 2)?;
+		let t_one = PublicKey::from_combination(secp, round2.iter().map(|m| &m.t_one).collect())?;
+		let t_two = PublicKey::from_combination(secp, round2.iter().map(|m| &m.t_two).collect())?;
+		Ok((t_one, t_two))
+	}
+}
+
+fn check_participant(participants: usize, id: usize) -> Result<(), Error> {
+	if id >= participants {
+		return Err(ErrorKind::Session(format!(
+			"Participant {} out of {} participants",
+			id, participants
+		))
+		.into());
+	}
+	Ok(())
+}
+
+// Records the message of a participant for a round. A participant can send
+// the same message again, but no other one.
+fn collect<T>(
+	msgs: &mut BTreeMap<usize, T>,
+	participants: usize,
+	id: usize,
+	msg: T,
+) -> Result<(), Error>
+where
+	T: PartialEq,
+{
+	check_participant(participants, id)?;
+	match msgs.get(&id) {
+		Some(prev) if *prev != msg => {
+			Err(ErrorKind::Session(format!("Conflicting messages from participant {}", id)).into())
+		}
+		_ => {
+			msgs.insert(id, msg);
+			Ok(())
+		}
+	}
+}
+
+// The messages of a round, once we have the ones of all the participants.
+fn complete<T>(
+	msgs: &BTreeMap<usize, T>,
+	participants: usize,
+	round: u8,
+) -> Result<Vec<&T>, Error> {
+	if msgs.len() < participants {
+		return Err(ErrorKind::Session(format!(
+			"Round {} incomplete, {} of {} participants",
+			round,
+			msgs.len(),
+			participants
+		))
+		.into());
+	}
+	Ok(msgs.values().collect())
+}
@@ -0,0 +1,149 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Payment proofs. The recipient of a payment signs the amount received,
+//! the excess of the transaction kernel and the address of the sender. The
+//! sender can then show anyone it paid the recipient, the kernel being on
+//! chain telling the transaction went through.
+
+use crate::core::hash::Hashed;
+use crate::keychain::{Identifier, Keychain};
+use crate::libtx::error::{Error, ErrorKind};
+use crate::libtx::{aggsig, secp_ser};
+use crate::util::secp::key::PublicKey;
+use crate::util::secp::pedersen::Commitment;
+use crate::util::secp::{self, Secp256k1, Signature};
+
+/// Receipt of a payment, signed by its recipient.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaymentProof {
+	/// Amount received
+	pub amount: u64,
+	/// Excess of the kernel of the payment transaction
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::commitment_from_hex"
+	)]
+	pub excess: Commitment,
+	/// Address of the sender
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub sender_address: PublicKey,
+	/// Address of the recipient, the key the proof is signed with
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub recipient_address: PublicKey,
+	/// Signature of the recipient
+	#[serde(with = "secp_ser::sig_serde")]
+	pub recipient_sig: Signature,
+}
+
+impl PaymentProof {
+	/// Signs the proof of a payment as its recipient, with the key of our
+	/// address.
+	pub fn create<K>(
+		keychain: &K,
+		key_id: &Identifier,
+		amount: u64,
+		excess: Commitment,
+		sender_address: PublicKey,
+	) -> Result<PaymentProof, Error>
+	where
+		K: Keychain,
+	{
+		let secp = keychain.secp();
+		let sec_key = keychain.derive_key(0, key_id)?;
+		let recipient_address = PublicKey::from_secret_key(secp, &sec_key)?;
+		let msg = payment_proof_msg(secp, amount, &excess, &sender_address)?;
+		let recipient_sig =
+			aggsig::sign_single(secp, &msg, &sec_key, None, Some(&recipient_address))?;
+		Ok(PaymentProof {
+			amount,
+			excess,
+			sender_address,
+			recipient_address,
+			recipient_sig,
+		})
+	}
+
+	/// Checks the proof is signed by its recipient. Whether its kernel is on
+	/// chain is up to the caller.
+	pub fn verify(&self, secp: &Secp256k1) -> Result<(), Error> {
+		let msg = payment_proof_msg(secp, self.amount, &self.excess, &self.sender_address)?;
+		let addr = &self.recipient_address;
+		let sig = &self.recipient_sig;
+		if !aggsig::verify_single(secp, sig, &msg, None, addr, Some(addr), false) {
+			return Err(ErrorKind::Signature("Payment proof signature error".to_string()).into());
+		}
+		Ok(())
+	}
+}
+
+/// Address of a keychain to send or receive payments with, the public key
+/// of the provided key id.
+pub fn address<K>(keychain: &K, key_id: &Identifier) -> Result<PublicKey, Error>
+where
+	K: Keychain,
+{
+	let sec_key = keychain.derive_key(0, key_id)?;
+	Ok(PublicKey::from_secret_key(keychain.secp(), &sec_key)?)
+}
+
+/// The message the recipient of a payment signs, the amount, kernel excess
+/// and sender address.
+pub fn payment_proof_msg(
+	secp: &Secp256k1,
+	amount: u64,
+	excess: &Commitment,
+	sender_address: &PublicKey,
+) -> Result<secp::Message, Error> {
+	let sender_address = sender_address.serialize_vec(secp, true).to_vec();
+	let hash = (amount, excess, sender_address).hash();
+	Ok(secp::Message::from_slice(&hash.as_bytes())?)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::keychain::ExtKeychain;
+
+	#[test]
+	fn payment_proof_verify() {
+		let sender = ExtKeychain::from_random_seed(false).unwrap();
+		let recipient = ExtKeychain::from_random_seed(false).unwrap();
+		let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+		let sender_address = address(&sender, &key_id).unwrap();
+		let excess = recipient.commit(0, &key_id).unwrap();
+
+		let proof = PaymentProof::create(&recipient, &key_id, 500, excess, sender_address).unwrap();
+		assert_eq!(
+			proof.recipient_address,
+			address(&recipient, &key_id).unwrap()
+		);
+		proof.verify(recipient.secp()).unwrap();
+
+		let json = serde_json::to_string(&proof).unwrap();
+		let proof2: PaymentProof = serde_json::from_str(&json).unwrap();
+		assert_eq!(proof, proof2);
+
+		// any change to what's signed voids the proof
+		let mut bad_proof = proof.clone();
+		bad_proof.amount = 5_000;
+		assert!(bad_proof.verify(recipient.secp()).is_err());
+		let mut bad_proof = proof.clone();
+		bad_proof.sender_address = proof.recipient_address;
+		assert!(bad_proof.verify(recipient.secp()).is_err());
+		let mut bad_proof = proof.clone();
+		bad_proof.recipient_address = sender_address;
+		assert!(bad_proof.verify(recipient.secp()).is_err());
+	}
+}
@@ -70,6 +70,7 @@ where
 		excess_sig: sig,
 		fee: 0,
 		lock_height: 0,
+		rel_kernel: None,
 	};
 	Ok((output, proof))
 }
@@ -131,6 +132,7 @@ where
 		// lock_height here is 0
 		// *not* the maturity of the coinbase output (only spendable 1,440 blocks later)
 		lock_height: 0,
+		rel_kernel: None,
 	};
 	Ok((output, proof))
 }
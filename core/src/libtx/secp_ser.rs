@@ -96,6 +96,38 @@ pub mod option_sig_serde {
 
 }
 
+/// Serializes an Option<Commitment> to and from hex
+pub mod option_commitment_serde {
+	use crate::serde::{Deserialize, Deserializer, Serializer};
+	use crate::util::secp::pedersen::Commitment;
+	use crate::util::{from_hex, to_hex};
+	use serde::de::Error;
+
+	///
+	pub fn serialize<S>(commit: &Option<Commitment>, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		match commit {
+			Some(commit) => serializer.serialize_str(&to_hex(commit.as_ref().to_vec())),
+			None => serializer.serialize_none(),
+		}
+	}
+
+	///
+	pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Commitment>, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		Option::<String>::deserialize(deserializer).and_then(|res| match res {
+			Some(string) => from_hex(string)
+				.map_err(|err| Error::custom(err.to_string()))
+				.map(|bytes: Vec<u8>| Some(Commitment::from_vec(bytes))),
+			None => Ok(None),
+		})
+	}
+}
+
 /// Serializes a secp::Signature to and from hex
 pub mod sig_serde {
 	use crate::serde::{Deserialize, Deserializer, Serializer};
@@ -290,6 +322,8 @@ mod test {
 		pub pub_key: PublicKey,
 		#[serde(with = "option_sig_serde")]
 		pub opt_sig: Option<Signature>,
+		#[serde(with = "option_commitment_serde")]
+		pub opt_commit: Option<Commitment>,
 		#[serde(with = "sig_serde")]
 		pub sig: Signature,
 		#[serde(with = "string_or_u64")]
@@ -310,6 +344,7 @@ mod test {
 			SerTest {
 				pub_key: PublicKey::from_secret_key(&secp, &sk).unwrap(),
 				opt_sig: Some(sig.clone()),
+				opt_commit: Some(Commitment::from_vec(vec![3; 33])),
 				sig: sig.clone(),
 				num: 30,
 				opt_num: Some(33),
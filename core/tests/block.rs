@@ -14,7 +14,7 @@
 
 pub mod common;
 use crate::common::{new_block, tx1i2o, tx2i1o, txspend1i1o};
-use crate::core::consensus::BLOCK_OUTPUT_WEIGHT;
+use crate::core::consensus::{self, BLOCK_OUTPUT_WEIGHT};
 use crate::core::core::block::Error;
 use crate::core::core::hash::Hashed;
 use crate::core::core::id::ShortIdentifiable;
@@ -117,6 +117,73 @@ fn block_with_cut_through() {
 	assert_eq!(b.outputs().len(), 3);
 }
 
+#[test]
+// NoRecentDuplicate kernels are only valid in blocks from the header version
+// introducing them
+fn block_with_nrd_kernel() {
+	let keychain = ExtKeychain::from_random_seed(false).unwrap();
+	let key_id1 = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+	let key_id2 = ExtKeychain::derive_key_id(1, 2, 0, 0, 0);
+	let key_id3 = ExtKeychain::derive_key_id(1, 3, 0, 0, 0);
+
+	let tx = build::transaction(
+		vec![
+			input(7, key_id1),
+			output(5, key_id2),
+			with_fee(2),
+			build::with_relative_height(1440),
+		],
+		&keychain,
+	)
+	.unwrap();
+	let prev = BlockHeader::default();
+	let mut b = new_block(vec![&tx], &keychain, &prev, &key_id3);
+
+	b.header.version = HeaderVersion::new(consensus::NRD_HEADER_VERSION);
+	b.validate(&BlindingFactor::zero(), verifier_cache())
+		.unwrap();
+
+	b.header.version = HeaderVersion::default();
+	assert_eq!(
+		b.validate(&BlindingFactor::zero(), verifier_cache()),
+		Err(Error::NRDKernelPreHeaderVersion)
+	);
+}
+
+#[test]
+// Relative height locked kernels are only valid in blocks from the header
+// version introducing them
+fn block_with_relative_lock_kernel() {
+	let keychain = ExtKeychain::from_random_seed(false).unwrap();
+	let key_id1 = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+	let key_id2 = ExtKeychain::derive_key_id(1, 2, 0, 0, 0);
+	let key_id3 = ExtKeychain::derive_key_id(1, 3, 0, 0, 0);
+	let rel_kernel = keychain.commit(0, &key_id1).unwrap();
+
+	let tx = build::transaction(
+		vec![
+			input(7, key_id1),
+			output(5, key_id2),
+			with_fee(2),
+			build::with_relative_lock(60, rel_kernel),
+		],
+		&keychain,
+	)
+	.unwrap();
+	let prev = BlockHeader::default();
+	let mut b = new_block(vec![&tx], &keychain, &prev, &key_id3);
+
+	b.header.version = HeaderVersion::new(consensus::RELATIVE_LOCK_HEADER_VERSION);
+	b.validate(&BlindingFactor::zero(), verifier_cache())
+		.unwrap();
+
+	b.header.version = HeaderVersion::new(consensus::NRD_HEADER_VERSION);
+	assert_eq!(
+		b.validate(&BlindingFactor::zero(), verifier_cache()),
+		Err(Error::RelativeLockPreHeaderVersion)
+	);
+}
+
 #[test]
 fn empty_block_with_coinbase_is_valid() {
 	let keychain = ExtKeychain::from_random_seed(false).unwrap();
@@ -0,0 +1,38 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Kept in a test binary of its own, as it switches the global chain type
+//! back and forth.
+
+use epic_core as core;
+
+use self::core::consensus::{nrd_enabled, relative_lock_enabled};
+use self::core::global;
+
+#[test]
+fn kernel_features_schedule() {
+	// NoRecentDuplicate and relative height locked kernels not scheduled yet
+	global::set_mining_mode(global::ChainTypes::Mainnet);
+	assert!(!nrd_enabled(10));
+	assert!(!relative_lock_enabled(10));
+
+	global::set_mining_mode(global::ChainTypes::Floonet);
+	assert!(!nrd_enabled(10));
+	assert!(!relative_lock_enabled(10));
+
+	// but test chains can exercise them
+	global::set_mining_mode(global::ChainTypes::AutomatedTesting);
+	assert!(nrd_enabled(0));
+	assert!(relative_lock_enabled(10));
+}
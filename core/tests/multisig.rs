@@ -0,0 +1,146 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Multi-party signing and shared output integration tests
+
+use self::core::core::transaction::Weighting;
+use self::core::core::verifier_cache::{LruVerifierCache, VerifierCache};
+use self::core::core::{Input, OutputFeatures, Transaction, TxKernel};
+use self::core::libtx::multisig::{OutputSession, SignRound2, SignSession};
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use epic_core as core;
+use epic_keychain as keychain;
+use epic_util as util;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+// Every message goes through JSON, as it would between parties.
+fn relay<T: Serialize + DeserializeOwned>(msg: &T) -> T {
+	serde_json::from_str(&serde_json::to_string(msg).unwrap()).unwrap()
+}
+
+/// Three parties build an output they own together, funded by an input of
+/// the first one, and jointly sign the kernel of the transaction.
+#[test]
+fn three_party_shared_output() {
+	let kcs = (0..3)
+		.map(|_| ExtKeychain::from_random_seed(false).unwrap())
+		.collect::<Vec<_>>();
+	let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+
+	// funding input of the first party
+	let input_commit = kcs[0].commit(50, &key_id).unwrap();
+	let input_blind = kcs[0].derive_key(50, &key_id).unwrap();
+
+	// blinding shares of the shared output
+	let blinds = kcs
+		.iter()
+		.map(|kc| kc.derive_key(0, &ExtKeychain::derive_key_id(1, 2, 0, 0, 0)))
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+
+	let mut outs = (0..3)
+		.map(|i| OutputSession::new(kcs[i].secp(), 40, 3, i, blinds[i].clone()).unwrap())
+		.collect::<Vec<_>>();
+	let round1 = (0..3)
+		.map(|i| outs[i].round1(kcs[i].secp()).unwrap())
+		.collect::<Vec<_>>();
+	for (i, out) in outs.iter_mut().enumerate() {
+		for msg in round1.iter().filter(|msg| msg.id != i) {
+			out.add_round1(relay(msg)).unwrap();
+		}
+	}
+	let round2 = (0..3)
+		.map(|i| outs[i].round2(kcs[i].secp()).unwrap())
+		.collect::<Vec<_>>();
+	for (i, out) in outs.iter_mut().enumerate() {
+		for msg in round2.iter().filter(|msg| msg.id != i) {
+			out.add_round2(relay(msg)).unwrap();
+		}
+	}
+	let round3 = (0..3)
+		.map(|i| outs[i].round3(kcs[i].secp()).unwrap())
+		.collect::<Vec<_>>();
+	for (i, out) in outs.iter_mut().enumerate() {
+		for msg in round3.iter().filter(|msg| msg.id != i) {
+			out.add_round3(relay(msg)).unwrap();
+		}
+	}
+	let output = outs[0].finalize(kcs[0].secp()).unwrap();
+	assert_eq!(outs[2].commit(kcs[2].secp()).unwrap(), output.commit);
+
+	// the first party takes the input blinding off its share of the excess
+	let mut excesses = blinds.clone();
+	excesses[0] = kcs[0]
+		.secp()
+		.blind_sum(vec![blinds[0].clone()], vec![input_blind])
+		.unwrap();
+
+	let kernel = TxKernel::empty().with_fee(10);
+	let mut signs = (0..3)
+		.map(|i| {
+			let secp = kcs[i].secp();
+			SignSession::new(secp, kernel.clone(), 3, i, excesses[i].clone()).unwrap()
+		})
+		.collect::<Vec<_>>();
+	let round1 = (0..3)
+		.map(|i| signs[i].round1(kcs[i].secp()).unwrap())
+		.collect::<Vec<_>>();
+	for (i, sign) in signs.iter_mut().enumerate() {
+		for msg in round1.iter().filter(|msg| msg.id != i) {
+			sign.add_round1(relay(msg)).unwrap();
+		}
+	}
+	let round2 = (0..3)
+		.map(|i| signs[i].round2(kcs[i].secp()).unwrap())
+		.collect::<Vec<_>>();
+
+	// a partial signature passed as someone else's doesn't verify
+	let forged = SignRound2 {
+		id: 2,
+		part_sig: round2[1].part_sig.clone(),
+	};
+	assert!(signs[0].add_round2(kcs[0].secp(), forged).is_err());
+
+	for (i, sign) in signs.iter_mut().enumerate() {
+		for msg in round2.iter().filter(|msg| msg.id != i) {
+			sign.add_round2(kcs[i].secp(), relay(msg)).unwrap();
+		}
+	}
+	let kernel = signs[1].finalize(kcs[1].secp()).unwrap();
+	kernel.verify().unwrap();
+
+	let input = Input::new(OutputFeatures::Plain, input_commit);
+	let tx = Transaction::new(vec![input], vec![output], vec![kernel]);
+	let verifier_cache: Arc<RwLock<dyn VerifierCache>> =
+		Arc::new(RwLock::new(LruVerifierCache::new()));
+	tx.validate(Weighting::AsTransaction, verifier_cache)
+		.unwrap();
+}
+
+/// No signing before every participant sent its first round message.
+#[test]
+fn incomplete_round() {
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+	let sec_key = kc.derive_key(0, &key_id).unwrap();
+	let kernel = TxKernel::empty().with_fee(10);
+
+	assert!(SignSession::new(kc.secp(), kernel.clone(), 2, 2, sec_key.clone()).is_err());
+	let mut sign = SignSession::new(kc.secp(), kernel, 2, 0, sec_key).unwrap();
+	assert!(sign.round2(kc.secp()).is_err());
+	assert!(sign.finalize(kc.secp()).is_err());
+}
@@ -0,0 +1,182 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Atomic swap settlement with adaptor signatures
+
+use self::core::core::transaction::Weighting;
+use self::core::core::verifier_cache::{LruVerifierCache, VerifierCache};
+use self::core::core::{Input, Output, OutputFeatures, Transaction, TxKernel};
+use self::core::libtx::{aggsig, proof};
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::secp::key::{PublicKey, SecretKey};
+use self::util::RwLock;
+use epic_core as core;
+use epic_keychain as keychain;
+use epic_util as util;
+use rand::thread_rng;
+use std::sync::Arc;
+
+/// Alice sells 50 to Bob, paying a fee of 10 from an input of 60. Bob only
+/// gets the transaction by completing Alice's adaptor signature with his
+/// secret, which Alice then learns from the kernel on chain.
+fn settle_swap() {
+	let alice = ExtKeychain::from_random_seed(false).unwrap();
+	let bob = ExtKeychain::from_random_seed(false).unwrap();
+	let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+
+	let input_commit = alice.commit(60, &key_id).unwrap();
+	let input_blind = alice.derive_key(60, &key_id).unwrap();
+	let output_commit = bob.commit(50, &key_id).unwrap();
+	let output_blind = bob.derive_key(50, &key_id).unwrap();
+	let output = Output {
+		features: OutputFeatures::Plain,
+		commit: output_commit,
+		proof: proof::create(&bob, 50, &key_id, output_commit, None).unwrap(),
+	};
+
+	// Bob's secret, unlocking the other chain
+	let secret = SecretKey::new(bob.secp(), &mut thread_rng());
+	let adaptor_point = PublicKey::from_secret_key(bob.secp(), &secret).unwrap();
+
+	// each side's share of the kernel excess and signing nonce
+	let alice_excess = alice.secp().blind_sum(vec![], vec![input_blind]).unwrap();
+	let alice_pubkey = PublicKey::from_secret_key(alice.secp(), &alice_excess).unwrap();
+	let alice_nonce = aggsig::create_secnonce(alice.secp()).unwrap();
+	let bob_pubkey = PublicKey::from_secret_key(bob.secp(), &output_blind).unwrap();
+	let bob_nonce = aggsig::create_secnonce(bob.secp()).unwrap();
+
+	let secp = alice.secp();
+	let pubkey_sum = PublicKey::from_combination(secp, vec![&alice_pubkey, &bob_pubkey]).unwrap();
+	let nonce_sum = PublicKey::from_combination(
+		secp,
+		vec![
+			&PublicKey::from_secret_key(secp, &alice_nonce).unwrap(),
+			&PublicKey::from_secret_key(secp, &bob_nonce).unwrap(),
+		],
+	)
+	.unwrap();
+	let kernel = TxKernel::empty().with_fee(10);
+	let msg = kernel.msg_to_sign().unwrap();
+
+	let bob_sig = aggsig::calculate_adaptor_partial_sig(
+		bob.secp(),
+		&output_blind,
+		&bob_nonce,
+		&nonce_sum,
+		&adaptor_point,
+		Some(&pubkey_sum),
+		&msg,
+	)
+	.unwrap();
+	aggsig::verify_adaptor_partial_sig(
+		alice.secp(),
+		&bob_sig,
+		&nonce_sum,
+		&adaptor_point,
+		&bob_pubkey,
+		Some(&pubkey_sum),
+		&msg,
+	)
+	.unwrap();
+	let alice_sig = aggsig::calculate_adaptor_partial_sig(
+		alice.secp(),
+		&alice_excess,
+		&alice_nonce,
+		&nonce_sum,
+		&adaptor_point,
+		Some(&pubkey_sum),
+		&msg,
+	)
+	.unwrap();
+	aggsig::verify_adaptor_partial_sig(
+		bob.secp(),
+		&alice_sig,
+		&nonce_sum,
+		&adaptor_point,
+		&alice_pubkey,
+		Some(&pubkey_sum),
+		&msg,
+	)
+	.unwrap();
+
+	// a partial signature doesn't pass as the other side's
+	assert!(aggsig::verify_adaptor_partial_sig(
+		bob.secp(),
+		&bob_sig,
+		&nonce_sum,
+		&adaptor_point,
+		&alice_pubkey,
+		Some(&pubkey_sum),
+		&msg,
+	)
+	.is_err());
+
+	// nor is the signature complete without the right secret
+	let wrong_secret = SecretKey::new(bob.secp(), &mut thread_rng());
+	assert!(aggsig::complete_adaptor_sig(
+		bob.secp(),
+		vec![&alice_sig, &bob_sig],
+		&nonce_sum,
+		&wrong_secret,
+		&pubkey_sum,
+		&msg,
+	)
+	.is_err());
+
+	// Bob settles
+	let sig = aggsig::complete_adaptor_sig(
+		bob.secp(),
+		vec![&alice_sig, &bob_sig],
+		&nonce_sum,
+		&secret,
+		&pubkey_sum,
+		&msg,
+	)
+	.unwrap();
+	let mut kernel = kernel;
+	let fee_commit = bob.secp().commit_value(10).unwrap();
+	kernel.excess = bob
+		.secp()
+		.commit_sum(vec![output_commit, fee_commit], vec![input_commit])
+		.unwrap();
+	kernel.excess_sig = sig;
+	kernel.verify().unwrap();
+
+	let input = Input::new(OutputFeatures::Plain, input_commit);
+	let tx = Transaction::new(vec![input], vec![output], vec![kernel.clone()]);
+	let verifier_cache: Arc<RwLock<dyn VerifierCache>> =
+		Arc::new(RwLock::new(LruVerifierCache::new()));
+	tx.validate(Weighting::AsTransaction, verifier_cache)
+		.unwrap();
+
+	// Alice learns the secret from the kernel
+	let extracted = aggsig::extract_adaptor_secret(
+		alice.secp(),
+		&kernel.excess_sig,
+		vec![&alice_sig, &bob_sig],
+		&nonce_sum,
+		&adaptor_point,
+	)
+	.unwrap();
+	assert_eq!(extracted, secret);
+}
+
+#[test]
+fn adaptor_sig_swap() {
+	// signers negate their nonces for about half of the nonce sums, run
+	// enough swaps to go through both cases
+	for _ in 0..8 {
+		settle_swap();
+	}
+}
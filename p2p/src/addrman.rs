@@ -0,0 +1,255 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Address manager, selecting which known addresses we connect to.
+//!
+//! Addresses we only heard of live in the "new" table, addresses we
+//! successfully connected to in the "tried" table. Both tables are split in
+//! buckets picked by a keyed hash of the network group (/16 for IPv4) of the
+//! address and, for new addresses, of the peer that told us about it. A
+//! single party controlling many addresses in a few network ranges can then
+//! only ever fill a handful of buckets, which makes it much harder to eclipse
+//! a node by flooding it with addresses.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::IpAddr;
+
+use rand::{thread_rng, Rng};
+
+use crate::types::PeerAddr;
+
+/// Number of buckets in the new table
+const NEW_BUCKET_COUNT: u64 = 256;
+
+/// Number of buckets in the tried table
+const TRIED_BUCKET_COUNT: u64 = 64;
+
+/// Max number of addresses in a single bucket
+const BUCKET_SIZE: usize = 64;
+
+/// Number of new buckets addresses from a single source group can end up in
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 16;
+
+/// Number of tried buckets addresses from a single group can end up in
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// Network group of an address. Addresses in the same group are likely
+/// controlled by the same party.
+pub type NetGroup = Vec<u8>;
+
+/// Network group of the provided address: /16 for IPv4, /32 for IPv6.
+/// Local addresses (loopback, private ranges) each get their own group so
+/// local test networks are not constrained.
+pub fn netgroup(addr: &PeerAddr) -> NetGroup {
+	let ip = match addr.0.ip() {
+		IpAddr::V6(ip) => match ip.to_ipv4() {
+			Some(ip) if !ip.is_unspecified() => IpAddr::V4(ip),
+			_ => IpAddr::V6(ip),
+		},
+		ip => ip,
+	};
+	match ip {
+		IpAddr::V4(ip) if !(ip.is_loopback() || ip.is_private() || ip.is_link_local()) => {
+			let octets = ip.octets();
+			vec![4, octets[0], octets[1]]
+		}
+		IpAddr::V6(ip) if !ip.is_loopback() => {
+			let segments = ip.segments();
+			vec![
+				6,
+				(segments[0] >> 8) as u8,
+				segments[0] as u8,
+				(segments[1] >> 8) as u8,
+				segments[1] as u8,
+			]
+		}
+		_ => {
+			let mut group = vec![0];
+			group.extend(addr.as_key().into_bytes());
+			group
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Table {
+	New,
+	Tried,
+}
+
+/// Bucketed tables of known addresses.
+pub struct AddrManager {
+	// keyed hasher, so nobody can predict which bucket an address lands in
+	key: RandomState,
+	new: Vec<Vec<PeerAddr>>,
+	tried: Vec<Vec<PeerAddr>>,
+	index: HashMap<PeerAddr, (Table, usize)>,
+}
+
+impl AddrManager {
+	/// Empty address manager with a fresh random bucketing key.
+	pub fn new() -> AddrManager {
+		AddrManager {
+			key: RandomState::new(),
+			new: vec![vec![]; NEW_BUCKET_COUNT as usize],
+			tried: vec![vec![]; TRIED_BUCKET_COUNT as usize],
+			index: HashMap::new(),
+		}
+	}
+
+	/// Number of addresses in the new table.
+	pub fn new_count(&self) -> usize {
+		self.new.iter().map(|b| b.len()).sum()
+	}
+
+	/// Number of addresses in the tried table.
+	pub fn tried_count(&self) -> usize {
+		self.tried.iter().map(|b| b.len()).sum()
+	}
+
+	/// Whether the address is known, in either table.
+	pub fn contains(&self, addr: &PeerAddr) -> bool {
+		self.index.contains_key(addr)
+	}
+
+	/// Whether the address is in the tried table.
+	pub fn is_tried(&self, addr: &PeerAddr) -> bool {
+		self.index.get(addr).map(|(t, _)| *t) == Some(Table::Tried)
+	}
+
+	/// Add an address we heard of from source to the new table. If the
+	/// bucket is full a random address gets evicted from it. Returns false if
+	/// the address was already known.
+	pub fn add(&mut self, addr: PeerAddr, source: &PeerAddr) -> bool {
+		if self.contains(&addr) {
+			return false;
+		}
+		let bucket = self.new_bucket(&addr, source);
+		self.insert(Table::New, bucket, addr);
+		true
+	}
+
+	/// Move an address we successfully connected to into the tried table.
+	/// If the tried bucket is full a random address gets evicted from it,
+	/// back to the new table.
+	pub fn mark_tried(&mut self, addr: PeerAddr) {
+		if self.is_tried(&addr) {
+			return;
+		}
+		self.remove(&addr);
+		let bucket = self.tried_bucket(&addr);
+		if let Some(evicted) = self.insert(Table::Tried, bucket, addr) {
+			let bucket = self.new_bucket(&evicted, &evicted);
+			self.insert(Table::New, bucket, evicted);
+		}
+	}
+
+	/// Forget an address altogether.
+	pub fn remove(&mut self, addr: &PeerAddr) {
+		if let Some((table, bucket)) = self.index.remove(addr) {
+			self.bucket_mut(table, bucket).retain(|a| a != addr);
+		}
+	}
+
+	/// Forget all addresses not matching the predicate.
+	pub fn retain<F>(&mut self, keep: F)
+	where
+		F: Fn(&PeerAddr) -> bool,
+	{
+		let gone = self
+			.index
+			.keys()
+			.filter(|addr| !keep(addr))
+			.cloned()
+			.collect::<Vec<_>>();
+		for addr in gone {
+			self.remove(&addr);
+		}
+	}
+
+	/// Randomly select up to count addresses, from the tried and new tables
+	/// with equal probability, skipping the ones matching exclude.
+	pub fn select<F>(&self, count: usize, exclude: F) -> Vec<PeerAddr>
+	where
+		F: Fn(&PeerAddr) -> bool,
+	{
+		let mut rng = thread_rng();
+		let mut selected: Vec<PeerAddr> = vec![];
+		let (new_count, tried_count) = (self.new_count(), self.tried_count());
+		if new_count + tried_count == 0 {
+			return selected;
+		}
+
+		// bounded number of attempts, most buckets are likely sparse
+		for _ in 0..count * 32 {
+			if selected.len() >= count {
+				break;
+			}
+			let use_tried = tried_count > 0 && (new_count == 0 || rng.gen_bool(0.5));
+			let table = if use_tried { &self.tried } else { &self.new };
+			let bucket = &table[rng.gen_range(0, table.len())];
+			if bucket.is_empty() {
+				continue;
+			}
+			let addr = bucket[rng.gen_range(0, bucket.len())];
+			if !selected.contains(&addr) && !exclude(&addr) {
+				selected.push(addr);
+			}
+		}
+		selected
+	}
+
+	fn new_bucket(&self, addr: &PeerAddr, source: &PeerAddr) -> usize {
+		let source_group = netgroup(source);
+		let slot = self.hash(&(&source_group, netgroup(addr))) % NEW_BUCKETS_PER_SOURCE_GROUP;
+		(self.hash(&(&source_group, slot)) % NEW_BUCKET_COUNT) as usize
+	}
+
+	fn tried_bucket(&self, addr: &PeerAddr) -> usize {
+		let slot = self.hash(&addr.as_key()) % TRIED_BUCKETS_PER_GROUP;
+		(self.hash(&(netgroup(addr), slot)) % TRIED_BUCKET_COUNT) as usize
+	}
+
+	fn hash<T: Hash>(&self, t: &T) -> u64 {
+		let mut hasher = self.key.build_hasher();
+		t.hash(&mut hasher);
+		hasher.finish()
+	}
+
+	fn bucket_mut(&mut self, table: Table, bucket: usize) -> &mut Vec<PeerAddr> {
+		match table {
+			Table::New => &mut self.new[bucket],
+			Table::Tried => &mut self.tried[bucket],
+		}
+	}
+
+	// Insert in the provided bucket, returning whatever got evicted to make
+	// room for it.
+	fn insert(&mut self, table: Table, bucket: usize, addr: PeerAddr) -> Option<PeerAddr> {
+		let mut evicted = None;
+		let entries = self.bucket_mut(table, bucket);
+		if entries.len() >= BUCKET_SIZE {
+			let i = thread_rng().gen_range(0, entries.len());
+			evicted = Some(entries.swap_remove(i));
+		}
+		entries.push(addr);
+		if let Some(evicted) = evicted {
+			self.index.remove(&evicted);
+		}
+		self.index.insert(addr, (table, bucket));
+		evicted
+	}
+}
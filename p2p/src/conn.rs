@@ -19,6 +19,9 @@
 //! async I/O to be able to both read *and* write on the connection. Which
 //! forces us to go through some additional gymnastic to loop over the async
 //! stream and make sure we get the right number of bytes out.
+//!
+//! Bandwidth can be limited globally and per connection, regular messages and
+//! attachments (txhashset archives, kernel data) each having their own budget.
 
 use std::fs::File;
 use std::io::{self, Read, Write};
@@ -36,9 +39,12 @@ use crate::msg::{
 	read_body, read_discard, read_header, read_item, write_to_buf, MsgHeader, MsgHeaderWrapper,
 	Type,
 };
-use crate::types::Error;
+use crate::types::{Error, P2PConfig};
 use crate::util::read_write::{read_exact, write_all};
-use crate::util::{RateCounter, RwLock};
+use crate::util::{Mutex, RateCounter, RwLock};
+
+/// How long a peer shows as throttled after the last time a limit kicked in
+const THROTTLED_DISPLAY_SECS: u64 = 10;
 
 /// A trait to be implemented in order to receive messages from the
 /// connection. Allows providing an optional response.
@@ -139,6 +145,7 @@ impl<'a> Response<'a> {
 		msg.append(&mut self.body);
 		write_all(&mut self.stream, &msg[..], time::Duration::from_secs(10))?;
 		tracker.inc_sent(msg.len() as u64);
+		tracker.consume_sent(msg.len() as u64);
 
 		if let Some(mut file) = self.attachment {
 			let mut buf = [0u8; 8000];
@@ -146,6 +153,7 @@ impl<'a> Response<'a> {
 				match file.read(&mut buf[..]) {
 					Ok(0) => break,
 					Ok(n) => {
+						tracker.throttle_attachment_sent(n as u64);
 						write_all(&mut self.stream, &buf[..n], time::Duration::from_secs(10))?;
 						// Increase sent bytes "quietly" without incrementing the counter.
 						// (In a loop here for the single attachment).
@@ -217,20 +225,122 @@ impl ConnHandle {
 	}
 }
 
+/// Token bucket limiting a transfer rate, in bytes per second. Allows bursts
+/// of up to a second worth of bytes.
+pub struct RateLimiter {
+	rate: u64,
+	tokens: f64,
+	last_refill: time::Instant,
+}
+
+impl RateLimiter {
+	pub fn new(rate: u64) -> RateLimiter {
+		RateLimiter {
+			rate,
+			tokens: rate as f64,
+			last_refill: time::Instant::now(),
+		}
+	}
+
+	fn refill(&mut self) {
+		let now = time::Instant::now();
+		let elapsed = now.duration_since(self.last_refill);
+		let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+		self.tokens = (self.tokens + secs * self.rate as f64).min(self.rate as f64);
+		self.last_refill = now;
+	}
+
+	/// How long to wait before more bytes can go through, zero if there is
+	/// budget left.
+	pub fn delay(&mut self) -> time::Duration {
+		self.refill();
+		if self.tokens >= 0.0 {
+			time::Duration::from_millis(0)
+		} else {
+			let millis = (-self.tokens * 1000.0 / self.rate as f64).ceil();
+			time::Duration::from_millis(millis as u64)
+		}
+	}
+
+	/// Use up the budget for the provided number of bytes. The bucket can go
+	/// in debt, so a message larger than the burst still goes through, the
+	/// following ones having to wait for the debt to be repaid.
+	pub fn consume(&mut self, bytes: u64) {
+		self.refill();
+		self.tokens -= bytes as f64;
+	}
+}
+
+fn limiter(rate: Option<u64>) -> Option<Arc<Mutex<RateLimiter>>> {
+	rate.filter(|r| *r > 0)
+		.map(|r| Arc::new(Mutex::new(RateLimiter::new(r))))
+}
+
+fn delay(limiters: &[&Option<Arc<Mutex<RateLimiter>>>]) -> time::Duration {
+	limiters
+		.iter()
+		.filter_map(|l| l.as_ref().map(|l| l.lock().delay()))
+		.max()
+		.unwrap_or(time::Duration::from_millis(0))
+}
+
+fn consume(limiters: &[&Option<Arc<Mutex<RateLimiter>>>], bytes: u64) {
+	for l in limiters {
+		if let Some(l) = l {
+			l.lock().consume(bytes);
+		}
+	}
+}
+
+/// Bandwidth limits, as configured. The global limiters are shared by all
+/// connections, each connection getting its own limiters for the per peer
+/// rates. Attachments only count against their own global budget.
+#[derive(Clone, Default)]
+pub struct BandwidthLimits {
+	upload: Option<Arc<Mutex<RateLimiter>>>,
+	download: Option<Arc<Mutex<RateLimiter>>>,
+	attachment_upload: Option<Arc<Mutex<RateLimiter>>>,
+	attachment_download: Option<Arc<Mutex<RateLimiter>>>,
+	peer_upload_rate: Option<u64>,
+	peer_download_rate: Option<u64>,
+}
+
+impl BandwidthLimits {
+	pub fn new(config: &P2PConfig) -> BandwidthLimits {
+		BandwidthLimits {
+			upload: limiter(config.upload_rate),
+			download: limiter(config.download_rate),
+			attachment_upload: limiter(config.attachment_rate),
+			attachment_download: limiter(config.attachment_rate),
+			peer_upload_rate: config.peer_upload_rate,
+			peer_download_rate: config.peer_download_rate,
+		}
+	}
+}
+
 pub struct Tracker {
 	/// Bytes we've sent.
 	pub sent_bytes: Arc<RwLock<RateCounter>>,
 	/// Bytes we've received.
 	pub received_bytes: Arc<RwLock<RateCounter>>,
+	/// Last time one of the bandwidth limits held back this connection.
+	throttled_at: RwLock<Option<time::Instant>>,
+	limits: BandwidthLimits,
+	peer_upload: Option<Arc<Mutex<RateLimiter>>>,
+	peer_download: Option<Arc<Mutex<RateLimiter>>>,
 }
 
 impl Tracker {
-	pub fn new() -> Tracker {
+	pub fn new(limits: BandwidthLimits) -> Tracker {
 		let received_bytes = Arc::new(RwLock::new(RateCounter::new()));
 		let sent_bytes = Arc::new(RwLock::new(RateCounter::new()));
 		Tracker {
 			received_bytes,
 			sent_bytes,
+			throttled_at: RwLock::new(None),
+			peer_upload: limiter(limits.peer_upload_rate),
+			peer_download: limiter(limits.peer_download_rate),
+			limits,
 		}
 	}
 
@@ -249,6 +359,64 @@ impl Tracker {
 	pub fn inc_quiet_sent(&self, size: u64) {
 		self.sent_bytes.write().inc_quiet(size);
 	}
+
+	/// Whether a bandwidth limit held back this connection recently.
+	pub fn is_throttled(&self) -> bool {
+		match *self.throttled_at.read() {
+			Some(t) => t.elapsed() < time::Duration::from_secs(THROTTLED_DISPLAY_SECS),
+			None => false,
+		}
+	}
+
+	fn check_throttled(&self, delay: time::Duration) -> bool {
+		if delay > time::Duration::from_millis(0) {
+			*self.throttled_at.write() = Some(time::Instant::now());
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Whether the upload limits allow sending more right now.
+	pub fn can_send(&self) -> bool {
+		!self.check_throttled(delay(&[&self.limits.upload, &self.peer_upload]))
+	}
+
+	/// Whether the download limits allow reading more right now.
+	pub fn can_receive(&self) -> bool {
+		!self.check_throttled(delay(&[&self.limits.download, &self.peer_download]))
+	}
+
+	/// Account for bytes written to the connection against the upload limits.
+	pub fn consume_sent(&self, size: u64) {
+		consume(&[&self.limits.upload, &self.peer_upload], size);
+	}
+
+	/// Account for bytes read from the connection against the download limits.
+	pub fn consume_received(&self, size: u64) {
+		consume(&[&self.limits.download, &self.peer_download], size);
+	}
+
+	/// Wait for the attachment upload budget before sending a chunk of an
+	/// attachment. Attachments are streamed in a blocking loop so we can
+	/// just sleep here.
+	pub fn throttle_attachment_sent(&self, size: u64) {
+		self.throttle_attachment(&self.limits.attachment_upload, size);
+	}
+
+	/// Wait for the attachment download budget before reading a chunk of an
+	/// attachment.
+	pub fn throttle_attachment_received(&self, size: u64) {
+		self.throttle_attachment(&self.limits.attachment_download, size);
+	}
+
+	fn throttle_attachment(&self, limiter: &Option<Arc<Mutex<RateLimiter>>>, size: u64) {
+		let wait = delay(&[limiter]);
+		if self.check_throttled(wait) {
+			thread::sleep(wait);
+		}
+		consume(&[limiter], size);
+	}
 }
 
 /// Start listening on the provided connection and wraps it. Does not hang
@@ -301,8 +469,14 @@ where
 			let sleep_time = time::Duration::from_millis(5);
 			let mut retry_send = Err(());
 			loop {
-				// check the read end
-				match try_break!(read_header(&mut reader, None)) {
+				// check the read end, leaving data in the socket while over our
+				// download limits
+				let header = if tracker.can_receive() {
+					try_break!(read_header(&mut reader, None))
+				} else {
+					None
+				};
+				match header {
 					Some(MsgHeaderWrapper::Known(header)) => {
 						let msg = Message::from_header(header, &mut reader);
 
@@ -314,6 +488,7 @@ where
 
 						// Increase received bytes counter
 						tracker.inc_received(MsgHeader::LEN as u64 + msg.header.msg_len);
+						tracker.consume_received(MsgHeader::LEN as u64 + msg.header.msg_len);
 
 						if let Some(Some(resp)) =
 							try_break!(handler.consume(msg, &mut writer, tracker.clone()))
@@ -324,24 +499,30 @@ where
 					Some(MsgHeaderWrapper::Unknown(msg_len)) => {
 						// Increase received bytes counter
 						tracker.inc_received(MsgHeader::LEN as u64 + msg_len);
+						tracker.consume_received(MsgHeader::LEN as u64 + msg_len);
 
 						try_break!(read_discard(msg_len, &mut reader));
 					}
 					None => {}
 				}
 
-				// check the write end, use or_else so try_recv is lazily eval'd
-				let maybe_data = retry_send.or_else(|_| send_rx.try_recv());
-				retry_send = Err(());
-				if let Ok(data) = maybe_data {
-					let written = try_break!(write_all(
-						&mut writer,
-						&data[..],
-						std::time::Duration::from_secs(10)
-					)
-					.map_err(&From::from));
-					if written.is_none() {
-						retry_send = Ok(data);
+				// check the write end, use or_else so try_recv is lazily eval'd,
+				// keeping messages in the channel while over our upload limits
+				if tracker.can_send() {
+					let maybe_data = retry_send.or_else(|_| send_rx.try_recv());
+					retry_send = Err(());
+					if let Ok(data) = maybe_data {
+						let written = try_break!(write_all(
+							&mut writer,
+							&data[..],
+							std::time::Duration::from_secs(10)
+						)
+						.map_err(&From::from));
+						if written.is_none() {
+							retry_send = Ok(data);
+						} else {
+							tracker.consume_sent(data.len() as u64);
+						}
 					}
 				}
 
@@ -362,3 +543,55 @@ where
 			let _ = conn.shutdown(Shutdown::Both);
 		})
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_rate_limiter() {
+		let mut limiter = RateLimiter::new(1000);
+		assert_eq!(limiter.delay(), time::Duration::from_millis(0));
+
+		// a burst larger than the budget goes through, then we wait
+		limiter.consume(1500);
+		let delay = limiter.delay();
+		assert!(delay > time::Duration::from_millis(400));
+		assert!(delay <= time::Duration::from_millis(500));
+
+		thread::sleep(delay);
+		assert_eq!(limiter.delay(), time::Duration::from_millis(0));
+	}
+
+	#[test]
+	fn test_unlimited_tracker() {
+		let tracker = Tracker::new(BandwidthLimits::default());
+		tracker.consume_sent(1_000_000_000);
+		tracker.consume_received(1_000_000_000);
+		assert!(tracker.can_send());
+		assert!(tracker.can_receive());
+		assert!(!tracker.is_throttled());
+	}
+
+	#[test]
+	fn test_global_and_peer_limits() {
+		let config = P2PConfig {
+			upload_rate: Some(10_000),
+			peer_download_rate: Some(1_000),
+			..P2PConfig::default()
+		};
+		let limits = BandwidthLimits::new(&config);
+		let t1 = Tracker::new(limits.clone());
+		let t2 = Tracker::new(limits);
+
+		// upload budget is shared by all peers
+		t1.consume_sent(20_000);
+		assert!(!t2.can_send());
+		assert!(t2.is_throttled());
+
+		// download budget is per peer
+		t1.consume_received(2_000);
+		assert!(!t1.can_receive());
+		assert!(t2.can_receive());
+	}
+}
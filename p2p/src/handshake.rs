@@ -14,7 +14,7 @@
 
 use crate::core::core::hash::Hash;
 use crate::core::pow::Difficulty;
-use crate::msg::{read_message, write_message, Hand, ProtocolVersion, Shake, Type, USER_AGENT};
+use crate::msg::{read_message, user_agent, write_message, Hand, ProtocolVersion, Shake, Type};
 use crate::peer::Peer;
 use crate::types::{Capabilities, Direction, Error, P2PConfig, PeerAddr, PeerInfo, PeerLiveInfo};
 use crate::util::RwLock;
@@ -80,7 +80,7 @@ impl Handshake {
 			total_difficulty: total_difficulty,
 			sender_addr: self_addr,
 			receiver_addr: peer_addr,
-			user_agent: USER_AGENT.to_string(),
+			user_agent: user_agent(),
 		};
 
 		// write and read the handshake response
@@ -173,7 +173,7 @@ impl Handshake {
 			capabilities: capab,
 			genesis: self.genesis,
 			total_difficulty: total_difficulty,
-			user_agent: USER_AGENT.to_string(),
+			user_agent: user_agent(),
 		};
 
 		write_message(conn, shake, Type::Shake)?;
@@ -36,6 +36,7 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+pub mod addrman;
 mod conn;
 pub mod handshake;
 pub mod msg;
@@ -46,12 +47,12 @@ mod serv;
 mod store;
 pub mod types;
 
-pub use crate::conn::SEND_CHANNEL_CAP;
+pub use crate::conn::{BandwidthLimits, SEND_CHANNEL_CAP};
 pub use crate::peer::Peer;
 pub use crate::peers::Peers;
 pub use crate::serv::{DummyAdapter, Server};
-pub use crate::store::{PeerData, State};
+pub use crate::store::{PeerData, PeerStore, State};
 pub use crate::types::{
 	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	ScoreEvent, Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
 };
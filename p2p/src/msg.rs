@@ -42,6 +42,18 @@ const PROTOCOL_VERSION: u32 = 1;
 /// Epic's user agent with current version
 pub const USER_AGENT: &'static str = concat!("MW/Epic ", env!("CARGO_PKG_VERSION"));
 
+/// User agent advertised to our peers, carrying the release version of the
+/// node once set rather than the version of this crate.
+pub fn user_agent() -> String {
+	match global::get_epic_version() {
+		Some(version) => format!(
+			"MW/Epic {}.{}",
+			version.version_major, version.version_minor
+		),
+		None => USER_AGENT.to_string(),
+	}
+}
+
 /// Magic numbers expected in the header of every message
 const OTHER_MAGIC: [u8; 2] = [73, 43];
 const FLOONET_MAGIC: [u8; 2] = [83, 59];
@@ -588,6 +600,9 @@ pub struct Ping {
 	pub height: u64,
 	/// local time
 	pub local_timestamp: i64,
+	/// height of the oldest full block the sender can serve, not sent by
+	/// older peers
+	pub tail_height: Option<u64>,
 }
 
 impl Writeable for Ping {
@@ -595,6 +610,9 @@ impl Writeable for Ping {
 		self.total_difficulty.write(writer)?;
 		self.height.write(writer)?;
 		self.local_timestamp.write(writer)?;
+		if let Some(tail_height) = self.tail_height {
+			tail_height.write(writer)?;
+		}
 		Ok(())
 	}
 }
@@ -604,10 +622,12 @@ impl Readable for Ping {
 		let total_difficulty = Difficulty::read(reader)?;
 		let height = reader.read_u64()?;
 		let local_timestamp = reader.read_i64()?;
+		let tail_height = reader.read_u64().ok();
 		Ok(Ping {
 			total_difficulty,
 			height,
 			local_timestamp,
+			tail_height,
 		})
 	}
 }
@@ -620,6 +640,9 @@ pub struct Pong {
 	pub height: u64,
 	/// local time
 	pub local_timestamp: i64,
+	/// height of the oldest full block the sender can serve, not sent by
+	/// older peers
+	pub tail_height: Option<u64>,
 }
 
 impl Writeable for Pong {
@@ -627,6 +650,9 @@ impl Writeable for Pong {
 		self.total_difficulty.write(writer)?;
 		self.height.write(writer)?;
 		self.local_timestamp.write(writer)?;
+		if let Some(tail_height) = self.tail_height {
+			tail_height.write(writer)?;
+		}
 		Ok(())
 	}
 }
@@ -636,10 +662,12 @@ impl Readable for Pong {
 		let total_difficulty = Difficulty::read(reader)?;
 		let height = reader.read_u64()?;
 		let local_timestamp = reader.read_i64()?;
+		let tail_height = reader.read_u64().ok();
 		Ok(Pong {
 			total_difficulty,
 			height,
 			local_timestamp,
+			tail_height,
 		})
 	}
 }
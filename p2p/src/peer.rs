@@ -33,7 +33,7 @@ use crate::msg::{
 use crate::protocol::Protocol;
 use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	ScoreEvent, TxHashSetRead,
 };
 use chrono::prelude::{DateTime, Utc};
 
@@ -70,11 +70,16 @@ impl fmt::Debug for Peer {
 
 impl Peer {
 	// Only accept and connect can be externally used to build a peer
-	fn new(info: PeerInfo, conn: TcpStream, adapter: Arc<dyn NetAdapter>) -> std::io::Result<Peer> {
+	fn new(
+		info: PeerInfo,
+		conn: TcpStream,
+		adapter: Arc<dyn NetAdapter>,
+		limits: &conn::BandwidthLimits,
+	) -> std::io::Result<Peer> {
 		let state = Arc::new(RwLock::new(State::Connected));
 		let tracking_adapter = TrackingAdapter::new(adapter);
 		let handler = Protocol::new(Arc::new(tracking_adapter.clone()), info.clone());
-		let tracker = Arc::new(conn::Tracker::new());
+		let tracker = Arc::new(conn::Tracker::new(limits.clone()));
 		let (sendh, stoph) = conn::listen(conn, tracker.clone(), handler)?;
 		let send_handle = Mutex::new(sendh);
 		let stop_handle = Mutex::new(stoph);
@@ -94,11 +99,12 @@ impl Peer {
 		total_difficulty: Difficulty,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
+		limits: &conn::BandwidthLimits,
 	) -> Result<Peer, Error> {
 		debug!("accept: handshaking from {:?}", conn.peer_addr());
 		let info = hs.accept(capab, total_difficulty, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok(info) => Ok(Peer::new(info, conn, adapter, limits)?),
 			Err(e) => {
 				debug!(
 					"accept: handshaking from {:?} failed with error: {:?}",
@@ -120,11 +126,12 @@ impl Peer {
 		self_addr: PeerAddr,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
+		limits: &conn::BandwidthLimits,
 	) -> Result<Peer, Error> {
 		debug!("connect: handshaking with {:?}", conn.peer_addr());
 		let info = hs.initiate(capab, total_difficulty, self_addr, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok(info) => Ok(Peer::new(info, conn, adapter, limits)?),
 			Err(e) => {
 				debug!(
 					"connect: handshaking with {:?} failed with error: {:?}",
@@ -211,6 +218,11 @@ impl Peer {
 		Some(received_bytes.bytes_per_min())
 	}
 
+	/// Whether our bandwidth limits held back traffic with the peer recently
+	pub fn is_throttled(&self) -> bool {
+		self.tracker.is_throttled()
+	}
+
 	pub fn last_min_message_counts(&self) -> Option<(u64, u64)> {
 		let received_bytes = self.tracker.received_bytes.read();
 		let sent_bytes = self.tracker.sent_bytes.read();
@@ -230,13 +242,19 @@ impl Peer {
 	}
 
 	/// Send a ping to the remote peer, providing our local difficulty and
-	/// height
-	pub fn send_ping(&self, total_difficulty: Difficulty, height: u64, local_timestamp: i64) -> Result<(), Error> {
+	/// height, along with the height of the oldest full block we hold
+	pub fn send_ping(
+		&self,
+		total_difficulty: Difficulty,
+		height: u64,
+		local_timestamp: i64,
+		tail_height: u64,
+	) -> Result<(), Error> {
 		let ping_msg = Ping {
 			total_difficulty,
 			height,
-			local_timestamp
-
+			local_timestamp,
+			tail_height: Some(tail_height),
 		};
 		self.send(ping_msg, msg::Type::Ping)
 	}
@@ -479,6 +497,10 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.total_height()
 	}
 
+	fn tail_height(&self) -> Result<u64, chain::Error> {
+		self.adapter.tail_height()
+	}
+
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction> {
 		self.adapter.get_transaction(kernel_hash)
 	}
@@ -496,6 +518,7 @@ impl ChainAdapter for TrackingAdapter {
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// Do not track the tx hash for stem txs.
 		// Otherwise we fail to handle the subsequent fluff or embargo expiration
@@ -504,7 +527,7 @@ impl ChainAdapter for TrackingAdapter {
 			let kernel = &tx.kernels()[0];
 			self.push_recv(kernel.hash());
 		}
-		self.adapter.transaction_received(tx, stem)
+		self.adapter.transaction_received(tx, stem, peer_info)
 	}
 
 	fn block_received(
@@ -601,8 +624,8 @@ impl NetAdapter for TrackingAdapter {
 		self.adapter.find_peer_addrs(capab)
 	}
 
-	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>) {
-		self.adapter.peer_addrs_received(addrs)
+	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>, source: PeerAddr) {
+		self.adapter.peer_addrs_received(addrs, source)
 	}
 	fn peer_difficulty(&self, addr: PeerAddr, diff: Difficulty, height: u64, local_timestamp: i64) {
 		self.adapter.peer_difficulty(addr, diff, height, local_timestamp)
@@ -610,4 +633,7 @@ impl NetAdapter for TrackingAdapter {
 	fn is_banned(&self, addr: PeerAddr) -> bool {
 		self.adapter.is_banned(addr)
 	}
+	fn update_score(&self, addr: PeerAddr, event: ScoreEvent) {
+		self.adapter.update_score(addr, event)
+	}
 }
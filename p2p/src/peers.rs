@@ -13,7 +13,8 @@
 // limitations under the License.
 
 use crate::util::RwLock;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
@@ -22,6 +23,7 @@ use std::sync::Arc;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
+use crate::addrman::{self, AddrManager, NetGroup};
 use crate::chain;
 use crate::core::core;
 use crate::core::core::hash::{Hash, Hashed};
@@ -31,27 +33,58 @@ use crate::peer::Peer;
 use crate::store::{PeerData, PeerStore, State};
 use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead, MAX_PEER_ADDRS,
+	ScoreEvent, TxHashSetRead, BAN_SCORE, MAX_PEER_ADDRS, MAX_PEER_SCORE,
 };
 use chrono::prelude::*;
 use chrono::Duration;
+use epic_store::StoreStats;
 
 const LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
+/// A connected peer not heard from (via ping/pong) for that long is
+/// penalized as slow to respond.
+const SLOW_RESPONSE_SECS: i64 = 60;
+
+/// Max number of outbound connections to peers in the same network group
+const MAX_OUTBOUND_PER_NETGROUP: usize = 2;
+
+/// Number of outbound peers we remember on shutdown and reconnect to first
+/// on the next start
+const MAX_ANCHORS: usize = 2;
+
 pub struct Peers {
 	pub adapter: Arc<dyn ChainAdapter>,
 	store: PeerStore,
 	peers: RwLock<HashMap<PeerAddr, Arc<Peer>>>,
+	addrman: RwLock<AddrManager>,
 	config: P2PConfig,
 }
 
 impl Peers {
 	pub fn new(store: PeerStore, adapter: Arc<dyn ChainAdapter>, config: P2PConfig) -> Peers {
+		// Rebuild the address tables from what we know. We don't keep track
+		// of where an address came from across restarts so each address is
+		// treated as its own source.
+		let mut addrman = AddrManager::new();
+		match store.all_peers() {
+			Ok(peers) => {
+				for p in peers.into_iter().filter(|p| p.flags != State::Banned) {
+					// known capabilities means we once completed a handshake
+					if p.capabilities != Capabilities::UNKNOWN {
+						addrman.mark_tried(p.addr);
+					} else {
+						addrman.add(p.addr, &p.addr);
+					}
+				}
+			}
+			Err(e) => error!("failed to load peers for the address manager: {:?}", e),
+		}
 		Peers {
 			adapter,
 			store,
 			config,
 			peers: RwLock::new(HashMap::new()),
+			addrman: RwLock::new(addrman),
 		}
 	}
 
@@ -65,6 +98,13 @@ impl Peers {
 				return Err(Error::Timeout);
 			}
 		};
+		// a reconnecting peer keeps the reputation it earned before
+		let score = self
+			.store
+			.get_peer(peer.info.addr)
+			.map(|p| p.score)
+			.unwrap_or(0);
+		peer.info.live_info.write().score = score;
 		let peer_data = PeerData {
 			addr: peer.info.addr,
 			capabilities: peer.info.capabilities,
@@ -74,10 +114,14 @@ impl Peers {
 			ban_reason: ReasonForBan::None,
 			last_connected: Utc::now().timestamp(),
 			local_timestamp: Utc::now().timestamp(),
+			score,
 		};
 		debug!("Saving newly connected peer {}.", peer_data.addr);
 		self.save_peer(&peer_data)?;
 		peers.insert(peer_data.addr, peer.clone());
+		if peer.info.is_outbound() {
+			self.addrman.write().mark_tried(peer_data.addr);
+		}
 
 		Ok(())
 	}
@@ -94,6 +138,7 @@ impl Peers {
 			ban_reason,
 			last_connected: Utc::now().timestamp(),
 			local_timestamp: Utc::now().timestamp(),
+			score: BAN_SCORE,
 		};
 		debug!("Banning peer {}.", addr);
 		self.save_peer(&peer_data)
@@ -248,6 +293,7 @@ impl Peers {
 			error!("Couldn't ban {}: {:?}", peer_addr, e);
 			return;
 		}
+		self.addrman.write().remove(&peer_addr);
 
 		if let Some(peer) = self.get_connected_peer(peer_addr) {
 			debug!("Banning peer {}", peer_addr);
@@ -270,6 +316,55 @@ impl Peers {
 		}
 	}
 
+	/// Adjust the score of a peer following some good or bad behaviour.
+	/// The peer gets banned once its score drops to BAN_SCORE. Penalties are
+	/// saved right away, rewards are saved along with the periodic
+	/// clean_peers.
+	pub fn update_score(&self, peer_addr: PeerAddr, event: ScoreEvent) {
+		let delta = event.score_delta();
+		let score = match self.get_connected_peer(peer_addr) {
+			Some(peer) => peer.info.adjust_score(delta),
+			None => match self.get_peer(peer_addr) {
+				Ok(peer) => cmp::max(BAN_SCORE, cmp::min(MAX_PEER_SCORE, peer.score + delta)),
+				Err(_) => return,
+			},
+		};
+		if delta >= 0 {
+			return;
+		}
+
+		debug!(
+			"update_score: {} penalized for {:?}, score now {}",
+			peer_addr, event, score
+		);
+		if score <= BAN_SCORE {
+			self.ban_peer(peer_addr, event.ban_reason());
+		}
+		if let Err(e) = self.store.update_scores(&[(peer_addr, score)]) {
+			error!("Couldn't save score of {}: {:?}", peer_addr, e);
+		}
+	}
+
+	/// Disconnect the connected peer with the lowest score if that score is
+	/// below the provided one, freeing its slot for a better peer. Returns
+	/// the evicted peer, if any.
+	pub fn evict_lowest_score(&self, below: i32) -> Option<PeerAddr> {
+		let peer = self
+			.connected_peers()
+			.into_iter()
+			.min_by_key(|x| x.info.score())?;
+		if peer.info.score() >= below {
+			return None;
+		}
+		debug!(
+			"evict_lowest_score: disconnecting {}, score {}",
+			peer.info.addr,
+			peer.info.score()
+		);
+		peer.stop();
+		Some(peer.info.addr)
+	}
+
 	/// Unban a peer, checks if it exists and banned then unban
 	pub fn unban_peer(&self, peer_addr: PeerAddr) {
 		debug!("unban_peer: peer {}", peer_addr);
@@ -279,6 +374,7 @@ impl Peers {
 					if let Err(e) = self.update_state(peer_addr, State::Healthy) {
 						error!("Couldn't unban {}: {:?}", peer_addr, e);
 					}
+					self.addrman.write().add(peer_addr, &peer_addr);
 				} else {
 					error!("Couldn't unban {}: peer is not banned", peer_addr);
 				}
@@ -359,9 +455,14 @@ impl Peers {
 
 	/// Ping all our connected peers. Always automatically expects a pong back
 	/// or disconnects. This acts as a liveness test.
-	pub fn check_all(&self, total_difficulty: Difficulty, height: u64) {
+	pub fn check_all(&self, total_difficulty: Difficulty, height: u64, tail_height: u64) {
 		for p in self.connected_peers().iter() {
-			if let Err(e) = p.send_ping(total_difficulty.clone(), height, Utc::now().timestamp()) {
+			if let Err(e) = p.send_ping(
+				total_difficulty.clone(),
+				height,
+				Utc::now().timestamp(),
+				tail_height,
+			) {
 				debug!("Error pinging peer {:?}: {:?}", &p.info.addr, e);
 				let mut peers = match self.peers.try_write_for(LOCK_TIMEOUT) {
 					Some(peers) => peers,
@@ -378,6 +479,11 @@ impl Peers {
 
 
 
+	}
+
+	/// Usage statistics of the peer database.
+	pub fn db_stats(&self) -> Result<StoreStats, Error> {
+		Ok(self.store.stats()?)
 	}
 
 	/// All peer information we have in storage
@@ -426,10 +532,20 @@ impl Peers {
 
 	/// Iterate over the peer list and prune all peers we have
 	/// lost connection to or have been deemed problematic.
-	/// Also avoid connected peer count getting too high.
+	/// Also avoid connected peer count getting too high, evicting the
+	/// peers with the lowest score first.
 	pub fn clean_peers(&self, max_count: usize) {
 		let mut rm = vec![];
 
+		// penalize peers that stopped answering our pings, possibly getting
+		// them banned (and so removed below)
+		let slow_cutoff = Utc::now() - Duration::seconds(SLOW_RESPONSE_SECS);
+		for peer in self.connected_peers() {
+			if peer.info.report_stall(slow_cutoff) {
+				self.update_score(peer.info.addr, ScoreEvent::SlowResponse);
+			}
+		}
+
 		// build a list of peers to be cleaned up
 		{
 			let peers = match self.peers.try_read_for(LOCK_TIMEOUT) {
@@ -479,8 +595,13 @@ impl Peers {
 			.saturating_sub(max_count);
 		if excess_count > 0 {
 			// map peers to addrs in a block to bound how long we keep the read lock for
-			let mut addrs = self
+			let mut peers = self
 				.connected_peers()
+				.into_iter()
+				.filter(|x| !rm.contains(&x.info.addr))
+				.collect::<Vec<_>>();
+			peers.sort_by_key(|x| x.info.score());
+			let mut addrs = peers
 				.iter()
 				.take(excess_count)
 				.map(|x| x.info.addr.clone())
@@ -488,6 +609,8 @@ impl Peers {
 			rm.append(&mut addrs);
 		}
 
+		self.save_scores();
+
 		// now clean up peer map based on the list to remove
 		{
 			let mut peers = match self.peers.try_write_for(LOCK_TIMEOUT) {
@@ -504,7 +627,94 @@ impl Peers {
 		}
 	}
 
+	/// Save the current scores of all connected peers.
+	fn save_scores(&self) {
+		let scores = self
+			.connected_peers()
+			.iter()
+			.map(|p| (p.info.addr, p.info.score()))
+			.collect::<Vec<_>>();
+		if let Err(e) = self.store.update_scores(&scores) {
+			error!("save_scores: failed to save peer scores: {:?}", e);
+		}
+	}
+
+	/// Outbound peers we were connected to on last shutdown, to reconnect to
+	/// first. Anchors make it harder for an attacker to take over all our
+	/// connections across a restart.
+	pub fn anchors(&self) -> Vec<PeerAddr> {
+		match self.store.anchors() {
+			Ok(anchors) => anchors,
+			Err(e) => {
+				error!("failed to load anchor peers: {:?}", e);
+				vec![]
+			}
+		}
+	}
+
+	// Remember our best scored outbound peers as anchors for the next start.
+	fn save_anchors(&self) {
+		let mut outbound = self.outgoing_connected_peers();
+		outbound.sort_by_key(|p| -p.info.score());
+		let anchors = outbound
+			.iter()
+			.take(MAX_ANCHORS)
+			.map(|p| p.info.addr)
+			.collect::<Vec<_>>();
+		debug!("save_anchors: {:?}", anchors);
+		if let Err(e) = self.store.save_anchors(anchors) {
+			error!("save_anchors: failed to save anchor peers: {:?}", e);
+		}
+	}
+
+	// Number of outbound connections per network group.
+	fn outbound_netgroups(&self) -> HashMap<NetGroup, usize> {
+		let mut groups = HashMap::new();
+		for p in self.outgoing_connected_peers() {
+			*groups.entry(addrman::netgroup(&p.info.addr)).or_insert(0) += 1;
+		}
+		groups
+	}
+
+	/// Whether we can open one more outbound connection to the network group
+	/// of the provided address.
+	pub fn netgroup_has_room(&self, addr: PeerAddr) -> bool {
+		let count = self
+			.outbound_netgroups()
+			.get(&addrman::netgroup(&addr))
+			.cloned()
+			.unwrap_or(0);
+		count < MAX_OUTBOUND_PER_NETGROUP
+	}
+
+	/// Select up to count addresses to try connecting to from the address
+	/// manager, skipping connected, defunct or banned peers and not exceeding
+	/// MAX_OUTBOUND_PER_NETGROUP connections per network group.
+	pub fn select_outbound_candidates(&self, count: usize) -> Vec<PeerAddr> {
+		let mut groups = self.outbound_netgroups();
+		let candidates = self.addrman.read().select(count, |addr| {
+			self.is_known(*addr)
+				|| self
+					.store
+					.get_peer(*addr)
+					.map(|p| p.flags != State::Healthy)
+					.unwrap_or(false)
+		});
+
+		let mut selected = vec![];
+		for addr in candidates {
+			let count = groups.entry(addrman::netgroup(&addr)).or_insert(0);
+			if *count < MAX_OUTBOUND_PER_NETGROUP {
+				*count += 1;
+				selected.push(addr);
+			}
+		}
+		selected
+	}
+
 	pub fn stop(&self) {
+		self.save_scores();
+		self.save_anchors();
 		let mut peers = self.peers.write();
 		for (_, peer) in peers.drain() {
 			peer.stop_and_wait();
@@ -544,6 +754,15 @@ impl Peers {
 
 			should_remove
 		});
+
+		// and from the address manager
+		let known = self
+			.all_peers()
+			.into_iter()
+			.filter(|p| p.flags != State::Banned)
+			.map(|p| p.addr)
+			.collect::<HashSet<_>>();
+		self.addrman.write().retain(|addr| known.contains(addr));
 	}
 }
 
@@ -556,6 +775,10 @@ impl ChainAdapter for Peers {
 		self.adapter.total_height()
 	}
 
+	fn tail_height(&self) -> Result<u64, chain::Error> {
+		self.adapter.tail_height()
+	}
+
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction> {
 		self.adapter.get_transaction(kernel_hash)
@@ -573,8 +796,9 @@ impl ChainAdapter for Peers {
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
-		self.adapter.transaction_received(tx, stem)
+		self.adapter.transaction_received(tx, stem, peer_info)
 	}
 
 	fn block_received(
@@ -584,16 +808,23 @@ impl ChainAdapter for Peers {
 		was_requested: bool,
 	) -> Result<bool, chain::Error> {
 		let hash = b.hash();
+		if !was_requested {
+			// full blocks are only ever sent on request
+			self.update_score(peer_info.addr, ScoreEvent::UnrequestedData);
+		}
 		if !self.adapter.block_received(b, peer_info, was_requested)? {
 			// if the peer sent us a block that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
+			// they are either mistaken or malevolent
 			debug!(
-				"Received a bad block {} from  {}, the peer will be banned",
+				"Received a bad block {} from  {}, the peer will be penalized",
 				hash, peer_info.addr,
 			);
-			self.ban_peer(peer_info.addr, ReasonForBan::BadBlock);
+			self.update_score(peer_info.addr, ScoreEvent::BadBlock);
 			Ok(false)
 		} else {
+			if was_requested {
+				self.update_score(peer_info.addr, ScoreEvent::UsefulBlock);
+			}
 			Ok(true)
 		}
 	}
@@ -606,14 +837,15 @@ impl ChainAdapter for Peers {
 		let hash = cb.hash();
 		if !self.adapter.compact_block_received(cb, peer_info)? {
 			// if the peer sent us a block that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
+			// they are either mistaken or malevolent
 			debug!(
-				"Received a bad compact block {} from  {}, the peer will be banned",
+				"Received a bad compact block {} from  {}, the peer will be penalized",
 				hash, peer_info.addr
 			);
-			self.ban_peer(peer_info.addr, ReasonForBan::BadCompactBlock);
+			self.update_score(peer_info.addr, ScoreEvent::BadCompactBlock);
 			Ok(false)
 		} else {
+			self.update_score(peer_info.addr, ScoreEvent::UsefulBlock);
 			Ok(true)
 		}
 	}
@@ -625,8 +857,8 @@ impl ChainAdapter for Peers {
 	) -> Result<bool, chain::Error> {
 		if !self.adapter.header_received(bh, peer_info)? {
 			// if the peer sent us a block header that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
-			self.ban_peer(peer_info.addr, ReasonForBan::BadBlockHeader);
+			// they are either mistaken or malevolent
+			self.update_score(peer_info.addr, ScoreEvent::BadBlockHeader);
 			Ok(false)
 		} else {
 			Ok(true)
@@ -640,8 +872,8 @@ impl ChainAdapter for Peers {
 	) -> Result<bool, chain::Error> {
 		if !self.adapter.headers_received(headers, peer_info)? {
 			// if the peer sent us a block header that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
-			self.ban_peer(peer_info.addr, ReasonForBan::BadBlockHeader);
+			// they are either mistaken or malevolent
+			self.update_score(peer_info.addr, ScoreEvent::BadBlockHeader);
 			Ok(false)
 		} else {
 			Ok(true)
@@ -680,10 +912,10 @@ impl ChainAdapter for Peers {
 	) -> Result<bool, chain::Error> {
 		if !self.adapter.txhashset_write(h, txhashset_data, peer_info)? {
 			debug!(
-				"Received a bad txhashset data from {}, the peer will be banned",
+				"Received a bad txhashset data from {}, the peer will be penalized",
 				peer_info.addr
 			);
-			self.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet);
+			self.update_score(peer_info.addr, ScoreEvent::BadTxHashSet);
 			Ok(false)
 		} else {
 			Ok(true)
@@ -719,14 +951,19 @@ impl NetAdapter for Peers {
 	}
 
 	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddr>) {
-		trace!("Received {} peer addrs, saving.", peer_addrs.len());
+	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddr>, source: PeerAddr) {
+		trace!(
+			"Received {} peer addrs from {}, saving.",
+			peer_addrs.len(),
+			source
+		);
 		for pa in peer_addrs {
 			if let Ok(e) = self.exists_peer(pa) {
 				if e {
 					continue;
 				}
 			}
+			self.addrman.write().add(pa, &source);
 			let peer = PeerData {
 				addr: pa,
 				capabilities: Capabilities::UNKNOWN,
@@ -736,6 +973,7 @@ impl NetAdapter for Peers {
 				ban_reason: ReasonForBan::None,
 				last_connected: Utc::now().timestamp(),
 				local_timestamp: Utc::now().timestamp(),
+				score: 0,
 			};
 			if let Err(e) = self.save_peer(&peer) {
 				error!("Could not save received peer address: {:?}", e);
@@ -758,4 +996,8 @@ impl NetAdapter for Peers {
 			false
 		}
 	}
+
+	fn update_score(&self, addr: PeerAddr, event: ScoreEvent) {
+		Peers::update_score(self, addr, event)
+	}
 }
@@ -18,7 +18,7 @@ use crate::msg::{
 	BanReason, GetPeerAddrs, Headers, KernelDataResponse, Locator, PeerAddrs, Ping, Pong,
 	TxHashSetArchive, TxHashSetRequest, Type,
 };
-use crate::types::{Error, NetAdapter, PeerInfo};
+use crate::types::{Error, NetAdapter, PeerInfo, ScoreEvent};
 use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::cmp;
@@ -62,6 +62,7 @@ impl MessageHandler for Protocol {
 			Type::Ping => {
 				let ping: Ping = msg.body()?;
 				adapter.peer_difficulty(self.peer_info.addr, ping.total_difficulty, ping.height, ping.local_timestamp);
+				self.peer_info.update_tail_height(ping.tail_height);
 
 				Ok(Some(Response::new(
 					Type::Pong,
@@ -69,6 +70,7 @@ impl MessageHandler for Protocol {
 						total_difficulty: adapter.total_difficulty()?,
 						height: adapter.total_height()?,
 						local_timestamp: Utc::now().timestamp(),
+						tail_height: Some(adapter.tail_height()?),
 					},
 					writer,
 				)?))
@@ -77,6 +79,7 @@ impl MessageHandler for Protocol {
 			Type::Pong => {
 				let pong: Pong = msg.body()?;
 				adapter.peer_difficulty(self.peer_info.addr, pong.total_difficulty, pong.height, pong.local_timestamp);
+				self.peer_info.update_tail_height(pong.tail_height);
 				Ok(None)
 			}
 
@@ -116,7 +119,7 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, false)?;
+				adapter.transaction_received(tx, false, &self.peer_info)?;
 				Ok(None)
 			}
 
@@ -126,7 +129,7 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, true)?;
+				adapter.transaction_received(tx, true, &self.peer_info)?;
 				Ok(None)
 			}
 
@@ -245,7 +248,7 @@ impl MessageHandler for Protocol {
 
 			Type::PeerAddrs => {
 				let peer_addrs: PeerAddrs = msg.body()?;
-				adapter.peer_addrs_received(peer_addrs.peers);
+				adapter.peer_addrs_received(peer_addrs.peers, self.peer_info.addr);
 				Ok(None)
 			}
 
@@ -273,7 +276,9 @@ impl MessageHandler for Protocol {
 				let mut remaining_size = total_size;
 
 				while remaining_size > 0 {
-					let size = msg.copy_attachment(remaining_size, &mut writer)?;
+					let request_size = cmp::min(48_000, remaining_size);
+					tracker.throttle_attachment_received(request_size as u64);
+					let size = msg.copy_attachment(request_size, &mut writer)?;
 					remaining_size = remaining_size.saturating_sub(size);
 
 					// Increase received bytes quietly (without affecting the counters).
@@ -334,6 +339,8 @@ impl MessageHandler for Protocol {
 					error!(
 						"handle_payload: txhashset archive received but SyncStatus not on TxHashsetDownload",
 					);
+					self.adapter
+						.update_score(self.peer_info.addr, ScoreEvent::UnrequestedData);
 					return Err(Error::BadMessage);
 				}
 
@@ -354,6 +361,7 @@ impl MessageHandler for Protocol {
 					let mut downloaded_size: usize = 0;
 					let mut request_size = cmp::min(48_000, total_size);
 					while request_size > 0 {
+						tracker.throttle_attachment_received(request_size as u64);
 						let size = msg.copy_attachment(request_size, &mut tmp_zip)?;
 						downloaded_size += size;
 						request_size = cmp::min(48_000, total_size - downloaded_size);
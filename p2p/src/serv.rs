@@ -21,6 +21,7 @@ use std::thread;
 use std::time::Duration;
 
 use crate::chain;
+use crate::conn::BandwidthLimits;
 use crate::core::core;
 use crate::core::core::hash::Hash;
 use crate::core::global;
@@ -31,7 +32,7 @@ use crate::peers::Peers;
 use crate::store::PeerStore;
 use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	ScoreEvent, TxHashSetRead,
 };
 use crate::util::StopState;
 use chrono::prelude::{DateTime, Utc};
@@ -42,6 +43,7 @@ pub struct Server {
 	pub config: P2PConfig,
 	capabilities: Capabilities,
 	handshake: Arc<Handshake>,
+	bandwidth_limits: BandwidthLimits,
 	pub peers: Arc<Peers>,
 	stop_state: Arc<StopState>,
 }
@@ -61,6 +63,7 @@ impl Server {
 			config: config.clone(),
 			capabilities: capab,
 			handshake: Arc::new(Handshake::new(genesis, config.clone())),
+			bandwidth_limits: BandwidthLimits::new(&config),
 			peers: Arc::new(Peers::new(PeerStore::new(db_root)?, adapter, config)),
 			stop_state,
 		})
@@ -166,6 +169,7 @@ impl Server {
 					PeerAddr(addr),
 					&self.handshake,
 					self.peers.clone(),
+					&self.bandwidth_limits,
 				)?;
 				let peer = Arc::new(peer);
 				self.peers.add_connected(peer.clone())?;
@@ -197,6 +201,7 @@ impl Server {
 			total_diff,
 			&self.handshake,
 			self.peers.clone(),
+			&self.bandwidth_limits,
 		)?;
 		self.peers.add_connected(Arc::new(peer))?;
 		Ok(())
@@ -256,6 +261,9 @@ impl ChainAdapter for DummyAdapter {
 	fn total_height(&self) -> Result<u64, chain::Error> {
 		Ok(0)
 	}
+	fn tail_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
 
 	fn get_transaction(&self, _h: Hash) -> Option<core::Transaction> {
 		None
@@ -268,6 +276,7 @@ impl ChainAdapter for DummyAdapter {
 		&self,
 		_: core::Transaction,
 		_stem: bool,
+		_peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		Ok(true)
 	}
@@ -346,9 +355,10 @@ impl NetAdapter for DummyAdapter {
 	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
 		vec![]
 	}
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>, _: PeerAddr) {}
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64, _: i64) {}
 	fn is_banned(&self, _: PeerAddr) -> bool {
 		false
 	}
+	fn update_score(&self, _: PeerAddr, _: ScoreEvent) {}
 }
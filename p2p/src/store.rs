@@ -20,13 +20,15 @@ use rand::seq::SliceRandom;
 use rand::thread_rng;
 
 use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::msg::PeerAddrs;
 use crate::types::{Capabilities, PeerAddr, ReasonForBan};
-use epic_store::{self, option_to_not_found, to_key, Error};
+use epic_store::{self, option_to_not_found, to_key, Error, KVStore, StoreStats};
 
 const DB_NAME: &'static str = "peer";
 const STORE_SUBPATH: &'static str = "peers";
 
 const PEER_PREFIX: u8 = 'P' as u8;
+const ANCHORS_PREFIX: u8 = 'A' as u8;
 
 /// Types of messages
 enum_from_primitive! {
@@ -58,6 +60,9 @@ pub struct PeerData {
 	pub last_connected: i64,
 	/// Local utc from peer
 	pub local_timestamp: i64,
+	/// Reputation of the peer, see ScoreEvent.
+	#[serde(default)]
+	pub score: i32,
 }
 
 impl Writeable for PeerData {
@@ -71,7 +76,8 @@ impl Writeable for PeerData {
 			[write_i64, self.last_banned],
 			[write_i32, self.ban_reason as i32],
 			[write_i64, self.last_connected],
-			[write_i64, self.local_timestamp]
+			[write_i64, self.local_timestamp],
+			[write_i32, self.score]
 		);
 		Ok(())
 	}
@@ -98,6 +104,9 @@ impl Readable for PeerData {
 			Ok(lt) => lt,
 		};
 
+		// peers saved before scoring was introduced start with a neutral score
+		let score = reader.read_i32().unwrap_or(0);
+
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let capabilities = Capabilities::from_bits_truncate(capab);
 		let ban_reason = ReasonForBan::from_i32(br).ok_or(ser::Error::CorruptedData)?;
@@ -112,15 +121,17 @@ impl Readable for PeerData {
 				ban_reason,
 				last_connected,
 				local_timestamp,
+				score,
 			}),
 			None => Err(ser::Error::CorruptedData),
 		}
 	}
 }
 
-/// Storage facility for peer data.
-pub struct PeerStore {
-	db: epic_store::Store,
+/// Storage facility for peer data, on top of a key-value store (LMDB by
+/// default).
+pub struct PeerStore<S = epic_store::Store> {
+	db: S,
 }
 
 impl PeerStore {
@@ -130,6 +141,30 @@ impl PeerStore {
 		Ok(PeerStore { db: db })
 	}
 
+	/// Compact the peer database under the provided root, which must not be
+	/// open meanwhile. Returns the size of the database file before and after.
+	pub fn compact(db_root: &str) -> Result<(u64, u64), Error> {
+		epic_store::compact(db_root, Some(DB_NAME))
+	}
+}
+
+impl<S: KVStore> PeerStore<S> {
+	/// Instantiates a peer store on top of the provided key-value store.
+	pub fn with_store(db: S) -> PeerStore<S> {
+		PeerStore { db }
+	}
+
+	/// Page usage of the peer database.
+	pub fn usage(&self) -> Result<StoreStats, Error> {
+		self.db.usage()
+	}
+
+	/// Usage statistics of the peer database.
+	pub fn stats(&self) -> Result<StoreStats, Error> {
+		self.db
+			.stats(&[(PEER_PREFIX, "peers"), (ANCHORS_PREFIX, "anchors")])
+	}
+
 	pub fn save_peer(&self, p: &PeerData) -> Result<(), Error> {
 		debug!("save_peer: {:?} marked {:?}", p.addr, p.flags);
 
@@ -157,6 +192,8 @@ impl PeerStore {
 		batch.commit()
 	}
 
+	/// Find peers with the provided state and capabilities, best scored
+	/// first (in random order for equal scores).
 	pub fn find_peers(
 		&self,
 		state: State,
@@ -170,6 +207,7 @@ impl PeerStore {
 			.filter(|p| p.flags == state && p.capabilities.contains(cap))
 			.collect::<Vec<_>>();
 		peers[..].shuffle(&mut thread_rng());
+		peers.sort_by(|a, b| b.score.cmp(&a.score));
 		Ok(peers.iter().take(count).cloned().collect())
 	}
 
@@ -186,6 +224,7 @@ impl PeerStore {
 
 	/// Convenience method to load a peer data, update its status and save it
 	/// back. If new state is Banned its last banned time will be updated too.
+	/// A peer coming out of a ban starts over with a neutral score.
 	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<(), Error> {
 		let batch = self.db.batch()?;
 
@@ -193,6 +232,9 @@ impl PeerStore {
 			batch.get_ser::<PeerData>(&peer_key(peer_addr)[..]),
 			&format!("Peer at address: {}", peer_addr),
 		)?;
+		if peer.flags == State::Banned && new_state != State::Banned {
+			peer.score = 0;
+		}
 		peer.flags = new_state;
 		if new_state == State::Banned {
 			peer.last_banned = Utc::now().timestamp();
@@ -202,6 +244,32 @@ impl PeerStore {
 		batch.commit()
 	}
 
+	/// Save the scores of the provided peers in a single batch. Unknown peers
+	/// are skipped.
+	pub fn update_scores(&self, scores: &[(PeerAddr, i32)]) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		for (peer_addr, score) in scores {
+			if let Some(mut peer) = batch.get_ser::<PeerData>(&peer_key(*peer_addr)[..])? {
+				peer.score = *score;
+				batch.put_ser(&peer_key(*peer_addr)[..], &peer)?;
+			}
+		}
+		batch.commit()
+	}
+
+	/// Outbound peers we were connected to on last shutdown.
+	pub fn anchors(&self) -> Result<Vec<PeerAddr>, Error> {
+		let anchors: Option<PeerAddrs> = self.db.get_ser(&anchors_key()[..])?;
+		Ok(anchors.map(|a| a.peers).unwrap_or(vec![]))
+	}
+
+	/// Replace the saved anchor peers.
+	pub fn save_anchors(&self, peers: Vec<PeerAddr>) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		batch.put_ser(&anchors_key()[..], &PeerAddrs { peers })?;
+		batch.commit()
+	}
+
 	/// Deletes peers from the storage that satisfy some condition `predicate`
 	pub fn delete_peers<F>(&self, predicate: F) -> Result<(), Error>
 	where
@@ -230,6 +298,10 @@ impl PeerStore {
 	}
 }
 
+fn anchors_key() -> Vec<u8> {
+	to_key(ANCHORS_PREFIX, &mut "anchors".to_string().into_bytes())
+}
+
 // Ignore the port unless ip is loopback address.
 fn peer_key(peer_addr: PeerAddr) -> Vec<u8> {
 	to_key(PEER_PREFIX, &mut peer_addr.as_key().into_bytes())
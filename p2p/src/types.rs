@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use crate::util::RwLock;
+use std::cmp;
 use std::convert::From;
 use std::fs::File;
 use std::io::{self, Read};
@@ -23,6 +24,7 @@ use std::sync::mpsc;
 use std::sync::Arc;
 
 use chrono::prelude::*;
+use chrono::Duration;
 
 use crate::chain;
 use crate::core::core;
@@ -55,6 +57,13 @@ const PEER_MAX_COUNT: u32 = 125;
 /// min preferred peer count
 const PEER_MIN_PREFERRED_COUNT: u32 = 8;
 
+/// Best score a peer can reach, bounds how much good behaviour can offset
+/// later misbehaviour
+pub const MAX_PEER_SCORE: i32 = 100;
+
+/// Score at (or below) which a peer gets banned
+pub const BAN_SCORE: i32 = -100;
+
 #[derive(Debug)]
 pub enum Error {
 	Serialization(ser::Error),
@@ -235,6 +244,22 @@ pub struct P2PConfig {
 	pub peer_min_preferred_count: Option<u32>,
 
 	pub dandelion_peer: Option<PeerAddr>,
+
+	/// Max upload rate over all peers, in bytes per second (unlimited if unset)
+	pub upload_rate: Option<u64>,
+
+	/// Max download rate over all peers, in bytes per second
+	pub download_rate: Option<u64>,
+
+	/// Max upload rate to a single peer, in bytes per second
+	pub peer_upload_rate: Option<u64>,
+
+	/// Max download rate from a single peer, in bytes per second
+	pub peer_download_rate: Option<u64>,
+
+	/// Max rate for attachments (txhashset archives, kernel data), in bytes
+	/// per second, each way. Attachments don't count against the other limits.
+	pub attachment_rate: Option<u64>,
 }
 
 /// Default address for peer-to-peer connections.
@@ -254,6 +279,11 @@ impl Default for P2PConfig {
 			peer_max_count: None,
 			peer_min_preferred_count: None,
 			dandelion_peer: None,
+			upload_rate: None,
+			download_rate: None,
+			peer_upload_rate: None,
+			peer_download_rate: None,
+			attachment_rate: None,
 		}
 	}
 }
@@ -321,10 +351,13 @@ bitflags! {
 		const PEER_LIST = 0b00000100;
 		/// Can broadcast and request txs by kernel hash.
 		const TX_KERNEL_HASH = 0b00001000;
+		/// Can provide full blocks back to genesis (archive mode). Pruned
+		/// nodes advertise the lowest block they hold in ping/pong instead.
+		const BLOCK_HIST = 0b00010000;
 
 		/// All nodes right now are "full nodes".
 		/// Some nodes internally may maintain longer block histories (archival_mode)
-		/// but we do not advertise this to other nodes.
+		/// and advertise this with BLOCK_HIST on top.
 		/// All nodes by default will accept lightweight "kernel first" tx broadcast.
 		const FULL_NODE = Capabilities::HEADER_HIST.bits
 			| Capabilities::TXHASHSET_HIST.bits
@@ -354,6 +387,61 @@ enum_from_primitive! {
 		ManualBan = 5,
 		FraudHeight = 6,
 		BadHandshake = 7,
+		LowScore = 8,
+		PoolQuotaExceeded = 9,
+	}
+}
+
+/// Peer behaviour we keep score of. Misbehaviour lowers the score of a peer
+/// and useful contributions raise it, the peer gets banned once its score
+/// drops to BAN_SCORE.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreEvent {
+	/// Sent us a block that is intrinsically invalid
+	BadBlock,
+	/// Sent us a compact block that is intrinsically invalid
+	BadCompactBlock,
+	/// Sent us one or more invalid headers
+	BadBlockHeader,
+	/// Sent us a txhashset archive that failed validation
+	BadTxHashSet,
+	/// Sent us data we never asked for
+	UnrequestedData,
+	/// Did not answer a request or a ping in time
+	SlowResponse,
+	/// Sent us more txs than its share of our pool
+	PoolQuotaExceeded,
+	/// Sent us a valid block
+	UsefulBlock,
+}
+
+impl ScoreEvent {
+	/// How much the event changes the score of a peer.
+	pub fn score_delta(&self) -> i32 {
+		match self {
+			ScoreEvent::BadBlock => -100,
+			ScoreEvent::BadCompactBlock => -100,
+			ScoreEvent::BadBlockHeader => -75,
+			ScoreEvent::BadTxHashSet => -75,
+			ScoreEvent::UnrequestedData => -10,
+			ScoreEvent::SlowResponse => -5,
+			ScoreEvent::PoolQuotaExceeded => -20,
+			ScoreEvent::UsefulBlock => 1,
+		}
+	}
+
+	/// Reason recorded when the event gets the peer banned.
+	pub fn ban_reason(&self) -> ReasonForBan {
+		match self {
+			ScoreEvent::BadBlock => ReasonForBan::BadBlock,
+			ScoreEvent::BadCompactBlock => ReasonForBan::BadCompactBlock,
+			ScoreEvent::BadBlockHeader => ReasonForBan::BadBlockHeader,
+			ScoreEvent::BadTxHashSet => ReasonForBan::BadTxHashSet,
+			ScoreEvent::PoolQuotaExceeded => ReasonForBan::PoolQuotaExceeded,
+			ScoreEvent::UnrequestedData | ScoreEvent::SlowResponse | ScoreEvent::UsefulBlock => {
+				ReasonForBan::LowScore
+			}
+		}
 	}
 }
 
@@ -365,6 +453,14 @@ pub struct PeerLiveInfo {
 	pub stuck_detector: DateTime<Utc>,
 	pub first_seen: DateTime<Utc>,
 	pub local_timestamp: i64,
+	pub score: i32,
+	pub tail_height: Option<u64>,
+	/// Last seen time of the peer when it was last penalized for not
+	/// answering, so a single stall only gets penalized once.
+	pub stall_reported: Option<DateTime<Utc>>,
+	/// When the peer was last penalized for going over its pool quota, so a
+	/// busy relay only gets penalized once per window.
+	pub quota_reported: Option<DateTime<Utc>>,
 }
 
 /// General information about a connected peer that's useful to other modules.
@@ -387,6 +483,10 @@ impl PeerLiveInfo {
 			last_seen: Utc::now(),
 			local_timestamp: 0,
 			stuck_detector: Utc::now(),
+			score: 0,
+			tail_height: None,
+			stall_reported: None,
+			quota_reported: None,
 		}
 	}
 }
@@ -421,6 +521,65 @@ impl PeerInfo {
 		self.live_info.read().local_timestamp
 	}
 
+	/// Current score of the peer.
+	pub fn score(&self) -> i32 {
+		self.live_info.read().score
+	}
+
+	/// Height of the oldest full block the peer advertised it holds, if any.
+	pub fn tail_height(&self) -> Option<u64> {
+		self.live_info.read().tail_height
+	}
+
+	/// Whether the peer is expected to hold the full block at the provided
+	/// height. Peers that did not advertise their tail are assumed to be
+	/// pruned nodes keeping the cut-through horizon, unless archive nodes.
+	pub fn has_block(&self, height: u64) -> bool {
+		match self.tail_height() {
+			Some(tail_height) => tail_height <= height,
+			None => {
+				self.capabilities.contains(Capabilities::BLOCK_HIST)
+					|| self
+						.height()
+						.saturating_sub(global::cut_through_horizon() as u64)
+						<= height
+			}
+		}
+	}
+
+	/// Add delta to the score of the peer, bounded by BAN_SCORE and
+	/// MAX_PEER_SCORE. Returns the new score.
+	pub fn adjust_score(&self, delta: i32) -> i32 {
+		let mut live_info = self.live_info.write();
+		live_info.score = cmp::max(BAN_SCORE, cmp::min(MAX_PEER_SCORE, live_info.score + delta));
+		live_info.score
+	}
+
+	/// Whether the peer hasn't been seen since before the cutoff. Only true
+	/// once per stall, until the peer is seen again.
+	pub fn report_stall(&self, cutoff: DateTime<Utc>) -> bool {
+		let mut live_info = self.live_info.write();
+		if live_info.last_seen >= cutoff || live_info.stall_reported == Some(live_info.last_seen) {
+			return false;
+		}
+		live_info.stall_reported = Some(live_info.last_seen);
+		true
+	}
+
+	/// Whether the peer going over its pool quota should be penalized, at
+	/// most once per window however many txs get rejected meanwhile.
+	pub fn report_quota_exceeded(&self, window: Duration) -> bool {
+		let mut live_info = self.live_info.write();
+		let now = Utc::now();
+		if let Some(at) = live_info.quota_reported {
+			if at + window > now {
+				return false;
+			}
+		}
+		live_info.quota_reported = Some(now);
+		true
+	}
+
 	/// Update the total_difficulty, height and last_seen of the peer.
 	/// Takes a write lock on the live_info.
 	pub fn update(&self, height: u64, total_difficulty: Difficulty, local_timestamp: i64) {
@@ -433,6 +592,12 @@ impl PeerInfo {
 		live_info.last_seen = Utc::now();
 		live_info.local_timestamp = local_timestamp;
 	}
+
+	/// Update the height of the oldest full block held by the peer, unknown
+	/// for peers not advertising it.
+	pub fn update_tail_height(&self, tail_height: Option<u64>) {
+		self.live_info.write().tail_height = tail_height;
+	}
 }
 
 /// Flatten out a PeerInfo and nested PeerLiveInfo (taking a read lock on it)
@@ -446,6 +611,10 @@ pub struct PeerInfoDisplay {
 	pub direction: Direction,
 	pub total_difficulty: Difficulty,
 	pub height: u64,
+	#[serde(default)]
+	pub score: i32,
+	#[serde(default)]
+	pub tail_height: Option<u64>,
 }
 
 impl From<PeerInfo> for PeerInfoDisplay {
@@ -458,6 +627,8 @@ impl From<PeerInfo> for PeerInfoDisplay {
 			direction: info.direction.clone(),
 			total_difficulty: info.total_difficulty(),
 			height: info.height(),
+			score: info.score(),
+			tail_height: info.tail_height(),
 		}
 	}
 }
@@ -483,9 +654,16 @@ pub trait ChainAdapter: Sync + Send {
 	/// Current total height
 	fn total_height(&self) -> Result<u64, chain::Error>;
 
+	/// Height of the oldest full block we can serve
+	fn tail_height(&self) -> Result<u64, chain::Error>;
+
 	/// A valid transaction has been received from one of our peers
-	fn transaction_received(&self, tx: core::Transaction, stem: bool)
-		-> Result<bool, chain::Error>;
+	fn transaction_received(
+		&self,
+		tx: core::Transaction,
+		stem: bool,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction>;
 
@@ -584,12 +762,15 @@ pub trait NetAdapter: ChainAdapter {
 	/// addresses.
 	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddr>;
 
-	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>);
+	/// A list of peers has been received from one of our peers (the source).
+	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>, source: PeerAddr);
 
 	/// Heard total_difficulty from a connected peer (via ping/pong).
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64, _: i64);
 
 	/// Is this peer currently banned?
 	fn is_banned(&self, addr: PeerAddr) -> bool;
+
+	/// Adjust the score of a peer after some good or bad behaviour.
+	fn update_score(&self, addr: PeerAddr, event: ScoreEvent);
 }
@@ -0,0 +1,80 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use epic_p2p as p2p;
+
+use p2p::addrman::{netgroup, AddrManager};
+use p2p::PeerAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+fn addr(a: u8, b: u8, c: u8, d: u8) -> PeerAddr {
+	PeerAddr(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), 3414))
+}
+
+#[test]
+fn test_netgroup() {
+	assert_eq!(
+		netgroup(&addr(54, 233, 1, 1)),
+		netgroup(&addr(54, 233, 200, 7))
+	);
+	assert!(netgroup(&addr(54, 233, 1, 1)) != netgroup(&addr(54, 234, 1, 1)));
+
+	// local addresses are each in their own group
+	let local1 = PeerAddr("127.0.0.1:13414".parse().unwrap());
+	let local2 = PeerAddr("127.0.0.1:13415".parse().unwrap());
+	assert!(netgroup(&local1) != netgroup(&local2));
+	assert!(netgroup(&addr(192, 168, 0, 1)) != netgroup(&addr(192, 168, 0, 2)));
+}
+
+#[test]
+fn test_new_and_tried() {
+	let mut addrman = AddrManager::new();
+	let source = addr(1, 2, 3, 4);
+	let a = addr(54, 233, 1, 1);
+
+	assert!(addrman.add(a, &source));
+	assert!(!addrman.add(a, &source));
+	assert_eq!((addrman.new_count(), addrman.tried_count()), (1, 0));
+
+	addrman.mark_tried(a);
+	assert!(addrman.is_tried(&a));
+	assert_eq!((addrman.new_count(), addrman.tried_count()), (0, 1));
+
+	assert_eq!(addrman.select(10, |_| false), vec![a]);
+	assert!(addrman.select(10, |x| *x == a).is_empty());
+
+	addrman.remove(&a);
+	assert!(!addrman.contains(&a));
+	assert!(addrman.select(10, |_| false).is_empty());
+}
+
+#[test]
+fn test_source_flooding_is_bounded() {
+	let mut addrman = AddrManager::new();
+	// a single source group advertising lots of addresses from lots of groups
+	let source = addr(6, 6, 6, 6);
+	for a in 0..=255 {
+		for b in 0..=255 {
+			addrman.add(addr(a, b, 1, 1), &source);
+		}
+	}
+	// can only fill the few buckets reserved to its group
+	assert!(addrman.new_count() <= 16 * 64);
+
+	// while an honest source still gets its addresses in
+	let honest = addr(8, 8, 8, 8);
+	let a = addr(99, 1, 1, 1);
+	addrman.add(a, &honest);
+	assert!(addrman.contains(&a));
+}
@@ -78,6 +78,7 @@ fn peer_handshake() {
 		my_addr,
 		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
 		net_adapter,
+		&p2p::BandwidthLimits::default(),
 	)
 	.unwrap();
 
@@ -85,7 +86,7 @@ fn peer_handshake() {
 
 	thread::sleep(time::Duration::from_secs(1));
 
-	peer.send_ping(Difficulty::min(), 0, Utc::now().timestamp()).unwrap();
+	peer.send_ping(Difficulty::min(), 0, Utc::now().timestamp(), 0).unwrap();
 	thread::sleep(time::Duration::from_secs(1));
 
 	let server_peer = server.peers.get_connected_peer(my_addr).unwrap();
@@ -12,10 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use epic_core as core;
 use epic_p2p as p2p;
 
 use num::FromPrimitive;
 
+use crate::core::pow::Difficulty;
+use crate::core::ser;
+
 // Test that Healthy == 0.
 #[test]
 fn test_store_state_enum() {
@@ -35,6 +39,35 @@ fn test_reason_for_ban_enum() {
 	);
 }
 
+#[test]
+fn test_score_event_ban_reason() {
+	use p2p::types::{ReasonForBan, ScoreEvent};
+
+	assert_eq!(ReasonForBan::from_i32(8), Some(ReasonForBan::LowScore));
+	assert_eq!(ScoreEvent::BadBlock.ban_reason(), ReasonForBan::BadBlock);
+	assert_eq!(ScoreEvent::SlowResponse.ban_reason(), ReasonForBan::LowScore);
+	assert!(ScoreEvent::UsefulBlock.score_delta() > 0);
+	assert!(ScoreEvent::UnrequestedData.score_delta() < 0);
+	// a single bad block is enough to get a neutral peer banned
+	assert!(ScoreEvent::BadBlock.score_delta() <= p2p::types::BAN_SCORE);
+	// while invalid headers or txhashsets take a couple of times, or a
+	// few more for a peer that behaved well so far
+	assert!(ScoreEvent::BadBlockHeader.score_delta() * 2 <= p2p::types::BAN_SCORE);
+	assert!(ScoreEvent::BadTxHashSet.score_delta() * 3 <= p2p::types::BAN_SCORE);
+	assert!(ScoreEvent::BadTxHashSet.score_delta() > p2p::types::BAN_SCORE);
+	assert_eq!(
+		ReasonForBan::from_i32(9),
+		Some(ReasonForBan::PoolQuotaExceeded)
+	);
+	assert_eq!(
+		ScoreEvent::PoolQuotaExceeded.ban_reason(),
+		ReasonForBan::PoolQuotaExceeded
+	);
+	// a peer repeatedly going over its pool quota ends up banned
+	assert!(ScoreEvent::PoolQuotaExceeded.score_delta() < 0);
+	assert!(ScoreEvent::PoolQuotaExceeded.score_delta() > p2p::types::BAN_SCORE);
+}
+
 #[test]
 fn test_type_enum() {
 	assert_eq!(p2p::msg::Type::from_i32(0), Some(p2p::msg::Type::Error));
@@ -61,7 +94,7 @@ fn test_capabilities() {
 	);
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b11111111 as u32),
-		p2p::types::Capabilities::FULL_NODE
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::BLOCK_HIST
 	);
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32),
@@ -78,3 +111,23 @@ fn test_capabilities() {
 			.contains(p2p::types::Capabilities::TX_KERNEL_HASH)
 	);
 }
+
+#[test]
+fn test_ping_tail_height() {
+	use p2p::msg::Ping;
+
+	let ping = Ping {
+		total_difficulty: Difficulty::min(),
+		height: 100,
+		local_timestamp: 0,
+		tail_height: Some(20),
+	};
+	let bytes = ser::ser_vec(&ping).unwrap();
+	let read: Ping = ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(read.tail_height, Some(20));
+
+	// older peers don't send their tail
+	let read: Ping = ser::deserialize(&mut &bytes[..bytes.len() - 8]).unwrap();
+	assert_eq!(read.height, 100);
+	assert_eq!(read.tail_height, None);
+}
@@ -0,0 +1,193 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fee estimation. Follows the txs of the txpool until they get mined or
+//! evicted, and tells from their fee rate (`fee_to_weight`) which one gets
+//! a tx mined within a number of blocks.
+
+use self::core::core::hash::{Hash, Hashed};
+use self::core::core::Transaction;
+use epic_core as core;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// Number of recent blocks the outcome of txs is kept for.
+pub const FEE_ESTIMATE_BLOCKS: usize = 100;
+
+/// Largest number of blocks we estimate a fee rate for.
+pub const MAX_TARGET_BLOCKS: u64 = 50;
+
+/// Fraction of the txs paying at least the estimated fee rate that got mined
+/// within the target number of blocks.
+const SUCCESS_RATIO: f64 = 0.85;
+
+/// Number of txs an estimate has to be based on.
+const MIN_SAMPLES: usize = 10;
+
+/// Ratio between the fee rates of two consecutive buckets.
+const FEE_RATE_BUCKET_SPACING: f64 = 1.1;
+
+/// Precision multiplier of `Transaction::fee_to_weight`, the fee rate being
+/// a thousand times the fee per unit of weight.
+const FEE_RATE_PRECISION: u64 = 1_000;
+
+/// What became of the txs that left the txpool with a block.
+struct BlockOutcome {
+	/// Fee rate of the txs mined in the block, and how many blocks they
+	/// waited in the pool.
+	mined: Vec<(u64, u64)>,
+	/// Fee rate of the txs evicted from the pool since the previous block.
+	evicted: Vec<u64>,
+}
+
+/// Fee estimator, fed by the transaction pool.
+pub struct FeeEstimator {
+	/// Fee rate of the txs in the txpool, with the chain height when they came
+	/// in.
+	pending: HashMap<Hash, (u64, u64)>,
+	/// Fee rate of the txs evicted since the last block.
+	evicted: Vec<u64>,
+	/// Outcome of the txs for the recent blocks, oldest first.
+	blocks: VecDeque<BlockOutcome>,
+	/// Height of the last block seen.
+	height: u64,
+}
+
+impl FeeEstimator {
+	/// An estimator knowing nothing yet.
+	pub fn new() -> FeeEstimator {
+		FeeEstimator {
+			pending: HashMap::new(),
+			evicted: vec![],
+			blocks: VecDeque::new(),
+			height: 0,
+		}
+	}
+
+	/// A tx went into the txpool, with the chain at the provided height.
+	pub fn tx_added(&mut self, tx: &Transaction, height: u64) {
+		self.height = self.height.max(height);
+		self.pending
+			.entry(tx.hash())
+			.or_insert((tx.fee_to_weight(), height));
+	}
+
+	/// A tx was evicted from the txpool without being mined.
+	pub fn tx_evicted(&mut self, hash: &Hash) {
+		if let Some((fee_rate, _)) = self.pending.remove(hash) {
+			self.evicted.push(fee_rate);
+		}
+	}
+
+	/// A block at the provided height was added to the chain. Txs it mined
+	/// are the mined ones, txs that are neither mined nor still in the pool
+	/// were evicted.
+	pub fn block_processed(&mut self, height: u64, mined: &[Hash], in_pool: &HashSet<Hash>) {
+		self.height = height;
+		let mut outcome = BlockOutcome {
+			mined: vec![],
+			evicted: self.evicted.drain(..).collect(),
+		};
+		for hash in mined {
+			if let Some((fee_rate, added_at)) = self.pending.remove(hash) {
+				outcome
+					.mined
+					.push((fee_rate, height.saturating_sub(added_at).max(1)));
+			}
+		}
+		let gone = self
+			.pending
+			.keys()
+			.filter(|hash| !in_pool.contains(hash))
+			.cloned()
+			.collect::<Vec<_>>();
+		for hash in gone {
+			if let Some((fee_rate, _)) = self.pending.remove(&hash) {
+				outcome.evicted.push(fee_rate);
+			}
+		}
+		self.blocks.push_back(outcome);
+		if self.blocks.len() > FEE_ESTIMATE_BLOCKS {
+			self.blocks.pop_front();
+		}
+	}
+
+	/// Lowest fee rate, as `fee_to_weight`, for a tx to get mined within the
+	/// target number of blocks. Txs mined later than that, evicted, or still
+	/// in the pool after that many blocks all count as failures. None if too
+	/// few txs came through recently to tell.
+	pub fn estimate_fee_rate(&self, target_blocks: u64) -> Option<u64> {
+		let target_blocks = target_blocks.max(1).min(MAX_TARGET_BLOCKS);
+
+		// (successes, total) by bucket
+		let mut buckets: BTreeMap<i32, (usize, usize)> = BTreeMap::new();
+		let mut add = |fee_rate: u64, success: bool| {
+			let counts = buckets.entry(bucket(fee_rate)).or_insert((0, 0));
+			counts.0 += success as usize;
+			counts.1 += 1;
+		};
+		for block in &self.blocks {
+			for (fee_rate, waited) in &block.mined {
+				add(*fee_rate, *waited <= target_blocks);
+			}
+			for fee_rate in &block.evicted {
+				add(*fee_rate, false);
+			}
+		}
+		for fee_rate in &self.evicted {
+			add(*fee_rate, false);
+		}
+		for (fee_rate, added_at) in self.pending.values() {
+			if self.height.saturating_sub(*added_at) > target_blocks {
+				add(*fee_rate, false);
+			}
+		}
+
+		// Walk down from the highest fee rates as long as enough of the txs
+		// paying at least the bucket rate got mined in time.
+		let (mut successes, mut total) = (0, 0);
+		let mut estimate = None;
+		for (bucket, (bucket_successes, bucket_total)) in buckets.iter().rev() {
+			successes += bucket_successes;
+			total += bucket_total;
+			if total < MIN_SAMPLES {
+				continue;
+			}
+			if (successes as f64) < SUCCESS_RATIO * total as f64 {
+				break;
+			}
+			estimate = Some(bucket_fee_rate(*bucket));
+		}
+		estimate
+	}
+}
+
+/// Bucket of a fee rate, buckets being spaced exponentially.
+fn bucket(fee_rate: u64) -> i32 {
+	((fee_rate.max(1) as f64).ln() / FEE_RATE_BUCKET_SPACING.ln()).floor() as i32
+}
+
+/// Lowest fee rate of a bucket.
+fn bucket_fee_rate(bucket: i32) -> u64 {
+	FEE_RATE_BUCKET_SPACING.powi(bucket).ceil() as u64
+}
+
+/// Fee rate, as `fee_to_weight`, of a fee per unit of weight.
+pub fn fee_base_to_rate(fee_base: u64) -> u64 {
+	fee_base.saturating_mul(FEE_RATE_PRECISION)
+}
+
+/// Fee per unit of weight paying at least the provided fee rate.
+pub fn fee_rate_to_base(fee_rate: u64) -> u64 {
+	fee_rate.saturating_add(FEE_RATE_PRECISION - 1) / FEE_RATE_PRECISION
+}
@@ -30,12 +30,17 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+pub mod fee_estimator;
+mod persistence;
 mod pool;
+pub mod rate_limit;
 pub mod transaction_pool;
 pub mod types;
 
+pub use crate::persistence::POOL_FILE;
 pub use crate::pool::Pool;
 pub use crate::transaction_pool::TransactionPool;
 pub use crate::types::{
-	BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource,
+	BlockChain, DandelionConfig, FeeEstimate, PoolAdapter, PoolConfig, PoolEntry, PoolError,
+	TxSource,
 };
@@ -0,0 +1,107 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistence of the transaction pool across node restarts. The txpool,
+//! stempool and reorg cache entries are saved to a single file, to be read
+//! back and revalidated against the chain on startup.
+
+use self::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::types::{PoolEntry, PoolError};
+use epic_core as core;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Name of the file the pool is saved to, in the db root.
+pub const POOL_FILE: &str = "pool.bin";
+
+/// Age past which saved txpool and stempool entries are dropped.
+pub const ENTRY_EXPIRY_SECS: i64 = 24 * 60 * 60;
+
+/// Age past which saved reorg cache entries are dropped, the reorg cache only
+/// keeping 30 mins of txs.
+pub const REORG_CACHE_EXPIRY_SECS: i64 = 30 * 60;
+
+/// Version of the pool file format.
+const POOL_FILE_VERSION: u8 = 1;
+
+/// Entries of the pool, as saved to disk.
+pub struct PoolSnapshot {
+	/// Txpool entries, in insertion order
+	pub txpool: Vec<PoolEntry>,
+	/// Stempool entries, in insertion order
+	pub stempool: Vec<PoolEntry>,
+	/// Reorg cache entries, oldest first
+	pub reorg_cache: Vec<PoolEntry>,
+}
+
+impl Writeable for PoolSnapshot {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(POOL_FILE_VERSION)?;
+		for entries in &[&self.txpool, &self.stempool, &self.reorg_cache] {
+			writer.write_u64(entries.len() as u64)?;
+			for entry in entries.iter() {
+				entry.write(writer)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Readable for PoolSnapshot {
+	fn read(reader: &mut dyn Reader) -> Result<PoolSnapshot, ser::Error> {
+		if reader.read_u8()? != POOL_FILE_VERSION {
+			return Err(ser::Error::CorruptedData);
+		}
+		Ok(PoolSnapshot {
+			txpool: read_entries(reader)?,
+			stempool: read_entries(reader)?,
+			reorg_cache: read_entries(reader)?,
+		})
+	}
+}
+
+fn read_entries(reader: &mut dyn Reader) -> Result<Vec<PoolEntry>, ser::Error> {
+	let len = reader.read_u64()?;
+	ser::read_multi(reader, len)
+}
+
+/// Saves the pool entries to the provided file, going through a temporary
+/// file so a crash never leaves a partially written pool behind.
+pub fn save(path: &Path, snapshot: &PoolSnapshot) -> Result<(), PoolError> {
+	let tmp_path = path.with_extension("tmp");
+	{
+		let mut writer = BufWriter::new(File::create(&tmp_path).map_err(io_error)?);
+		ser::serialize(&mut writer, snapshot)
+			.map_err(|e| PoolError::Other(format!("pool serialization error: {}", e)))?;
+		writer.flush().map_err(io_error)?;
+		writer.get_ref().sync_all().map_err(io_error)?;
+	}
+	fs::rename(&tmp_path, path).map_err(io_error)
+}
+
+/// Reads the pool entries saved to the provided file, None if there is none.
+pub fn load(path: &Path) -> Result<Option<PoolSnapshot>, PoolError> {
+	if !path.exists() {
+		return Ok(None);
+	}
+	let mut reader = BufReader::new(File::open(path).map_err(io_error)?);
+	let snapshot = ser::deserialize(&mut reader)
+		.map_err(|e| PoolError::Other(format!("pool deserialization error: {}", e)))?;
+	Ok(Some(snapshot))
+}
+
+fn io_error(e: std::io::Error) -> PoolError {
+	PoolError::Other(format!("pool file error: {}", e))
+}
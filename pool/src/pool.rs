@@ -20,13 +20,15 @@ use self::core::core::id::{ShortId, ShortIdentifiable};
 use self::core::core::transaction;
 use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{
-	Block, BlockHeader, BlockSums, Committed, Transaction, TxKernel, Weighting,
+	Block, BlockHeader, BlockSums, Committed, Transaction, TransactionBody, TxKernel, Weighting,
 };
+use self::core::{consensus, global};
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
 use crate::types::{BlockChain, PoolEntry, PoolError};
 use epic_core as core;
 use epic_util as util;
-use std::cmp::Reverse;
+use std::cmp::{min, Reverse};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
@@ -122,6 +124,7 @@ impl Pool {
 	pub fn prepare_mineable_transactions(
 		&self,
 		max_weight: usize,
+		min_fee_to_weight: u64,
 	) -> Result<Vec<Transaction>, PoolError> {
 		let weighting = Weighting::AsLimitedTransaction(max_weight);
 
@@ -129,7 +132,8 @@ impl Pool {
 		//   * maintain dependency ordering
 		//   * maximize cut-through
 		//   * maximize overall fees
-		let txs = self.bucket_transactions(weighting);
+		//   * leave out txs below the minimum fee rate
+		let txs = self.bucket_transactions(weighting, min_fee_to_weight);
 
 		// Iteratively apply the txs to the current chain state,
 		// rejecting any that do not result in a valid state.
@@ -305,15 +309,27 @@ impl Pool {
 
 	/// Buckets consist of a vec of txs and track the aggregate fee_to_weight.
 	/// We aggregate (cut-through) dependent transactions within a bucket *unless* adding a tx
-	/// would reduce the aggregate fee_to_weight, in which case we start a new bucket.
-	/// Note this new bucket will by definition have a lower fee_to_weight than the bucket
-	/// containing the tx it depends on.
-	/// Sorting the buckets by fee_to_weight will therefore preserve dependency ordering,
+	/// would reduce the aggregate fee_to_weight, in which case we start a new bucket depending
+	/// on the bucket of its parent.
+	/// Child pays for parent: whenever a bucket ends up with a higher fee_to_weight than the
+	/// bucket it depends on, both are merged, a high fee child lifting its low fee parents.
+	/// A bucket therefore never has a higher fee_to_weight than the bucket it depends on and
+	/// sorting the buckets by fee_to_weight (then age) preserves dependency ordering,
 	/// maximizing both cut-through and overall fees.
-	pub fn bucket_transactions(&self, weighting: Weighting) -> Vec<Transaction> {
+	/// Buckets with a fee_to_weight below the provided minimum are left out. If the weighting
+	/// limits the weight, buckets are taken whole in order, a bucket that doesn't fit being
+	/// skipped (along with the buckets depending on it) in favour of later ones that do. The
+	/// weight limit only applies to the selection, a chain of txs is scored as a whole.
+	pub fn bucket_transactions(
+		&self,
+		weighting: Weighting,
+		min_fee_to_weight: u64,
+	) -> Vec<Transaction> {
 		let mut tx_buckets: Vec<Bucket> = Vec::new();
 		let mut output_commits = HashMap::new();
 		let mut rejected = HashSet::new();
+		// buckets merged into the bucket they depend on
+		let mut merged = HashMap::new();
 
 		for entry in &self.entries {
 			// check the commits index to find parents and their position
@@ -328,13 +344,14 @@ impl Pool {
 					is_rejected = true;
 					continue;
 				} else if let Some(pos) = output_commits.get(&input.commitment()) {
-					if insert_pos.is_some() {
+					let pos = merged_pos(&merged, *pos);
+					if insert_pos.is_some() && insert_pos != Some(pos) {
 						// Multiple dependencies so reject this tx (pick it up in next block).
 						is_rejected = true;
 						continue;
 					} else {
 						// Track the pos of the bucket we fall into.
-						insert_pos = Some(*pos);
+						insert_pos = Some(pos);
 					}
 				}
 			}
@@ -355,7 +372,7 @@ impl Pool {
 					// This is the common case for non 0-conf txs in the txpool.
 					// We assume the tx is valid here as we validated it on the way into the txpool.
 					insert_pos = Some(tx_buckets.len());
-					tx_buckets.push(Bucket::new(entry.tx.clone()));
+					tx_buckets.push(Bucket::new(entry.tx.clone(), tx_buckets.len(), None));
 				}
 				Some(pos) => {
 					// We found a single parent tx, so aggregate in the bucket
@@ -363,19 +380,23 @@ impl Pool {
 					// Otherwise discard and let the next block pick this tx up.
 					let bucket = &tx_buckets[pos];
 
-					if let Ok(new_bucket) = bucket.aggregate_with_tx(
-						entry.tx.clone(),
-						weighting,
+					if let Ok(new_bucket) = bucket.aggregate_with_txs(
+						vec![entry.tx.clone()],
+						Weighting::NoLimit,
 						self.verifier_cache.clone(),
 					) {
 						if new_bucket.fee_to_weight >= bucket.fee_to_weight {
 							// Only aggregate if it would not reduce the fee_to_weight ratio.
 							tx_buckets[pos] = new_bucket;
 						} else {
-							// Otherwise put it in its own bucket at the end.
-							// Note: This bucket will have a lower fee_to_weight
-							// than the bucket it depends on.
-							tx_buckets.push(Bucket::new(entry.tx.clone()));
+							// Otherwise put it in its own bucket at the end,
+							// depending on the bucket of its parent.
+							insert_pos = Some(tx_buckets.len());
+							tx_buckets.push(Bucket::new(
+								entry.tx.clone(),
+								tx_buckets.len(),
+								Some(pos),
+							));
 						}
 					} else {
 						// Aggregation failed so discard this new tx.
@@ -388,25 +409,56 @@ impl Pool {
 				for out in entry.tx.outputs() {
 					rejected.insert(out.commitment());
 				}
-			} else if let Some(insert_pos) = insert_pos {
+			} else if let Some(mut pos) = insert_pos {
+				// Child pays for parent, merge the bucket into the one it depends on
+				// as long as it pays more.
+				while let Some(parent) = tx_buckets[pos].parent.map(|x| merged_pos(&merged, x)) {
+					if tx_buckets[pos].fee_to_weight <= tx_buckets[parent].fee_to_weight {
+						break;
+					}
+					let txs = tx_buckets[pos].raw_txs.clone();
+					match tx_buckets[parent].aggregate_with_txs(
+						txs,
+						Weighting::NoLimit,
+						self.verifier_cache.clone(),
+					) {
+						Ok(new_bucket) => {
+							tx_buckets[parent] = new_bucket;
+							tx_buckets[pos].raw_txs.clear();
+							merged.insert(pos, parent);
+							pos = parent;
+						}
+						// Leave it as is, it gets picked up once its parent is mined.
+						Err(_) => break,
+					}
+				}
+
 				// We successfully added this tx to our set of buckets.
 				// Update commits index for subsequent txs.
 				for out in entry.tx.outputs() {
-					output_commits.insert(out.commitment(), insert_pos);
+					output_commits.insert(out.commitment(), pos);
 				}
 			}
 		}
 
-		// Sort them by fee_to_weight (descending).
+		for bucket in tx_buckets.iter_mut() {
+			bucket.parent = bucket.parent.map(|x| merged_pos(&merged, x));
+		}
+
+		// Sort them by fee_to_weight (descending), then age so a bucket never comes
+		// before the bucket it depends on.
 		// Txs with no dependencies will be toward the start of the vec.
 		// Txs with a big chain of dependencies will be toward the end of the vec.
-		tx_buckets.sort_unstable_by_key(|x| Reverse(x.fee_to_weight));
+		tx_buckets.sort_unstable_by_key(|x| (Reverse(x.fee_to_weight), x.age_idx));
 
-		tx_buckets
+		let tx_buckets = tx_buckets
 			.into_iter()
-			.map(|x| x.raw_txs)
-			.flatten()
-			.collect()
+			.filter(|x| !x.raw_txs.is_empty() && x.fee_to_weight >= min_fee_to_weight);
+
+		match max_weight(weighting) {
+			Some(max_weight) => truncate_to_weight(tx_buckets, max_weight),
+			None => tx_buckets.map(|x| x.raw_txs).flatten().collect(),
+		}
 	}
 
 	pub fn find_matching_transactions(&self, kernels: &[TxKernel]) -> Vec<Transaction> {
@@ -427,6 +479,28 @@ impl Pool {
 		found_txs
 	}
 
+	/// Entries spending any of the provided output commitments, directly or
+	/// through other entries. Outputs of these entries are added to the
+	/// commitments, so they can be looked for in another pool.
+	pub fn find_spending_entries(&self, commits: &mut HashSet<Commitment>) -> Vec<PoolEntry> {
+		let mut found = vec![];
+		// Entries are in insertion order, parents before children.
+		for entry in &self.entries {
+			if entry
+				.tx
+				.inputs()
+				.iter()
+				.any(|x| commits.contains(&x.commitment()))
+			{
+				for out in entry.tx.outputs() {
+					commits.insert(out.commitment());
+				}
+				found.push(entry.clone());
+			}
+		}
+		found
+	}
+
 	/// Quick reconciliation step - we can evict any txs in the pool where
 	/// inputs or kernels intersect with the block.
 	pub fn reconcile_block(&mut self, block: &Block) {
@@ -440,6 +514,14 @@ impl Pool {
 		});
 	}
 
+	/// Removes the last tx of the bucket transactions, it has the lowest fee_to_weight and no
+	/// other tx depends on it. Returns the evicted tx, None if the pool is empty.
+	pub fn evict_lowest_fee(&mut self) -> Option<Transaction> {
+		let tx = self.bucket_transactions(Weighting::NoLimit, 0).pop()?;
+		self.entries.retain(|x| x.tx != tx);
+		Some(tx)
+	}
+
 	/// Size of the pool.
 	pub fn size(&self) -> usize {
 		self.entries.len()
@@ -454,29 +536,127 @@ impl Pool {
 struct Bucket {
 	raw_txs: Vec<Transaction>,
 	fee_to_weight: u64,
+	age_idx: usize,
+	/// Bucket this one depends on, by age_idx
+	parent: Option<usize>,
 }
 
 impl Bucket {
-	fn new(tx: Transaction) -> Bucket {
+	fn new(tx: Transaction, age_idx: usize, parent: Option<usize>) -> Bucket {
 		Bucket {
 			fee_to_weight: tx.fee_to_weight(),
 			raw_txs: vec![tx.clone()],
+			age_idx,
+			parent,
 		}
 	}
 
-	fn aggregate_with_tx(
+	fn aggregate_with_txs(
 		&self,
-		new_tx: Transaction,
+		new_txs: Vec<Transaction>,
 		weighting: Weighting,
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	) -> Result<Bucket, PoolError> {
 		let mut raw_txs = self.raw_txs.clone();
-		raw_txs.push(new_tx);
+		raw_txs.extend(new_txs);
 		let agg_tx = transaction::aggregate(raw_txs.clone())?;
 		agg_tx.validate(weighting, verifier_cache)?;
 		Ok(Bucket {
 			fee_to_weight: agg_tx.fee_to_weight(),
 			raw_txs: raw_txs,
+			age_idx: self.age_idx,
+			parent: self.parent,
 		})
 	}
 }
+
+/// Position of the bucket that ended up with the txs of the bucket at the
+/// provided position, following merges.
+fn merged_pos(merged: &HashMap<usize, usize>, mut pos: usize) -> usize {
+	while let Some(x) = merged.get(&pos) {
+		pos = *x;
+	}
+	pos
+}
+
+/// Max weight (as block) of the txs selected under the provided weighting, leaving room
+/// for the coinbase reward. None if not limited.
+fn max_weight(weighting: Weighting) -> Option<usize> {
+	let coinbase_weight = consensus::BLOCK_OUTPUT_WEIGHT + consensus::BLOCK_KERNEL_WEIGHT;
+	match weighting {
+		Weighting::AsTransaction => {
+			Some(global::max_block_weight().saturating_sub(coinbase_weight))
+		}
+		Weighting::AsLimitedTransaction(max_weight) => {
+			Some(min(global::max_block_weight(), max_weight).saturating_sub(coinbase_weight))
+		}
+		Weighting::AsBlock => Some(global::max_block_weight()),
+		Weighting::NoLimit => None,
+	}
+}
+
+/// Takes the txs of the sorted buckets as long as they fit in max_weight, accounting for
+/// cut-through between them. A bucket goes in completely or not at all, a prefix of a bucket
+/// merged for its children fees would leave their fees out. Buckets that don't fit are skipped
+/// in favour of later ones that do, and a bucket is only considered once the bucket it depends
+/// on went in.
+fn truncate_to_weight<I>(tx_buckets: I, max_weight: usize) -> Vec<Transaction>
+where
+	I: Iterator<Item = Bucket>,
+{
+	let mut txs = vec![];
+	let mut weight = 0;
+	let mut output_commits = HashSet::new();
+	let mut complete = HashSet::new();
+
+	for bucket in tx_buckets {
+		if let Some(parent) = bucket.parent {
+			if !complete.contains(&parent) {
+				continue;
+			}
+		}
+		// Outputs the bucket adds and those it spends from the ones taken so
+		// far, only applied once the whole bucket fits.
+		let mut bucket_weight = weight;
+		let mut added_commits = HashSet::new();
+		let mut spent_commits = HashSet::new();
+		let mut fits = true;
+		for tx in &bucket.raw_txs {
+			// An input spending an output we took cuts through both.
+			let (spent, unspent): (Vec<_>, Vec<_>) =
+				tx.inputs().iter().map(|x| x.commitment()).partition(|x| {
+					added_commits.contains(x)
+						|| (output_commits.contains(x) && !spent_commits.contains(x))
+				});
+			let added = TransactionBody::weight_as_block(
+				unspent.len(),
+				tx.outputs().len(),
+				tx.kernels().len(),
+			);
+			let removed = spent.len() * consensus::BLOCK_OUTPUT_WEIGHT;
+			bucket_weight = (bucket_weight + added).saturating_sub(removed);
+			if bucket_weight > max_weight {
+				fits = false;
+				break;
+			}
+			for commit in spent {
+				if !added_commits.remove(&commit) {
+					spent_commits.insert(commit);
+				}
+			}
+			for out in tx.outputs() {
+				added_commits.insert(out.commitment());
+			}
+		}
+		if fits {
+			weight = bucket_weight;
+			for commit in &spent_commits {
+				output_commits.remove(commit);
+			}
+			output_commits.extend(added_commits);
+			txs.extend(bucket.raw_txs);
+			complete.insert(bucket.age_idx);
+		}
+	}
+	txs
+}
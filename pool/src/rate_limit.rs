@@ -0,0 +1,74 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rolling limit on the transaction weight admitted to the pool from each
+//! peer and API client, so none of them can churn through it faster than
+//! blocks get mined, nor use up the budget of the others.
+
+use crate::types::TxSource;
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+use std::collections::{HashMap, VecDeque};
+
+/// Length of the rolling window the admitted weight is limited over.
+pub const RATE_LIMIT_WINDOW_SECS: i64 = 60;
+
+/// Weight of the txs admitted from each source over the last rolling window.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimit {
+	admitted: VecDeque<(DateTime<Utc>, TxSource, u64)>,
+	totals: HashMap<TxSource, u64>,
+}
+
+impl RateLimit {
+	/// New rate limit, nothing admitted yet.
+	pub fn new() -> RateLimit {
+		RateLimit::default()
+	}
+
+	/// Weight admitted from the source within the window ending at the
+	/// provided time.
+	pub fn admitted_weight(&mut self, src: &TxSource, now: DateTime<Utc>) -> u64 {
+		let cutoff = now - Duration::seconds(RATE_LIMIT_WINDOW_SECS);
+		while self.admitted.front().map_or(false, |x| x.0 <= cutoff) {
+			if let Some((_, expired_src, weight)) = self.admitted.pop_front() {
+				let total = self.totals.get(&expired_src).map_or(0, |x| *x);
+				if total > weight {
+					self.totals.insert(expired_src, total - weight);
+				} else {
+					self.totals.remove(&expired_src);
+				}
+			}
+		}
+		self.totals.get(src).map_or(0, |x| *x)
+	}
+
+	/// Whether admitting the additional weight from the source keeps it
+	/// within the limit.
+	pub fn allows(
+		&mut self,
+		src: &TxSource,
+		weight: u64,
+		max_weight: u64,
+		now: DateTime<Utc>,
+	) -> bool {
+		self.admitted_weight(src, now).saturating_add(weight) <= max_weight
+	}
+
+	/// Count the weight of a tx newly admitted from the source.
+	pub fn admit(&mut self, src: &TxSource, weight: u64, now: DateTime<Utc>) {
+		self.admitted.push_back((now, src.clone(), weight));
+		*self.totals.entry(src.clone()).or_insert(0) += weight;
+	}
+}
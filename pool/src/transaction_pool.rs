@@ -22,14 +22,26 @@ use self::core::core::id::ShortId;
 use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{transaction, Block, BlockHeader, Transaction, Weighting};
 use self::util::RwLock;
+use crate::fee_estimator::{self, FeeEstimator, MAX_TARGET_BLOCKS};
+use crate::persistence::{self, PoolSnapshot, ENTRY_EXPIRY_SECS, REORG_CACHE_EXPIRY_SECS};
 use crate::pool::Pool;
-use crate::types::{BlockChain, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource};
+use crate::rate_limit::RateLimit;
+use crate::types::{
+	BlockChain, FeeEstimate, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource,
+};
 use chrono::prelude::*;
+use chrono::Duration;
 use epic_core as core;
 use epic_util as util;
-use std::collections::VecDeque;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
 use std::sync::Arc;
 
+/// Maximum number of txs a replacement can evict from the pool, counting the
+/// txs depending on the ones it double spends.
+pub const MAX_REPLACED_TXS: usize = 100;
+
 /// Transaction pool implementation.
 pub struct TransactionPool {
 	/// Pool Config
@@ -45,6 +57,10 @@ pub struct TransactionPool {
 	pub verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	/// The pool adapter
 	pub adapter: Arc<dyn PoolAdapter>,
+	/// Fee estimator, following the txpool txs.
+	pub fee_estimator: FeeEstimator,
+	/// Weight admitted recently from each peer and API client.
+	pub rate_limit: RateLimit,
 }
 
 impl TransactionPool {
@@ -67,6 +83,8 @@ impl TransactionPool {
 			blockchain: chain,
 			verifier_cache,
 			adapter,
+			fee_estimator: FeeEstimator::new(),
+			rate_limit: RateLimit::new(),
 		}
 	}
 
@@ -112,6 +130,7 @@ impl TransactionPool {
 			}
 		}
 		self.txpool.add_to_pool(entry.clone(), vec![], header)?;
+		self.fee_estimator.tx_added(&entry.tx, header.height);
 
 		// We now need to reconcile the stempool based on the new state of the txpool.
 		// Some stempool txs may no longer be valid and we need to evict them.
@@ -137,6 +156,12 @@ impl TransactionPool {
 			return Err(PoolError::DuplicateTx);
 		}
 
+		// Peers and API clients only get their share of the pool.
+		let quota = src.quota(&self.config);
+		if let Some(quota) = quota {
+			self.check_admission(&src, quota, &tx)?;
+		}
+
 		// Do we have the capacity to accept this transaction?
 		let acceptability = self.is_acceptable(&tx, stem);
 		let mut evict = false;
@@ -153,10 +178,37 @@ impl TransactionPool {
 
 		// Check the tx lock_time is valid based on current chain state.
 		self.blockchain.verify_tx_lock_height(&tx)?;
+		self.blockchain.verify_tx_relative_lock_height(&tx)?;
 
 		// Check coinbase maturity before we go any further.
 		self.blockchain.verify_coinbase_maturity(&tx)?;
 
+		// Make room for the tx if it pays enough to replace the txs it double spends,
+		// keeping them around until the tx gets in.
+		let replaced = if self.config.replace_by_fee {
+			self.replaced_entries(&tx)?
+		} else {
+			vec![]
+		};
+		let replaced = replaced.iter().map(|x| x.tx.hash()).collect::<HashSet<_>>();
+		let backup = if replaced.is_empty() {
+			None
+		} else {
+			debug!(
+				"replace_by_fee: {} replacing {} txs",
+				tx.hash(),
+				replaced.len()
+			);
+			let backup = (self.txpool.entries.clone(), self.stempool.entries.clone());
+			self.txpool
+				.entries
+				.retain(|x| !replaced.contains(&x.tx.hash()));
+			self.stempool
+				.entries
+				.retain(|x| !replaced.contains(&x.tx.hash()));
+			Some(backup)
+		};
+
 		let entry = PoolEntry {
 			src,
 			tx_at: Utc::now(),
@@ -172,11 +224,33 @@ impl TransactionPool {
 				.and_then(|_| self.adapter.stem_tx_accepted(&entry.tx))
 				.is_err()
 		{
-			self.add_to_txpool(entry.clone(), header)?;
+			if let Err(e) = self.add_to_txpool(entry.clone(), header) {
+				// Nothing gets replaced after all.
+				if let Some((txpool, stempool)) = backup {
+					self.txpool.entries = txpool;
+					self.stempool.entries = stempool;
+				}
+				return Err(e);
+			}
 			self.add_to_reorg_cache(entry.clone());
 			self.adapter.tx_accepted(&entry.tx);
 		}
 
+		if quota.is_some() {
+			self.rate_limit
+				.admit(&entry.src, entry.tx.tx_weight_as_block() as u64, Utc::now());
+		}
+
+		// The replaced txs are gone for good, a re-org doesn't bring them back.
+		if !replaced.is_empty() {
+			for hash in &replaced {
+				self.fee_estimator.tx_evicted(hash);
+			}
+			self.reorg_cache
+				.write()
+				.retain(|x| !replaced.contains(&x.tx.hash()));
+		}
+
 		// Transaction passed all the checks but we have to make space for it
 		if evict {
 			self.evict_from_txpool();
@@ -185,25 +259,84 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	/// Fails if the source of the tx already has as many txs in the txpool
+	/// and stempool as its quota allows, or if admitting the tx would go over
+	/// the weight admitted from it over the rolling window.
+	fn check_admission(
+		&mut self,
+		src: &TxSource,
+		quota: usize,
+		tx: &Transaction,
+	) -> Result<(), PoolError> {
+		let count = self
+			.txpool
+			.entries
+			.iter()
+			.chain(self.stempool.entries.iter())
+			.filter(|x| x.src.debug_name == src.debug_name && x.src.identifier == src.identifier)
+			.count();
+		if count >= quota {
+			return Err(PoolError::QuotaExceeded(src.identifier.clone()));
+		}
+
+		let weight = tx.tx_weight_as_block() as u64;
+		let max_weight = self.config.max_admitted_weight_per_min;
+		if !self.rate_limit.allows(src, weight, max_weight, Utc::now()) {
+			return Err(PoolError::RateLimited);
+		}
+		Ok(())
+	}
+
+	/// Pool entries the tx replaces, the txpool and stempool entries double
+	/// spending its inputs and the entries depending on these. Fails unless
+	/// the tx pays a strictly higher fee rate than the entries it double
+	/// spends and enough fee for all the entries it replaces, plus the minimum
+	/// relay fee for its own weight.
+	fn replaced_entries(&self, tx: &Transaction) -> Result<Vec<PoolEntry>, PoolError> {
+		let inputs = tx
+			.inputs()
+			.iter()
+			.map(|x| x.commitment())
+			.collect::<HashSet<_>>();
+		let mut commits = inputs.clone();
+		let mut replaced = self.txpool.find_spending_entries(&mut commits);
+		replaced.extend(self.stempool.find_spending_entries(&mut commits));
+		if replaced.is_empty() {
+			return Ok(replaced);
+		}
+		if replaced.len() > MAX_REPLACED_TXS {
+			return Err(PoolError::TooManyReplacements(replaced.len()));
+		}
+
+		let weight = tx.tx_weight() as u64;
+		let max_fee_to_weight = replaced
+			.iter()
+			.filter(|x| {
+				x.tx.inputs()
+					.iter()
+					.any(|y| inputs.contains(&y.commitment()))
+			})
+			.map(|x| x.tx.fee_to_weight())
+			.max()
+			.unwrap_or(0);
+		// fee_to_weight being fee * 1000 / weight
+		let fee_to_outbid = ((max_fee_to_weight + 1) * weight + 999) / 1_000;
+		let replaced_fees = replaced.iter().map(|x| x.tx.fee()).sum::<u64>();
+		let required_fee = max(
+			fee_to_outbid,
+			replaced_fees.saturating_add(weight * self.config.min_relay_fee_base),
+		);
+		if tx.fee() < required_fee {
+			return Err(PoolError::LowFeeReplacement(required_fee));
+		}
+		Ok(replaced)
+	}
+
 	// Remove the last transaction from the flattened bucket transactions.
 	// No other tx depends on it, it has low fee_to_weight and is unlikely to participate in any cut-through.
 	pub fn evict_from_txpool(&mut self) {
-		// Get bucket transactions
-		let bucket_transactions = self.txpool.bucket_transactions(Weighting::NoLimit);
-
-		// Get last transaction and remove it
-		match bucket_transactions.last() {
-			Some(evictable_transaction) => {
-				// Remove transaction
-				self.txpool.entries = self
-					.txpool
-					.entries
-					.iter()
-					.filter(|x| x.tx != *evictable_transaction)
-					.map(|x| x.clone())
-					.collect::<Vec<_>>();
-			}
-			None => (),
+		if let Some(evicted) = self.txpool.evict_lowest_fee() {
+			self.fee_estimator.tx_evicted(&evicted.hash());
 		}
 	}
 
@@ -238,10 +371,28 @@ impl TransactionPool {
 	/// Reconcile the transaction pool (both txpool and stempool) against the
 	/// provided block.
 	pub fn reconcile_block(&mut self, block: &Block) -> Result<(), PoolError> {
+		// Txs the block mined, for fee estimation.
+		let mined = self
+			.txpool
+			.entries
+			.iter()
+			.filter(|x| x.tx.kernels().iter().all(|k| block.kernels().contains(k)))
+			.map(|x| x.tx.hash())
+			.collect::<Vec<_>>();
+
 		// First reconcile the txpool.
 		self.txpool.reconcile_block(block);
 		self.txpool.reconcile(None, &block.header)?;
 
+		let in_pool = self
+			.txpool
+			.entries
+			.iter()
+			.map(|x| x.tx.hash())
+			.collect::<HashSet<_>>();
+		self.fee_estimator
+			.block_processed(block.header.height, &mined, &in_pool);
+
 		// Now reconcile our stempool, accounting for the updated txpool txs.
 		self.stempool.reconcile_block(block);
 		{
@@ -305,7 +456,145 @@ impl TransactionPool {
 	/// Returns a vector of transactions from the txpool so we can build a
 	/// block from them.
 	pub fn prepare_mineable_transactions(&self) -> Result<Vec<Transaction>, PoolError> {
-		self.txpool
-			.prepare_mineable_transactions(self.config.mineable_max_weight)
+		self.txpool.prepare_mineable_transactions(
+			self.config.mineable_max_weight,
+			fee_estimator::fee_base_to_rate(self.config.min_relay_fee_base),
+		)
 	}
+
+	/// Saves the txpool, stempool and reorg cache entries to the provided file,
+	/// to restore them after a restart.
+	pub fn save(&self, path: &Path) -> Result<(), PoolError> {
+		let snapshot = PoolSnapshot {
+			txpool: self.txpool.entries.clone(),
+			stempool: self.stempool.entries.clone(),
+			reorg_cache: self.reorg_cache.read().iter().cloned().collect(),
+		};
+		persistence::save(path, &snapshot)?;
+		debug!(
+			"save: txpool {}, stempool {}, reorg_cache {}",
+			snapshot.txpool.len(),
+			snapshot.stempool.len(),
+			snapshot.reorg_cache.len(),
+		);
+		Ok(())
+	}
+
+	/// Restores the entries saved to the provided file, if any, in place of the
+	/// current ones. Expired entries are dropped and the txpool and stempool
+	/// ones revalidated against the chain state at the provided header, the
+	/// invalid ones being dropped too. Entries over the quota of their source
+	/// are dropped, as they would have been at admission, and the lowest fee
+	/// ones past the pool capacity evicted. Returns the number of txpool and
+	/// stempool entries restored.
+	pub fn restore(&mut self, path: &Path, header: &BlockHeader) -> Result<usize, PoolError> {
+		let snapshot = match persistence::load(path)? {
+			Some(snapshot) => snapshot,
+			None => return Ok(0),
+		};
+		let now = Utc::now();
+		let unexpired = |entries: Vec<PoolEntry>, expiry_secs: i64| {
+			entries
+				.into_iter()
+				.filter(|x| x.tx_at > now - Duration::seconds(expiry_secs))
+				.collect::<Vec<_>>()
+		};
+
+		let mut counts = HashMap::new();
+
+		let entries = unexpired(snapshot.txpool, ENTRY_EXPIRY_SECS);
+		let entries = within_quotas(entries, &self.config, &mut counts);
+		self.txpool.entries = revalidate(&self.txpool, entries, None, header)?;
+		while self.txpool.size() > self.config.max_pool_size
+			&& self.txpool.evict_lowest_fee().is_some()
+		{}
+		for entry in &self.txpool.entries {
+			self.fee_estimator.tx_added(&entry.tx, header.height);
+		}
+
+		let entries = unexpired(snapshot.stempool, ENTRY_EXPIRY_SECS);
+		let entries = within_quotas(entries, &self.config, &mut counts);
+		let txpool_tx = self.txpool.all_transactions_aggregate()?;
+		self.stempool.entries = revalidate(&self.stempool, entries, txpool_tx, header)?;
+		while self.stempool.size() > self.config.max_stempool_size
+			&& self.stempool.evict_lowest_fee().is_some()
+		{}
+
+		// Reorg cache entries get revalidated if a re-org brings them back.
+		*self.reorg_cache.write() = unexpired(snapshot.reorg_cache, REORG_CACHE_EXPIRY_SECS).into();
+
+		debug!(
+			"restore: txpool {}, stempool {}, reorg_cache {}",
+			self.txpool.size(),
+			self.stempool.size(),
+			self.reorg_cache.read().len(),
+		);
+		Ok(self.txpool.size() + self.stempool.size())
+	}
+
+	/// Estimates the fee for a transaction to get mined within the target
+	/// number of blocks, based on the transactions recently mined and evicted
+	/// from the txpool. Never below the minimum relay fee.
+	pub fn estimate_fee(&self, target_blocks: u64) -> FeeEstimate {
+		let target_blocks = target_blocks.max(1).min(MAX_TARGET_BLOCKS);
+		// an estimate below what the pool accepts would be of little use
+		let min_fee_base = self
+			.config
+			.min_relay_fee_base
+			.max(self.config.accept_fee_base);
+		match self.fee_estimator.estimate_fee_rate(target_blocks) {
+			Some(fee_rate) => FeeEstimate {
+				target_blocks,
+				fee_base: fee_estimator::fee_rate_to_base(fee_rate).max(min_fee_base),
+				enough_data: true,
+			},
+			None => FeeEstimate {
+				target_blocks,
+				fee_base: min_fee_base,
+				enough_data: false,
+			},
+		}
+	}
+}
+
+/// The entries within the quota of their source, the oldest first as admitted,
+/// counting the entries of each source already kept.
+fn within_quotas(
+	entries: Vec<PoolEntry>,
+	config: &PoolConfig,
+	counts: &mut HashMap<(String, String), usize>,
+) -> Vec<PoolEntry> {
+	entries
+		.into_iter()
+		.filter(|x| match x.src.quota(config) {
+			Some(quota) => {
+				let count = counts
+					.entry((x.src.debug_name.clone(), x.src.identifier.clone()))
+					.or_insert(0);
+				*count += 1;
+				*count <= quota
+			}
+			None => true,
+		})
+		.collect()
+}
+
+/// The entries whose tx is still valid against the chain state at the provided
+/// header, along with the previous entries and the extra tx.
+fn revalidate(
+	pool: &Pool,
+	entries: Vec<PoolEntry>,
+	extra_tx: Option<Transaction>,
+	header: &BlockHeader,
+) -> Result<Vec<PoolEntry>, PoolError> {
+	let txs = entries.iter().map(|x| x.tx.clone()).collect::<Vec<_>>();
+	let valid = pool
+		.validate_raw_txs(&txs, extra_tx, header, Weighting::NoLimit)?
+		.iter()
+		.map(|x| x.hash())
+		.collect::<HashSet<_>>();
+	Ok(entries
+		.into_iter()
+		.filter(|x| valid.contains(&x.tx.hash()))
+		.collect())
 }
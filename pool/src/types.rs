@@ -15,13 +15,14 @@
 //! The primary module containing the implementations of the transaction pool
 //! and its top-level members.
 
-use chrono::prelude::{DateTime, Utc};
+use chrono::prelude::{DateTime, TimeZone, Utc};
 
 use self::core::core::block;
 use self::core::core::committed;
 use self::core::core::hash::Hash;
 use self::core::core::transaction::{self, Transaction};
 use self::core::core::{BlockHeader, BlockSums};
+use self::core::ser::{self, Readable, Reader, Writeable, Writer};
 use self::core::{consensus, global};
 use epic_core as core;
 use epic_keychain as keychain;
@@ -107,6 +108,32 @@ pub struct PoolConfig {
 	/// blocks.
 	#[serde(default = "default_mineable_max_weight")]
 	pub mineable_max_weight: usize,
+
+	/// Minimum fee per unit of weight for a transaction to get selected to
+	/// build a block from. Fee estimates never go below it either.
+	#[serde(default = "default_min_relay_fee_base")]
+	pub min_relay_fee_base: u64,
+
+	/// Whether a transaction double spending txpool or stempool transactions
+	/// can replace them, paying a higher fee. The minimum relay fee base is
+	/// the fee increment a replacement has to pay for its own weight.
+	#[serde(default = "default_replace_by_fee")]
+	pub replace_by_fee: bool,
+
+	/// Maximum number of transactions a single peer can have in the txpool
+	/// and stempool at any time.
+	#[serde(default = "default_max_txs_per_peer")]
+	pub max_txs_per_peer: usize,
+
+	/// Maximum number of transactions a single client of the push API can
+	/// have in the txpool and stempool at any time.
+	#[serde(default = "default_max_txs_per_api_client")]
+	pub max_txs_per_api_client: usize,
+
+	/// Maximum total weight of transactions from a single peer or API client
+	/// the pool admits over any rolling minute.
+	#[serde(default = "default_max_admitted_weight_per_min")]
+	pub max_admitted_weight_per_min: u64,
 }
 
 impl Default for PoolConfig {
@@ -116,6 +143,11 @@ impl Default for PoolConfig {
 			max_pool_size: default_max_pool_size(),
 			max_stempool_size: default_max_stempool_size(),
 			mineable_max_weight: default_mineable_max_weight(),
+			min_relay_fee_base: default_min_relay_fee_base(),
+			replace_by_fee: default_replace_by_fee(),
+			max_txs_per_peer: default_max_txs_per_peer(),
+			max_txs_per_api_client: default_max_txs_per_api_client(),
+			max_admitted_weight_per_min: default_max_admitted_weight_per_min(),
 		}
 	}
 }
@@ -132,6 +164,33 @@ fn default_max_stempool_size() -> usize {
 fn default_mineable_max_weight() -> usize {
 	global::max_block_weight()
 }
+fn default_min_relay_fee_base() -> u64 {
+	consensus::MILLI_EPIC
+}
+fn default_replace_by_fee() -> bool {
+	false
+}
+fn default_max_txs_per_peer() -> usize {
+	500
+}
+fn default_max_txs_per_api_client() -> usize {
+	5_000
+}
+fn default_max_admitted_weight_per_min() -> u64 {
+	10 * global::max_block_weight() as u64
+}
+
+/// Fee for a transaction to get mined within a number of blocks.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FeeEstimate {
+	/// Number of blocks the estimate is for
+	pub target_blocks: u64,
+	/// Fee per unit of transaction weight, as `accept_fee_base`
+	pub fee_base: u64,
+	/// Whether enough transactions went through the pool recently to base
+	/// the estimate on, the minimum relay fee being used otherwise
+	pub enough_data: bool,
+}
 
 /// Represents a single entry in the pool.
 /// A single (possibly aggregated) transaction.
@@ -145,6 +204,35 @@ pub struct PoolEntry {
 	pub tx: Transaction,
 }
 
+impl Writeable for PoolEntry {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_bytes(&self.src.debug_name)?;
+		writer.write_bytes(&self.src.identifier)?;
+		writer.write_i64(self.tx_at.timestamp())?;
+		self.tx.write(writer)
+	}
+}
+
+impl Readable for PoolEntry {
+	fn read(reader: &mut dyn Reader) -> Result<PoolEntry, ser::Error> {
+		let debug_name = reader.read_bytes_len_prefix()?;
+		let identifier = reader.read_bytes_len_prefix()?;
+		let tx_at = Utc
+			.timestamp_opt(reader.read_i64()?, 0)
+			.single()
+			.ok_or(ser::Error::CorruptedData)?;
+		let tx = Transaction::read(reader)?;
+		Ok(PoolEntry {
+			src: TxSource {
+				debug_name: String::from_utf8(debug_name).map_err(|_| ser::Error::CorruptedData)?,
+				identifier: String::from_utf8(identifier).map_err(|_| ser::Error::CorruptedData)?,
+			},
+			tx_at,
+			tx,
+		})
+	}
+}
+
 /// Placeholder: the data representing where we heard about a tx from.
 ///
 /// Used to make decisions based on transaction acceptance priority from
@@ -153,7 +241,7 @@ pub struct PoolEntry {
 ///
 /// Most likely this will evolve to contain some sort of network identifier,
 /// once we get a better sense of what transaction building might look like.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TxSource {
 	/// Human-readable name used for logging and errors.
 	pub debug_name: String,
@@ -161,6 +249,41 @@ pub struct TxSource {
 	pub identifier: String,
 }
 
+/// Debug name of the txs received from our peers.
+pub const PEER_SOURCE: &'static str = "p2p";
+
+/// Debug name of the txs pushed through the API.
+pub const API_SOURCE: &'static str = "push-api";
+
+impl TxSource {
+	/// Source of a tx received from a peer at the provided IP address, all
+	/// the connections from the same host sharing it.
+	pub fn peer(addr: String) -> TxSource {
+		TxSource {
+			debug_name: PEER_SOURCE.to_string(),
+			identifier: addr,
+		}
+	}
+
+	/// Source of a tx pushed by the API client at the provided address.
+	pub fn api_client(addr: String) -> TxSource {
+		TxSource {
+			debug_name: API_SOURCE.to_string(),
+			identifier: addr,
+		}
+	}
+
+	/// Maximum number of pool txs allowed from this source given the pool
+	/// config, if it's subject to a quota at all.
+	pub fn quota(&self, config: &PoolConfig) -> Option<usize> {
+		match self.debug_name.as_str() {
+			PEER_SOURCE => Some(config.max_txs_per_peer),
+			API_SOURCE => Some(config.max_txs_per_api_client),
+			_ => None,
+		}
+	}
+}
+
 /// Possible errors when interacting with the transaction pool.
 #[derive(Debug, Fail, PartialEq)]
 pub enum PoolError {
@@ -206,6 +329,22 @@ pub enum PoolError {
 	/// Attempt to add a duplicate tx to the pool.
 	#[fail(display = "Duplicate tx! Attempt to add a duplicate tx to the pool!")]
 	DuplicateTx,
+	/// Replacement transaction fee is too low to replace the transactions it
+	/// double spends
+	#[fail(
+		display = "Low fee replacement! A fee of {} is required to replace!",
+		_0
+	)]
+	LowFeeReplacement(u64),
+	/// Replacement transaction would evict too many transactions
+	#[fail(display = "Too many replacements! Replacing {} transactions!", _0)]
+	TooManyReplacements(usize),
+	/// The peer or API client already has as many txs in the pool as allowed
+	#[fail(display = "Quota exceeded! Too many pool transactions from {}!", _0)]
+	QuotaExceeded(String),
+	/// Too much transaction weight was admitted to the pool recently
+	#[fail(display = "Rate limited! Too many transactions admitted recently!")]
+	RateLimited,
 	/// Other kinds of error (not yet pulled out into meaningful errors).
 	#[fail(display = "General pool error {}", _0)]
 	Other(String),
@@ -245,6 +384,13 @@ pub trait BlockChain: Sync + Send {
 	/// have matured sufficiently.
 	fn verify_tx_lock_height(&self, tx: &transaction::Transaction) -> Result<(), PoolError>;
 
+	/// Verify the kernels referenced by any relative height locked kernel
+	/// were confirmed long enough ago.
+	fn verify_tx_relative_lock_height(
+		&self,
+		tx: &transaction::Transaction,
+	) -> Result<(), PoolError>;
+
 	fn validate_tx(&self, tx: &Transaction) -> Result<(), PoolError>;
 
 	fn chain_head(&self) -> Result<BlockHeader, PoolError>;
@@ -0,0 +1,113 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::{PoolError, TxSource};
+use self::util::RwLock;
+use crate::common::*;
+use epic_core as core;
+use epic_keychain as keychain;
+use epic_pool as pool;
+use epic_util as util;
+use std::sync::Arc;
+
+/// Test peers and API clients can't go over their share of the pool, nor
+/// over the weight admitted from each of them per minute.
+#[test]
+fn test_admission_limits() {
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let chain = Arc::new(ChainAdapter::init().unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	// Initialize a new pool with our chain adapter.
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(&keychain, &key_id, 0, false, height).unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+
+	let initial_tx =
+		test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700, 800, 900]);
+	let txs = vec![500, 600, 700, 800, 900]
+		.into_iter()
+		.map(|v| test_transaction(&keychain, vec![v], vec![v - 10]))
+		.collect::<Vec<_>>();
+
+	let peer_a = TxSource::peer("10.0.0.1".to_string());
+	let peer_b = TxSource::peer("10.0.0.2".to_string());
+	let peer_c = TxSource::peer("10.0.0.3".to_string());
+	let client = TxSource::api_client("10.0.0.1".to_string());
+
+	let mut write_pool = pool.write();
+	write_pool.config.max_txs_per_peer = 2;
+	write_pool.config.max_txs_per_api_client = 1;
+
+	// Our own txs aren't subject to any quota.
+	write_pool
+		.add_to_pool(test_source(), initial_tx, false, &header)
+		.unwrap();
+
+	// Peer A gets to fill its quota, in the txpool and the stempool alike.
+	write_pool
+		.add_to_pool(peer_a.clone(), txs[0].clone(), false, &header)
+		.unwrap();
+	write_pool
+		.add_to_pool(peer_a.clone(), txs[1].clone(), true, &header)
+		.unwrap();
+	assert_eq!(
+		write_pool.add_to_pool(peer_a.clone(), txs[2].clone(), false, &header),
+		Err(PoolError::QuotaExceeded("10.0.0.1".to_string()))
+	);
+
+	// Other peers and API clients have their own quota.
+	write_pool
+		.add_to_pool(peer_b.clone(), txs[2].clone(), false, &header)
+		.unwrap();
+	write_pool
+		.add_to_pool(client.clone(), txs[3].clone(), false, &header)
+		.unwrap();
+	assert_eq!(
+		write_pool.add_to_pool(client.clone(), txs[4].clone(), false, &header),
+		Err(PoolError::QuotaExceeded("10.0.0.1".to_string()))
+	);
+
+	// Nothing more gets admitted from a peer or API client within the minute
+	// once the weight admitted from it reaches the limit, others still get
+	// their own share.
+	write_pool.config.max_admitted_weight_per_min = txs[2].tx_weight_as_block() as u64;
+	assert_eq!(
+		write_pool.add_to_pool(peer_b, txs[4].clone(), false, &header),
+		Err(PoolError::RateLimited)
+	);
+	write_pool
+		.add_to_pool(peer_c, txs[4].clone(), false, &header)
+		.unwrap();
+	assert_eq!(write_pool.total_size(), 5);
+	assert_eq!(write_pool.stempool.size(), 1);
+}
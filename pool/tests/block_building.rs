@@ -17,6 +17,7 @@ pub mod common;
 use self::core::core::hash::Hashed;
 use self::core::core::verifier_cache::LruVerifierCache;
 use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::global;
 use self::core::libtx;
 use self::core::pow::Difficulty;
 use self::keychain::{ExtKeychain, Keychain};
@@ -32,11 +33,8 @@ fn test_transaction_pool_block_building() {
 	util::init_test_logger();
 	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
 
-	let db_root = ".epic_block_building".to_string();
-	clean_output_dir(db_root.clone());
-
 	{
-		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+		let mut chain = ChainAdapter::init().unwrap();
 
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
@@ -128,6 +126,96 @@ fn test_transaction_pool_block_building() {
 			assert_eq!(write_pool.total_size(), 0);
 		}
 	}
-	// Cleanup db directory
-	clean_output_dir(db_root.clone());
+}
+
+#[test]
+fn test_block_building_child_pays_for_parent() {
+	util::init_test_logger();
+	global::set_mining_mode(global::ChainTypes::AutomatedTesting);
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let mut chain = ChainAdapter::init().unwrap();
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let add_block = |prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+		let height = prev_header.height + 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let fee = txs.iter().map(|x| x.fee()).sum();
+		let reward = libtx::reward::output(&keychain, &key_id, fee, false, height).unwrap();
+		let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+		// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+		block.header.prev_root = prev_header.hash();
+
+		chain.update_db_for_block(&block);
+		block
+	};
+
+	let block = add_block(BlockHeader::default(), vec![], &mut chain);
+	let header = block.header;
+
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![100, 200, 300]);
+	let block = add_block(header, vec![initial_tx], &mut chain);
+	let header = block.header;
+
+	let pool = RwLock::new(test_setup(Arc::new(chain.clone()), verifier_cache));
+
+	// A chain of 3 txs, the low fee child lowering the fee rate of its parent
+	// and the high fee grandchild lifting both.
+	let parent = test_transaction(&keychain, vec![100], vec![90]);
+	let child = test_transaction(&keychain, vec![90], vec![89]);
+	let grandchild = test_transaction(&keychain, vec![89], vec![60]);
+	let tx_1 = test_transaction(&keychain, vec![200], vec![190]);
+	let tx_2 = test_transaction(&keychain, vec![300], vec![297]);
+
+	assert_eq!(
+		[&parent, &child, &grandchild, &tx_1, &tx_2]
+			.iter()
+			.map(|x| x.fee_to_weight())
+			.collect::<Vec<_>>(),
+		[2500, 250, 7250, 2500, 750]
+	);
+
+	{
+		let mut write_pool = pool.write();
+		for tx in &[&parent, &child, &grandchild, &tx_1, &tx_2] {
+			write_pool
+				.add_to_pool(test_source(), (*tx).clone(), false, &header)
+				.unwrap();
+		}
+		assert_eq!(write_pool.total_size(), 5);
+
+		// Room for the whole chain and a single tx (block weight of 31 and 25,
+		// on top of the coinbase reward weighting 24).
+		write_pool.config.mineable_max_weight = 24 + 60;
+	}
+
+	// The whole chain pays more than any other tx, it goes first.
+	let txs = pool.read().prepare_mineable_transactions().unwrap();
+	assert_eq!(
+		txs,
+		vec![
+			parent.clone(),
+			child.clone(),
+			grandchild.clone(),
+			tx_1.clone()
+		]
+	);
+
+	// Without room for the whole chain, it's left out rather than cut before
+	// the grandchild paying for it, in favour of the next best tx.
+	pool.write().config.mineable_max_weight = 24 + 30;
+	let txs = pool.read().prepare_mineable_transactions().unwrap();
+	assert_eq!(txs, vec![tx_1.clone()]);
+
+	pool.write().config.mineable_max_weight = 24 + 60;
+	let txs = pool.read().prepare_mineable_transactions().unwrap();
+	let block = add_block(header, txs, &mut chain);
+	assert_eq!(block.kernels().len(), 5);
+
+	{
+		let mut write_pool = pool.write();
+		write_pool.reconcile_block(&block).unwrap();
+		assert_eq!(write_pool.txpool.all_transactions(), vec![tx_2]);
+	}
 }
@@ -37,11 +37,8 @@ fn test_block_building_max_weight() {
 
 	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
 
-	let db_root = ".epic_block_building_max_weight".to_string();
-	clean_output_dir(db_root.clone());
-
 	{
-		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+		let mut chain = ChainAdapter::init().unwrap();
 
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
@@ -150,6 +147,4 @@ fn test_block_building_max_weight() {
 			assert_eq!(write_pool.total_size(), 2);
 		}
 	}
-	// Cleanup db directory
-	clean_output_dir(db_root.clone());
 }
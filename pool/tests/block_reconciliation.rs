@@ -32,10 +32,8 @@ use std::sync::Arc;
 fn test_transaction_pool_block_reconciliation() {
 	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
 
-	let db_root = ".epic_block_reconciliation".to_string();
-	clean_output_dir(db_root.clone());
 	{
-		let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+		let chain = Arc::new(ChainAdapter::init().unwrap());
 
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
@@ -189,6 +187,4 @@ fn test_transaction_pool_block_reconciliation() {
 			assert_eq!(write_pool.txpool.entries[3].tx, valid_child_valid);
 		}
 	}
-	// Cleanup db directory
-	clean_output_dir(db_root.clone());
 }
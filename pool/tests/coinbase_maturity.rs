@@ -62,6 +62,10 @@ impl BlockChain for CoinbaseMaturityErrorChainAdapter {
 	fn verify_tx_lock_height(&self, _tx: &Transaction) -> Result<(), PoolError> {
 		Ok(())
 	}
+
+	fn verify_tx_relative_lock_height(&self, _tx: &Transaction) -> Result<(), PoolError> {
+		Ok(())
+	}
 }
 
 /// Test we correctly verify coinbase maturity when adding txs to the pool.
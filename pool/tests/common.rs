@@ -29,22 +29,20 @@ use epic_chain as chain;
 use epic_core as core;
 use epic_keychain as keychain;
 use epic_pool as pool;
+use epic_store::MemStore;
 use epic_util as util;
 use std::collections::HashSet;
-use std::fs;
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct ChainAdapter {
-	pub store: Arc<RwLock<ChainStore>>,
+	pub store: Arc<RwLock<ChainStore<MemStore>>>,
 	pub utxo: Arc<RwLock<HashSet<Commitment>>>,
 }
 
 impl ChainAdapter {
-	pub fn init(db_root: String) -> Result<ChainAdapter, String> {
-		let target_dir = format!("target/{}", db_root);
-		let chain_store = ChainStore::new(&target_dir)
-			.map_err(|e| format!("failed to init chain_store, {:?}", e))?;
+	pub fn init() -> Result<ChainAdapter, String> {
+		let chain_store = ChainStore::with_store(MemStore::new());
 		let store = Arc::new(RwLock::new(chain_store));
 		let utxo = Arc::new(RwLock::new(HashSet::new()));
 
@@ -146,6 +144,10 @@ impl BlockChain for ChainAdapter {
 	fn verify_tx_lock_height(&self, _tx: &Transaction) -> Result<(), PoolError> {
 		Ok(())
 	}
+
+	fn verify_tx_relative_lock_height(&self, _tx: &Transaction) -> Result<(), PoolError> {
+		Ok(())
+	}
 }
 
 pub fn test_setup(
@@ -158,6 +160,11 @@ pub fn test_setup(
 			max_pool_size: 50,
 			max_stempool_size: 50,
 			mineable_max_weight: 10_000,
+			min_relay_fee_base: 0,
+			replace_by_fee: false,
+			max_txs_per_peer: 50,
+			max_txs_per_api_client: 50,
+			max_admitted_weight_per_min: 1_000_000,
 		},
 		chain.clone(),
 		verifier_cache.clone(),
@@ -234,9 +241,3 @@ pub fn test_source() -> TxSource {
 		identifier: format!("127.0.0.1"),
 	}
 }
-
-pub fn clean_output_dir(db_root: String) {
-	if let Err(e) = fs::remove_dir_all(format!("target/{}", db_root)) {
-		println!("cleaning output dir failed - {:?}", e)
-	}
-}
@@ -0,0 +1,119 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::fee_estimator::fee_rate_to_base;
+use self::util::RwLock;
+use crate::common::*;
+use epic_core as core;
+use epic_keychain as keychain;
+use epic_pool as pool;
+use epic_util as util;
+use std::sync::Arc;
+
+#[test]
+fn test_fee_estimate() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let mut chain = ChainAdapter::init().unwrap();
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let add_block = |prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+		let height = prev_header.height + 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let fee = txs.iter().map(|x| x.fee()).sum();
+		let reward = libtx::reward::output(&keychain, &key_id, fee, false, height).unwrap();
+		let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+		// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+		block.header.prev_root = prev_header.hash();
+
+		chain.update_db_for_block(&block);
+		block
+	};
+
+	let block = add_block(BlockHeader::default(), vec![], &mut chain);
+	let header = block.header;
+
+	// 13 outputs to spend, each in a tx paying twice the fee of the previous
+	let values = (0..13).map(|i| 1_000_000 + i).collect::<Vec<u64>>();
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, values.clone());
+	let block = add_block(header, vec![initial_tx], &mut chain);
+	let header = block.header;
+
+	let pool = RwLock::new(test_setup(Arc::new(chain.clone()), verifier_cache));
+
+	// nothing to go by yet
+	let estimate = pool.read().estimate_fee(1);
+	assert!(!estimate.enough_data);
+	assert_eq!(estimate.fee_base, 0);
+
+	let txs = values
+		.iter()
+		.enumerate()
+		.map(|(i, v)| test_transaction(&keychain, vec![*v], vec![*v - (100 << i)]))
+		.collect::<Vec<_>>();
+	{
+		let mut write_pool = pool.write();
+		for tx in &txs {
+			write_pool
+				.add_to_pool(test_source(), tx.clone(), false, &header)
+				.unwrap();
+		}
+		assert_eq!(write_pool.total_size(), 13);
+
+		// The 3 txs paying the least are below the minimum relay fee.
+		write_pool.config.min_relay_fee_base = txs[3].fee_to_weight() / 1_000;
+	}
+
+	let mineable = pool.read().prepare_mineable_transactions().unwrap();
+	assert_eq!(mineable.len(), 10);
+	assert!(!mineable.contains(&txs[2]));
+	let block = add_block(header, mineable, &mut chain);
+	{
+		let mut write_pool = pool.write();
+		write_pool.config.min_relay_fee_base = 0;
+		write_pool.reconcile_block(&block).unwrap();
+		assert_eq!(write_pool.total_size(), 3);
+	}
+
+	// Everything that left the pool got mined right away.
+	let estimate = pool.read().estimate_fee(1);
+	assert!(estimate.enough_data);
+	assert!(estimate.fee_base > fee_rate_to_base(txs[2].fee_to_weight()));
+	assert!(estimate.fee_base <= fee_rate_to_base(txs[3].fee_to_weight()));
+
+	// The low fee txs are still waiting after another block, paying barely
+	// more than them doesn't get a tx mined in the next block anymore.
+	let block = add_block(block.header, vec![], &mut chain);
+	pool.write().reconcile_block(&block).unwrap();
+	let estimate = pool.read().estimate_fee(1);
+	assert!(estimate.enough_data);
+	assert!(estimate.fee_base > fee_rate_to_base(txs[1].fee_to_weight()));
+	assert!(estimate.fee_base <= fee_rate_to_base(txs[3].fee_to_weight()));
+
+	// Willing to wait longer, they didn't fail yet.
+	let estimate = pool.read().estimate_fee(5);
+	assert!(estimate.enough_data);
+	assert!(estimate.fee_base > fee_rate_to_base(txs[2].fee_to_weight()));
+	assert!(estimate.fee_base <= fee_rate_to_base(txs[3].fee_to_weight()));
+}
@@ -0,0 +1,171 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::TxSource;
+use self::util::RwLock;
+use crate::common::*;
+use chrono::prelude::Utc;
+use chrono::Duration;
+use epic_core as core;
+use epic_keychain as keychain;
+use epic_pool as pool;
+use epic_util as util;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Test the pool entries survive a restart, the ones no longer valid or too
+/// old being dropped.
+#[test]
+fn test_pool_persistence() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+	let dir = "target/.pool_persistence";
+	let _ = fs::remove_dir_all(dir);
+	fs::create_dir_all(dir).unwrap();
+	let path = Path::new(dir).join("pool.bin");
+
+	let mut chain = ChainAdapter::init().unwrap();
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let add_block = |prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+		let height = prev_header.height + 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let fee = txs.iter().map(|x| x.fee()).sum();
+		let reward = libtx::reward::output(&keychain, &key_id, fee, false, height).unwrap();
+		let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+		// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+		block.header.prev_root = prev_header.hash();
+
+		chain.update_db_for_block(&block);
+		block
+	};
+
+	let block = add_block(BlockHeader::default(), vec![], &mut chain);
+	let header = block.header;
+
+	let initial_tx =
+		test_transaction_spending_coinbase(&keychain, &header, vec![100, 200, 300, 400]);
+	let block = add_block(header, vec![initial_tx], &mut chain);
+	let header = block.header;
+
+	let tx1 = test_transaction(&keychain, vec![100], vec![90]);
+	let tx2 = test_transaction(&keychain, vec![90], vec![80]);
+	let tx3 = test_transaction(&keychain, vec![200], vec![180]);
+	let tx4 = test_transaction(&keychain, vec![300], vec![290]);
+	let stem_tx = test_transaction(&keychain, vec![400], vec![390]);
+
+	let pool = RwLock::new(test_setup(Arc::new(chain.clone()), verifier_cache.clone()));
+	let tx_at = {
+		let mut write_pool = pool.write();
+		for tx in vec![tx1.clone(), tx2.clone(), tx3.clone(), tx4.clone()] {
+			write_pool
+				.add_to_pool(test_source(), tx, false, &header)
+				.unwrap();
+		}
+		write_pool
+			.add_to_pool(test_source(), stem_tx.clone(), true, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 4);
+		assert_eq!(write_pool.stempool.size(), 1);
+
+		// tx4 has been around for too long.
+		write_pool.txpool.entries[3].tx_at = Utc::now() - Duration::days(2);
+		write_pool.save(&path).unwrap();
+		write_pool.txpool.entries[0].tx_at
+	};
+
+	// Everything comes back as it was.
+	{
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+		assert_eq!(pool.restore(&path, &header).unwrap(), 4);
+		assert_eq!(
+			pool.txpool.all_transactions(),
+			vec![tx1.clone(), tx2.clone(), tx3.clone()]
+		);
+		assert_eq!(pool.stempool.all_transactions(), vec![stem_tx.clone()]);
+		let entry = &pool.txpool.entries[0];
+		let src = test_source();
+		assert_eq!(entry.src.debug_name, src.debug_name);
+		assert_eq!(entry.src.identifier, src.identifier);
+		assert_eq!(entry.tx_at.timestamp(), tx_at.timestamp());
+		assert_eq!(
+			pool.reorg_cache
+				.read()
+				.iter()
+				.map(|x| x.tx.clone())
+				.collect::<Vec<_>>(),
+			vec![tx1.clone(), tx2.clone(), tx3.clone(), tx4]
+		);
+	}
+
+	// Less room in the pool than before the restart, the lowest fee txs
+	// are evicted.
+	{
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+		pool.config.max_pool_size = 2;
+		pool.config.max_stempool_size = 0;
+		assert_eq!(pool.restore(&path, &header).unwrap(), 2);
+		assert_eq!(
+			pool.txpool.all_transactions(),
+			vec![tx1.clone(), tx3.clone()]
+		);
+		assert!(pool.stempool.is_empty());
+	}
+
+	// Sources don't get more than their quota back.
+	{
+		let mut write_pool = pool.write();
+		let peer = TxSource::peer("10.0.0.1".to_string());
+		write_pool.txpool.entries[2].src = peer.clone();
+		write_pool.stempool.entries[0].src = peer;
+		write_pool.save(&path).unwrap();
+
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+		pool.config.max_txs_per_peer = 1;
+		assert_eq!(pool.restore(&path, &header).unwrap(), 3);
+		assert_eq!(
+			pool.txpool.all_transactions(),
+			vec![tx1.clone(), tx2.clone(), tx3.clone()]
+		);
+		assert!(pool.stempool.is_empty());
+	}
+
+	// tx3b got mined while we were down, tx3 double spending it now.
+	let tx3b = test_transaction(&keychain, vec![200], vec![170]);
+	let block = add_block(header, vec![tx3b], &mut chain);
+	{
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+		assert_eq!(pool.restore(&path, &block.header).unwrap(), 3);
+		assert_eq!(pool.txpool.all_transactions(), vec![tx1, tx2]);
+		assert_eq!(pool.stempool.all_transactions(), vec![stem_tx]);
+	}
+
+	// Nothing to restore without a saved pool.
+	{
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache);
+		let path = Path::new(dir).join("missing.bin");
+		assert_eq!(pool.restore(&path, &block.header).unwrap(), 0);
+		assert_eq!(pool.total_size(), 0);
+	}
+}
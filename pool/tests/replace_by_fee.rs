@@ -0,0 +1,150 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::PoolError;
+use self::util::RwLock;
+use crate::common::*;
+use epic_core as core;
+use epic_keychain as keychain;
+use epic_pool as pool;
+use epic_util as util;
+use std::sync::Arc;
+
+/// Test a tx paying enough replaces the txs it double spends, along with the
+/// txs depending on them.
+#[test]
+fn test_replace_by_fee() {
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let chain = Arc::new(ChainAdapter::init().unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	// Initialize a new pool with our chain adapter.
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(&keychain, &key_id, 0, false, height).unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700]);
+
+	// tx1 spends an output of the initial tx, tx2 spends the output of tx1.
+	let tx1 = test_transaction(&keychain, vec![500], vec![490]);
+	let tx2 = test_transaction(&keychain, vec![490], vec![480]);
+	// tx1 double spent, paying more than both tx1 and tx2.
+	let tx1b = test_transaction(&keychain, vec![500], vec![470]);
+	assert_eq!(
+		[&tx1, &tx2, &tx1b]
+			.iter()
+			.map(|x| x.fee_to_weight())
+			.collect::<Vec<_>>(),
+		[2500, 2500, 7500]
+	);
+
+	{
+		let mut write_pool = pool.write();
+		for tx in vec![initial_tx.clone(), tx1.clone(), tx2.clone()] {
+			write_pool
+				.add_to_pool(test_source(), tx, false, &header)
+				.unwrap();
+		}
+		assert_eq!(write_pool.total_size(), 3);
+
+		// Double spends get rejected unless replace by fee is enabled.
+		assert!(write_pool
+			.add_to_pool(test_source(), tx1b.clone(), false, &header)
+			.is_err());
+		assert_eq!(write_pool.total_size(), 3);
+		write_pool.config.replace_by_fee = true;
+	}
+
+	// Paying a higher fee rate than tx1 isn't enough, the replacement has to
+	// pay for tx2 as well.
+	{
+		let tx = test_transaction(&keychain, vec![500], vec![485]);
+		let mut write_pool = pool.write();
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx, false, &header),
+			Err(PoolError::LowFeeReplacement(20))
+		);
+		assert_eq!(write_pool.total_size(), 3);
+	}
+
+	// Paying enough but invalid otherwise, nothing gets replaced.
+	{
+		let tx = test_transaction(&keychain, vec![500, 10_001], vec![400]);
+		let mut write_pool = pool.write();
+		assert!(write_pool
+			.add_to_pool(test_source(), tx, false, &header)
+			.is_err());
+		assert_eq!(
+			write_pool.txpool.all_transactions(),
+			vec![initial_tx.clone(), tx1.clone(), tx2.clone()]
+		);
+	}
+
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), tx1b.clone(), false, &header)
+			.unwrap();
+		assert_eq!(
+			write_pool.txpool.all_transactions(),
+			vec![initial_tx.clone(), tx1b.clone()]
+		);
+
+		// Replaced txs are not brought back after a re-org.
+		let cached = write_pool
+			.reorg_cache
+			.read()
+			.iter()
+			.map(|x| x.tx.clone())
+			.collect::<Vec<_>>();
+		assert_eq!(cached, vec![initial_tx.clone(), tx1b.clone()]);
+	}
+
+	// Stem txs get replaced too.
+	{
+		let tx3 = test_transaction(&keychain, vec![600], vec![590]);
+		let tx3b = test_transaction(&keychain, vec![600], vec![570]);
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), tx3, true, &header)
+			.unwrap();
+		assert_eq!(write_pool.stempool.size(), 1);
+		write_pool
+			.add_to_pool(test_source(), tx3b.clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.stempool.size(), 0);
+		assert_eq!(
+			write_pool.txpool.all_transactions(),
+			vec![initial_tx, tx1b, tx3b]
+		);
+	}
+}
@@ -31,10 +31,7 @@ use std::sync::Arc;
 fn test_the_transaction_pool() {
 	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
 
-	let db_root = ".epic_transaction_pool".to_string();
-	clean_output_dir(db_root.clone());
-
-	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+	let chain = Arc::new(ChainAdapter::init().unwrap());
 
 	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
@@ -236,7 +233,7 @@ fn test_the_transaction_pool() {
 	// Check we cannot "double spend" an output spent in a previous block.
 	// We use the initial coinbase output here for convenience.
 	{
-		let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+		let chain = Arc::new(ChainAdapter::init().unwrap());
 
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
@@ -455,6 +452,4 @@ fn test_the_transaction_pool() {
 				.is_err());
 		}
 	}
-	// Cleanup db directory
-	clean_output_dir(db_root.clone());
 }
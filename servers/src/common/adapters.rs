@@ -65,6 +65,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		Ok(self.chain().head()?.height)
 	}
 
+	fn tail_height(&self) -> Result<u64, chain::Error> {
+		// no tail yet means we never pruned anything
+		Ok(self.chain().tail().map(|t| t.height).unwrap_or(0))
+	}
+
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction> {
 		self.tx_pool.read().retrieve_tx_by_kernel_hash(kernel_hash)
 	}
@@ -93,16 +98,14 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// nothing much we can do with a new transaction while syncing
 		if self.sync_state.is_syncing() {
 			return Ok(true);
 		}
 
-		let source = pool::TxSource {
-			debug_name: "p2p".to_string(),
-			identifier: "?.?.?.?".to_string(),
-		};
+		let source = pool::TxSource::peer(peer_info.addr.0.ip().to_string());
 
 		let header = self.chain().head_header()?;
 
@@ -112,9 +115,23 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 
 		let tx_hash = tx.hash();
 
-		let mut tx_pool = self.tx_pool.write();
-		match tx_pool.add_to_pool(source, tx, stem, &header) {
+		let res = self.tx_pool.write().add_to_pool(source, tx, stem, &header);
+		match res {
 			Ok(_) => Ok(true),
+			Err(pool::PoolError::QuotaExceeded(_)) => {
+				debug!(
+					"Transaction {} rejected, {} is over its pool quota",
+					tx_hash, peer_info.addr
+				);
+				// relays of busy peers go over it too, only sustained abuse
+				// ends up in a ban
+				let window = Duration::seconds(pool::rate_limit::RATE_LIMIT_WINDOW_SECS);
+				if peer_info.report_quota_exceeded(window) {
+					self.peers()
+						.update_score(peer_info.addr, p2p::ScoreEvent::PoolQuotaExceeded);
+				}
+				Ok(false)
+			}
 			Err(e) => {
 				debug!("Transaction {} rejected: {:?}", tx_hash, e);
 				Ok(false)
@@ -923,4 +940,10 @@ impl pool::BlockChain for PoolToChainAdapter {
 			.verify_tx_lock_height(tx)
 			.map_err(|_| pool::PoolError::ImmatureTransaction)
 	}
+
+	fn verify_tx_relative_lock_height(&self, tx: &Transaction) -> Result<(), pool::PoolError> {
+		self.chain()
+			.verify_tx_relative_lock_height(tx)
+			.map_err(|_| pool::PoolError::ImmatureTransaction)
+	}
 }
@@ -168,6 +168,8 @@ pub struct PeerStats {
 	pub sent_bytes_per_sec: u64,
 	/// Number of bytes we've received from the peer.
 	pub received_bytes_per_sec: u64,
+	/// Whether our bandwidth limits are holding back traffic with the peer.
+	pub throttled: bool,
 }
 
 impl StratumStats {
@@ -214,6 +216,7 @@ impl PeerStats {
 			local_timestamp: peer.info.local_timestamp(),
 			sent_bytes_per_sec: peer.last_min_sent_bytes().unwrap_or(0) / 60,
 			received_bytes_per_sec: peer.last_min_received_bytes().unwrap_or(0) / 60,
+			throttled: peer.is_throttled(),
 		}
 	}
 }
@@ -14,6 +14,7 @@
 
 //! Server types
 use std::convert::From;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use chrono::prelude::{DateTime, Utc};
@@ -169,6 +170,14 @@ pub struct ServerConfig {
 	/// Whether this node is a full archival node or a fast-sync, pruned node
 	pub archive_mode: Option<bool>,
 
+	/// Number of days of full blocks a pruned node keeps, never less than the
+	/// cut-through horizon
+	pub block_retention_days: Option<u64>,
+
+	/// Compact the chain and peer databases on startup when at least that
+	/// percentage of their file is left free by deletions, 0 to never do it
+	pub db_compact_free_percent: Option<u64>,
+
 	/// Whether to skip the sync timeout on startup
 	/// (To assist testing on solo chains)
 	pub skip_sync_wait: Option<bool>,
@@ -205,6 +214,10 @@ pub struct ServerConfig {
 	/// Configuration for the webhooks that trigger on certain events
 	#[serde(default)]
 	pub webhook_config: WebHooksConfig,
+
+	/// Configuration for the check of our version against the allowed one
+	#[serde(default)]
+	pub version_check_config: VersionCheckConfig,
 	//#[serde(default)]
 	// Configuration for the proportions policy on EPIC
 	//pub policy_config: PolicyConfig,
@@ -224,6 +237,8 @@ impl Default for ServerConfig {
 			stratum_mining_config: Some(StratumServerConfig::default()),
 			chain_type: ChainTypes::default(),
 			archive_mode: Some(false),
+			block_retention_days: Some(0),
+			db_compact_free_percent: Some(50),
 			chain_validation_mode: ChainValidationMode::default(),
 			pool_config: pool::PoolConfig::default(),
 			skip_sync_wait: Some(false),
@@ -232,11 +247,20 @@ impl Default for ServerConfig {
 			run_test_miner: Some(false),
 			test_miner_wallet_url: None,
 			webhook_config: WebHooksConfig::default(),
+			version_check_config: VersionCheckConfig::default(),
 			//policy_config: PolicyConfig::default(),
 		}
 	}
 }
 
+impl ServerConfig {
+	/// Number of blocks below the head a pruned node keeps full blocks for,
+	/// the chain still keeps at least the cut-through horizon.
+	pub fn block_retention(&self) -> u64 {
+		self.block_retention_days.unwrap_or(0) * consensus::DAY_HEIGHT
+	}
+}
+
 /// Stratum (Mining server) configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StratumServerConfig {
@@ -323,6 +347,68 @@ impl Default for WebHooksConfig {
 	}
 }
 
+/// Where the minimum version nodes are allowed to run is taken from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum VersionSourceType {
+	/// TXT record of a DNS domain
+	DNS,
+	/// Plain text body of an HTTP endpoint
+	HTTP,
+	/// First line of a local file
+	File,
+	/// User agents advertised by our peers in the handshake
+	Peers,
+	/// No version check
+	None,
+}
+
+impl Default for VersionSourceType {
+	fn default() -> VersionSourceType {
+		VersionSourceType::DNS
+	}
+}
+
+/// Version check configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VersionCheckConfig {
+	/// Where to get the allowed version from
+	#[serde(default)]
+	pub source: VersionSourceType,
+	/// Domain holding the version as a TXT record, for the DNS source
+	#[serde(default = "default_version_dns_domain")]
+	pub dns_domain: String,
+	/// Name server to query instead of the system ones, for the DNS source
+	pub dns_server: Option<SocketAddr>,
+	/// Endpoint serving the version, for the HTTP source
+	pub http_url: Option<String>,
+	/// Path of the file holding the version, for the File source
+	pub file_path: Option<String>,
+	/// Minutes between two checks
+	#[serde(default = "default_version_check_interval")]
+	pub interval_mins: u32,
+}
+
+fn default_version_dns_domain() -> String {
+	"epicversion.epic.tech.".to_string()
+}
+
+fn default_version_check_interval() -> u32 {
+	15
+}
+
+impl Default for VersionCheckConfig {
+	fn default() -> VersionCheckConfig {
+		VersionCheckConfig {
+			source: VersionSourceType::default(),
+			dns_domain: default_version_dns_domain(),
+			dns_server: None,
+			http_url: None,
+			file_path: None,
+			interval_mins: default_version_check_interval(),
+		}
+	}
+}
+
 /// Various status sync can be in, whether it's fast sync or archival.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[allow(missing_docs)]
@@ -365,6 +451,8 @@ pub enum SyncStatus {
 	BodySync {
 		current_height: u64,
 		highest_height: u64,
+		/// Blocks received over the last minute
+		blocks_per_min: u64,
 	},
 	Shutdown,
 }
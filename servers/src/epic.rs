@@ -14,7 +14,9 @@
 
 //! Epic P2P / API server
 
+pub mod chain_validator;
 pub mod dandelion_monitor;
+pub mod offline;
 pub mod seed;
 pub mod server;
 pub mod sync;
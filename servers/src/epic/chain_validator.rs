@@ -0,0 +1,78 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Background validation of the full chain state (MMRs, rangeproofs and
+//! kernel signatures), done a small chunk at a time so a running node isn't
+//! slowed down. Progress is checkpointed in the chain store, a validation
+//! interrupted by a restart resumes where it stopped.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chain;
+use crate::common::types::SyncState;
+use crate::util::StopState;
+
+/// How long we wait before looking again for a validation to run.
+const IDLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Pause after each chunk, as a multiple of the time the chunk took, so the
+/// validation only ever gets a fraction of a core.
+const PAUSE_FACTOR: u32 = 2;
+
+/// Runs the validation started through the API, if any, in its own thread.
+/// Nothing is validated while the node is syncing.
+pub fn run(
+	chain: Arc<chain::Chain>,
+	sync_state: Arc<SyncState>,
+	stop_state: Arc<StopState>,
+) -> std::io::Result<thread::JoinHandle<()>> {
+	thread::Builder::new()
+		.name("chain_validator".to_string())
+		.spawn(move || {
+			let mut last_step: Option<Instant> = None;
+			loop {
+				if stop_state.is_stopped() {
+					break;
+				}
+
+				if sync_state.is_syncing() {
+					last_step = None;
+					thread::sleep(IDLE_INTERVAL);
+					continue;
+				}
+
+				let now = Instant::now();
+				let elapsed = last_step
+					.map(|t| now.duration_since(t))
+					.unwrap_or_else(|| Duration::from_secs(0));
+				match chain.validation_step(elapsed) {
+					Ok(true) => {
+						last_step = Some(now);
+						thread::sleep(now.elapsed() * PAUSE_FACTOR);
+					}
+					Ok(false) => {
+						last_step = None;
+						thread::sleep(IDLE_INTERVAL);
+					}
+					Err(e) => {
+						error!("chain_validator: validation step failed: {}", e);
+						last_step = None;
+						thread::sleep(IDLE_INTERVAL);
+					}
+				}
+			}
+		})
+}
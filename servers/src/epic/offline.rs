@@ -0,0 +1,636 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Offline maintenance of the chain data, for commands run while the server
+//! is stopped.
+//!
+//! Blocks can be exported to a portable archive file and imported again on
+//! another node, which is a lot faster than syncing over p2p from a trusted
+//! node on the same network. The archive is a small header followed by one
+//! length-prefixed record per block, in height order:
+//!
+//! ```text
+//! version (u16) | genesis hash | first height (u64) | block count (u64)
+//! height (u64) | length (u64) | block (length bytes)
+//! ...
+//! ```
+//!
+//! Snapshots of the chain state at a given height can also be written to a
+//! directory and restored on another node. A snapshot holds the txhashset zip
+//! served to peers during state sync, the header chain up to that height and
+//! a manifest with the header hash and roots the restore is checked against.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::chain;
+use crate::common::types::{Error, ServerConfig};
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::core::{Block, BlockHeader};
+use crate::core::ser::{self, Readable, Writeable};
+use crate::core::{global, pow};
+use crate::epic::server::{genesis_block, Server};
+use crate::p2p;
+use crate::store::StoreStats;
+use crate::util::RwLock;
+
+/// Version of the block archive format
+const ARCHIVE_VERSION: u16 = 1;
+
+/// Version of the snapshot format
+const SNAPSHOT_VERSION: u16 = 1;
+
+/// Snapshot file names, within the snapshot directory
+const SNAPSHOT_MANIFEST: &str = "manifest.json";
+const SNAPSHOT_TXHASHSET: &str = "txhashset.zip";
+const SNAPSHOT_HEADERS: &str = "headers.bin";
+
+/// Number of headers added to the header chain at once on restore
+const HEADERS_BATCH_SIZE: usize = 512;
+
+/// Opens the chain of the provided configuration, without any of the network
+/// parts of the server. Returns the lock file too, which must be kept around
+/// for as long as the chain is used so a server can't start meanwhile.
+pub fn open_chain(config: &ServerConfig) -> Result<(Arc<chain::Chain>, Arc<File>), Error> {
+	let lock_file = Server::one_epic_at_a_time(config)?;
+	Ok((init_chain(config)?, lock_file))
+}
+
+fn init_chain(config: &ServerConfig) -> Result<Arc<chain::Chain>, Error> {
+	global::set_foundation_path(config.foundation_path.clone());
+
+	let genesis = genesis_block(&config.chain_type);
+	let mut chain = chain::Chain::init(
+		config.db_root.clone(),
+		Arc::new(chain::types::NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		config.archive_mode.unwrap_or(false),
+	)?;
+	chain.set_block_retention(config.block_retention());
+	Ok(Arc::new(chain))
+}
+
+/// Outcome of a check of the chain database.
+pub struct DbCheck {
+	/// Inconsistencies found and repaired
+	pub repaired: Vec<String>,
+	/// Inconsistencies found that could not be repaired
+	pub errors: Vec<String>,
+	/// Chain head before the check
+	pub prev_head: chain::Tip,
+	/// Chain head after the check, behind the previous one if the chain
+	/// state didn't match the blocks at the top of the chain
+	pub head: chain::Tip,
+	/// Number of outputs in the rebuilt output position index
+	pub indexed_outputs: u64,
+}
+
+/// Checks the chain database of the provided configuration, repairing what a
+/// node killed in the middle of a write may have left behind. The MMR
+/// backend files are checked against the chain store first, as opening the
+/// chain on inconsistent files rewinds its head. The output position index
+/// is then rebuilt and the chain state validated, without the rangeproofs
+/// and kernel signatures.
+pub fn check_db(config: &ServerConfig) -> Result<DbCheck, Error> {
+	let _lock_file = Server::one_epic_at_a_time(config)?;
+
+	let (prev_head, check) = {
+		let store = chain::ChainStore::new(&config.db_root)?;
+		let check = chain::txhashset::check_backend_files(&config.db_root, &store)?;
+		(store.head()?, check)
+	};
+	let (repaired, mut errors) = (check.repaired, check.errors);
+
+	let chain = init_chain(config)?;
+	let indexed_outputs = chain.rebuild_output_index()?;
+	if let Err(e) = chain.validate(true) {
+		errors.push(format!("chain state: {}", e));
+	}
+
+	Ok(DbCheck {
+		repaired,
+		errors,
+		prev_head,
+		head: chain.head()?,
+		indexed_outputs,
+	})
+}
+
+/// Outcome of a compaction of the databases: the size of the database files
+/// before and after, in bytes, or none if a database wasn't compacted.
+pub struct DbCompaction {
+	/// The chain database
+	pub chain: Option<(u64, u64)>,
+	/// The peer database
+	pub peers: Option<(u64, u64)>,
+}
+
+/// Compacts the chain and peer databases of the provided configuration,
+/// giving back the space left free by deleted blocks and peers.
+pub fn compact_db(config: &ServerConfig) -> Result<DbCompaction, Error> {
+	let _lock_file = Server::one_epic_at_a_time(config)?;
+	compact_databases(&config.db_root, 0)
+}
+
+/// Compacts the chain and peer databases under the provided root, skipping
+/// the ones with less than the provided percentage of their file free. The
+/// databases must not be open meanwhile.
+pub fn compact_databases(db_root: &str, min_free_percent: u64) -> Result<DbCompaction, Error> {
+	let worth_it =
+		|usage: StoreStats| usage.free_pages * 100 >= usage.total_pages * min_free_percent;
+
+	let chain = if worth_it(chain::ChainStore::new(db_root)?.usage()?) {
+		Some(chain::ChainStore::compact(db_root)?)
+	} else {
+		None
+	};
+	let peers = if worth_it(p2p::PeerStore::new(db_root)?.usage()?) {
+		Some(p2p::PeerStore::compact(db_root)?)
+	} else {
+		None
+	};
+	Ok(DbCompaction { chain, peers })
+}
+
+struct ArchiveHeader {
+	version: u16,
+	genesis: Hash,
+	first_height: u64,
+	count: u64,
+}
+
+impl Writeable for ArchiveHeader {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u16(self.version)?;
+		self.genesis.write(writer)?;
+		writer.write_u64(self.first_height)?;
+		writer.write_u64(self.count)
+	}
+}
+
+impl Readable for ArchiveHeader {
+	fn read(reader: &mut dyn ser::Reader) -> Result<ArchiveHeader, ser::Error> {
+		let version = reader.read_u16()?;
+		if version != ARCHIVE_VERSION {
+			return Err(ser::Error::CorruptedData);
+		}
+		Ok(ArchiveHeader {
+			version,
+			genesis: Hash::read(reader)?,
+			first_height: reader.read_u64()?,
+			count: reader.read_u64()?,
+		})
+	}
+}
+
+struct RecordHeader {
+	height: u64,
+	len: u64,
+}
+
+impl Writeable for RecordHeader {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.height)?;
+		writer.write_u64(self.len)
+	}
+}
+
+impl Readable for RecordHeader {
+	fn read(reader: &mut dyn ser::Reader) -> Result<RecordHeader, ser::Error> {
+		Ok(RecordHeader {
+			height: reader.read_u64()?,
+			len: reader.read_u64()?,
+		})
+	}
+}
+
+fn ser_error(e: ser::Error) -> Error {
+	Error::General(format!("Invalid block archive: {:?}", e))
+}
+
+/// Exports the blocks of our chain between the provided heights (both
+/// included, up to our head by default) to an archive file. Fails on pruned
+/// nodes that don't hold the full blocks anymore.
+pub fn export_blocks<F>(
+	chain: &chain::Chain,
+	path: &Path,
+	from: u64,
+	to: Option<u64>,
+	mut progress: F,
+) -> Result<u64, Error>
+where
+	F: FnMut(u64, u64),
+{
+	let head = chain.head()?;
+	let from = from.max(1);
+	let to = to.unwrap_or(head.height).min(head.height);
+	if from > to {
+		return Err(Error::ArgumentError(format!(
+			"Nothing to export between heights {} and {} (head at {})",
+			from, to, head.height
+		)));
+	}
+
+	let mut writer = BufWriter::new(File::create(path)?);
+	let header = ArchiveHeader {
+		version: ARCHIVE_VERSION,
+		genesis: chain.get_header_by_height(0)?.hash(),
+		first_height: from,
+		count: to - from + 1,
+	};
+	ser::serialize(&mut writer, &header).map_err(ser_error)?;
+
+	for height in from..=to {
+		let hash = chain.get_header_by_height(height)?.hash();
+		let block = chain.get_block(&hash).map_err(|e| {
+			Error::General(format!(
+				"Block {} at {} not found, is this an archive node? {:?}",
+				hash, height, e
+			))
+		})?;
+		let bytes = ser::ser_vec(&block).map_err(ser_error)?;
+		let record = RecordHeader {
+			height,
+			len: bytes.len() as u64,
+		};
+		ser::serialize(&mut writer, &record).map_err(ser_error)?;
+		writer.write_all(&bytes)?;
+		progress(height, to);
+	}
+	writer.flush()?;
+	Ok(header.count)
+}
+
+/// Imports the blocks of an archive file, running them through the full
+/// block validation. Blocks at or below our head are skipped, so an
+/// interrupted import resumes where it stopped. Returns the number of blocks
+/// processed.
+pub fn import_blocks<F>(chain: &chain::Chain, path: &Path, progress: F) -> Result<u64, Error>
+where
+	F: FnMut(u64, u64),
+{
+	import(chain, path, chain::Options::NONE, progress)
+}
+
+fn import<F>(
+	chain: &chain::Chain,
+	path: &Path,
+	opts: chain::Options,
+	mut progress: F,
+) -> Result<u64, Error>
+where
+	F: FnMut(u64, u64),
+{
+	let mut reader = BufReader::new(File::open(path)?);
+	let header: ArchiveHeader = ser::deserialize(&mut reader).map_err(ser_error)?;
+	let genesis = chain.get_header_by_height(0)?.hash();
+	if header.genesis != genesis {
+		return Err(Error::ArgumentError(format!(
+			"Archive built for another chain, genesis {} instead of {}",
+			header.genesis, genesis
+		)));
+	}
+
+	let head = chain.head()?;
+	let last_height = header.first_height + header.count.saturating_sub(1);
+	if header.first_height > head.height + 1 {
+		return Err(Error::ArgumentError(format!(
+			"Archive starts at {}, past our head at {}",
+			header.first_height, head.height
+		)));
+	}
+
+	let mut imported = 0;
+	for _ in 0..header.count {
+		let record: RecordHeader = ser::deserialize(&mut reader).map_err(ser_error)?;
+		if record.height <= head.height {
+			reader.seek(SeekFrom::Current(record.len as i64))?;
+			continue;
+		}
+		let block: Block = ser::deserialize(&mut reader).map_err(ser_error)?;
+		if block.header.height != record.height {
+			return Err(Error::General(format!(
+				"Invalid block archive: block at {} recorded at {}",
+				block.header.height, record.height
+			)));
+		}
+		chain.process_block(block, opts)?;
+		imported += 1;
+		progress(record.height, last_height);
+	}
+	Ok(imported)
+}
+
+/// Description of a chain snapshot, checked on restore.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotManifest {
+	/// Version of the snapshot format
+	pub version: u16,
+	/// Hash of the genesis block of the chain
+	pub genesis: String,
+	/// Height of the snapshot
+	pub height: u64,
+	/// Hash of the header at the snapshot height
+	pub hash: String,
+	/// Output MMR root at the snapshot height
+	pub output_root: String,
+	/// Rangeproof MMR root at the snapshot height
+	pub range_proof_root: String,
+	/// Kernel MMR root at the snapshot height
+	pub kernel_root: String,
+	/// Sha256 of the txhashset zip
+	pub txhashset_sha256: String,
+}
+
+impl SnapshotManifest {
+	fn from_header(genesis: Hash, header: &BlockHeader, txhashset_sha256: String) -> Self {
+		SnapshotManifest {
+			version: SNAPSHOT_VERSION,
+			genesis: genesis.to_hex(),
+			height: header.height,
+			hash: header.hash().to_hex(),
+			output_root: header.output_root.to_hex(),
+			range_proof_root: header.range_proof_root.to_hex(),
+			kernel_root: header.kernel_root.to_hex(),
+			txhashset_sha256,
+		}
+	}
+}
+
+/// Logs the progress of the txhashset validation on restore
+struct LogStatus;
+
+impl chain::TxHashsetWriteStatus for LogStatus {
+	fn on_setup(&self) {
+		info!("restore_snapshot: setting up the txhashset");
+	}
+	fn on_validation(&self, kernels: u64, kernel_total: u64, rproofs: u64, rproof_total: u64) {
+		info!(
+			"restore_snapshot: validated {}/{} kernels, {}/{} rangeproofs",
+			kernels, kernel_total, rproofs, rproof_total
+		);
+	}
+	fn on_save(&self) {
+		info!("restore_snapshot: saving the txhashset");
+	}
+	fn on_done(&self) {
+		info!("restore_snapshot: done");
+	}
+}
+
+fn invalid_snapshot(msg: String) -> Error {
+	Error::General(format!("Invalid snapshot: {}", msg))
+}
+
+/// Sha256 of a snapshot file, failing if it's missing or can't be read.
+fn snapshot_sha256(path: &Path) -> Result<String, Error> {
+	let path_str = path
+		.to_str()
+		.ok_or(invalid_snapshot(format!("invalid path {}", path.display())))?;
+	global::try_get_file_sha256(path_str)
+		.map_err(|e| invalid_snapshot(format!("cannot read {}, {}", path.display(), e)))
+}
+
+/// Writes a snapshot of the chain state at the provided height to a
+/// directory: the txhashset zip at that height, the header chain up to it and
+/// a manifest.
+pub fn create_snapshot(
+	chain: &chain::Chain,
+	dir: &Path,
+	height: u64,
+) -> Result<SnapshotManifest, Error> {
+	let head = chain.head()?;
+	if height == 0 || height > head.height {
+		return Err(Error::ArgumentError(format!(
+			"Snapshot height must be between 1 and our head at {}",
+			head.height
+		)));
+	}
+	fs::create_dir_all(dir)?;
+
+	let header = chain.get_header_by_height(height)?;
+	let (_, _, mut zip) = chain.txhashset_read(header.hash())?;
+	let zip_path = dir.join(SNAPSHOT_TXHASHSET);
+	io::copy(&mut zip, &mut File::create(&zip_path)?)?;
+
+	let mut writer = BufWriter::new(File::create(dir.join(SNAPSHOT_HEADERS))?);
+	ser::serialize(&mut writer, &height).map_err(ser_error)?;
+	for h in 1..=height {
+		let header = chain.get_header_by_height(h)?;
+		ser::serialize(&mut writer, &header).map_err(ser_error)?;
+	}
+	writer.flush()?;
+
+	let genesis = chain.get_header_by_height(0)?.hash();
+	let sha256 = snapshot_sha256(&zip_path)?;
+	let manifest = SnapshotManifest::from_header(genesis, &header, sha256);
+	let json = serde_json::to_string_pretty(&manifest)
+		.map_err(|e| Error::General(format!("Cannot write manifest: {}", e)))?;
+	fs::write(dir.join(SNAPSHOT_MANIFEST), json)?;
+	Ok(manifest)
+}
+
+/// Rebuilds the chain state from a snapshot directory. The snapshot headers
+/// are added to our header chain, then the txhashset goes through the same
+/// validation as one received during state sync.
+pub fn restore_snapshot<F>(
+	chain: &chain::Chain,
+	dir: &Path,
+	progress: F,
+) -> Result<SnapshotManifest, Error>
+where
+	F: FnMut(u64, u64),
+{
+	restore(chain, dir, chain::Options::SYNC, progress)
+}
+
+fn restore<F>(
+	chain: &chain::Chain,
+	dir: &Path,
+	opts: chain::Options,
+	mut progress: F,
+) -> Result<SnapshotManifest, Error>
+where
+	F: FnMut(u64, u64),
+{
+	let json = fs::read_to_string(dir.join(SNAPSHOT_MANIFEST))?;
+	let manifest: SnapshotManifest = serde_json::from_str(&json)
+		.map_err(|e| invalid_snapshot(format!("bad manifest, {}", e)))?;
+	if manifest.version != SNAPSHOT_VERSION {
+		return Err(invalid_snapshot(format!(
+			"unsupported version {}",
+			manifest.version
+		)));
+	}
+	let genesis = chain.get_header_by_height(0)?.hash();
+	if manifest.genesis != genesis.to_hex() {
+		return Err(invalid_snapshot(format!(
+			"built for another chain, genesis {}",
+			manifest.genesis
+		)));
+	}
+	let zip_path = dir.join(SNAPSHOT_TXHASHSET);
+	if !zip_path.is_file() {
+		return Err(invalid_snapshot(format!(
+			"{} is missing",
+			zip_path.display()
+		)));
+	}
+	let sha256 = snapshot_sha256(&zip_path)?;
+	if sha256 != manifest.txhashset_sha256 {
+		return Err(invalid_snapshot(format!("txhashset sha256 is {}", sha256)));
+	}
+
+	// add the headers to our header chain, as header sync would
+	let header_head = chain.header_head()?;
+	chain.reset_sync_head()?;
+	chain.rebuild_sync_mmr(&header_head)?;
+
+	let mut reader = BufReader::new(File::open(dir.join(SNAPSHOT_HEADERS))?);
+	let count: u64 = ser::deserialize(&mut reader).map_err(ser_error)?;
+	let mut headers = Vec::with_capacity(HEADERS_BATCH_SIZE);
+	for _ in 0..count {
+		headers.push(ser::deserialize::<BlockHeader>(&mut reader).map_err(ser_error)?);
+		if headers.len() == HEADERS_BATCH_SIZE {
+			chain.sync_block_headers(&headers, opts)?;
+			progress(headers.last().map(|h| h.height).unwrap_or(0), count);
+			headers.clear();
+		}
+	}
+	if !headers.is_empty() {
+		chain.sync_block_headers(&headers, opts)?;
+		progress(count, count);
+	}
+
+	// the header at the snapshot height must be the one of the manifest, with
+	// the same roots
+	let header = chain.get_header_by_height(manifest.height)?;
+	let expected = SnapshotManifest::from_header(genesis, &header, sha256);
+	if expected != manifest {
+		return Err(invalid_snapshot(format!(
+			"header at {} is {} and doesn't match the manifest",
+			manifest.height, expected.hash
+		)));
+	}
+
+	chain.txhashset_restore(header.hash(), File::open(&zip_path)?, &LogStatus)?;
+	Ok(manifest)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::chain::types::NoopAdapter;
+	use crate::core::global::ChainTypes;
+	use crate::core::libtx;
+	use crate::core::pow::Difficulty;
+	use crate::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+	use chrono::Duration;
+
+	fn init_test_chain(dir: &str) -> chain::Chain {
+		let _ = fs::remove_dir_all(dir);
+		chain::Chain::init(
+			dir.to_string(),
+			Arc::new(NoopAdapter {}),
+			pow::mine_genesis_block().unwrap(),
+			pow::verify_size,
+			Arc::new(RwLock::new(LruVerifierCache::new())),
+			false,
+		)
+		.unwrap()
+	}
+
+	// Adds empty blocks on top of the chain, with random proofs of work.
+	fn add_blocks(chain: &chain::Chain, count: u64) {
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+		for _ in 0..count {
+			let prev = chain.head_header().unwrap();
+			let height = prev.height + 1;
+			let key_id = ExtKeychainPath::new(1, height as u32, 0, 0, 0).to_identifier();
+			let reward = libtx::reward::output(&kc, &key_id, 0, false, height).unwrap();
+			let diff = Difficulty::from_num(height + 1);
+			let mut b = Block::new(&prev, vec![], diff.clone(), reward).unwrap();
+			b.header.timestamp = prev.timestamp + Duration::seconds(60);
+			b.header.pow.total_difficulty = prev.total_difficulty() + diff;
+			b.header.pow.proof = pow::Proof::random(global::proofsize());
+			chain.set_txhashset_roots(&mut b).unwrap();
+			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		}
+	}
+
+	#[test]
+	fn test_export_import_blocks() {
+		global::set_mining_mode(ChainTypes::AutomatedTesting);
+		let (src_dir, dst_dir) = (".epic_export_src", ".epic_export_dst");
+		let archive = Path::new(".epic_export.bin");
+		{
+			let src = init_test_chain(src_dir);
+			add_blocks(&src, 5);
+			assert_eq!(export_blocks(&src, archive, 0, None, |_, _| {}).unwrap(), 5);
+
+			// the blocks only have random proofs of work
+			let dst = init_test_chain(dst_dir);
+			let imported = import(&dst, archive, chain::Options::SKIP_POW, |_, _| {}).unwrap();
+			assert_eq!(imported, 5);
+			assert_eq!(dst.head().unwrap(), src.head().unwrap());
+			dst.validate(false).unwrap();
+
+			// importing again skips the blocks we already have
+			let imported = import(&dst, archive, chain::Options::SKIP_POW, |_, _| {}).unwrap();
+			assert_eq!(imported, 0);
+		}
+		let _ = fs::remove_dir_all(src_dir);
+		let _ = fs::remove_dir_all(dst_dir);
+		let _ = fs::remove_file(archive);
+	}
+
+	#[test]
+	fn test_snapshot() {
+		global::set_mining_mode(ChainTypes::AutomatedTesting);
+		let (src_dir, dst_dir) = (".epic_snapshot_src", ".epic_snapshot_dst");
+		let snapshot_dir = Path::new(".epic_snapshot");
+		let _ = fs::remove_dir_all(snapshot_dir);
+		{
+			let src = init_test_chain(src_dir);
+			add_blocks(&src, 5);
+			let manifest = create_snapshot(&src, snapshot_dir, 4).unwrap();
+			let header = src.get_header_by_height(4).unwrap();
+			assert_eq!(manifest.height, 4);
+			assert_eq!(manifest.hash, header.hash().to_hex());
+
+			// the headers only have random proofs of work
+			let dst = init_test_chain(dst_dir);
+			let opts = chain::Options::SYNC | chain::Options::SKIP_POW;
+			let restored = restore(&dst, snapshot_dir, opts, |_, _| {}).unwrap();
+			assert_eq!(restored, manifest);
+			assert_eq!(dst.head().unwrap().last_block_h, header.hash());
+			assert_eq!(dst.head_header().unwrap().output_root, header.output_root);
+		}
+		{
+			// a snapshot missing its txhashset is an error, not a crash
+			fs::remove_file(snapshot_dir.join(SNAPSHOT_TXHASHSET)).unwrap();
+			let dst = init_test_chain(dst_dir);
+			assert!(restore_snapshot(&dst, snapshot_dir, |_, _| {}).is_err());
+			assert_eq!(dst.head().unwrap().height, 0);
+		}
+		let _ = fs::remove_dir_all(src_dir);
+		let _ = fs::remove_dir_all(dst_dir);
+		let _ = fs::remove_dir_all(snapshot_dir);
+	}
+}
@@ -114,8 +114,13 @@ pub fn connect_and_monitor(
 				if Utc::now() - prev_ping > Duration::seconds(10) {
 					let total_diff = peers.total_difficulty();
 					let total_height = peers.total_height();
-					if total_diff.is_ok() && total_height.is_ok() {
-						peers.check_all(total_diff.unwrap(), total_height.unwrap());
+					let tail_height = peers.tail_height();
+					if total_diff.is_ok() && total_height.is_ok() && tail_height.is_ok() {
+						peers.check_all(
+							total_diff.unwrap(),
+							total_height.unwrap(),
+							tail_height.unwrap(),
+						);
 						prev_ping = Utc::now();
 					} else {
 						error!("failed to get peers difficulty and/or height");
@@ -176,6 +181,11 @@ fn monitor_peers(
 	// maintenance step first, clean up p2p server peers
 	peers.clean_peers(config.peer_max_count() as usize);
 
+	// when full, make room for a new peer in place of one that misbehaved
+	// repeatedly, a single minor penalty isn't worth the churn
+	if peers.peer_count() >= config.peer_max_count() {
+		peers.evict_lowest_score(p2p::types::BAN_SCORE / 2);
+	}
 
 	if peers.healthy_peers_mix() {
 		return;
@@ -234,22 +244,18 @@ fn monitor_peers(
 		let _ = peers.update_state(defuncts[0].addr, p2p::State::Healthy);
 	}
 
-	// find some peers from our db
+	// pick some peers from the address manager, spread over network groups,
 	// and queue them up for a connection attempt
-	let new_peers = peers.find_peers(
-		p2p::State::Healthy,
-		p2p::Capabilities::UNKNOWN,
-		config.peer_max_count() as usize,
-	);
+	let new_peers = peers.select_outbound_candidates(config.peer_max_count() as usize);
 
-	for p in new_peers.iter().filter(|p| !peers.is_known(p.addr)) {
+	for addr in new_peers {
 		trace!(
 			"monitor_peers: on {}:{}, queue to soon try {}",
 			config.host,
 			config.port,
-			p.addr,
+			addr,
 		);
-		tx.send(p.addr).unwrap();
+		tx.send(addr).unwrap();
 	}
 }
 
@@ -261,16 +267,19 @@ fn connect_to_seeds_and_preferred_peers(
 	seed_list: Box<dyn Fn() -> Vec<PeerAddr>>,
 	peers_preferred_list: Option<Vec<PeerAddr>>,
 ) {
+	// reconnect to the anchor peers we had on last shutdown first
+	let mut peer_addrs = peers.anchors();
+
 	// check if we have some peers in db
 	// look for peers that are able to give us other peers (via PEER_LIST capability)
 	let peers = peers.find_peers(p2p::State::Healthy, p2p::Capabilities::PEER_LIST, 100);
 
 	// if so, get their addresses, otherwise use our seeds
-	let mut peer_addrs = if peers.len() > 3 {
-		peers.iter().map(|p| p.addr).collect::<Vec<_>>()
+	if peers.len() > 3 {
+		peer_addrs.extend(peers.iter().map(|p| p.addr));
 	} else {
-		seed_list()
-	};
+		peer_addrs.extend(seed_list());
+	}
 
 	// If we have preferred peers add them to the connection
 	match peers_preferred_list {
@@ -332,6 +341,21 @@ fn listen_for_addrs(
 		}
 		connecting_history.insert(addr, now);
 
+		// do not let a single network group take over our outbound connections,
+		// explicitly preferred peers are exempt
+		let preferred = p2p
+			.config
+			.peers_preferred
+			.as_ref()
+			.map_or(false, |p| p.contains(&addr));
+		if !preferred && !peers.netgroup_has_room(addr) {
+			debug!(
+				"peer_connect: skipping {}, too many outbound peers in its network group",
+				addr
+			);
+			continue;
+		}
+
 		let peers_c = peers.clone();
 		let p2p_c = p2p.clone();
 		thread::Builder::new()
@@ -41,9 +41,10 @@ use crate::common::stats::{DiffBlock, DiffStats, PeerStats, ServerStateInfo, Ser
 use crate::common::types::{Error, ServerConfig, StratumServerConfig, SyncState, SyncStatus};
 use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
 use crate::core::core::verifier_cache::{LruVerifierCache, VerifierCache};
+use crate::core::core::Block;
 use crate::core::pow::{PoWType, Proof};
 use crate::core::{consensus, genesis, global, pow, consensus::DIFFICULTY_ADJUST_WINDOW};
-use crate::epic::{dandelion_monitor, seed, sync, version};
+use crate::epic::{chain_validator, dandelion_monitor, offline, seed, sync, version};
 use crate::mining::stratumserver;
 use crate::mining::test_miner::Miner;
 use crate::p2p;
@@ -53,6 +54,19 @@ use crate::util::file::get_first_line;
 use crate::util::{RwLock, StopState};
 use chrono::prelude::Utc;
 
+/// Interval between two saves of the transaction pool to disk.
+const POOL_SAVE_INTERVAL_MINS: u32 = 5;
+
+/// Genesis block of the provided chain type.
+pub fn genesis_block(chain_type: &global::ChainTypes) -> Block {
+	match chain_type {
+		global::ChainTypes::AutomatedTesting => genesis::genesis_dev(),
+		global::ChainTypes::UserTesting => genesis::genesis_dev(),
+		global::ChainTypes::Floonet => genesis::genesis_floo(),
+		global::ChainTypes::Mainnet => genesis::genesis_main(),
+	}
+}
+
 /// Epic server holding internal structures.
 pub struct Server {
 	/// server config
@@ -77,6 +91,7 @@ pub struct Server {
 	connect_thread: Option<JoinHandle<()>>,
 	sync_thread: JoinHandle<()>,
 	dandelion_thread: JoinHandle<()>,
+	validator_thread: JoinHandle<()>,
 	p2p_thread: JoinHandle<()>,
 	version_checker_thread: ScheduleHandle,
 }
@@ -148,7 +163,7 @@ impl Server {
 	// Exclusive (advisory) lock_file to ensure we do not run multiple
 	// instance of epic server from the same dir.
 	// This uses fs2 and should be safe cross-platform unless somebody abuses the file itself.
-	fn one_epic_at_a_time(config: &ServerConfig) -> Result<Arc<File>, Error> {
+	pub(crate) fn one_epic_at_a_time(config: &ServerConfig) -> Result<Arc<File>, Error> {
 		let path = Path::new(&config.db_root);
 		fs::create_dir_all(path.clone())?;
 		let path = path.join("epic.lock");
@@ -175,6 +190,11 @@ impl Server {
 		// Obtain our lock_file or fail immediately with an error.
 		let lock_file = Server::one_epic_at_a_time(&config)?;
 
+		let compact_free_percent = config.db_compact_free_percent.unwrap_or(0);
+		if compact_free_percent > 0 {
+			offline::compact_databases(&config.db_root, compact_free_percent)?;
+		}
+
 		// Defaults to None (optional) in config file.
 		// This translates to false here.
 		let archive_mode = match config.archive_mode {
@@ -206,26 +226,33 @@ impl Server {
 			init_chain_hooks(&config),
 		));
 
-		let genesis = match config.chain_type {
-			global::ChainTypes::AutomatedTesting => genesis::genesis_dev(),
-			global::ChainTypes::UserTesting => genesis::genesis_dev(),
-			global::ChainTypes::Floonet => genesis::genesis_floo(),
-			global::ChainTypes::Mainnet => genesis::genesis_main(),
-		};
+		let genesis = genesis_block(&config.chain_type);
 
 		info!("Starting server, genesis block: {}", genesis.hash());
 
-		let shared_chain = Arc::new(chain::Chain::init(
+		let mut chain = chain::Chain::init(
 			config.db_root.clone(),
 			chain_adapter.clone(),
 			genesis.clone(),
 			pow::verify_size,
 			verifier_cache.clone(),
 			archive_mode,
-		)?);
+		)?;
+		chain.set_block_retention(config.block_retention());
+		let shared_chain = Arc::new(chain);
 
 		pool_adapter.set_chain(shared_chain.clone());
 
+		// Restore the pool saved on shutdown, now the chain can validate it.
+		let pool_path = Path::new(&config.db_root).join(pool::POOL_FILE);
+		{
+			let head = shared_chain.head_header()?;
+			match tx_pool.write().restore(&pool_path, &head) {
+				Ok(restored) => info!("Restored {} pool transactions", restored),
+				Err(e) => warn!("Failed to restore the transaction pool: {}", e),
+			}
+		}
+
 		let net_adapter = Arc::new(NetToChainAdapter::new(
 			sync_state.clone(),
 			shared_chain.clone(),
@@ -235,9 +262,15 @@ impl Server {
 			init_net_hooks(&config),
 		));
 
+		// archive nodes can serve full blocks all the way back to genesis
+		let mut capabilities = config.p2p_config.capabilities;
+		if archive_mode {
+			capabilities.insert(p2p::Capabilities::BLOCK_HIST);
+		}
+
 		let p2p_server = Arc::new(p2p::Server::new(
 			&config.db_root,
-			config.p2p_config.capabilities,
+			capabilities,
 			config.p2p_config.clone(),
 			net_adapter.clone(),
 			genesis.hash(),
@@ -335,29 +368,48 @@ impl Server {
 			stop_state.clone(),
 		)?;
 
+		info!("Starting the chain validator");
+		let validator_thread =
+			chain_validator::run(shared_chain.clone(), sync_state.clone(), stop_state.clone())?;
+
 		info!("Starting the version checker monitor!");
 		let mut scheduler = Scheduler::new();
-		scheduler.every(15.minutes()).run(|| {
-			if let Ok(dns_version) = version::get_dns_version() {
-				if let Some(our_version) = global::get_epic_version() {
-					if !version::is_version_valid(our_version.clone(), dns_version.clone()) {
-						error!(
-							"Your current epic node version {}.{}.X.X is outdated! Please consider updating your code to the newest version {}.{}.X.X!",
-							our_version.version_major,
-							our_version.version_minor,
-							dns_version.version_major,
-							dns_version.version_minor,
-						);
-						error!("Closing the application!");
-						std::process::exit(1);
+		let version_config = config.version_check_config.clone();
+		if let Some(source) = version::version_source(&version_config, p2p_server.peers.clone()) {
+			scheduler
+				.every(version_config.interval_mins.max(1).minutes())
+				.run(move || {
+					let check = version::check_version(source.as_ref());
+					if check.status == global::VersionCheckStatus::Outdated {
+						if let (Some(our_version), Some(allowed_version)) =
+							(global::get_epic_version(), check.allowed_version)
+						{
+							warn!(
+								"Your current epic node version {}.{}.X.X is outdated! Please consider updating your code to the newest version {}.{}.X.X!",
+								our_version.version_major,
+								our_version.version_minor,
+								allowed_version.version_major,
+								allowed_version.version_minor,
+							);
+						}
+						if source.trusted() {
+							error!("Closing the application!");
+							std::process::exit(1);
+						}
 					}
-				} else {
-					error!("Failed to retrieve information about the this application's version!");
-				}
-			} else {
-				error!("Unable to get the allowed versions from the dns server!");
-			}
-		});
+				});
+		}
+		// Save the pool every now and then, in case we don't get to on shutdown.
+		{
+			let tx_pool = tx_pool.clone();
+			scheduler
+				.every(POOL_SAVE_INTERVAL_MINS.minutes())
+				.run(move || {
+					if let Err(e) = tx_pool.read().save(&pool_path) {
+						error!("Failed to save the transaction pool: {}", e);
+					}
+				});
+		}
 		let version_checker_thread = scheduler.watch_thread(Duration::from_millis(100));
 
 		warn!("Epic server started.");
@@ -377,6 +429,7 @@ impl Server {
 			sync_thread,
 			p2p_thread,
 			dandelion_thread,
+			validator_thread,
 			version_checker_thread,
 		})
 	}
@@ -391,7 +444,10 @@ impl Server {
 	/// their heights
 	pub fn ping_peers(&self) -> Result<(), Error> {
 		let head = self.chain.head()?;
-		self.p2p.peers.check_all(head.total_difficulty, head.height);
+		let tail = self.chain.tail().unwrap_or_else(|_| head.clone());
+		self.p2p
+			.peers
+			.check_all(head.total_difficulty, head.height, tail.height);
 		Ok(())
 	}
 
@@ -627,6 +683,11 @@ impl Server {
 				Err(e) => error!("failed to join to dandelion_monitor thread: {:?}", e),
 				Ok(_) => info!("dandelion_monitor thread stopped"),
 			}
+
+			match self.validator_thread.join() {
+				Err(e) => error!("failed to join to chain_validator thread: {:?}", e),
+				Ok(_) => info!("chain_validator thread stopped"),
+			}
 		}
 		self.p2p.stop();
 		match self.p2p_thread.join() {
@@ -635,6 +696,12 @@ impl Server {
 		}
 
 		self.version_checker_thread.stop();
+
+		let pool_path = Path::new(&self.config.db_root).join(pool::POOL_FILE);
+		match self.tx_pool.read().save(&pool_path) {
+			Ok(_) => info!("transaction pool saved"),
+			Err(e) => error!("failed to save the transaction pool: {}", e),
+		}
 		let _ = self.lock_file.unlock();
 	}
 
@@ -14,23 +14,38 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
-use std::cmp;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 
 use crate::chain;
 use crate::common::types::{SyncState, SyncStatus};
 use crate::core::core::hash::Hash;
-use crate::p2p;
+use crate::p2p::{self, PeerAddr};
+
+/// How far ahead of our body head we are willing to request blocks.
+/// Anything beyond that would only end up in the orphan pool.
+const DOWNLOAD_WINDOW: usize = 512;
+
+/// Max number of blocks in flight for a single peer, bounded by the size of
+/// its send channel.
+const MAX_IN_FLIGHT_PER_PEER: usize = p2p::SEND_CHANNEL_CAP * 2;
+
+/// Time after which a block request is considered lost and reassigned.
+const REQUEST_TIMEOUT_SECS: i64 = 15;
+
+/// Latency assumed for a peer we have not downloaded anything from yet.
+const DEFAULT_LATENCY_MS: i64 = 2_000;
+
+/// How often the scheduler checks for received and timed out blocks.
+const CHECK_INTERVAL_MS: i64 = 500;
 
 pub struct BodySync {
 	chain: Arc<chain::Chain>,
 	peers: Arc<p2p::Peers>,
 	sync_state: Arc<SyncState>,
 
-	blocks_requested: u64,
-
-	receive_timeout: DateTime<Utc>,
-	prev_blocks_received: u64,
+	scheduler: DownloadScheduler,
+	next_check: DateTime<Utc>,
 }
 
 impl BodySync {
@@ -43,9 +58,8 @@ impl BodySync {
 			sync_state,
 			peers,
 			chain,
-			blocks_requested: 0,
-			receive_timeout: Utc::now(),
-			prev_blocks_received: 0,
+			scheduler: DownloadScheduler::new(),
+			next_check: Utc::now(),
 		}
 	}
 
@@ -56,17 +70,21 @@ impl BodySync {
 		head: &chain::Tip,
 		highest_height: u64,
 	) -> Result<bool, chain::Error> {
-		// run the body_sync every 5s
-		if self.body_sync_due()? {
-			if self.body_sync()? {
-				return Ok(true);
-			}
+		let now = Utc::now();
+		if now < self.next_check {
+			return Ok(false);
+		}
+		self.next_check = now + Duration::milliseconds(CHECK_INTERVAL_MS);
 
-			self.sync_state.update(SyncStatus::BodySync {
-				current_height: head.height,
-				highest_height: highest_height,
-			});
+		if self.body_sync()? {
+			return Ok(true);
 		}
+
+		self.sync_state.update(SyncStatus::BodySync {
+			current_height: head.height,
+			highest_height: highest_height,
+			blocks_per_min: self.scheduler.blocks_per_min(now),
+		});
 		Ok(false)
 	}
 
@@ -87,6 +105,7 @@ impl BodySync {
 			debug!(
 				"body_sync: cannot sync full blocks earlier than horizon. will request txhashset",
 			);
+			self.scheduler.reset();
 			return Ok(true);
 		}
 
@@ -100,97 +119,379 @@ impl BodySync {
 
 		hashes.reverse();
 
+		let now = Utc::now();
+
+		// first account for everything that arrived since the last check,
+		// a block is "received" once it is either stored or in our orphan list
+		let chain = self.chain.clone();
+		let received = self.scheduler.mark_received(now, |h| {
+			chain.block_exists(*h).unwrap_or(false) || chain.is_orphan(h)
+		});
+
+		// then release whatever a peer failed to deliver in time so it can be
+		// asked of someone else
+		let timed_out = self.scheduler.expire(now);
+		if !timed_out.is_empty() {
+			debug!(
+				"body_sync: {} block requests timed out, reassigning",
+				timed_out.len()
+			);
+		}
+		for (_, addr) in timed_out {
+			self.peers.update_score(addr, p2p::ScoreEvent::SlowResponse);
+		}
+
 		let peers = self.peers.more_work_peers()?;
+		self.scheduler
+			.retain_peers(&peers.iter().map(|p| p.info.addr).collect::<Vec<_>>());
+		if peers.is_empty() {
+			return Ok(false);
+		}
 
-		// if we have 5 peers to sync from then ask for 50 blocks total (peer_count *
-		// 10) max will be 80 if all 8 peers are advertising more work
-		// also if the chain is already saturated with orphans, throttle
-		let block_count = cmp::min(
-			cmp::min(100, peers.len() * p2p::SEND_CHANNEL_CAP),
-			chain::MAX_ORPHAN_SIZE.saturating_sub(self.chain.orphans_len()) + 1,
-		);
+		// cap memory use: blocks in flight may all end up in the orphan pool
+		let orphan_capacity = chain::MAX_ORPHAN_SIZE.saturating_sub(self.chain.orphans_len());
+		let free_slots = orphan_capacity.saturating_sub(self.scheduler.in_flight_count());
 
+		// only consider the sliding window right above our body head, skipping
+		// what we already have or what is already on its way
 		let hashes_to_get = hashes
 			.iter()
+			.take(DOWNLOAD_WINDOW)
 			.filter(|x| {
-				// only ask for blocks that we have not yet processed
-				// either successfully stored or in our orphan list
-				!self.chain.get_block(x).is_ok() && !self.chain.is_orphan(x)
+				!self.scheduler.is_in_flight(x)
+					&& !self.chain.block_exists(**x).unwrap_or(false)
+					&& !self.chain.is_orphan(x)
 			})
-			.take(block_count)
+			.take(free_slots)
+			.cloned()
 			.collect::<Vec<_>>();
 
-		if hashes_to_get.len() > 0 {
-			let body_head = self.chain.head()?;
-			let header_head = self.chain.header_head()?;
+		if hashes_to_get.is_empty() {
+			return Ok(false);
+		}
 
-			debug!(
-				"block_sync: {}/{} requesting blocks {:?} from {} peers",
-				body_head.height,
-				header_head.height,
-				hashes_to_get,
-				peers.len(),
-			);
+		let body_head = self.chain.head()?;
+		let header_head = self.chain.header_head()?;
 
-			// reinitialize download tracking state
-			self.blocks_requested = 0;
-			self.receive_timeout = Utc::now() + Duration::seconds(6);
-
-			let mut peers_iter = peers.iter().cycle();
-			for hash in hashes_to_get.clone() {
-				if let Some(peer) = peers_iter.next() {
-					if let Err(e) = peer.send_block_request(*hash) {
-						debug!("Skipped request to {}: {:?}", peer.info.addr, e);
-						peer.stop();
-					} else {
-						self.blocks_requested += 1;
-					}
-				}
+		debug!(
+			"block_sync: {}/{} requesting {} blocks from {} peers ({} received, {} in flight)",
+			body_head.height,
+			header_head.height,
+			hashes_to_get.len(),
+			peers.len(),
+			received,
+			self.scheduler.in_flight_count(),
+		);
+
+		for hash in hashes_to_get {
+			// only ask the peers still holding the full block, pruned peers
+			// may not go back as far as we need
+			let height = match self.chain.get_block_header(&hash) {
+				Ok(header) => header.height,
+				Err(_) => continue,
+			};
+			let addrs = peers
+				.iter()
+				.filter(|p| p.info.has_block(height))
+				.map(|p| p.info.addr)
+				.collect::<Vec<_>>();
+			if addrs.is_empty() {
+				debug!("body_sync: no peer holds the block at {}", height);
+				break;
+			}
+			let addr = match self.scheduler.pick_peer(&hash, &addrs) {
+				Some(addr) => addr,
+				// every peer is at capacity, wait for some deliveries
+				None => break,
+			};
+			let peer = match peers.iter().find(|p| p.info.addr == addr) {
+				Some(peer) => peer,
+				None => break,
+			};
+			if let Err(e) = peer.send_block_request(hash) {
+				debug!("Skipped request to {}: {:?}", peer.info.addr, e);
+				peer.stop();
+				self.scheduler.remove_peer(&addr);
+			} else {
+				self.scheduler.requested(hash, addr, now);
 			}
 		}
-		return Ok(false);
+		Ok(false)
+	}
+}
+
+/// A single outstanding block request.
+struct InFlight {
+	peer: PeerAddr,
+	requested_at: DateTime<Utc>,
+}
+
+/// Per peer download statistics, used to prefer fast peers.
+struct PeerDownloadStats {
+	in_flight: usize,
+	// exponentially weighted moving average of request latency
+	latency_ms: i64,
+	timeouts: u64,
+}
+
+impl PeerDownloadStats {
+	fn new() -> PeerDownloadStats {
+		PeerDownloadStats {
+			in_flight: 0,
+			latency_ms: DEFAULT_LATENCY_MS,
+			timeouts: 0,
+		}
+	}
+
+	fn record_latency(&mut self, latency_ms: i64) {
+		self.latency_ms = (self.latency_ms * 3 + latency_ms) / 4;
+	}
+
+	// Expected time for this peer to deliver one more block, the lower the better.
+	fn expected_delivery_ms(&self) -> i64 {
+		self.latency_ms * (self.in_flight as i64 + 1)
+	}
+}
+
+/// Keeps track of the blocks requested from each peer during body sync.
+/// Requests not answered within REQUEST_TIMEOUT_SECS get reassigned to
+/// another peer and the slow peer gets penalized, so fast peers end up
+/// serving the bulk of the download.
+pub struct DownloadScheduler {
+	in_flight: HashMap<Hash, InFlight>,
+	// last peer a given block timed out on, to avoid asking it again
+	timed_out_on: HashMap<Hash, PeerAddr>,
+	peer_stats: HashMap<PeerAddr, PeerDownloadStats>,
+	// receive times over the last minute, for the throughput
+	received: VecDeque<DateTime<Utc>>,
+}
+
+impl DownloadScheduler {
+	pub fn new() -> DownloadScheduler {
+		DownloadScheduler {
+			in_flight: HashMap::new(),
+			timed_out_on: HashMap::new(),
+			peer_stats: HashMap::new(),
+			received: VecDeque::new(),
+		}
+	}
+
+	/// Forget about all requests, keeping peer statistics.
+	pub fn reset(&mut self) {
+		self.in_flight.clear();
+		self.timed_out_on.clear();
+		for stats in self.peer_stats.values_mut() {
+			stats.in_flight = 0;
+		}
+	}
+
+	pub fn in_flight_count(&self) -> usize {
+		self.in_flight.len()
 	}
 
-	// Should we run block body sync and ask for more full blocks?
-	fn body_sync_due(&mut self) -> Result<bool, chain::Error> {
-		let blocks_received = self.blocks_received()?;
+	pub fn is_in_flight(&self, h: &Hash) -> bool {
+		self.in_flight.contains_key(h)
+	}
+
+	/// Record a block request sent to a peer.
+	pub fn requested(&mut self, h: Hash, peer: PeerAddr, now: DateTime<Utc>) {
+		if let Some(prev) = self.in_flight.insert(
+			h,
+			InFlight {
+				peer,
+				requested_at: now,
+			},
+		) {
+			self.release(&prev.peer);
+		}
+		self.peer_stats
+			.entry(peer)
+			.or_insert_with(PeerDownloadStats::new)
+			.in_flight += 1;
+	}
 
-		// some blocks have been requested
-		if self.blocks_requested > 0 {
-			// but none received since timeout, ask again
-			let timeout = Utc::now() > self.receive_timeout;
-			if timeout && blocks_received <= self.prev_blocks_received {
-				debug!(
-					"body_sync: expecting {} more blocks and none received for a while",
-					self.blocks_requested,
-				);
-				return Ok(true);
+	/// Check all requests in flight against the provided predicate, marking
+	/// the ones it reports as received. Returns the number of received blocks.
+	pub fn mark_received<F>(&mut self, now: DateTime<Utc>, is_received: F) -> usize
+	where
+		F: Fn(&Hash) -> bool,
+	{
+		let received = self
+			.in_flight
+			.keys()
+			.filter(|h| is_received(h))
+			.cloned()
+			.collect::<Vec<_>>();
+		for h in &received {
+			if let Some(req) = self.in_flight.remove(h) {
+				let latency = (now - req.requested_at).num_milliseconds().max(0);
+				if let Some(stats) = self.peer_stats.get_mut(&req.peer) {
+					stats.in_flight = stats.in_flight.saturating_sub(1);
+					stats.record_latency(latency);
+				}
+				self.received.push_back(now);
 			}
+			self.timed_out_on.remove(h);
 		}
+		self.truncate_received(now);
+		received.len()
+	}
 
-		if blocks_received > self.prev_blocks_received {
-			// some received, update for next check
-			self.receive_timeout = Utc::now() + Duration::seconds(1);
-			self.blocks_requested = self
-				.blocks_requested
-				.saturating_sub(blocks_received - self.prev_blocks_received);
-			self.prev_blocks_received = blocks_received;
+	/// Release all requests older than the timeout, penalizing the peers
+	/// that did not deliver. Returns the released hashes along with the peer
+	/// each of them timed out on.
+	pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<(Hash, PeerAddr)> {
+		let deadline = now - Duration::seconds(REQUEST_TIMEOUT_SECS);
+		let expired = self
+			.in_flight
+			.iter()
+			.filter(|(_, req)| req.requested_at < deadline)
+			.map(|(h, req)| (*h, req.peer))
+			.collect::<Vec<_>>();
+		for (h, _) in &expired {
+			if let Some(req) = self.in_flight.remove(h) {
+				if let Some(stats) = self.peer_stats.get_mut(&req.peer) {
+					stats.in_flight = stats.in_flight.saturating_sub(1);
+					stats.timeouts += 1;
+					stats.record_latency(REQUEST_TIMEOUT_SECS * 1000 * 2);
+					debug!(
+						"body_sync: block {} timed out on {} ({} timeouts so far)",
+						h, req.peer, stats.timeouts
+					);
+				}
+				self.timed_out_on.insert(*h, req.peer);
+			}
 		}
+		expired
+	}
 
-		// off by one to account for broadcast adding a couple orphans
-		if self.blocks_requested < 2 {
-			// no pending block requests, ask more
-			debug!("body_sync: no pending block request, asking more");
-			return Ok(true);
+	/// Drop statistics and release requests of peers no longer available.
+	pub fn retain_peers(&mut self, peers: &[PeerAddr]) {
+		let gone = self
+			.peer_stats
+			.keys()
+			.filter(|addr| !peers.contains(addr))
+			.cloned()
+			.collect::<Vec<_>>();
+		for addr in gone {
+			self.remove_peer(&addr);
 		}
+	}
 
-		Ok(false)
+	/// Forget a peer, releasing everything that was requested from it.
+	pub fn remove_peer(&mut self, peer: &PeerAddr) {
+		self.peer_stats.remove(peer);
+		self.in_flight.retain(|_, req| req.peer != *peer);
+	}
+
+	/// Pick the peer expected to deliver the provided block the fastest,
+	/// among those not at capacity. Avoids the peer the block last timed
+	/// out on if there is any other choice.
+	pub fn pick_peer(&mut self, h: &Hash, peers: &[PeerAddr]) -> Option<PeerAddr> {
+		let avoid = self.timed_out_on.get(h).cloned();
+		for addr in peers {
+			self.peer_stats
+				.entry(*addr)
+				.or_insert_with(PeerDownloadStats::new);
+		}
+		let mut candidates = peers
+			.iter()
+			.filter(|addr| self.peer_stats[*addr].in_flight < MAX_IN_FLIGHT_PER_PEER)
+			.cloned()
+			.collect::<Vec<_>>();
+		candidates.sort_by_key(|addr| {
+			(
+				Some(*addr) == avoid,
+				self.peer_stats[addr].expected_delivery_ms(),
+			)
+		});
+		candidates.first().cloned()
 	}
 
-	// Total numbers received on this chain, including the head and orphans
-	fn blocks_received(&self) -> Result<u64, chain::Error> {
-		Ok((self.chain.head()?).height
-			+ self.chain.orphans_len() as u64
-			+ self.chain.orphans_evicted_len() as u64)
+	/// Number of blocks received over the last minute.
+	pub fn blocks_per_min(&mut self, now: DateTime<Utc>) -> u64 {
+		self.truncate_received(now);
+		self.received.len() as u64
+	}
+
+	fn release(&mut self, peer: &PeerAddr) {
+		if let Some(stats) = self.peer_stats.get_mut(peer) {
+			stats.in_flight = stats.in_flight.saturating_sub(1);
+		}
+	}
+
+	fn truncate_received(&mut self, now: DateTime<Utc>) {
+		let cutoff = now - Duration::minutes(1);
+		while self.received.front().map_or(false, |t| *t < cutoff) {
+			self.received.pop_front();
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::core::hash::{Hashed, ZERO_HASH};
+	use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+	fn addr(n: u8) -> PeerAddr {
+		PeerAddr(SocketAddr::new(
+			IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)),
+			3414,
+		))
+	}
+
+	#[test]
+	fn test_prefers_fast_peers() {
+		let mut sched = DownloadScheduler::new();
+		let now = Utc::now();
+		let (fast, slow) = (addr(1), addr(2));
+		let h1 = 1u64.hash();
+		let h2 = 2u64.hash();
+
+		sched.requested(h1, fast, now);
+		sched.requested(h2, slow, now);
+		sched.mark_received(now + Duration::milliseconds(100), |h| *h == h1);
+		sched.mark_received(now + Duration::seconds(5), |h| *h == h2);
+
+		assert_eq!(sched.pick_peer(&3u64.hash(), &[slow, fast]), Some(fast));
+		assert_eq!(sched.blocks_per_min(now + Duration::seconds(5)), 2);
+		assert_eq!(sched.blocks_per_min(now + Duration::minutes(2)), 0);
+	}
+
+	#[test]
+	fn test_timeout_reassigns() {
+		let mut sched = DownloadScheduler::new();
+		let now = Utc::now();
+		let (a, b) = (addr(1), addr(2));
+		let h = 1u64.hash();
+
+		sched.requested(h, a, now);
+		assert!(sched.expire(now + Duration::seconds(1)).is_empty());
+		assert_eq!(
+			sched.expire(now + Duration::seconds(REQUEST_TIMEOUT_SECS + 1)),
+			vec![(h, a)]
+		);
+		assert!(!sched.is_in_flight(&h));
+
+		// the block timed out on a, so b gets it even though a is equally idle
+		assert_eq!(sched.pick_peer(&h, &[a, b]), Some(b));
+		// but a is still used when it is the only choice
+		assert_eq!(sched.pick_peer(&h, &[a]), Some(a));
+	}
+
+	#[test]
+	fn test_per_peer_capacity() {
+		let mut sched = DownloadScheduler::new();
+		let now = Utc::now();
+		let a = addr(1);
+		for i in 0..MAX_IN_FLIGHT_PER_PEER as u64 {
+			sched.requested(i.hash(), a, now);
+		}
+		assert_eq!(sched.pick_peer(&ZERO_HASH, &[a]), None);
+
+		sched.remove_peer(&a);
+		assert_eq!(sched.in_flight_count(), 0);
+		assert_eq!(sched.pick_peer(&ZERO_HASH, &[a]), Some(a));
 	}
 }
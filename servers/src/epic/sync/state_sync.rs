@@ -97,6 +97,7 @@ impl StateSync {
 			self.sync_state.update(SyncStatus::BodySync {
 				current_height: 0,
 				highest_height: 0,
+				blocks_per_min: 0,
 			});
 			true
 		} else {
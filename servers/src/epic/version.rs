@@ -1,29 +1,237 @@
-use crate::core::global::Version;
+//! Checks of this node's version against the minimum version nodes are
+//! allowed to run, taken from a configurable source.
+
+use crate::api;
+use crate::common::types::{VersionCheckConfig, VersionSourceType};
+use crate::core::global::{self, Version, VersionCheck, VersionCheckStatus};
+use crate::p2p;
+use crate::util::file::get_first_line;
+use chrono::prelude::Utc;
+use hyper::{Body, Request};
 use std::io::{self, Error, ErrorKind};
+use std::net::SocketAddr;
 use std::str;
+use std::sync::Arc;
 use trust_dns_resolver::config::*;
 use trust_dns_resolver::Resolver;
 
-pub fn get_dns_version() -> io::Result<Version> {
-	let resolver = Resolver::new(ResolverConfig::default(), ResolverOpts::default())?;
-	let response = resolver.txt_lookup("epicversion.epic.tech.")?;
-	let response_next = response.iter().next().ok_or(Error::new(
-		ErrorKind::Other,
-		"Invalid response when checking the node version!",
-	))?;
-	let version_next = response_next.iter().next().ok_or(Error::new(
-		ErrorKind::Other,
-		"Invalid response! Response doesn't include the node version!",
-	))?;
-	let version_string = str::from_utf8(version_next).map_err(|_e| {
-		Error::new(
+/// Minimum number of connected peers needed to trust their user agents
+const MIN_PEERS_FOR_VERSION: usize = 4;
+
+/// A source for the minimum version nodes are allowed to run.
+pub trait VersionSource: Send + Sync {
+	/// Short description of the source, reported in the status
+	fn name(&self) -> String;
+
+	/// The minimum allowed version
+	fn allowed_version(&self) -> io::Result<Version>;
+
+	/// Whether the node can be stopped on the word of the source. Versions
+	/// reported by untrusted sources only ever get warned about.
+	fn trusted(&self) -> bool {
+		true
+	}
+}
+
+/// Version stored as a TXT record of a domain.
+pub struct DnsVersionSource {
+	domain: String,
+	server: Option<SocketAddr>,
+}
+
+impl DnsVersionSource {
+	/// Queries the provided domain, through the provided name server or the
+	/// system ones if none.
+	pub fn new(domain: String, server: Option<SocketAddr>) -> DnsVersionSource {
+		DnsVersionSource { domain, server }
+	}
+}
+
+impl VersionSource for DnsVersionSource {
+	fn name(&self) -> String {
+		match self.server {
+			Some(server) => format!("dns:{}@{}", self.domain, server),
+			None => format!("dns:{}", self.domain),
+		}
+	}
+
+	fn allowed_version(&self) -> io::Result<Version> {
+		let resolver = match self.server {
+			Some(server) => {
+				let name_servers =
+					NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port());
+				Resolver::new(
+					ResolverConfig::from_parts(None, vec![], name_servers),
+					ResolverOpts::default(),
+				)?
+			}
+			None => Resolver::new(ResolverConfig::default(), ResolverOpts::default())?,
+		};
+		let response = resolver.txt_lookup(self.domain.as_str())?;
+		let response_next = response.iter().next().ok_or(Error::new(
 			ErrorKind::Other,
-			"Invalid response! The version inside the response it's not a valid utf8 string!",
-		)
-	})?;
+			"Invalid response when checking the node version!",
+		))?;
+		let version_next = response_next.iter().next().ok_or(Error::new(
+			ErrorKind::Other,
+			"Invalid response! Response doesn't include the node version!",
+		))?;
+		let version_string = str::from_utf8(version_next).map_err(|_e| {
+			Error::new(
+				ErrorKind::Other,
+				"Invalid response! The version inside the response it's not a valid utf8 string!",
+			)
+		})?;
+		parse_version(version_string)
+	}
+}
+
+/// Version served as plain text by an HTTP endpoint.
+pub struct HttpVersionSource {
+	url: String,
+}
+
+impl HttpVersionSource {
+	/// Fetches the version from the provided url.
+	pub fn new(url: String) -> HttpVersionSource {
+		HttpVersionSource { url }
+	}
+}
+
+impl VersionSource for HttpVersionSource {
+	fn name(&self) -> String {
+		format!("http:{}", self.url)
+	}
+
+	fn allowed_version(&self) -> io::Result<Version> {
+		let req = Request::get(self.url.as_str())
+			.body(Body::empty())
+			.map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+		let body = api::client::send_request(req)
+			.map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+		parse_version(body.trim())
+	}
+}
+
+/// Version stored on the first line of a local file.
+pub struct FileVersionSource {
+	path: String,
+}
+
+impl FileVersionSource {
+	/// Reads the version from the file at the provided path.
+	pub fn new(path: String) -> FileVersionSource {
+		FileVersionSource { path }
+	}
+}
+
+impl VersionSource for FileVersionSource {
+	fn name(&self) -> String {
+		format!("file:{}", self.path)
+	}
+
+	fn allowed_version(&self) -> io::Result<Version> {
+		let line = get_first_line(Some(self.path.clone())).ok_or(Error::new(
+			ErrorKind::NotFound,
+			format!("Unable to read the node version from {}!", self.path),
+		))?;
+		parse_version(&line)
+	}
+}
+
+/// Version advertised in the user agents of our connected peers. The
+/// allowed version is the highest one run by at least two thirds of them.
+/// Not trusted, an outdated result is only warned about.
+pub struct PeersVersionSource {
+	peers: Arc<p2p::Peers>,
+}
+
+impl PeersVersionSource {
+	/// Looks at the peers currently connected.
+	pub fn new(peers: Arc<p2p::Peers>) -> PeersVersionSource {
+		PeersVersionSource { peers }
+	}
+}
+
+impl VersionSource for PeersVersionSource {
+	fn name(&self) -> String {
+		"peers".to_string()
+	}
+
+	// Any peer can claim any version, they can't get to stop us.
+	fn trusted(&self) -> bool {
+		false
+	}
+
+	fn allowed_version(&self) -> io::Result<Version> {
+		let user_agents = self
+			.peers
+			.connected_peers()
+			.iter()
+			.map(|p| p.info.user_agent.clone())
+			.collect::<Vec<_>>();
+		version_from_user_agents(&user_agents)
+	}
+}
+
+/// Picks the highest version run by at least two thirds of the provided
+/// user agents, ignoring the ones we can't parse.
+pub fn version_from_user_agents(user_agents: &[String]) -> io::Result<Version> {
+	let mut versions = user_agents
+		.iter()
+		.filter_map(|ua| ua.rsplit(' ').next())
+		.filter_map(|v| parse_version(v).ok())
+		.map(|v| (v.version_major, v.version_minor))
+		.collect::<Vec<_>>();
+	if versions.len() < MIN_PEERS_FOR_VERSION {
+		return Err(Error::new(
+			ErrorKind::Other,
+			format!(
+				"Not enough peers advertising their version ({} out of {} needed)!",
+				versions.len(),
+				MIN_PEERS_FOR_VERSION
+			),
+		));
+	}
+	versions.sort_by(|a, b| b.cmp(a));
+	let (major, minor) = versions[(versions.len() * 2 + 2) / 3 - 1];
+	Ok(Version::new(major, minor))
+}
+
+/// Builds the version source set in the configuration, if any.
+pub fn version_source(
+	config: &VersionCheckConfig,
+	peers: Arc<p2p::Peers>,
+) -> Option<Box<dyn VersionSource>> {
+	match config.source {
+		VersionSourceType::DNS => Some(Box::new(DnsVersionSource::new(
+			config.dns_domain.clone(),
+			config.dns_server,
+		))),
+		VersionSourceType::HTTP => match config.http_url.clone() {
+			Some(url) => Some(Box::new(HttpVersionSource::new(url))),
+			None => {
+				warn!("Version check from HTTP configured without an http_url, disabling it.");
+				None
+			}
+		},
+		VersionSourceType::File => match config.file_path.clone() {
+			Some(path) => Some(Box::new(FileVersionSource::new(path))),
+			None => {
+				warn!("Version check from a file configured without a file_path, disabling it.");
+				None
+			}
+		},
+		VersionSourceType::Peers => Some(Box::new(PeersVersionSource::new(peers))),
+		VersionSourceType::None => None,
+	}
+}
+
+/// Parses a "major.minor[.whatever]" version string.
+pub fn parse_version(version_string: &str) -> io::Result<Version> {
 	let mut sanitezed = version_string.to_string();
 	sanitezed.retain(|c| !r#"(),";:'"#.contains(c));
-	let version_numbers: Vec<&str> = sanitezed.split(".").collect();
+	let version_numbers: Vec<&str> = sanitezed.trim().split(".").collect();
 	if version_numbers.len() >= 2 {
 		let version_major: u32 = if let Ok(number) = version_numbers[0].parse() {
 			number
@@ -50,9 +258,112 @@ pub fn get_dns_version() -> io::Result<Version> {
 	}
 }
 
+/// Checks our version against the one from the source, recording the
+/// outcome for the status API. Failing to reach the source is only logged
+/// the first time in a row it happens.
+pub fn check_version(source: &dyn VersionSource) -> VersionCheck {
+	let previous = global::get_version_check();
+	let mut check = VersionCheck {
+		source: source.name(),
+		last_checked: Some(Utc::now().timestamp()),
+		..VersionCheck::default()
+	};
+	match (source.allowed_version(), global::get_epic_version()) {
+		(Ok(allowed_version), Some(our_version)) => {
+			check.status = if is_version_valid(our_version, allowed_version.clone()) {
+				VersionCheckStatus::UpToDate
+			} else {
+				VersionCheckStatus::Outdated
+			};
+			check.allowed_version = Some(allowed_version);
+		}
+		(Ok(_), None) => {
+			check.status = VersionCheckStatus::Unavailable;
+			check.error = Some("Unknown version for this application".to_string());
+		}
+		(Err(e), _) => {
+			if previous.status != VersionCheckStatus::Unavailable {
+				warn!(
+					"Unable to get the allowed version from {}: {}",
+					check.source, e
+				);
+			}
+			check.status = VersionCheckStatus::Unavailable;
+			check.error = Some(e.to_string());
+		}
+	}
+	global::set_version_check(check.clone());
+	check
+}
+
 /// Compare if the current version of this application is newer than the allowed version
 pub fn is_version_valid(our_version: Version, allowed_version: Version) -> bool {
 	our_version.version_major > allowed_version.version_major
 		|| (our_version.version_major == allowed_version.version_major
 			&& our_version.version_minor >= allowed_version.version_minor)
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::net::UdpSocket;
+	use std::thread;
+
+	// Minimal stand-in name server, answering a single query with a TXT
+	// record holding the provided text.
+	fn stand_in_resolver(txt: &'static str) -> SocketAddr {
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let addr = socket.local_addr().unwrap();
+		thread::spawn(move || {
+			let mut buf = [0u8; 512];
+			let (len, from) = socket.recv_from(&mut buf).unwrap();
+			let query = &buf[..len];
+
+			// question section: name labels up to the root, then type and class
+			let mut end = 12;
+			while query[end] != 0 {
+				end += query[end] as usize + 1;
+			}
+			end += 5;
+
+			let mut resp = vec![];
+			resp.extend_from_slice(&query[0..2]);
+			resp.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+			resp.extend_from_slice(&query[12..end]);
+			// answer pointing back at the question name, TXT, IN, ttl 60
+			resp.extend_from_slice(&[0xc0, 0x0c, 0, 16, 0, 1, 0, 0, 0, 60]);
+			resp.extend_from_slice(&[0, txt.len() as u8 + 1, txt.len() as u8]);
+			resp.extend_from_slice(txt.as_bytes());
+			socket.send_to(&resp, from).unwrap();
+		});
+		addr
+	}
+
+	#[test]
+	fn test_dns_version_source() {
+		let server = stand_in_resolver("2.3");
+		let source = DnsVersionSource::new("version.test.".to_string(), Some(server));
+		let version = source.allowed_version().unwrap();
+		assert_eq!((version.version_major, version.version_minor), (2, 3));
+		assert!(source.trusted());
+	}
+
+	#[test]
+	fn test_parse_version() {
+		let version = parse_version("\"3.1.0\"").unwrap();
+		assert_eq!((version.version_major, version.version_minor), (3, 1));
+		assert!(parse_version("3").is_err());
+		assert!(parse_version("a.b").is_err());
+	}
+
+	#[test]
+	fn test_version_from_user_agents() {
+		let ua = |v: &str| format!("MW/Epic {}", v);
+		let agents = vec![ua("3.1.0"), ua("3.1.2"), ua("3.0.0"), ua("9.0.0")];
+		let version = version_from_user_agents(&agents).unwrap();
+		assert_eq!((version.version_major, version.version_minor), (3, 1));
+
+		// not enough peers to trust
+		assert!(version_from_user_agents(&agents[..3]).is_err());
+	}
+}
@@ -43,4 +43,5 @@ mod mining;
 
 pub use crate::common::stats::{DiffBlock, PeerStats, ServerStats, StratumStats, WorkerStats};
 pub use crate::common::types::{ServerConfig, StratumServerConfig};
+pub use crate::epic::offline;
 pub use crate::epic::server::Server;
@@ -17,7 +17,7 @@ use std::process::exit;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use std::fs;
 use std::fs::File;
@@ -33,6 +33,7 @@ use crate::config::GlobalConfig;
 use crate::core::global;
 use crate::p2p::{PeerAddr, Seeding};
 use crate::servers;
+use crate::servers::offline;
 use crate::tui::ui;
 use crate::util::zip;
 
@@ -165,6 +166,206 @@ fn start_server_tui(config: servers::ServerConfig) {
 	}
 }
 
+/// Reports progress of a long running offline command every so many blocks
+const PROGRESS_INTERVAL: u64 = 1000;
+
+fn print_progress(verb: &str, height: u64, last_height: u64, start: Instant) {
+	if height % PROGRESS_INTERVAL == 0 || height == last_height {
+		let elapsed = start.elapsed().as_secs().max(1);
+		println!(
+			"{} block {}/{} ({}%, {}s elapsed)",
+			verb,
+			height,
+			last_height,
+			height * 100 / last_height.max(1),
+			elapsed
+		);
+	}
+}
+
+// Height passed as the named argument, if any.
+fn height_arg(args: &ArgMatches<'_>, name: &str) -> Result<Option<u64>, String> {
+	match args.value_of(name) {
+		Some(h) => h
+			.parse()
+			.map(Some)
+			.map_err(|e| format!("Invalid --{} {}: {}", name, h, e)),
+		None => Ok(None),
+	}
+}
+
+fn export_blocks(config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let path = PathBuf::from(args.value_of("file").unwrap());
+	let (from, to) = match (height_arg(args, "from"), height_arg(args, "to")) {
+		(Ok(from), Ok(to)) => (from.unwrap_or(1), to),
+		(Err(e), _) | (_, Err(e)) => {
+			println!("{}", e);
+			return 1;
+		}
+	};
+
+	let start = Instant::now();
+	let res = offline::open_chain(config).and_then(|(chain, _lock)| {
+		offline::export_blocks(&chain, &path, from, to, |height, last_height| {
+			print_progress("Exported", height, last_height, start)
+		})
+	});
+	match res {
+		Ok(count) => {
+			println!("Exported {} blocks to {}", count, path.display());
+			0
+		}
+		Err(e) => {
+			println!("Failed to export blocks: {:?}", e);
+			1
+		}
+	}
+}
+
+fn import_blocks(config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let path = PathBuf::from(args.value_of("file").unwrap());
+
+	let start = Instant::now();
+	let res = offline::open_chain(config).and_then(|(chain, _lock)| {
+		println!(
+			"Importing blocks from {} on top of {}",
+			path.display(),
+			chain.head()?.height
+		);
+		offline::import_blocks(&chain, &path, |height, last_height| {
+			print_progress("Imported", height, last_height, start)
+		})
+	});
+	match res {
+		Ok(count) => {
+			println!("Imported {} blocks from {}", count, path.display());
+			0
+		}
+		Err(e) => {
+			println!("Failed to import blocks: {:?}", e);
+			1
+		}
+	}
+}
+
+fn snapshot(config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let dir = PathBuf::from(args.value_of("dir").unwrap());
+	let height = match height_arg(args, "height") {
+		Ok(height) => height.unwrap(),
+		Err(e) => {
+			println!("{}", e);
+			return 1;
+		}
+	};
+
+	let res = offline::open_chain(config)
+		.and_then(|(chain, _lock)| offline::create_snapshot(&chain, &dir, height));
+	match res {
+		Ok(manifest) => {
+			println!(
+				"Wrote snapshot at {} ({}) to {}",
+				manifest.height,
+				manifest.hash,
+				dir.display()
+			);
+			0
+		}
+		Err(e) => {
+			println!("Failed to write snapshot: {:?}", e);
+			1
+		}
+	}
+}
+
+fn restore_snapshot(config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let dir = PathBuf::from(args.value_of("dir").unwrap());
+
+	let start = Instant::now();
+	let res = offline::open_chain(config).and_then(|(chain, _lock)| {
+		offline::restore_snapshot(&chain, &dir, |height, last_height| {
+			println!(
+				"Added header {}/{} ({}s elapsed)",
+				height,
+				last_height,
+				start.elapsed().as_secs()
+			)
+		})
+	});
+	match res {
+		Ok(manifest) => {
+			println!(
+				"Restored snapshot at {} ({}) from {}",
+				manifest.height,
+				manifest.hash,
+				dir.display()
+			);
+			0
+		}
+		Err(e) => {
+			println!("Failed to restore snapshot: {:?}", e);
+			1
+		}
+	}
+}
+
+fn check_db(config: &servers::ServerConfig) -> i32 {
+	match offline::check_db(config) {
+		Ok(check) => {
+			for repaired in &check.repaired {
+				println!("Repaired {}", repaired);
+			}
+			for error in &check.errors {
+				println!("Error {}", error);
+			}
+			if check.head.last_block_h != check.prev_head.last_block_h {
+				println!(
+					"Chain head rewound from {} ({}) to {} ({})",
+					check.prev_head.height,
+					check.prev_head.last_block_h,
+					check.head.height,
+					check.head.last_block_h
+				);
+			}
+			println!(
+				"Rebuilt the output index, {} outputs",
+				check.indexed_outputs
+			);
+			if check.errors.is_empty() {
+				println!("Chain database at {} is consistent", check.head.height);
+				0
+			} else {
+				println!("Chain database can't be repaired, it needs a resync");
+				1
+			}
+		}
+		Err(e) => {
+			println!("Failed to check the chain database: {:?}", e);
+			1
+		}
+	}
+}
+
+fn compact_db(config: &servers::ServerConfig) -> i32 {
+	match offline::compact_db(config) {
+		Ok(compaction) => {
+			let databases = vec![("chain", compaction.chain), ("peer", compaction.peers)];
+			for (name, sizes) in databases {
+				if let Some((before, after)) = sizes {
+					println!(
+						"Compacted the {} database from {} to {} bytes",
+						name, before, after
+					);
+				}
+			}
+			0
+		}
+		Err(e) => {
+			println!("Failed to compact the databases: {:?}", e);
+			1
+		}
+	}
+}
+
 /// Handles the server part of the command line, mostly running, starting and
 /// stopping the Epic blockchain server. Processes all the command line
 /// arguments to build a proper configuration and runs Epic with that
@@ -218,6 +419,24 @@ pub fn server_command(
 			("run", _) => {
 				start_server(server_config);
 			}
+			("export-blocks", Some(args)) => {
+				return export_blocks(&server_config, args);
+			}
+			("import-blocks", Some(args)) => {
+				return import_blocks(&server_config, args);
+			}
+			("snapshot", Some(args)) => {
+				return snapshot(&server_config, args);
+			}
+			("restore-snapshot", Some(args)) => {
+				return restore_snapshot(&server_config, args);
+			}
+			("check-db", _) => {
+				return check_db(&server_config);
+			}
+			("compact-db", _) => {
+				return compact_db(&server_config);
+			}
 			("", _) => {
 				println!("Subcommand required, use 'epic help server' for details");
 			}
@@ -69,9 +69,10 @@ impl TableViewItem<PeerColumn> for PeerStats {
 			PeerColumn::Address => self.addr.clone(),
 			PeerColumn::State => self.state.clone(),
 			PeerColumn::UsedBandwidth => format!(
-				"S: {}, R: {}",
+				"S: {}, R: {}{}",
 				size_to_string(self.sent_bytes_per_sec),
 				size_to_string(self.received_bytes_per_sec),
+				if self.throttled { " (throttled)" } else { "" },
 			)
 			.to_string(),
 			PeerColumn::TotalDifficulty => format!(
@@ -192,13 +192,17 @@ impl TUIStatusListener for TUIStatusView {
 				SyncStatus::BodySync {
 					current_height,
 					highest_height,
+					blocks_per_min,
 				} => {
 					let percent = if highest_height == 0 {
 						0
 					} else {
 						current_height * 100 / highest_height
 					};
-					format!("Downloading blocks: {}%, step 4/4", percent)
+					format!(
+						"Downloading blocks: {}% at {} blocks/min, step 4/4",
+						percent, blocks_per_min
+					)
 				}
 				SyncStatus::Shutdown => "Shutting down, closing connections".to_string(),
 			}
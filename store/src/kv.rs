@@ -0,0 +1,231 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key-value backends the chain and peer stores are built on. Backends deal
+//! in raw bytes, serialization of core types is layered on top of them.
+
+use std::collections::BTreeMap;
+use std::marker;
+
+use crate::core::ser;
+use crate::lmdb::{Error, StoreStats};
+
+/// Iterator over raw (key, value) pairs, in key order.
+pub type RawIterator = Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)>>;
+
+/// A key-value store. All writes are done through a batch, committed
+/// atomically.
+pub trait KVStore: Send + Sync {
+	/// Gets a value, provided its key
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Whether the provided key exists
+	fn exists(&self, key: &[u8]) -> Result<bool, Error>;
+
+	/// Produces the (key, value) pairs with keys starting with the provided
+	/// prefix, all of them if empty.
+	fn iter_raw(&self, prefix: &[u8]) -> Result<RawIterator, Error>;
+
+	/// Builds a new batch of writes.
+	fn batch_raw(&self) -> Result<Box<dyn KVBatch + '_>, Error>;
+
+	/// Page usage of the store, without the count of entries by kind. Stores
+	/// without pages only report their number of entries.
+	fn usage(&self) -> Result<StoreStats, Error>;
+
+	/// Usage statistics of the store. Entries get counted by kind, as told
+	/// by the first byte of their key, the provided prefixes naming the
+	/// kinds. Entries with any other prefix are counted as "other". Counting
+	/// walks all the keys of the store, which can take a while.
+	fn stats(&self, prefixes: &[(u8, &str)]) -> Result<StoreStats, Error> {
+		let mut stats = self.usage()?;
+		for (_, name) in prefixes {
+			stats.entries_by_prefix.insert(name.to_string(), 0);
+		}
+		for (key, _) in self.iter_raw(&[])? {
+			count_entry(&mut stats.entries_by_prefix, prefixes, &key);
+		}
+		Ok(stats)
+	}
+
+	/// Gets a `Readable` value, provided its key. Encapsulates serialization.
+	fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error>
+	where
+		Self: Sized,
+	{
+		self.get(key)?.map(|v| deserialize(&v)).transpose()
+	}
+
+	/// Produces an iterator of (key, value) pairs, where values are
+	/// `Readable` types, for the keys starting with the provided prefix.
+	fn iter<T: ser::Readable>(&self, prefix: &[u8]) -> Result<SerIterator<T>, Error>
+	where
+		Self: Sized,
+	{
+		Ok(SerIterator::new(self.iter_raw(prefix)?))
+	}
+
+	/// Builds a new batch to be used with this store.
+	fn batch(&self) -> Result<Batch<'_>, Error>
+	where
+		Self: Sized,
+	{
+		Ok(Batch::new(self.batch_raw()?))
+	}
+}
+
+/// Writes to a key-value store, done atomically on commit and abandoned
+/// otherwise. Reads see the writes of the batch, except for iteration which
+/// only sees what was committed.
+pub trait KVBatch {
+	/// Writes a single key/value pair
+	fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+
+	/// Gets a value, provided its key
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Deletes a key/value pair, failing with NotFoundErr if there is none
+	/// with that key
+	fn delete(&self, key: &[u8]) -> Result<(), Error>;
+
+	/// Produces the committed (key, value) pairs with keys starting with the
+	/// provided prefix.
+	fn iter_raw(&self, prefix: &[u8]) -> Result<RawIterator, Error>;
+
+	/// Writes the batch to the store
+	fn commit(self: Box<Self>) -> Result<(), Error>;
+
+	/// Creates a child of this batch. It will be merged with its parent on
+	/// commit, abandoned otherwise.
+	fn child(&mut self) -> Result<Box<dyn KVBatch + '_>, Error>;
+}
+
+/// Batch to write multiple Writeables to a store in an atomic manner.
+pub struct Batch<'a> {
+	inner: Box<dyn KVBatch + 'a>,
+}
+
+impl<'a> Batch<'a> {
+	/// Wraps a batch of the backend.
+	pub fn new(inner: Box<dyn KVBatch + 'a>) -> Batch<'a> {
+		Batch { inner }
+	}
+
+	/// Writes a single key/value pair to the db
+	pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+		self.inner.put(key, value)
+	}
+
+	/// Writes a single key and its `Writeable` value to the db. Encapsulates
+	/// serialization.
+	pub fn put_ser<W: ser::Writeable>(&self, key: &[u8], value: &W) -> Result<(), Error> {
+		let ser_value = ser::ser_vec(value);
+		match ser_value {
+			Ok(data) => self.put(key, &data),
+			Err(err) => Err(Error::SerErr(format!("{}", err))),
+		}
+	}
+
+	/// gets a value from the db, provided its key
+	pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		self.inner.get(key)
+	}
+
+	/// Whether the provided key exists
+	pub fn exists(&self, key: &[u8]) -> Result<bool, Error> {
+		Ok(self.inner.get(key)?.is_some())
+	}
+
+	/// Produces an iterator of `Readable` types moving forward from the
+	/// provided key.
+	pub fn iter<T: ser::Readable>(&self, from: &[u8]) -> Result<SerIterator<T>, Error> {
+		Ok(SerIterator::new(self.inner.iter_raw(from)?))
+	}
+
+	/// Gets a `Readable` value from the db, provided its key, taking the
+	/// content of the current batch into account.
+	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
+		self.get(key)?.map(|v| deserialize(&v)).transpose()
+	}
+
+	/// Deletes a key/value pair from the db
+	pub fn delete(&self, key: &[u8]) -> Result<(), Error> {
+		self.inner.delete(key)
+	}
+
+	/// Writes the batch to db
+	pub fn commit(self) -> Result<(), Error> {
+		self.inner.commit()
+	}
+
+	/// Creates a child of this batch. It will be merged with its parent on
+	/// commit, abandoned otherwise.
+	pub fn child(&mut self) -> Result<Batch<'_>, Error> {
+		Ok(Batch::new(self.inner.child()?))
+	}
+}
+
+/// An iterator thad produces Readable instances back. Wraps the lower level
+/// raw iterator of a store and deserializes the returned values, stopping at
+/// the first one that can't be.
+pub struct SerIterator<T>
+where
+	T: ser::Readable,
+{
+	inner: RawIterator,
+	_marker: marker::PhantomData<T>,
+}
+
+impl<T> SerIterator<T>
+where
+	T: ser::Readable,
+{
+	/// Deserializes the values of the provided raw iterator.
+	pub fn new(inner: RawIterator) -> SerIterator<T> {
+		SerIterator {
+			inner,
+			_marker: marker::PhantomData,
+		}
+	}
+}
+
+impl<T> Iterator for SerIterator<T>
+where
+	T: ser::Readable,
+{
+	type Item = (Vec<u8>, T);
+
+	fn next(&mut self) -> Option<(Vec<u8>, T)> {
+		let (key, value) = self.inner.next()?;
+		deserialize(&value).ok().map(|value| (key, value))
+	}
+}
+
+fn deserialize<T: ser::Readable>(mut value: &[u8]) -> Result<T, Error> {
+	ser::deserialize(&mut value).map_err(|e| Error::SerErr(format!("{}", e)))
+}
+
+/// Counts the entry with the provided key under the name of its prefix, or
+/// under "other".
+pub(crate) fn count_entry(
+	entries: &mut BTreeMap<String, u64>,
+	prefixes: &[(u8, &str)],
+	key: &[u8],
+) {
+	let name = prefixes
+		.iter()
+		.find(|(prefix, _)| key.first() == Some(prefix))
+		.map_or("other", |(_, name)| *name);
+	*entries.entry(name.to_string()).or_insert(0) += 1;
+}
@@ -188,6 +188,14 @@ impl LeafSet {
 		Ok(())
 	}
 
+	/// Positions added and positions removed since the last flush.
+	pub fn pending_changes(&self) -> (Bitmap, Bitmap) {
+		(
+			self.bitmap.andnot(&self.bitmap_bak),
+			self.bitmap_bak.andnot(&self.bitmap),
+		)
+	}
+
 	/// Discard any pending changes.
 	pub fn discard(&mut self) {
 		self.bitmap = self.bitmap_bak.clone();
@@ -212,4 +220,16 @@ impl LeafSet {
 	pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
 		self.bitmap.iter().map(|x| x as u64)
 	}
+
+	/// Iterator over the positions in the leaf_set from the provided one on,
+	/// looking up the positions after it rather than walking the whole
+	/// bitmap up to it.
+	pub fn iter_from(&self, from: u64) -> impl Iterator<Item = u64> + '_ {
+		let max = if self.bitmap.is_empty() {
+			0
+		} else {
+			self.bitmap.maximum() as u64
+		};
+		(from.max(1)..=max).filter(move |&pos| self.bitmap.contains(pos as u32))
+	}
 }
@@ -31,8 +31,10 @@ extern crate epic_util as util;
 
 //use epic_core as core;
 
+pub mod kv;
 pub mod leaf_set;
 pub mod lmdb;
+pub mod mem;
 pub mod pmmr;
 pub mod prune_list;
 pub mod types;
@@ -41,7 +43,9 @@ const SEP: u8 = b':';
 
 use byteorder::{BigEndian, WriteBytesExt};
 
+pub use crate::kv::*;
 pub use crate::lmdb::*;
+pub use crate::mem::MemStore;
 
 /// Build a db key from a prefix and a byte vector identifier.
 pub fn to_key(prefix: u8, k: &mut Vec<u8>) -> Vec<u8> {
@@ -14,16 +14,18 @@
 
 //! Storage of core types using LMDB.
 
+use std::collections::BTreeMap;
 use std::fs;
-use std::marker;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
 use lmdb_zero as lmdb;
 use lmdb_zero::traits::CreateCursor;
 use lmdb_zero::LmdbResultExt;
 
-use crate::core::ser;
-use crate::util::{RwLock, RwLockReadGuard};
+use crate::kv::{count_entry, KVBatch, KVStore, RawIterator};
+use crate::util::RwLock;
 
 /// number of bytes to grow the database by when needed
 pub const ALLOC_CHUNK_SIZE: usize = 134_217_728; //128 MB
@@ -45,6 +47,9 @@ pub enum Error {
 	/// Wraps a serialization error for Writeable or Readable
 	#[fail(display = "Serialization Error")]
 	SerErr(String),
+	/// Wraps an io error, while handling the database files directly
+	#[fail(display = "IO Error: {}", _0)]
+	IoErr(String),
 }
 
 impl From<lmdb::error::Error> for Error {
@@ -53,6 +58,12 @@ impl From<lmdb::error::Error> for Error {
 	}
 }
 
+impl From<io::Error> for Error {
+	fn from(e: io::Error) -> Error {
+		Error::IoErr(e.to_string())
+	}
+}
+
 /// unwraps the inner option by converting the none case to a not found error
 pub fn option_to_not_found<T>(res: Result<Option<T>, Error>, field_name: &str) -> Result<T, Error> {
 	match res {
@@ -62,6 +73,74 @@ pub fn option_to_not_found<T>(res: Result<Option<T>, Error>, field_name: &str) -
 	}
 }
 
+/// Usage statistics of a store, see Store::stats.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StoreStats {
+	/// Size of a page, in bytes
+	pub page_size: u64,
+	/// Size of the memory map, how large the database can get before a resize
+	pub map_size: u64,
+	/// Number of pages in the database file
+	pub total_pages: u64,
+	/// Number of pages holding entries
+	pub used_pages: u64,
+	/// Number of pages left free by deletions, only reused by later writes
+	/// until the database is compacted
+	pub free_pages: u64,
+	/// Number of entries
+	pub entries: u64,
+	/// Number of entries by kind, as named by the key prefixes provided
+	pub entries_by_prefix: BTreeMap<String, u64>,
+}
+
+impl StoreStats {
+	/// Size of the database file, in bytes
+	pub fn file_size(&self) -> u64 {
+		self.total_pages * self.page_size
+	}
+}
+
+/// Compacts the environment under the provided directory (named "lmdb" by
+/// default), copying the pages in use only to a new file that then replaces
+/// the database file. The environment must not be open meanwhile. Returns
+/// the size of the database file before and after, in bytes, both 0 if
+/// there is no database yet.
+pub fn compact(root_path: &str, env_name: Option<&str>) -> Result<(u64, u64), Error> {
+	let name = env_name.unwrap_or("lmdb");
+	let env_path = Path::new(root_path).join(name);
+	let data_file = |dir: &Path| dir.join("data.mdb");
+	if !data_file(&env_path).exists() {
+		return Ok((0, 0));
+	}
+
+	let copy_path = Path::new(root_path).join(format!("{}.compact", name));
+	if copy_path.exists() {
+		fs::remove_dir_all(&copy_path)?;
+	}
+	fs::create_dir_all(&copy_path)?;
+
+	{
+		let mut env_builder = lmdb::EnvBuilder::new()?;
+		env_builder.set_maxdbs(8)?;
+		let env =
+			unsafe { env_builder.open(&env_path.to_string_lossy(), lmdb::open::NOTLS, 0o600)? };
+		env.copy(&copy_path.to_string_lossy(), lmdb::copy::COMPACT)?;
+	}
+
+	let size_before = fs::metadata(data_file(&env_path))?.len();
+	let size_after = fs::metadata(data_file(&copy_path))?.len();
+	fs::rename(data_file(&copy_path), data_file(&env_path))?;
+	fs::remove_dir_all(&copy_path)?;
+
+	info!(
+		"Compacted database {} from {} to {} bytes",
+		env_path.display(),
+		size_before,
+		size_after
+	);
+	Ok((size_before, size_after))
+}
+
 /// LMDB-backed store facilitating data access and serialization. All writes
 /// are done through a Batch abstraction providing atomicity.
 pub struct Store {
@@ -201,9 +280,10 @@ impl Store {
 		);
 		Ok(())
 	}
+}
 
-	/// Gets a value from the db, provided its key
-	pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+impl KVStore for Store {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
 		let db = self.db.read();
 		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
 		let access = txn.access();
@@ -213,34 +293,7 @@ impl Store {
 			.map_err(From::from)
 	}
 
-	/// Gets a `Readable` value from the db, provided its key. Encapsulates
-	/// serialization.
-	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
-		let db = self.db.read();
-		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
-		let access = txn.access();
-		self.get_ser_access(key, &access, db)
-	}
-
-	fn get_ser_access<T: ser::Readable>(
-		&self,
-		key: &[u8],
-		access: &lmdb::ConstAccessor<'_>,
-		db: RwLockReadGuard<'_, Option<Arc<lmdb::Database<'static>>>>,
-	) -> Result<Option<T>, Error> {
-		let res: lmdb::error::Result<&[u8]> = access.get(&db.as_ref().unwrap(), key);
-		match res.to_opt() {
-			Ok(Some(mut res)) => match ser::deserialize(&mut res) {
-				Ok(res) => Ok(Some(res)),
-				Err(e) => Err(Error::SerErr(format!("{}", e))),
-			},
-			Ok(None) => Ok(None),
-			Err(e) => Err(From::from(e)),
-		}
-	}
-
-	/// Whether the provided key exists
-	pub fn exists(&self, key: &[u8]) -> Result<bool, Error> {
+	fn exists(&self, key: &[u8]) -> Result<bool, Error> {
 		let db = self.db.read();
 		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
 		let access = txn.access();
@@ -248,44 +301,82 @@ impl Store {
 		res.to_opt().map(|r| r.is_some()).map_err(From::from)
 	}
 
-	/// Produces an iterator of (key, value) pairs, where values are `Readable` types
-	/// moving forward from the provided key.
-	pub fn iter<T: ser::Readable>(&self, from: &[u8]) -> Result<SerIterator<T>, Error> {
+	fn iter_raw(&self, prefix: &[u8]) -> Result<RawIterator, Error> {
 		let db = self.db.read();
 		let tx = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
 		let cursor = Arc::new(tx.cursor(db.as_ref().unwrap().clone()).unwrap());
-		Ok(SerIterator {
+		Ok(Box::new(LmdbIterator {
 			tx,
 			cursor,
 			seek: false,
-			prefix: from.to_vec(),
-			_marker: marker::PhantomData,
-		})
+			prefix: prefix.to_vec(),
+		}))
 	}
 
-	/// Builds a new batch to be used with this store.
-	pub fn batch(&self) -> Result<Batch<'_>, Error> {
+	fn batch_raw(&self) -> Result<Box<dyn KVBatch + '_>, Error> {
 		// check if the db needs resizing before returning the batch
 		if self.needs_resize()? {
 			self.do_resize()?;
 		}
 		let txn = lmdb::WriteTransaction::new(self.env.clone())?;
-		Ok(Batch {
+		Ok(Box::new(LmdbBatch {
 			store: self,
 			tx: txn,
+		}))
+	}
+
+	fn usage(&self) -> Result<StoreStats, Error> {
+		let env_info = self.env.info()?;
+		let env_stat = self.env.stat()?;
+		let db = self.db.read();
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let db_stat = txn.db_stat(&db.as_ref().unwrap())?;
+
+		// the main database only references the named ones, and the first
+		// two pages of the file are the meta pages
+		let pages =
+			|stat: &lmdb::Stat| (stat.branch_pages + stat.leaf_pages + stat.overflow_pages) as u64;
+		let total_pages = env_info.last_pgno as u64 + 1;
+		let used_pages = pages(&env_stat) + pages(&db_stat) + 2;
+		Ok(StoreStats {
+			page_size: env_stat.psize as u64,
+			map_size: env_info.mapsize as u64,
+			total_pages,
+			used_pages,
+			free_pages: total_pages.saturating_sub(used_pages),
+			entries: db_stat.entries as u64,
+			entries_by_prefix: BTreeMap::new(),
 		})
 	}
+
+	// Walks the keys with a cursor, without copying the values out.
+	fn stats(&self, prefixes: &[(u8, &str)]) -> Result<StoreStats, Error> {
+		let mut stats = self.usage()?;
+		for (_, name) in prefixes {
+			stats.entries_by_prefix.insert(name.to_string(), 0);
+		}
+
+		let db = self.db.read();
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let access = txn.access();
+		let mut cursor = txn.cursor(db.as_ref().unwrap().clone())?;
+		let mut next = cursor.first::<[u8], lmdb::Ignore>(&access).to_opt()?;
+		while let Some((key, _)) = next {
+			count_entry(&mut stats.entries_by_prefix, prefixes, key);
+			next = cursor.next::<[u8], lmdb::Ignore>(&access).to_opt()?;
+		}
+		Ok(stats)
+	}
 }
 
-/// Batch to write multiple Writeables to db in an atomic manner.
-pub struct Batch<'a> {
+/// Write transaction of an LMDB store.
+struct LmdbBatch<'a> {
 	store: &'a Store,
 	tx: lmdb::WriteTransaction<'a>,
 }
 
-impl<'a> Batch<'a> {
-	/// Writes a single key/value pair to the db
-	pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+impl<'a> KVBatch for LmdbBatch<'a> {
+	fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
 		let db = self.store.db.read();
 		self.tx
 			.access()
@@ -293,85 +384,54 @@ impl<'a> Batch<'a> {
 		Ok(())
 	}
 
-	/// Writes a single key and its `Writeable` value to the db. Encapsulates
-	/// serialization.
-	pub fn put_ser<W: ser::Writeable>(&self, key: &[u8], value: &W) -> Result<(), Error> {
-		let ser_value = ser::ser_vec(value);
-		match ser_value {
-			Ok(data) => self.put(key, &data),
-			Err(err) => Err(Error::SerErr(format!("{}", err))),
-		}
-	}
-
-	/// gets a value from the db, provided its key
-	pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-		self.store.get(key)
-	}
-
-	/// Whether the provided key exists
-	pub fn exists(&self, key: &[u8]) -> Result<bool, Error> {
-		self.store.exists(key)
-	}
-
-	/// Produces an iterator of `Readable` types moving forward from the
-	/// provided key.
-	pub fn iter<T: ser::Readable>(&self, from: &[u8]) -> Result<SerIterator<T>, Error> {
-		self.store.iter(from)
-	}
-
-	/// Gets a `Readable` value from the db, provided its key, taking the
-	/// content of the current batch into account.
-	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
 		let access = self.tx.access();
 		let db = self.store.db.read();
-		self.store.get_ser_access(key, &access, db)
+		let res: lmdb::error::Result<&[u8]> = access.get(&db.as_ref().unwrap(), key);
+		res.map(|res| res.to_vec()).to_opt().map_err(From::from)
 	}
 
-	/// Deletes a key/value pair from the db
-	pub fn delete(&self, key: &[u8]) -> Result<(), Error> {
+	fn delete(&self, key: &[u8]) -> Result<(), Error> {
 		let db = self.store.db.read();
-		self.tx.access().del_key(&db.as_ref().unwrap(), key)?;
-		Ok(())
+		self.tx
+			.access()
+			.del_key(&db.as_ref().unwrap(), key)
+			.to_opt()?
+			.ok_or_else(|| Error::NotFoundErr(format!("key {:?}", key)))
+	}
+
+	fn iter_raw(&self, prefix: &[u8]) -> Result<RawIterator, Error> {
+		self.store.iter_raw(prefix)
 	}
 
-	/// Writes the batch to db
-	pub fn commit(self) -> Result<(), Error> {
+	fn commit(self: Box<Self>) -> Result<(), Error> {
 		self.tx.commit()?;
 		Ok(())
 	}
 
-	/// Creates a child of this batch. It will be merged with its parent on
-	/// commit, abandoned otherwise.
-	pub fn child(&mut self) -> Result<Batch<'_>, Error> {
-		Ok(Batch {
+	fn child(&mut self) -> Result<Box<dyn KVBatch + '_>, Error> {
+		Ok(Box::new(LmdbBatch {
 			store: self.store,
 			tx: self.tx.child_tx()?,
-		})
+		}))
 	}
 }
 
-/// An iterator thad produces Readable instances back. Wraps the lower level
-/// DBIterator and deserializes the returned values.
-pub struct SerIterator<T>
-where
-	T: ser::Readable,
-{
+/// Iterator over the raw (key, value) pairs of a read transaction, moving
+/// forward from the provided prefix for as long as the keys match it.
+struct LmdbIterator {
 	tx: Arc<lmdb::ReadTransaction<'static>>,
 	cursor: Arc<lmdb::Cursor<'static, 'static>>,
 	seek: bool,
 	prefix: Vec<u8>,
-	_marker: marker::PhantomData<T>,
 }
 
-impl<T> Iterator for SerIterator<T>
-where
-	T: ser::Readable,
-{
-	type Item = (Vec<u8>, T);
+impl Iterator for LmdbIterator {
+	type Item = (Vec<u8>, Vec<u8>);
 
-	fn next(&mut self) -> Option<(Vec<u8>, T)> {
+	fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
 		let access = self.tx.access();
-		let kv = if self.seek {
+		let kv: lmdb::error::Result<(&[u8], &[u8])> = if self.seek {
 			Arc::get_mut(&mut self.cursor).unwrap().next(&access)
 		} else {
 			self.seek = true;
@@ -380,26 +440,8 @@ where
 				.seek_range_k(&access, &self.prefix[..])
 		};
 		match kv {
-			Ok((k, v)) => self.deser_if_prefix_match(k, v),
-			Err(_) => None,
-		}
-	}
-}
-
-impl<T> SerIterator<T>
-where
-	T: ser::Readable,
-{
-	fn deser_if_prefix_match(&self, key: &[u8], value: &[u8]) -> Option<(Vec<u8>, T)> {
-		let plen = self.prefix.len();
-		if plen == 0 || key[0..plen] == self.prefix[..] {
-			if let Ok(value) = ser::deserialize(&mut &value[..]) {
-				Some((key.to_vec(), value))
-			} else {
-				None
-			}
-		} else {
-			None
+			Ok((k, v)) if k.starts_with(&self.prefix) => Some((k.to_vec(), v.to_vec())),
+			_ => None,
 		}
 	}
 }
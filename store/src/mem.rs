@@ -0,0 +1,150 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory key-value store, for tests and throwaway nodes. Nothing is ever
+//! written to disk.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::kv::{KVBatch, KVStore, RawIterator};
+use crate::lmdb::{Error, StoreStats};
+use crate::util::RwLock;
+
+// Pending writes of a batch, None for a deletion.
+type Writes = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Key-value store kept in memory. Unlike LMDB, batches don't wait on each
+/// other, the last one committed wins on the keys they both write.
+pub struct MemStore {
+	data: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemStore {
+	/// An empty store.
+	pub fn new() -> MemStore {
+		MemStore {
+			data: RwLock::new(BTreeMap::new()),
+		}
+	}
+}
+
+impl KVStore for MemStore {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		Ok(self.data.read().get(key).cloned())
+	}
+
+	fn exists(&self, key: &[u8]) -> Result<bool, Error> {
+		Ok(self.data.read().contains_key(key))
+	}
+
+	// Takes a snapshot of the matching entries, later writes aren't seen.
+	fn iter_raw(&self, prefix: &[u8]) -> Result<RawIterator, Error> {
+		let entries = self
+			.data
+			.read()
+			.range(prefix.to_vec()..)
+			.take_while(|(k, _)| k.starts_with(prefix))
+			.map(|(k, v)| (k.clone(), v.clone()))
+			.collect::<Vec<_>>();
+		Ok(Box::new(entries.into_iter()))
+	}
+
+	fn batch_raw(&self) -> Result<Box<dyn KVBatch + '_>, Error> {
+		Ok(Box::new(MemBatch {
+			store: self,
+			parent: None,
+			writes: RefCell::new(BTreeMap::new()),
+		}))
+	}
+
+	fn usage(&self) -> Result<StoreStats, Error> {
+		Ok(StoreStats {
+			entries: self.data.read().len() as u64,
+			..StoreStats::default()
+		})
+	}
+}
+
+/// Batch of a memory store, or child of another batch, keeping its writes
+/// aside until committed.
+struct MemBatch<'a> {
+	store: &'a MemStore,
+	parent: Option<&'a MemBatch<'a>>,
+	writes: RefCell<Writes>,
+}
+
+impl<'a> MemBatch<'a> {
+	// Pending write of the key, in this batch or its parents
+	fn pending(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+		match self.writes.borrow().get(key) {
+			Some(value) => Some(value.clone()),
+			None => self.parent.and_then(|p| p.pending(key)),
+		}
+	}
+}
+
+impl<'a> KVBatch for MemBatch<'a> {
+	fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+		self.writes
+			.borrow_mut()
+			.insert(key.to_vec(), Some(value.to_vec()));
+		Ok(())
+	}
+
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		match self.pending(key) {
+			Some(value) => Ok(value),
+			None => self.store.get(key),
+		}
+	}
+
+	fn delete(&self, key: &[u8]) -> Result<(), Error> {
+		if self.get(key)?.is_none() {
+			return Err(Error::NotFoundErr(format!("key {:?}", key)));
+		}
+		self.writes.borrow_mut().insert(key.to_vec(), None);
+		Ok(())
+	}
+
+	fn iter_raw(&self, prefix: &[u8]) -> Result<RawIterator, Error> {
+		self.store.iter_raw(prefix)
+	}
+
+	fn commit(self: Box<Self>) -> Result<(), Error> {
+		let batch = *self;
+		let writes = batch.writes.into_inner();
+		match batch.parent {
+			Some(parent) => parent.writes.borrow_mut().extend(writes),
+			None => {
+				let mut data = batch.store.data.write();
+				for (key, value) in writes {
+					match value {
+						Some(value) => data.insert(key, value),
+						None => data.remove(&key),
+					};
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn child(&mut self) -> Result<Box<dyn KVBatch + '_>, Error> {
+		Ok(Box::new(MemBatch {
+			store: self.store,
+			parent: Some(&*self),
+			writes: RefCell::new(BTreeMap::new()),
+		}))
+	}
+}
@@ -13,16 +13,20 @@
 
 //! Implementation of the persistent Backend for the prunable MMR tree.
 
-use std::fs::{self, File};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Write};
 use std::{io, time};
 
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
 use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::pmmr::{self, family, Backend};
 use crate::core::core::BlockHeader;
-use crate::core::ser::{FixedLength, PMMRable};
+use crate::core::ser::{self, FixedLength, PMMRable, Readable, StreamingReader};
 use crate::leaf_set::LeafSet;
 use crate::prune_list::PruneList;
-use crate::types::{AppendOnlyFile, DataFile, SizeEntry, SizeInfo};
+use crate::save_via_temp_file;
+use crate::types::{AppendOnlyFile, DataFile, FileUndo, SizeEntry, SizeInfo};
 use croaring::Bitmap;
 use std::path::{Path, PathBuf};
 
@@ -31,6 +35,7 @@ const PMMR_DATA_FILE: &str = "pmmr_data.bin";
 const PMMR_LEAF_FILE: &str = "pmmr_leaf.bin";
 const PMMR_PRUN_FILE: &str = "pmmr_prun.bin";
 const PMMR_SIZE_FILE: &str = "pmmr_size.bin";
+const PMMR_JOURNAL_FILE: &str = "pmmr_journal.bin";
 const REWIND_FILE_CLEANUP_DURATION_SECONDS: u64 = 60 * 60 * 24; // 24 hours as seconds
 
 /// The list of PMMR_Files for internal purposes
@@ -41,6 +46,22 @@ pub const PMMR_FILES: [&str; 4] = [
 	PMMR_PRUN_FILE,
 ];
 
+/// Outcome of a check of the files of a PMMR backend.
+#[derive(Debug, Default)]
+pub struct PMMRCheck {
+	/// Inconsistencies found and repaired
+	pub repaired: Vec<String>,
+	/// Inconsistencies found that could not be repaired
+	pub errors: Vec<String>,
+}
+
+impl PMMRCheck {
+	/// Whether the files are consistent, once repaired.
+	pub fn is_ok(&self) -> bool {
+		self.errors.is_empty()
+	}
+}
+
 /// PMMR persistent backend implementation. Relies on multiple facilities to
 /// handle writing, reading and pruning.
 ///
@@ -140,6 +161,14 @@ impl<T: PMMRable> Backend<T> for PMMRBackend<T> {
 		}
 	}
 
+	fn leaf_pos_iter_from(&self, from: u64) -> Box<Iterator<Item = u64> + '_> {
+		if self.prunable {
+			Box::new(self.leaf_set.iter_from(from))
+		} else {
+			panic!("leaf_pos_iter_from not implemented for non-prunable PMMR")
+		}
+	}
+
 	fn data_as_temp_file(&self) -> Result<File, String> {
 		self.data_file
 			.as_temp_file()
@@ -185,6 +214,14 @@ impl<T: PMMRable> Backend<T> for PMMRBackend<T> {
 		Ok(())
 	}
 
+	fn n_compacted_leaves(&self) -> u64 {
+		if self.prunable {
+			self.prune_list.get_total_leaf_shift()
+		} else {
+			0
+		}
+	}
+
 	fn dump_stats(&self) {
 		debug!(
 			"pmmr backend: unpruned: {}, hashes: {}, data: {}, leaf_set: {}, prune_list: {}",
@@ -311,6 +348,47 @@ impl<T: PMMRable> PMMRBackend<T> {
 		self.leaf_set.discard();
 	}
 
+	/// Writes a journal of the current, non synced state of the backend ahead
+	/// of a sync, so the sync can be undone if it gets interrupted or the
+	/// caller never commits it. Ids must increase from one sync to the next,
+	/// see recover_journal. The journals of earlier syncs are kept until the
+	/// caller commits them, as told by the id of the last committed sync, so a
+	/// crash undoes every sync of a commit and not only the last one.
+	pub fn write_journal(&self, id: u64, committed_id: Option<u64>) -> io::Result<()> {
+		let path = self.data_dir.join(PMMR_JOURNAL_FILE);
+		let mut journals = if path.exists() {
+			Journal::read_all(&path)?
+		} else {
+			vec![]
+		};
+		journals.retain(|j| committed_id.map_or(true, |c| j.id > c));
+
+		let mut files = self.hash_file.undo()?;
+		files.extend(self.data_file.undo()?);
+		let (leaves_added, leaves_removed) = if self.prunable {
+			self.leaf_set.pending_changes()
+		} else {
+			(Bitmap::create(), Bitmap::create())
+		};
+		journals.push(Journal {
+			id,
+			files,
+			leaves_added,
+			leaves_removed,
+		});
+		Journal::save_all(&journals, &path)
+	}
+
+	// Compaction rewrites the files, a journal written before no longer
+	// applies to them.
+	fn remove_journal(&self) -> io::Result<()> {
+		let path = self.data_dir.join(PMMR_JOURNAL_FILE);
+		if path.exists() {
+			fs::remove_file(path)?;
+		}
+		Ok(())
+	}
+
 	/// Takes the leaf_set at a given cutoff_pos and generates an updated
 	/// prune_list. Saves the updated prune_list to disk, compacts the hash
 	/// and data files based on the prune_list and saves both to disk.
@@ -369,9 +447,99 @@ impl<T: PMMRable> PMMRBackend<T> {
 		// 5. cleanup rewind files
 		self.clean_rewind_files()?;
 
+		// 6. forget about the last sync, we can't undo it anymore
+		self.remove_journal()?;
+
 		Ok(true)
 	}
 
+	/// Checks the files of a backend in the provided dir are consistent with
+	/// each other and with a MMR of the provided size, repairing what an
+	/// interrupted flush or prune may have left behind: leftover temporary
+	/// files, torn or extra appends to the hash, data and size files and leaf
+	/// positions beyond the end of the MMR. Missing data can't be repaired.
+	/// The backend must not be open meanwhile.
+	pub fn check_files<P: AsRef<Path>>(
+		data_dir: P,
+		prunable: bool,
+		fixed_size: bool,
+		size: u64,
+	) -> io::Result<PMMRCheck> {
+		let data_dir = data_dir.as_ref();
+		let mut check = PMMRCheck::default();
+
+		// Pruned files are written aside, then moved in place of the
+		// original once it's removed.
+		for file in &[PMMR_HASH_FILE, PMMR_DATA_FILE, PMMR_SIZE_FILE] {
+			let path = data_dir.join(file);
+			recover_temp_file(&path, &path.with_extension("tmp"), &mut check)?;
+		}
+		for file in &[PMMR_LEAF_FILE, PMMR_PRUN_FILE] {
+			let tmp_path = data_dir.join(format!("{}.tmp", file));
+			recover_temp_file(&data_dir.join(file), &tmp_path, &mut check)?;
+		}
+
+		let prune_list = PruneList::open(data_dir.join(PMMR_PRUN_FILE))?;
+		let pruned_beyond = prune_list
+			.to_vec()
+			.iter()
+			.filter(|&&pos| pos > size)
+			.count();
+		if pruned_beyond > 0 {
+			check.errors.push(format!(
+				"{}: {} pruned subtrees beyond the end of the MMR",
+				data_dir.join(PMMR_PRUN_FILE).display(),
+				pruned_beyond
+			));
+		}
+
+		check_fixed_size_file(
+			&data_dir.join(PMMR_HASH_FILE),
+			Hash::LEN as u64,
+			size - prune_list.get_shift(size),
+			&mut check,
+		)?;
+
+		let leaf_count = pmmr::n_leaves(size) - prune_list.get_leaf_shift(size);
+		if fixed_size {
+			check_fixed_size_file(
+				&data_dir.join(PMMR_DATA_FILE),
+				T::E::LEN as u64,
+				leaf_count,
+				&mut check,
+			)?;
+		} else {
+			check_variable_size_files::<T::E>(
+				&data_dir.join(PMMR_DATA_FILE),
+				&data_dir.join(PMMR_SIZE_FILE),
+				leaf_count,
+				&mut check,
+			)?;
+		}
+
+		if prunable {
+			let leaf_set_path = data_dir.join(PMMR_LEAF_FILE);
+			let mut leaf_set = LeafSet::open(&leaf_set_path)?;
+			let invalid = leaf_set
+				.iter()
+				.filter(|&pos| pos > size || !pmmr::is_leaf(pos) || prune_list.is_pruned(pos))
+				.collect::<Vec<_>>();
+			if !invalid.is_empty() {
+				for pos in &invalid {
+					leaf_set.remove(*pos);
+				}
+				leaf_set.flush()?;
+				check.repaired.push(format!(
+					"{}: removed {} positions not in the MMR",
+					leaf_set_path.display(),
+					invalid.len()
+				));
+			}
+		}
+
+		Ok(check)
+	}
+
 	fn clean_rewind_files(&self) -> io::Result<u32> {
 		let data_dir = self.data_dir.clone();
 		let pattern = format!("{}.", PMMR_LEAF_FILE);
@@ -411,6 +579,270 @@ impl<T: PMMRable> PMMRBackend<T> {
 	}
 }
 
+/// Recovers the files of a backend in the provided dir from its journaled
+/// syncs. The changes of every sync the caller didn't commit, as told by the
+/// id of the last committed sync, get undone from the last one back, bringing
+/// the files back to their state before the first of them. Returns whether
+/// anything was undone. The backend must not be open meanwhile.
+pub fn recover_journal<P: AsRef<Path>>(data_dir: P, committed_id: Option<u64>) -> io::Result<bool> {
+	let data_dir = data_dir.as_ref();
+	let path = data_dir.join(PMMR_JOURNAL_FILE);
+
+	// an interrupted journal write means nothing got synced after it
+	let tmp_path = path.with_extension("tmp");
+	if tmp_path.exists() {
+		fs::remove_file(&tmp_path)?;
+	}
+	if !path.exists() {
+		return Ok(false);
+	}
+
+	let journals = Journal::read_all(&path)?
+		.into_iter()
+		.filter(|j| committed_id.map_or(true, |id| j.id > id))
+		.collect::<Vec<_>>();
+	for journal in journals.iter().rev() {
+		for file in &journal.files {
+			file.apply(data_dir)?;
+		}
+	}
+	if journals
+		.iter()
+		.any(|j| !j.leaves_added.is_empty() || !j.leaves_removed.is_empty())
+	{
+		let leaf_set_path = data_dir.join(PMMR_LEAF_FILE);
+		let tmp_path = data_dir.join(format!("{}.tmp", PMMR_LEAF_FILE));
+		recover_temp_file(&leaf_set_path, &tmp_path, &mut PMMRCheck::default())?;
+
+		let mut leaf_set = LeafSet::open(&leaf_set_path)?;
+		for journal in journals.iter().rev() {
+			for pos in journal.leaves_added.iter() {
+				leaf_set.remove(pos as u64);
+			}
+			for pos in journal.leaves_removed.iter() {
+				leaf_set.add(pos as u64);
+			}
+		}
+		leaf_set.flush()?;
+	}
+	fs::remove_file(&path)?;
+	Ok(!journals.is_empty())
+}
+
+/// Journal of the changes of a backend sync: undo records of its files and
+/// the leaf positions it adds and removes. The journal file holds those of
+/// all the syncs not committed yet, oldest first.
+struct Journal {
+	id: u64,
+	files: Vec<FileUndo>,
+	leaves_added: Bitmap,
+	leaves_removed: Bitmap,
+}
+
+impl Journal {
+	// Written aside then moved in place, so the journals are either complete
+	// or missing, and synced before any of the files they undo.
+	fn save_all(journals: &[Journal], path: &Path) -> io::Result<()> {
+		let tmp_path = path.with_extension("tmp");
+		{
+			let mut file = File::create(&tmp_path)?;
+			file.write_u64::<BigEndian>(journals.len() as u64)?;
+			for journal in journals {
+				journal.write(&mut file)?;
+			}
+			file.sync_all()?;
+		}
+		fs::rename(tmp_path, path)
+	}
+
+	fn read_all(path: &Path) -> io::Result<Vec<Journal>> {
+		let mut file = BufReader::new(File::open(path)?);
+		let count = file.read_u64::<BigEndian>()?;
+		let mut journals = vec![];
+		for _ in 0..count {
+			journals.push(Journal::read(&mut file)?);
+		}
+		Ok(journals)
+	}
+
+	fn write<W: Write>(&self, file: &mut W) -> io::Result<()> {
+		file.write_u64::<BigEndian>(self.id)?;
+		file.write_u64::<BigEndian>(self.files.len() as u64)?;
+		for undo in &self.files {
+			write_bytes(file, undo.file_name.as_bytes())?;
+			file.write_u64::<BigEndian>(undo.truncate_at)?;
+			write_bytes(file, &undo.tail)?;
+		}
+		write_bytes(file, &self.leaves_added.serialize())?;
+		write_bytes(file, &self.leaves_removed.serialize())
+	}
+
+	fn read<R: Read>(file: &mut R) -> io::Result<Journal> {
+		let id = file.read_u64::<BigEndian>()?;
+		let count = file.read_u64::<BigEndian>()?;
+		let mut files = vec![];
+		for _ in 0..count {
+			let file_name = String::from_utf8(read_bytes(file)?)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+			let truncate_at = file.read_u64::<BigEndian>()?;
+			let tail = read_bytes(file)?;
+			files.push(FileUndo {
+				file_name,
+				truncate_at,
+				tail,
+			});
+		}
+		let leaves_added = Bitmap::deserialize(&read_bytes(file)?);
+		let leaves_removed = Bitmap::deserialize(&read_bytes(file)?);
+		Ok(Journal {
+			id,
+			files,
+			leaves_added,
+			leaves_removed,
+		})
+	}
+}
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+	w.write_u64::<BigEndian>(bytes.len() as u64)?;
+	w.write_all(bytes)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+	let len = r.read_u64::<BigEndian>()?;
+	let mut bytes = vec![];
+	r.take(len).read_to_end(&mut bytes)?;
+	if bytes.len() as u64 != len {
+		return Err(io::Error::new(
+			io::ErrorKind::UnexpectedEof,
+			"truncated journal",
+		));
+	}
+	Ok(bytes)
+}
+
+// Moves a temporary file left behind by an interrupted replace in place of
+// the original, if that one was already removed. Otherwise the temporary
+// file may be incomplete and is removed.
+fn recover_temp_file(path: &Path, tmp_path: &Path, check: &mut PMMRCheck) -> io::Result<()> {
+	if !tmp_path.exists() {
+		return Ok(());
+	}
+	if path.exists() {
+		fs::remove_file(tmp_path)?;
+		check
+			.repaired
+			.push(format!("{}: removed leftover file", tmp_path.display()));
+	} else {
+		fs::rename(tmp_path, path)?;
+		check.repaired.push(format!(
+			"{}: restored from {}",
+			path.display(),
+			tmp_path.display()
+		));
+	}
+	Ok(())
+}
+
+fn file_len(path: &Path) -> io::Result<u64> {
+	if path.exists() {
+		Ok(fs::metadata(path)?.len())
+	} else {
+		Ok(0)
+	}
+}
+
+fn truncate_file(path: &Path, len: u64, new_len: u64, check: &mut PMMRCheck) -> io::Result<()> {
+	if new_len < len {
+		let file = OpenOptions::new().write(true).open(path)?;
+		file.set_len(new_len)?;
+		file.sync_all()?;
+		check.repaired.push(format!(
+			"{}: truncated {} trailing bytes",
+			path.display(),
+			len - new_len
+		));
+	}
+	Ok(())
+}
+
+// Checks a file of fixed size elements holds whole elements, and no more
+// than expected, truncating it otherwise.
+fn check_fixed_size_file(
+	path: &Path,
+	elmt_size: u64,
+	expected: u64,
+	check: &mut PMMRCheck,
+) -> io::Result<()> {
+	let len = file_len(path)?;
+	let count = len / elmt_size;
+	if count < expected {
+		check.errors.push(format!(
+			"{}: {} elements, expected {}",
+			path.display(),
+			count,
+			expected
+		));
+	}
+	truncate_file(path, len, count.min(expected) * elmt_size, check)
+}
+
+// Same for a file of variable size elements, also rewriting its size file if
+// it doesn't match the elements actually in the data file.
+fn check_variable_size_files<E: Readable>(
+	path: &Path,
+	size_path: &Path,
+	expected: u64,
+	check: &mut PMMRCheck,
+) -> io::Result<()> {
+	let len = file_len(path)?;
+	let mut entries = vec![];
+	if len > 0 {
+		let mut reader = BufReader::new(File::open(path)?);
+		let mut streaming_reader = StreamingReader::new(&mut reader, time::Duration::from_secs(1));
+		let mut offset = 0;
+		while (entries.len() as u64) < expected && E::read(&mut streaming_reader).is_ok() {
+			let end = streaming_reader.total_bytes_read();
+			entries.push(SizeEntry {
+				offset,
+				size: (end - offset) as u16,
+			});
+			offset = end;
+		}
+	}
+	if (entries.len() as u64) < expected {
+		check.errors.push(format!(
+			"{}: {} elements, expected {}",
+			path.display(),
+			entries.len(),
+			expected
+		));
+	}
+	let data_len = entries
+		.last()
+		.map(|e| e.offset + e.size as u64)
+		.unwrap_or(0);
+	truncate_file(path, len, data_len, check)?;
+
+	let mut sizes = vec![];
+	for entry in &entries {
+		sizes.extend(ser::ser_vec(entry).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?);
+	}
+	let current = if size_path.exists() {
+		fs::read(size_path)?
+	} else {
+		vec![]
+	};
+	if current != sizes {
+		save_via_temp_file(size_path, ".tmp", |mut w| w.write_all(&sizes))?;
+		check.repaired.push(format!(
+			"{}: rebuilt from the data file",
+			size_path.display()
+		));
+	}
+	Ok(())
+}
+
 /// Filter remove list to exclude roots.
 /// We want to keep roots around so we have hashes for Merkle proofs.
 fn removed_excl_roots(removed: &Bitmap) -> Bitmap {
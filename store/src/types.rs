@@ -18,9 +18,10 @@ use tempfile::tempfile;
 use crate::core::ser::{
 	self, BinWriter, FixedLength, Readable, Reader, StreamingReader, Writeable, Writer,
 };
+use std::cmp;
 use std::fmt::Debug;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::marker;
 use std::path::{Path, PathBuf};
 use std::time;
@@ -60,6 +61,44 @@ impl Writeable for SizeEntry {
 	}
 }
 
+/// Undo record of the pending changes to an append-only file, taken before
+/// flushing them. A flush truncates the file (if rewound) then appends to it,
+/// so putting back the bytes past the truncation point restores the file as
+/// it was, whether the flush completed, got torn or never started.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileUndo {
+	/// Name of the file, in the directory it lives in
+	pub file_name: String,
+	/// Length the flush truncates the file to before appending
+	pub truncate_at: u64,
+	/// Bytes of the file on disk past truncate_at
+	pub tail: Vec<u8>,
+}
+
+impl FileUndo {
+	/// Restore the file in the provided directory as it was before the flush.
+	pub fn apply(&self, dir: &Path) -> io::Result<()> {
+		let path = dir.join(&self.file_name);
+		let mut file = OpenOptions::new().write(true).create(true).open(&path)?;
+		let len = file.metadata()?.len();
+		if len < self.truncate_at {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!(
+					"{}: {} bytes, expected at least {}",
+					path.display(),
+					len,
+					self.truncate_at
+				),
+			));
+		}
+		file.set_len(self.truncate_at)?;
+		file.seek(SeekFrom::Start(self.truncate_at))?;
+		file.write_all(&self.tail)?;
+		file.sync_all()
+	}
+}
+
 /// Are we dealing with "fixed size" data or "variable size" data in a data file?
 pub enum SizeInfo {
 	/// Fixed size data.
@@ -130,6 +169,11 @@ where
 		self.file.discard()
 	}
 
+	/// Undo records of the unsynced changes to the file.
+	pub fn undo(&self) -> io::Result<Vec<FileUndo>> {
+		self.file.undo()
+	}
+
 	/// Size of the file in number of elements (not bytes).
 	pub fn size(&self) -> u64 {
 		self.file.size_in_elmts().unwrap_or(0)
@@ -395,6 +439,45 @@ where
 		self.buffer = vec![];
 	}
 
+	/// Undo records of the non-flushed changes, for the size file first if
+	/// we have one, then for this file.
+	pub fn undo(&self) -> io::Result<Vec<FileUndo>> {
+		let mut undo = vec![];
+		if let SizeInfo::VariableSize(ref size_file) = self.size_info {
+			undo.extend(size_file.undo()?);
+		}
+
+		let len = if self.path.exists() { self.size()? } else { 0 };
+		let truncate_at = if self.buffer_start_pos_bak == 0 {
+			len
+		} else if self.buffer_start_pos == 0 {
+			0
+		} else {
+			let (offset, size) = self.offset_and_size(self.buffer_start_pos - 1)?;
+			cmp::min(offset + size as u64, len)
+		};
+
+		let mut tail = vec![0; (len - truncate_at) as usize];
+		if !tail.is_empty() {
+			let mut file = File::open(&self.path)?;
+			file.seek(SeekFrom::Start(truncate_at))?;
+			file.read_exact(&mut tail)?;
+		}
+
+		let file_name = self
+			.path
+			.file_name()
+			.and_then(|n| n.to_str())
+			.unwrap_or_default()
+			.to_string();
+		undo.push(FileUndo {
+			file_name,
+			truncate_at,
+			tail,
+		});
+		Ok(undo)
+	}
+
 	/// Read the bytes representing the element at the given position (0-indexed).
 	/// Uses the offset cache to determine the offset to read from and the size
 	/// in bytes to actually read.
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use epic_store as store;
+use epic_store::KVStore;
 use epic_util as util;
 
 use epic_core::ser::{self, Readable, Reader, Writeable, Writer};
@@ -101,3 +102,59 @@ fn lmdb_allocate() -> Result<(), store::Error> {
 
 	Ok(())
 }
+
+#[test]
+fn lmdb_stats_and_compact() -> Result<(), store::Error> {
+	let test_dir = "test_output/lmdb_compact";
+	setup(test_dir);
+	let prefixes = [(b'a', "as"), (b'b', "bs")];
+	let key = |prefix: u8, i: usize| store::to_key(prefix, &mut format!("{}", i).into_bytes());
+	{
+		let store = store::Store::new(test_dir, Some("test2"), None, None)?;
+		let batch = store.batch()?;
+		for i in 0..2000 {
+			batch.put(&key(b'a', i), &[1; 512])?;
+		}
+		for i in 0..10 {
+			batch.put(&key(b'b', i), &[2; 512])?;
+			batch.put(&key(b'c', i), &[3; 512])?;
+		}
+		batch.commit()?;
+
+		let stats = store.stats(&prefixes)?;
+		assert_eq!(stats.entries, 2020);
+		assert_eq!(stats.entries_by_prefix["as"], 2000);
+		assert_eq!(stats.entries_by_prefix["bs"], 10);
+		assert_eq!(stats.entries_by_prefix["other"], 10);
+		assert!(stats.used_pages <= stats.total_pages);
+
+		// deleting most of them leaves the file as large
+		let batch = store.batch()?;
+		for i in 10..2000 {
+			batch.delete(&key(b'a', i))?;
+		}
+		match batch.delete(&key(b'a', 10)) {
+			Err(store::Error::NotFoundErr(_)) => (),
+			res => panic!("unexpected delete result: {:?}", res),
+		}
+		batch.commit()?;
+
+		let stats = store.stats(&prefixes)?;
+		assert_eq!(stats.entries, 30);
+		assert_eq!(stats.entries_by_prefix["as"], 10);
+		assert!(stats.free_pages > stats.used_pages);
+	}
+
+	let (size_before, size_after) = store::lmdb::compact(test_dir, Some("test2"))?;
+	assert!(size_after < size_before / 10);
+	{
+		let store = store::Store::new(test_dir, Some("test2"), None, None)?;
+		let stats = store.stats(&prefixes)?;
+		assert_eq!(stats.entries, 30);
+		assert!(stats.free_pages < stats.used_pages);
+		assert!(stats.file_size() <= size_after);
+		assert_eq!(store.get(&key(b'b', 3))?, Some(vec![2; 512]));
+	}
+	clean_output_dir(test_dir);
+	Ok(())
+}
@@ -0,0 +1,79 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use epic_store as store;
+use epic_store::{KVStore, MemStore};
+
+#[test]
+fn mem_batch_and_child() -> Result<(), store::Error> {
+	let store = MemStore::new();
+	{
+		let batch = store.batch()?;
+		batch.put(b"a1", b"one")?;
+		batch.put(b"a2", b"two")?;
+		batch.put(b"b1", b"three")?;
+		assert_eq!(batch.get(b"a1")?, Some(b"one".to_vec()));
+		// Nothing written until committed
+		assert!(!store.exists(b"a1")?);
+		batch.commit()?;
+	}
+	assert_eq!(store.get(b"a2")?, Some(b"two".to_vec()));
+
+	// Abandoned batch, deletion not applied
+	{
+		let batch = store.batch()?;
+		batch.delete(b"a1")?;
+		assert!(!batch.exists(b"a1")?);
+		// same as with lmdb, nothing to delete twice
+		match batch.delete(b"a1") {
+			Err(store::Error::NotFoundErr(_)) => (),
+			res => panic!("unexpected delete result: {:?}", res),
+		}
+	}
+	assert!(store.exists(b"a1")?);
+
+	// Child writes are only seen by the store once both child and parent
+	// are committed, an abandoned child leaves its parent untouched.
+	{
+		let mut batch = store.batch()?;
+		{
+			let child = batch.child()?;
+			child.delete(b"a1")?;
+			child.put(b"a3", b"four")?;
+			assert_eq!(child.get(b"a2")?, Some(b"two".to_vec()));
+			child.commit()?;
+		}
+		{
+			let child = batch.child()?;
+			child.put(b"a4", b"five")?;
+		}
+		assert!(!batch.exists(b"a1")?);
+		assert!(batch.exists(b"a3")?);
+		assert!(!batch.exists(b"a4")?);
+		assert!(store.exists(b"a1")?);
+		batch.commit()?;
+	}
+	assert!(!store.exists(b"a1")?);
+	assert!(!store.exists(b"a4")?);
+
+	let keys = store.iter_raw(b"a")?.map(|(k, _)| k).collect::<Vec<_>>();
+	assert_eq!(keys, vec![b"a2".to_vec(), b"a3".to_vec()]);
+
+	let stats = store.stats(&[(b'a', "as")])?;
+	assert_eq!(stats.entries, 3);
+	assert_eq!(stats.entries_by_prefix["as"], 2);
+	assert_eq!(stats.entries_by_prefix["other"], 1);
+
+	Ok(())
+}
@@ -17,6 +17,7 @@ use epic_core as core;
 use epic_store as store;
 
 use std::fs;
+use std::io::Write;
 
 use chrono::prelude::Utc;
 use croaring::Bitmap;
@@ -108,6 +109,38 @@ fn pmmr_append() {
 	teardown(data_dir);
 }
 
+#[test]
+fn pmmr_leaf_pos_iter_from() {
+	let (data_dir, elems) = setup("leaf_pos_iter_from");
+	{
+		let mut backend =
+			store::pmmr::PMMRBackend::new(data_dir.to_string(), true, false, None).unwrap();
+		let mmr_size = load(0, &elems[0..9], &mut backend);
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(2).unwrap();
+			pmmr.prune(8).unwrap();
+		}
+		backend.sync().unwrap();
+
+		// leaves at 1, 2, 4, 5, 8, 9, 11, 12 and 16, with 2 and 8 removed
+		assert_eq!(
+			backend.leaf_pos_iter_from(0).collect::<Vec<_>>(),
+			vec![1, 4, 5, 9, 11, 12, 16]
+		);
+		assert_eq!(
+			backend.leaf_pos_iter_from(5).collect::<Vec<_>>(),
+			vec![5, 9, 11, 12, 16]
+		);
+		assert_eq!(
+			backend.leaf_pos_iter_from(6).collect::<Vec<_>>(),
+			vec![9, 11, 12, 16]
+		);
+		assert_eq!(backend.leaf_pos_iter_from(17).count(), 0);
+	}
+	teardown(data_dir);
+}
+
 #[test]
 fn pmmr_compact_leaf_sibling() {
 	let (data_dir, elems) = setup("compact_leaf_sibling");
@@ -832,6 +865,234 @@ fn cleanup_rewind_files_test() {
 	teardown(data_dir);
 }
 
+#[test]
+fn pmmr_check_files() {
+	let (data_dir, elems) = setup("check_files");
+	let mmr_size = {
+		let mut backend =
+			store::pmmr::PMMRBackend::new(data_dir.to_string(), true, false, None).unwrap();
+		let mmr_size = load(0, &elems[0..4], &mut backend);
+		backend.sync().unwrap();
+
+		// flushed but never committed to the db
+		load(mmr_size, &elems[4..6], &mut backend);
+		backend.sync().unwrap();
+		mmr_size
+	};
+
+	// and a torn append on top of it
+	for file in &["pmmr_hash.bin", "pmmr_data.bin"] {
+		let mut f = fs::OpenOptions::new()
+			.append(true)
+			.open(format!("{}/{}", data_dir, file))
+			.unwrap();
+		f.write_all(&[1, 2, 3]).unwrap();
+	}
+
+	let check = store::pmmr::PMMRBackend::<TestElem>::check_files(&data_dir, true, false, mmr_size)
+		.unwrap();
+	assert!(check.is_ok(), "{:?}", check.errors);
+	// hash and data files truncated, size file rebuilt, leaf_set cleaned up
+	assert_eq!(check.repaired.len(), 4, "{:?}", check.repaired);
+
+	{
+		let backend =
+			store::pmmr::PMMRBackend::<TestElem>::new(data_dir.to_string(), true, false, None)
+				.unwrap();
+		assert_eq!(backend.unpruned_size(), mmr_size);
+		assert_eq!(backend.data_size(), 4);
+		assert_eq!(backend.get_data(5), Some(elems[3]));
+		assert_eq!(
+			backend.leaf_pos_iter().collect::<Vec<_>>(),
+			vec![1, 2, 4, 5]
+		);
+	}
+
+	// nothing left to repair
+	let check = store::pmmr::PMMRBackend::<TestElem>::check_files(&data_dir, true, false, mmr_size)
+		.unwrap();
+	assert!(check.is_ok());
+	assert!(check.repaired.is_empty(), "{:?}", check.repaired);
+
+	// missing data can't be repaired
+	let check =
+		store::pmmr::PMMRBackend::<TestElem>::check_files(&data_dir, true, false, mmr_size + 3)
+			.unwrap();
+	assert!(!check.is_ok());
+
+	teardown(data_dir);
+}
+
+#[test]
+fn pmmr_journal_crash_recovery() {
+	let (data_dir, elems) = setup("journal");
+	let files = ["pmmr_hash.bin", "pmmr_data.bin", "pmmr_leaf.bin"];
+	let read_files = |dir: &str| -> Vec<Vec<u8>> {
+		files
+			.iter()
+			.map(|f| fs::read(format!("{}/{}", dir, f)).unwrap())
+			.collect()
+	};
+
+	let (before, after, journal) = {
+		let mut backend =
+			store::pmmr::PMMRBackend::new(data_dir.to_string(), true, false, None).unwrap();
+		let mmr_size = load(0, &elems[0..6], &mut backend);
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(1).unwrap();
+		}
+		backend.sync().unwrap();
+		let before = read_files(&data_dir);
+
+		// a fork: rewind to 4 elements, add others and spend one
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.rewind(7, &Bitmap::create()).unwrap();
+		}
+		let mmr_size = load(7, &elems[10..13], &mut backend);
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(4).unwrap();
+		}
+		backend.write_journal(2, Some(1)).unwrap();
+		let journal = fs::read(format!("{}/pmmr_journal.bin", data_dir)).unwrap();
+		backend.sync().unwrap();
+		(before, read_files(&data_dir), journal)
+	};
+	assert!(before.iter().zip(after.iter()).all(|(b, a)| b != a));
+
+	// states the files can be left in by a crash at each step of the sync,
+	// which writes the hash file, the data file then the leaf set
+	let mut crashes = vec![];
+	for synced in 0..=files.len() {
+		let mut state = before.clone();
+		state[..synced].clone_from_slice(&after[..synced]);
+		crashes.push(state);
+	}
+	for torn in 0..2 {
+		let mut state = before.clone();
+		state[..torn].clone_from_slice(&after[..torn]);
+		state[torn] = after[torn][..after[torn].len() - 2].to_vec();
+		crashes.push(state);
+	}
+
+	let check = |name: &str, state: &[Vec<u8>], committed: u64, leaf_tmp: bool| {
+		let dir = format!("{}-{}", data_dir, name);
+		fs::create_dir_all(&dir).unwrap();
+		for (file, bytes) in files.iter().zip(state.iter()) {
+			fs::write(format!("{}/{}", dir, file), bytes).unwrap();
+		}
+		fs::write(format!("{}/pmmr_journal.bin", dir), &journal).unwrap();
+		if leaf_tmp {
+			// leaf set replaced half way, only the new one left aside
+			fs::rename(
+				format!("{}/pmmr_leaf.bin", dir),
+				format!("{}/pmmr_leaf.bin.tmp", dir),
+			)
+			.unwrap();
+		}
+
+		let undone = store::pmmr::recover_journal(&dir, Some(committed)).unwrap();
+		assert_eq!(undone, committed < 2, "{}", name);
+		assert!(!std::path::Path::new(&format!("{}/pmmr_journal.bin", dir)).exists());
+		let expected = if undone { &before } else { &after };
+		assert_eq!(&read_files(&dir), expected, "{}", name);
+
+		// and nothing left to do on the next start
+		assert!(!store::pmmr::recover_journal(&dir, Some(committed)).unwrap());
+		teardown(dir);
+	};
+
+	for (i, state) in crashes.iter().enumerate() {
+		check(&format!("crash{}", i), state, 1, false);
+	}
+	check("leaf_tmp", &after, 1, true);
+
+	// the db commit went through, the sync stays
+	check("committed", &after, 2, false);
+
+	// a journal left aside before any sync is just dropped
+	fs::write(format!("{}/pmmr_journal.tmp", data_dir), &journal[..10]).unwrap();
+	fs::remove_file(format!("{}/pmmr_journal.bin", data_dir)).unwrap();
+	assert!(!store::pmmr::recover_journal(&data_dir, Some(2)).unwrap());
+	assert!(!std::path::Path::new(&format!("{}/pmmr_journal.tmp", data_dir)).exists());
+
+	teardown(data_dir);
+}
+
+#[test]
+fn pmmr_journal_several_syncs() {
+	let (data_dir, elems) = setup("journal_syncs");
+	let files = [
+		"pmmr_hash.bin",
+		"pmmr_data.bin",
+		"pmmr_leaf.bin",
+		"pmmr_journal.bin",
+	];
+	let read_files = |dir: &str| -> Vec<Vec<u8>> {
+		files[..3]
+			.iter()
+			.map(|f| fs::read(format!("{}/{}", dir, f)).unwrap())
+			.collect()
+	};
+
+	let (before, middle, after, state) = {
+		let mut backend =
+			store::pmmr::PMMRBackend::new(data_dir.to_string(), true, false, None).unwrap();
+		let mmr_size = load(0, &elems[0..6], &mut backend);
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(1).unwrap();
+		}
+		backend.sync().unwrap();
+		let before = read_files(&data_dir);
+
+		// the same backend synced twice before the caller commits
+		let mmr_size = load(mmr_size, &elems[6..9], &mut backend);
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(4).unwrap();
+		}
+		backend.write_journal(1, Some(0)).unwrap();
+		backend.sync().unwrap();
+		let middle = read_files(&data_dir);
+
+		let mmr_size = load(mmr_size, &elems[9..12], &mut backend);
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(8).unwrap();
+		}
+		backend.write_journal(2, Some(0)).unwrap();
+		backend.sync().unwrap();
+		let state = files
+			.iter()
+			.map(|f| fs::read(format!("{}/{}", data_dir, f)).unwrap())
+			.collect::<Vec<_>>();
+		(before, middle, read_files(&data_dir), state)
+	};
+	assert!(before != middle && middle != after);
+
+	let check = |name: &str, committed: u64, expected: &[Vec<u8>]| {
+		let dir = format!("{}-{}", data_dir, name);
+		fs::create_dir_all(&dir).unwrap();
+		for (file, bytes) in files.iter().zip(state.iter()) {
+			fs::write(format!("{}/{}", dir, file), bytes).unwrap();
+		}
+		let undone = store::pmmr::recover_journal(&dir, Some(committed)).unwrap();
+		assert_eq!(undone, committed < 2, "{}", name);
+		assert_eq!(&read_files(&dir)[..], expected, "{}", name);
+		teardown(dir);
+	};
+
+	// a crash before the commit undoes both syncs, not only the last one
+	check("none", 0, &before);
+	check("first", 1, &middle);
+	check("both", 2, &after);
+
+	teardown(data_dir);
+}
+
 /// Create some files for testing with, for example
 ///
 /// ```text